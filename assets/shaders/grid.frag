@@ -0,0 +1,7 @@
+#version 120
+
+uniform vec3 uAmbient;
+
+void main() {
+    gl_FragColor = vec4(vec3(0.5, 0.5, 0.5) * uAmbient, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 120
+
+attribute vec2 aPos;
+attribute vec4 aColor;
+uniform mat4 uMVP;
+
+varying vec4 vColor;
+
+void main() {
+    vColor = aColor;
+    gl_Position = uMVP * vec4(aPos, 0.0, 1.0);
+}
@@ -0,0 +1,8 @@
+#version 120
+
+uniform vec3 uColor;
+uniform vec3 uAmbient;
+
+void main() {
+    gl_FragColor = vec4(uColor * uAmbient, 1.0);
+}
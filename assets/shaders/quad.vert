@@ -0,0 +1,8 @@
+#version 120
+
+attribute vec2 aPos;
+uniform mat4 uMVP;
+
+void main() {
+    gl_Position = uMVP * vec4(aPos.x, aPos.y, 0.0, 1.0);
+}
@@ -0,0 +1,67 @@
+// Quads of the wgpu renderer, one instance each. The unit square is moved onto the window by the
+// instance's origin and axes, then cut down to a circle or triangle by the fragment stage.
+
+struct Uniforms {
+    projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+
+struct Instance {
+    @location(0) origin: vec2<f32>,
+    @location(1) axis_x: vec2<f32>,
+    @location(2) axis_y: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) shape: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) shape: u32,
+}
+
+// Two triangles covering the unit square
+var<private> CORNERS: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(0.0, 0.0),
+    vec2<f32>(1.0, 0.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(1.0, 0.0),
+);
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: Instance) -> VertexOutput {
+    let local = CORNERS[index];
+    let pos = instance.origin + instance.axis_x * local.x + instance.axis_y * local.y;
+
+    var out: VertexOutput;
+    out.position = uniforms.projection * vec4<f32>(pos, 0.0, 1.0);
+    out.local = local;
+    out.color = instance.color;
+    out.shape = u32(instance.shape + 0.5);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    switch in.shape {
+        // Circle filling the square
+        case 1u: {
+            if distance(in.local, vec2<f32>(0.5, 0.5)) > 0.5 {
+                discard;
+            }
+        }
+        // Triangle pointing along +x
+        case 2u: {
+            if abs(in.local.y - 0.5) > 0.5 * (1.0 - in.local.x) {
+                discard;
+            }
+        }
+        default: {}
+    }
+
+    return in.color;
+}
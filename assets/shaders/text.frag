@@ -0,0 +1,10 @@
+#version 120
+
+uniform sampler2D uAtlas;
+
+varying vec2 vUv;
+varying vec3 vColor;
+
+void main() {
+    gl_FragColor = vec4(vColor, texture2D(uAtlas, vUv).a);
+}
@@ -0,0 +1,15 @@
+#version 120
+
+attribute vec2 aPos;
+attribute vec2 aUv;
+attribute vec3 aColor;
+uniform mat4 uMVP;
+
+varying vec2 vUv;
+varying vec3 vColor;
+
+void main() {
+    vUv = aUv;
+    vColor = aColor;
+    gl_Position = uMVP * vec4(aPos, 0.0, 1.0);
+}
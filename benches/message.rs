@@ -0,0 +1,98 @@
+use std::hint::black_box;
+
+use cgmath::{vec2, vec3};
+use criterion::{criterion_group, criterion_main, Criterion};
+use game_server_sample::{
+    codec::Codec,
+    identity::Identity,
+    message::{Message, WhisperError},
+    terrain::TerrainMap,
+    version::Version,
+    ClientId, Player, WorldMode,
+};
+
+/// One message of every variant, with realistic field values
+fn sample_messages() -> Vec<Message> {
+    let mut player = Player::new(42, vec3(0.25, 0.5, 0.75));
+    player.pos = vec2(-512.25, 1024.5);
+    let client_id = ClientId(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+    let identity = Identity::from_secret_hex(&"5a".repeat(32)).unwrap();
+
+    vec![
+        Message::Ping(1234),
+        Message::Pong(1234, Some(std::time::Duration::from_micros(66_667))),
+        Message::Handshake(
+            Some(client_id),
+            Some(String::from("K7QX2M")),
+            Some(Box::new(identity.prove(client_id))),
+            Some(Codec::Binary),
+        ),
+        Message::Ack(
+            42,
+            vec3(0.25, 0.5, 0.75),
+            WorldMode::Wrap,
+            Some(0x1234_5678_9abc_def0),
+            Some(Version::current()),
+        ),
+        Message::KeepAlive(0x1234_5678_9abc_def0),
+        Message::Leave(42),
+        Message::Replicate(player),
+        Message::ServerShutdown,
+        Message::Kick(Some(String::from("idle"))),
+        Message::TickRateChange(30),
+        Message::Map(TerrainMap::builtin()),
+        Message::WorldClock(0.3125),
+        Message::Stats(42, 1520.0, 125),
+        Message::Name(42, String::from("khoi")),
+        Message::Chat(42, String::from("meet at the ice lake: north side")),
+        Message::Whisper(String::from("khoi"), String::from("over here")),
+        Message::WhisperFrom(42, String::from("over here")),
+        Message::WhisperFailed(WhisperError::Ambiguous, String::from("kh")),
+        Message::Correction(vec2(-500.0, 1010.0)),
+        Message::Position(42, vec2(-512.25, 1024.5), Some(7200)),
+    ]
+}
+
+fn serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+
+    for msg in sample_messages() {
+        group.bench_function(msg.name(), |b| b.iter(|| black_box(&msg).serialize()));
+    }
+
+    group.finish();
+}
+
+fn deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+
+    for msg in sample_messages() {
+        let wire = msg.serialize();
+        group.bench_function(msg.name(), |b| {
+            b.iter(|| Message::deserialize(black_box(&wire)))
+        });
+    }
+
+    group.finish();
+}
+
+/// Every sample message through each codec and back
+fn codecs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec");
+    let messages = sample_messages();
+
+    for codec in [Codec::Text, Codec::Json] {
+        group.bench_function(format!("{codec:?}"), |b| {
+            b.iter(|| {
+                for msg in &messages {
+                    let _ = black_box(codec.decode(&codec.encode(black_box(msg))));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, serialize, deserialize, codecs);
+criterion_main!(benches);
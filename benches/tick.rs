@@ -0,0 +1,80 @@
+use std::hint::black_box;
+
+use cgmath::{vec2, vec3};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use game_server_sample::{
+    globals, message::Message, rules::GameRules, simulate_player, Player, WorldMode,
+};
+
+const PLAYER_COUNTS: [usize; 3] = [10, 100, 1000];
+
+/// Players spread over the world, a share of them outside the bounds so clamping has work to do
+fn spawn_players(count: usize) -> Vec<Player> {
+    let bounds = &globals::WORLD_BOUNDS;
+    let width = bounds.max_x - bounds.min_x;
+    let height = bounds.max_y - bounds.min_y;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let mut player = Player::new(i as u64 + 1, vec3(t, 1.0 - t, 0.5));
+            player.pos = vec2(
+                bounds.min_x + t * width * 1.2,
+                bounds.max_y - t * height * 1.2,
+            );
+            player
+        })
+        .collect()
+}
+
+/// The server tick: simulate every player and queue its replication
+fn simulation_tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulation_tick");
+
+    for count in PLAYER_COUNTS {
+        let mut players = spawn_players(count);
+        let rules = GameRules::default();
+        let mut replication = Vec::with_capacity(count);
+
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| {
+                replication.clear();
+                for player in players.iter_mut() {
+                    replication.push(simulate_player(player, WorldMode::Bounded, &rules));
+                }
+                black_box(&replication);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Turning one tick worth of replication into wire bytes, serialized once per message like the
+/// broadcast sender does
+fn snapshot_assembly(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot_assembly");
+
+    for count in PLAYER_COUNTS {
+        let replication: Vec<Message> = spawn_players(count)
+            .into_iter()
+            .map(Message::Replicate)
+            .collect();
+
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| {
+                replication
+                    .iter()
+                    .map(|msg| msg.serialize().into_bytes())
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, simulation_tick, snapshot_assembly);
+criterion_main!(benches);
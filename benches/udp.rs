@@ -0,0 +1,95 @@
+use std::net::SocketAddr;
+
+use cgmath::{vec2, vec3};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use game_server_sample::{
+    message::Message,
+    udp_batch::{recv_batch, RecvBatch},
+    Player,
+};
+use tokio::{net::UdpSocket, runtime::Runtime};
+
+// Datagrams per round, kept within what the loopback socket buffers hold without drops
+const BATCH_SIZES: [usize; 3] = [16, 64, 256];
+
+const RECV_BATCH_CAPACITY: usize = 64;
+
+/// Sending socket, receiving socket and the address to send to
+fn loopback(rt: &Runtime) -> (UdpSocket, UdpSocket, SocketAddr) {
+    rt.block_on(async {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = receiver.local_addr().unwrap();
+
+        (sender, receiver, target)
+    })
+}
+
+/// A replication message, the bulk of the server's traffic
+fn payload() -> Vec<u8> {
+    let mut player = Player::new(42, vec3(0.25, 0.5, 0.75));
+    player.pos = vec2(-512.25, 1024.5);
+
+    Message::Replicate(player).serialize().into_bytes()
+}
+
+/// One `send_to` and one `recv_from` per datagram, the server's path without batching
+fn per_datagram(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (sender, receiver, target) = loopback(&rt);
+    let payload = payload();
+    let mut buf = [0u8; 2048];
+
+    let mut group = c.benchmark_group("udp_per_datagram");
+    for count in BATCH_SIZES {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter(|| {
+                rt.block_on(async {
+                    for _ in 0..count {
+                        sender.send_to(&payload, target).await.unwrap();
+                    }
+                    for _ in 0..count {
+                        receiver.recv_from(&mut buf).await.unwrap();
+                    }
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Same traffic sent one datagram at a time like the client outboxes do, received through
+/// [`recv_batch`]. Compare builds with and without the `mmsg` feature to see what the syscall
+/// batching adds.
+fn batched(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (sender, receiver, target) = loopback(&rt);
+    let payload = payload();
+    let mut batch = RecvBatch::new(RECV_BATCH_CAPACITY);
+
+    let mut group = c.benchmark_group("udp_batched");
+    for count in BATCH_SIZES {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter(|| {
+                rt.block_on(async {
+                    for _ in 0..count {
+                        sender.send_to(&payload, target).await.unwrap();
+                    }
+
+                    let mut received = 0;
+                    while received < count {
+                        received += recv_batch(&receiver, &mut batch).await.unwrap();
+                    }
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, per_datagram, batched);
+criterion_main!(benches);
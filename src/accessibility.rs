@@ -0,0 +1,81 @@
+use std::{io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::servers::{read_json, write_json};
+
+/// GUI text size with large text on, relative to the normal size
+pub const LARGE_TEXT_SCALE: f32 = 1.35;
+
+/// Accessibility settings from the menu, kept in the user config next to the favorite servers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Accessibility {
+    /// No screen shake, hit-stop, particles or dash trails
+    pub reduced_motion: bool,
+
+    /// Outlines around every player and darker grid lines
+    pub high_contrast: bool,
+
+    /// Bigger GUI text
+    pub large_text: bool,
+}
+
+impl Accessibility {
+    /// Settings in the config at `path`, the defaults if there are none or they can't be read
+    pub fn load(path: &PathBuf) -> Self {
+        read_json(path)
+            .and_then(|config| serde_json::from_value(config["accessibility"].clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &PathBuf) -> io::Result<()> {
+        // Other settings may live in the same file, only these are replaced
+        let mut config = read_json(path)
+            .filter(Value::is_object)
+            .unwrap_or_else(|| json!({}));
+        config["accessibility"] = json!(self);
+
+        write_json(path, &config)
+    }
+
+    pub fn text_scale(&self) -> f32 {
+        if self.large_text {
+            LARGE_TEXT_SCALE
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_saved_next_to_the_rest_of_the_config() {
+        let dir = std::env::temp_dir().join(format!(
+            "game-server-sample-accessibility-{}",
+            std::process::id()
+        ));
+        let config = dir.join("config.json");
+        assert_eq!(Accessibility::load(&config), Accessibility::default());
+
+        write_json(&config, &json!({ "favorite_servers": ["127.0.0.1:8080"] })).unwrap();
+        let settings = Accessibility {
+            reduced_motion: true,
+            large_text: true,
+            ..Accessibility::default()
+        };
+        settings.save(&config).unwrap();
+
+        assert_eq!(Accessibility::load(&config), settings);
+        assert_eq!(
+            read_json(&config).unwrap()["favorite_servers"],
+            json!(["127.0.0.1:8080"])
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
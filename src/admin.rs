@@ -0,0 +1,382 @@
+use std::{
+    fmt::Write as _,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
+
+use cgmath::Vector2;
+use game_server_sample::{Avatar, PlayerId, Shape};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    commands::{Args, CommandRegistry, CommandResult, Executed, Param, ParamKind, Permission},
+    message::NoticeLevel,
+    paths,
+    server::{metrics_csv, ServerHandle, WorldSnapshot},
+};
+
+/// Admin commands need the server task to answer, so their handlers hand back a future
+pub type AdminFuture = Pin<Box<dyn Future<Output = CommandResult> + Send>>;
+
+pub type AdminCommands = CommandRegistry<ServerHandle, AdminFuture>;
+
+/// Commands of the dedicated server console, also typed into the TUI
+pub fn admin_commands() -> AdminCommands {
+    let mut commands = AdminCommands::new();
+
+    commands.register(
+        "dump",
+        &[Param::optional("file", ParamKind::Text)],
+        "Print the full server state as JSON, or write it to a file. Relative paths are placed \
+         in the dumps folder of the data directory",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let file = args.get::<String>("file");
+
+            Box::pin(async move {
+                match file? {
+                    Some(file) => {
+                        let dir = paths::ensure_dir(paths::dump_dir())
+                            .map_err(|e| format!("Failed to create dump folder: {e}"))?;
+
+                        // Joining keeps absolute paths as they are
+                        dump_to_file(&server, &dir.join(file)).await;
+                        Ok(None)
+                    }
+                    None => serde_json::to_string_pretty(&server.dump().await)
+                        .map(Some)
+                        .map_err(|e| format!("Failed to serialize server state: {e}")),
+                }
+            })
+        },
+    );
+
+    commands.register(
+        "save",
+        &[Param::required("file", ParamKind::Text)],
+        "Save the players, clock and MOTD to restore them after a restart. Relative paths are \
+         placed in the saves folder of the data directory",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let file = args.value::<String>("file");
+
+            Box::pin(async move {
+                let path = save_path(&file?)?;
+                let world = server.save_world().await;
+                let players = world.players.len();
+
+                serde_json::to_string_pretty(&world)
+                    .map_err(std::io::Error::from)
+                    .and_then(|json| std::fs::write(&path, json))
+                    .map_err(|e| format!("Failed to save the world to {}: {e}", path.display()))?;
+
+                Ok(Some(format!(
+                    "Saved {players} players to {}",
+                    path.display()
+                )))
+            })
+        },
+    );
+
+    commands.register(
+        "load",
+        &[Param::required("file", ParamKind::Text)],
+        "Restore a saved world, players get their place back when they join again. Only while \
+         nobody is connected",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let file = args.value::<String>("file");
+
+            Box::pin(async move {
+                let path = save_path(&file?)?;
+                let waiting = server.load_world(WorldSnapshot::load(&path)?).await?;
+                server
+                    .log(format!("World loaded from {}", path.display()))
+                    .await;
+
+                Ok(Some(format!("Loaded, {waiting} players can rejoin")))
+            })
+        },
+    );
+
+    commands.register(
+        "stats",
+        &[],
+        "Show traffic counters per message type",
+        Permission::Admin,
+        |server, _| {
+            let stats = message_stats(server);
+            Box::pin(async move { Ok(Some(stats)) })
+        },
+    );
+
+    commands.register(
+        "metrics",
+        &[Param::required("file", ParamKind::Text)],
+        "Write the player count, tick times and traffic of the last hour as CSV, one row per \
+         second. Relative paths are placed in the dumps folder of the data directory",
+        Permission::Admin,
+        |server, args| {
+            let samples = server.metrics_history();
+            let file = args.value::<String>("file");
+
+            Box::pin(async move {
+                let dir = paths::ensure_dir(paths::dump_dir())
+                    .map_err(|e| format!("Failed to create dump folder: {e}"))?;
+                let path = dir.join(file?);
+
+                std::fs::write(&path, metrics_csv(&samples))
+                    .map_err(|e| format!("Failed to write metrics to {}: {e}", path.display()))?;
+
+                Ok(Some(format!(
+                    "Wrote {} samples to {}",
+                    samples.len(),
+                    path.display()
+                )))
+            })
+        },
+    );
+
+    commands.register(
+        "flagged",
+        &[],
+        "List players flagged as suspected cheaters",
+        Permission::Admin,
+        |server, _| {
+            let server = server.clone();
+            Box::pin(async move { Ok(Some(flagged_players(&server).await)) })
+        },
+    );
+
+    commands.register(
+        "rewind",
+        &[Param::required("id", ParamKind::Integer)],
+        "Show where everyone was as the player saw the world, the state their hits are checked \
+         against",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let player_id = args.value::<PlayerId>("id");
+
+            Box::pin(async move {
+                let player_id = player_id?;
+                match server.rewind(player_id).await {
+                    Some((lag, positions)) => Ok(Some(rewound_positions(lag, &positions))),
+                    None => Err(format!("No player with id {player_id}")),
+                }
+            })
+        },
+    );
+
+    commands.register(
+        "kick",
+        &[Param::required("id", ParamKind::Integer)],
+        "Kick a player from the server",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let player_id = args.value::<PlayerId>("id");
+
+            Box::pin(async move {
+                let player_id = player_id?;
+                if server.kick(player_id).await {
+                    Ok(None)
+                } else {
+                    Err(format!("No player with id {player_id}"))
+                }
+            })
+        },
+    );
+
+    commands.register(
+        "avatar",
+        &[
+            Param::required("id", ParamKind::Integer),
+            Param::required("shape", ParamKind::Word),
+            Param::optional("size", ParamKind::Number),
+        ],
+        "Make a player a square, circle or triangle, optionally of another size than the rules \
+         give everyone",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let player_id = args.value::<PlayerId>("id");
+            let avatar = avatar_arg(args);
+
+            Box::pin(async move {
+                server.set_avatar(player_id?, avatar?).await?;
+                Ok(None)
+            })
+        },
+    );
+
+    commands.register(
+        "motd",
+        &[Param::optional("text", ParamKind::Text)],
+        "Show the message of the day, or set the one players see from now on. 'none' removes it",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let text = args.get::<String>("text");
+
+            Box::pin(async move {
+                match text?.as_deref() {
+                    None => Ok(Some(server.motd().unwrap_or_else(|| "No MOTD".to_string()))),
+                    Some("none") => {
+                        server.set_motd(None);
+                        Ok(None)
+                    }
+                    Some(text) => {
+                        server.set_motd(Some(text.to_string()));
+                        Ok(None)
+                    }
+                }
+            })
+        },
+    );
+
+    commands.register(
+        "say",
+        &[Param::required("text", ParamKind::Text)],
+        "Announce something in every player's log",
+        Permission::Admin,
+        |server, args| {
+            let server = server.clone();
+            let text = args.value::<String>("text");
+
+            Box::pin(async move {
+                server.notice(NoticeLevel::Info, text?).await;
+                Ok(None)
+            })
+        },
+    );
+
+    commands
+}
+
+/// Avatar from the shape and size of an `avatar` command
+pub fn avatar_arg(args: &Args) -> Result<Avatar, String> {
+    let shape = args.value::<String>("shape")?;
+
+    Ok(Avatar {
+        shape: Shape::parse(&shape).ok_or_else(|| {
+            format!("Unknown shape '{shape}', expected square, circle or triangle")
+        })?,
+        size: args.get("size")?,
+    })
+}
+
+/// Run one console line, returning what to show the operator
+pub async fn execute(
+    commands: &AdminCommands,
+    server: &ServerHandle,
+    line: &str,
+) -> Option<String> {
+    match commands.execute(&mut server.clone(), line, Permission::Admin) {
+        Ok(Executed::Ran(future)) => match future.await {
+            Ok(output) => output,
+            Err(e) => Some(e),
+        },
+        Ok(Executed::Help(help)) => Some(help),
+        Ok(Executed::Nothing) => None,
+        Err(e) => Some(e),
+    }
+}
+
+/// Line based admin console reading commands from stdin of the dedicated server. Returns when
+/// stdin is closed, e.g. when running as a service.
+pub async fn run_console(server: ServerHandle) {
+    let commands = admin_commands();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Some(output) = execute(&commands, &server, &line).await {
+            println!("{output}");
+        }
+    }
+}
+
+/// Write the server state dump to a file and report the outcome in the server log
+pub async fn dump_to_file(server: &ServerHandle, path: &Path) {
+    let result = serde_json::to_string_pretty(&server.dump().await)
+        .map_err(std::io::Error::from)
+        .and_then(|dump| std::fs::write(path, dump));
+
+    match result {
+        Ok(_) => {
+            server
+                .log(format!("Server state dumped to {}", path.display()))
+                .await
+        }
+        Err(e) => {
+            server
+                .log(format!(
+                    "Failed to dump server state to {}: {e}",
+                    path.display()
+                ))
+                .await
+        }
+    }
+}
+
+// Joining keeps absolute paths as they are
+fn save_path(file: &str) -> Result<PathBuf, String> {
+    let dir = paths::ensure_dir(paths::save_dir())
+        .map_err(|e| format!("Failed to create saves folder: {e}"))?;
+
+    Ok(dir.join(file))
+}
+
+fn message_stats(server: &ServerHandle) -> String {
+    let mut table = format!("{:<4} {:<10} {:>10} {:>12}", "", "TYPE", "COUNT", "BYTES");
+
+    for (direction, name, stats) in server.message_stats().iter() {
+        let _ = write!(
+            table,
+            "\n{:<4} {name:<10} {:>10} {:>12}",
+            direction.arrow(),
+            stats.count,
+            stats.bytes
+        );
+    }
+
+    table
+}
+
+fn rewound_positions(lag: Duration, positions: &[(PlayerId, Vector2<f32>)]) -> String {
+    let mut table = format!(
+        "{:.0} ms ago\n{:<6} {:>10} {:>10}",
+        lag.as_secs_f32() * 1000.0,
+        "ID",
+        "X",
+        "Y"
+    );
+    for (id, pos) in positions {
+        let _ = write!(table, "\n{:<6} {:>10.1} {:>10.1}", id, pos.x, pos.y);
+    }
+
+    table
+}
+
+async fn flagged_players(server: &ServerHandle) -> String {
+    let flagged = server.flagged_players().await;
+    if flagged.is_empty() {
+        return String::from("No flagged players");
+    }
+
+    let mut table = format!("{:<6} {:<22} {:>10}", "ID", "ADDRESS", "SUSPICION");
+    for p in flagged {
+        let _ = write!(
+            table,
+            "\n{:<6} {:<22} {:>10.1}",
+            p.player.id, p.addr, p.suspicion
+        );
+    }
+
+    table
+}
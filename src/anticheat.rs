@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use game_server_sample::{globals, simulation::MovementConfig};
+
+// Suspicion halves after this long without new violations
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(30);
+
+// Movement a client may bank up for bursts of delayed position updates
+const MOVEMENT_BURST: Duration = Duration::from_millis(500);
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// Extra movement granted for a dash has to be used up within this long, so it can't be saved up
+const GRANT_LIFETIME: Duration = Duration::from_secs(1);
+
+/// Thresholds for flagging players as suspicious
+#[derive(Clone, Copy, Debug)]
+pub struct CheatConfig {
+    /// Multiple of the regular player speed still accepted, to absorb network jitter
+    pub speed_tolerance: f32,
+
+    /// Messages per second a client may send before it counts as flooding
+    pub max_messages_per_sec: u32,
+
+    /// Suspicion score at which a player is reported to the admin
+    pub flag_score: f32,
+
+    /// Suspicion score at which a player is kicked, `None` to only report
+    pub kick_score: Option<f32>,
+}
+
+impl Default for CheatConfig {
+    fn default() -> Self {
+        Self {
+            speed_tolerance: 1.5,
+            max_messages_per_sec: 200,
+            flag_score: 5.0,
+            kick_score: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Violation {
+    /// Moved further than the speed allows
+    Speed { distance: f32, allowed: f32 },
+
+    /// Sent more messages within a second than allowed
+    Rate { messages: u32 },
+}
+
+/// Per-client bookkeeping of rule violations. Every violation adds one point to the suspicion
+/// score, which decays over time so occasional lag spikes don't add up to a flag.
+pub struct CheatTracker {
+    score: f32,
+    last_decay: Instant,
+    pub speed_violations: u32,
+    pub rate_violations: u32,
+
+    // Distance the client may still move, refilled with time like a token bucket
+    movement_allowance: f32,
+    last_position: Instant,
+
+    // Distance on top of the speed limit, e.g. for an accepted dash, and until when it's valid
+    granted_movement: Option<(f32, Instant)>,
+
+    rate_window_start: Instant,
+    messages_in_window: u32,
+
+    /// Whether the admin has already been told about this player
+    pub reported: bool,
+}
+
+impl CheatTracker {
+    pub fn new(config: &CheatConfig, movement: &MovementConfig) -> Self {
+        let now = Instant::now();
+
+        Self {
+            score: 0.0,
+            last_decay: now,
+            speed_violations: 0,
+            rate_violations: 0,
+            movement_allowance: max_speed(config, movement) * MOVEMENT_BURST.as_secs_f32(),
+            last_position: now,
+            granted_movement: None,
+            rate_window_start: now,
+            messages_in_window: 0,
+            reported: false,
+        }
+    }
+
+    /// Count an incoming message. Reports a violation once per window when the client floods.
+    pub fn on_message(&mut self, config: &CheatConfig) -> Option<Violation> {
+        if self.rate_window_start.elapsed() >= RATE_WINDOW {
+            self.rate_window_start = Instant::now();
+            self.messages_in_window = 0;
+        }
+
+        self.messages_in_window += 1;
+        if self.messages_in_window != config.max_messages_per_sec + 1 {
+            return None;
+        }
+
+        self.rate_violations += 1;
+        self.add_score();
+
+        Some(Violation::Rate {
+            messages: self.messages_in_window,
+        })
+    }
+
+    /// Check a reported movement of `distance` world units against the speed limit
+    pub fn on_position(
+        &mut self,
+        distance: f32,
+        config: &CheatConfig,
+        movement: &MovementConfig,
+    ) -> Option<Violation> {
+        let max_speed = max_speed(config, movement);
+        let elapsed = self.last_position.elapsed().as_secs_f32();
+        self.last_position = Instant::now();
+
+        self.movement_allowance = (self.movement_allowance + elapsed * max_speed)
+            .min(max_speed * MOVEMENT_BURST.as_secs_f32());
+
+        // Granted distance is used up first
+        let granted = match self.granted_movement {
+            Some((granted, until)) if until > self.last_position => granted,
+            _ => 0.0,
+        };
+        let distance_over_grant = (distance - granted).max(0.0);
+        self.granted_movement = match granted - distance {
+            left if left > 0.0 => self.granted_movement.map(|(_, until)| (left, until)),
+            _ => None,
+        };
+
+        if distance_over_grant <= self.movement_allowance {
+            self.movement_allowance -= distance_over_grant;
+            return None;
+        }
+
+        let allowed = self.movement_allowance + granted;
+        self.movement_allowance = 0.0;
+        self.speed_violations += 1;
+        self.add_score();
+
+        Some(Violation::Speed { distance, allowed })
+    }
+
+    /// Let the next moves go `distance` further than the speed limit, for a short while
+    pub fn grant_movement(&mut self, distance: f32) {
+        let granted = match self.granted_movement {
+            Some((granted, until)) if until > Instant::now() => granted,
+            _ => 0.0,
+        };
+
+        self.granted_movement = Some((granted + distance, Instant::now() + GRANT_LIFETIME));
+    }
+
+    /// Current suspicion score with decay applied
+    pub fn score(&mut self) -> f32 {
+        let elapsed = self.last_decay.elapsed().as_secs_f32();
+        self.last_decay = Instant::now();
+
+        self.score *= 0.5f32.powf(elapsed / SCORE_HALF_LIFE.as_secs_f32());
+        self.score
+    }
+
+    fn add_score(&mut self) {
+        self.score = self.score() + 1.0;
+    }
+}
+
+/// Fastest legit movement in world units per second
+fn max_speed(config: &CheatConfig, movement: &MovementConfig) -> f32 {
+    movement.speed * globals::MAX_LOGIC_UPDATE_PER_SEC * config.speed_tolerance
+}
@@ -1,59 +1,337 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     error::Error,
-    time::Duration,
+    io,
+    path::Path,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use cgmath::{InnerSpace, Vector2};
+use cgmath::{InnerSpace, Vector2, Vector3};
+use clap::ValueEnum;
 
-use egui::ahash::{HashMap, HashSet};
-use game_server_sample::{globals, Player, PlayerId};
-use tokio::task::JoinHandle;
+use game_server_sample::{
+    globals, identity::PublicKey, lerp_angle, rules::GameRules, simulation, terrain::TerrainMap,
+    Player, PlayerId, WorldMode,
+};
+use rand::Rng;
+use tokio::{sync::watch, task::JoinHandle};
 use winit::{
     application::ApplicationHandler,
+    dpi::{PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyEvent, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop},
     keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
     platform::pump_events::EventLoopExtPumpEvents,
-    window::Window,
+    window::{CursorGrabMode, Window, WindowAttributes},
 };
 
 use crate::{
-    client::{ClientSession, ClientSessionResult},
+    accessibility::Accessibility,
+    admin,
+    assets::{self, AssetCache},
+    client::{self, ClientConfig, ClientEvent, ClientSession, ServerStatus},
+    commands::{CommandRegistry, Executed, Param, ParamKind, Permission},
+    crash,
+    friends::{Friend, FriendList},
     fsm,
-    gui::Gui,
-    message::{self, Message},
-    renderer::Renderer,
-    server,
+    game_loop::{EventSource, FrameRenderer, GameLoop, Network, RealTime, Simulation},
+    gui::{
+        self, DebugWindow, FriendEntry, Gui, PlayerAction, PlayerList, PlayerListEntry,
+        PlayerStats, ServerEntry, Severity,
+    },
+    i18n::{self, tr, tr_args, Language},
+    message::{self, Ability, WhisperError},
+    net::addr::{self, Endpoint},
+    particles::{Effect, ParticleSystem},
+    paths,
+    portmap::{self, PortMapping},
+    renderer::{
+        self, CursorGrab, MotionDebug, Render, RenderSettings, Scene, Streak, WorldLabel, WorldView,
+    },
+    server::{self, ServerBuilder, ServerHandle},
+    servers::{self, ServerList},
 };
 
-type ConnectionTaskHandle = JoinHandle<ClientSessionResult>;
+/// The app with the window event loop feeding it, as driven by the game loop
+struct WindowedApp<'e, 'a> {
+    app: &'e mut App<'a>,
+    event_loop: &'e mut EventLoop<()>,
+}
+
+impl EventSource for WindowedApp<'_, '_> {
+    fn pump_events(&mut self) -> bool {
+        let _ = self
+            .event_loop
+            .pump_app_events(Some(Duration::ZERO), self.app);
+
+        !matches!(self.app.state_machine.peek().unwrap(), fsm::State::Quit)
+    }
+}
+
+impl Network for WindowedApp<'_, '_> {
+    fn poll(&mut self) {
+        if self.app.client_session.is_some() {
+            self.app.process_server_response();
+        }
+    }
+}
+
+impl Simulation for WindowedApp<'_, '_> {
+    fn update(&mut self) {
+        self.app.update();
+    }
+
+    fn time_scale(&self) -> f32 {
+        let scale = self
+            .app
+            .client_session
+            .as_ref()
+            .map_or(1.0, |session| session.time_scale());
+
+        if self
+            .app
+            .hit_stop
+            .is_some_and(|at| at.elapsed() < HIT_STOP_DURATION)
+        {
+            scale * HIT_STOP_TIME_SCALE
+        } else {
+            scale
+        }
+    }
+}
+
+impl FrameRenderer for WindowedApp<'_, '_> {
+    fn render(&mut self) {
+        self.app.window.as_ref().unwrap().request_redraw();
+        if let Some(debug_window) = &self.app.debug_window {
+            debug_window.request_redraw();
+        }
+    }
+
+    fn fps_max(&self) -> u32 {
+        self.app.fps_max
+    }
+}
+
+// Joined session, plus the server when hosting it from here
+type ConnectionTaskHandle =
+    JoinHandle<Result<(ClientSession, Option<ServerHandle>), Box<dyn Error + Send + Sync>>>;
+type PortMappingTaskHandle = JoinHandle<Result<PortMapping, Box<dyn Error + Send + Sync>>>;
+type ResyncTaskHandle = JoinHandle<client::ClientSessionResult>;
 type RemotePlayers = HashMap<PlayerId, Player>;
 
-pub fn run_app(rt: &tokio::runtime::Runtime) -> Result<(), Box<dyn Error>> {
-    let mut app = App::new(&rt)?;
+// Servers that answered the status query, by the address they were asked at
+type StatusQueryHandle = JoinHandle<HashMap<String, ServerStatus>>;
+
+// Pings come from the hosted server's status, no need to query it every frame
+const PLAYER_PINGS_INTERVAL: Duration = Duration::from_millis(500);
+
+const LOCATE_MARKER_DURATION: Duration = Duration::from_secs(3);
+
+// Keeps markers of players outside the view on the edge of the window
+const LOCATE_MARKER_MARGIN: f32 = 24.0;
+
+// Snapshots kept per remote player, far more than the longest interpolation delay needs
+const SNAPSHOT_BUFFER_LEN: usize = 64;
+
+// Optional font for the GUI in the asset files, e.g. for a language egui's own font lacks
+const UI_FONT: &str = "fonts/ui.ttf";
+
+// Remote players not replicated for this long are gone, even if their LEAVE never arrived
+const REMOTE_PLAYER_TIMEOUT: Duration = globals::CONNECTION_TIMEOUT_SEC;
+
+// Server corrections are smoothed out over about this long
+const CORRECTION_HALF_LIFE: Duration = Duration::from_millis(60);
+
+// Larger corrections jump right away, sliding across half the world looks worse than a jump
+const CORRECTION_SNAP_DISTANCE: f32 = 300.0;
+
+// Dash and knockback trails fade out over this long
+const TRAIL_DURATION: Duration = Duration::from_millis(300);
+
+// Camera shake on being knocked back, the offset at full intensity shrinks to nothing over the
+// duration
+const SHAKE_DURATION: Duration = Duration::from_millis(250);
+const SHAKE_AMPLITUDE: f32 = 10.0;
+
+// Hit-stop on knocking someone back, the local simulation slows to this speed for a moment
+const HIT_STOP_DURATION: Duration = Duration::from_millis(80);
+const HIT_STOP_TIME_SCALE: f32 = 0.2;
+
+// Knockbacks of remote players this many player sizes from the local player count as hits it dealt
+const HIT_REACH: f32 = 1.5;
+
+// Gap between a player quad and the name above it
+const NAME_LABEL_MARGIN: f32 = 6.0;
+
+// How often the menu asks listed servers and friends' servers for their status
+const STATUS_QUERY_INTERVAL: Duration = Duration::from_secs(5);
+
+// Warning again takes a clear recovery first, so a flaky link doesn't flood the toasts
+const CONNECTION_RECOVERED_BARS: u8 = 3;
+
+pub fn run_app(
+    rt: &tokio::runtime::Runtime,
+    render_settings: RenderSettings,
+    server_builder: ServerBuilder,
+    client_config: ClientConfig,
+    connect: Option<Endpoint>,
+) -> Result<(), Box<dyn Error>> {
+    let mut app = App::new(rt, render_settings, server_builder, client_config, connect)?;
     let mut event_loop = EventLoop::new()?;
     app.run(&mut event_loop);
 
     Ok(())
 }
 
+struct Trail {
+    at: Instant,
+    from: Vector2<f32>,
+    to: Vector2<f32>,
+    color: Vector3<f32>,
+}
+
 struct App<'a> {
     rt: &'a tokio::runtime::Runtime,
-    window: Option<Window>,
-    renderer: Option<Renderer>,
+    // Shared with the wgpu renderer, which draws to it through a surface of its own
+    window: Option<Arc<Window>>,
+    renderer: Option<Box<dyn Render>>,
+    render_settings: RenderSettings,
+
+    /// Settings for servers hosted from the menu
+    server_builder: ServerBuilder,
+
+    client_config: ClientConfig,
     gui: Option<Gui>,
+
+    /// Debug overlay moved out of the game window
+    debug_window: Option<DebugWindow>,
+
     client_session: Option<ClientSession>,
     connection_task: Option<ConnectionTaskHandle>,
+
+    /// Joining the same server again after it lost the session, see [`Self::start_resync`]
+    resync_task: Option<ResyncTaskHandle>,
+
+    /// Server started with Create Server, for the host's player list actions
+    hosted_server: Option<ServerHandle>,
+
+    /// Players on the hosted server, for the HUD
+    hosted_player_count: Option<watch::Receiver<usize>>,
+
+    /// Round trip times of all players, only known when hosting
+    player_pings: HashMap<PlayerId, Duration>,
+    player_pings_updated: Instant,
+
+    /// Router port forwarding of a server hosted from the menu, removed when the app exits
+    port_mapping_task: Option<PortMappingTaskHandle>,
+    port_mapping: Option<PortMapping>,
     input_state: InputState,
     local_player: Player,
+
+    /// Fixed update steps simulated since joining, stamped on reported positions
+    step: u32,
+
+    /// Where the local player is drawn relative to its position, left over from server
+    /// corrections and shrinking every frame
+    correction_offset: Vector2<f32>,
     camera_pos: Vector2<f32>,
+
+    /// When the local player was last knocked back, the camera shakes for a moment after
+    shake: Option<Instant>,
+
+    /// Offset of the drawn camera from `camera_pos` while shaking
+    camera_shake: Vector2<f32>,
+
+    /// When the local player last knocked someone back, see [`HIT_STOP_DURATION`]
+    hit_stop: Option<Instant>,
+
+    /// Last mouse position in the window, `None` while it's outside
+    cursor_pos: Option<PhysicalPosition<f64>>,
+
+    /// OS cursor hidden for the crosshair
+    cursor_captured: bool,
     remote_players: RemotePlayers,
+
+    /// Recent replicated states of each remote player and when they arrived, oldest first.
+    /// Remote players are drawn the session's interpolation delay behind them.
+    remote_snapshots: HashMap<PlayerId, VecDeque<(Instant, Player)>>,
+
+    /// When each remote player was last replicated, and its velocity estimated from that
+    remote_updates: HashMap<PlayerId, (Instant, Vector2<f32>)>,
+
+    /// Replication stops while the server is paused, remote players only time out once it has
+    /// been running again for [`REMOTE_PLAYER_TIMEOUT`]
+    replication_resumed: Instant,
+
+    /// Shift was pressed, the next update dashes if the cooldown allows
+    dash_requested: bool,
+    last_dash: Option<Instant>,
+
+    /// Recent dashes of everyone, drawn as fading trails
+    trails: Vec<Trail>,
+
+    particles: ParticleSystem,
+
+    /// Draw velocities and the freshness of server updates (F4)
+    debug_motion: bool,
+
+    /// Show the whole world over the game, the player stands still meanwhile (M)
+    map_open: bool,
+
+    /// World mode, rules and terrain of the joined server
+    world_mode: WorldMode,
+    rules: GameRules,
+    terrain: TerrainMap,
+
+    assets: AssetCache,
+
+    /// A UI font is loading, egui's built-in font shows until it's there
+    ui_font_pending: bool,
+
+    // Player list actions
+    spectating: Option<PlayerId>,
+    located: Option<(PlayerId, Instant)>,
+    muted_players: HashSet<PlayerId>,
+
+    /// Scoreboard totals from the server
+    player_stats: HashMap<PlayerId, PlayerStats>,
+
+    /// Names other players picked, the local one is `player_name`
+    player_names: HashMap<PlayerId, String>,
+
+    /// Public keys of players who joined with one, how friends are recognized
+    player_keys: HashMap<PlayerId, PublicKey>,
+
+    /// Address of the joined server, remembered for friends seen there
+    server_address: Option<String>,
+
+    friends: FriendList,
+    servers: ServerList,
+
+    /// Latest answers of the servers shown in the menu
+    server_statuses: HashMap<String, ServerStatus>,
+    status_query: Option<StatusQueryHandle>,
+    statuses_queried: Option<Instant>,
+
+    /// Whether the player was warned about the connection, until it recovers
+    connection_unstable: bool,
+
+    // Set from the console
+    commands: Rc<CommandRegistry<App<'a>>>,
+    player_name: Option<String>,
+
+    /// Frame rate cap, 0 for none
+    fps_max: u32,
+
     state_machine: fsm::StateMachine,
 }
 
 ////////////////////////////////////////////////////////////
 
+#[allow(clippy::enum_variant_names)]
 enum InputEvent {
     MoveUp,
     MoveDown,
@@ -90,113 +368,365 @@ impl std::ops::Index<InputEvent> for InputState {
 /////////////////////////////////////////////////////////////
 
 impl<'a> App<'a> {
-    fn new(rt: &'a tokio::runtime::Runtime) -> Result<App, Box<dyn Error>> {
+    fn new(
+        rt: &'a tokio::runtime::Runtime,
+        render_settings: RenderSettings,
+        server_builder: ServerBuilder,
+        client_config: ClientConfig,
+        connect: Option<Endpoint>,
+    ) -> Result<App<'a>, Box<dyn Error>> {
         let mut state_machine = fsm::StateMachine::new();
         state_machine.push(fsm::State::Menu);
+
+        // Joining from the command line skips the menu, it shows up again if that fails
+        if let Some(endpoint) = connect {
+            state_machine.push(fsm::State::Connecting {
+                endpoint,
+                session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                invite_code: None,
+            });
+        }
+
+        if let Some(report) = crash::take_unseen_report() {
+            state_machine.push(fsm::State::CrashReport(report));
+        }
+
         Ok(Self {
             rt,
             window: None,
             renderer: None,
+            render_settings,
+            server_builder,
+            client_config,
             gui: None,
+            debug_window: None,
             client_session: None,
             connection_task: None,
+            resync_task: None,
+            hosted_server: None,
+            hosted_player_count: None,
+            player_pings: HashMap::new(),
+            player_pings_updated: Instant::now(),
+            port_mapping_task: None,
+            port_mapping: None,
             input_state: InputState::default(),
             local_player: Player::default(),
+            step: 0,
+            correction_offset: Vector2::new(0.0, 0.0),
             camera_pos: Vector2::new(0.0, 0.0),
+            shake: None,
+            camera_shake: Vector2::new(0.0, 0.0),
+            hit_stop: None,
+            cursor_pos: None,
+            cursor_captured: false,
             remote_players: HashMap::new(),
+            remote_snapshots: HashMap::new(),
+            remote_updates: HashMap::new(),
+            replication_resumed: Instant::now(),
+            dash_requested: false,
+            last_dash: None,
+            trails: Vec::new(),
+            particles: ParticleSystem::default(),
+            debug_motion: false,
+            map_open: false,
+            world_mode: WorldMode::default(),
+            rules: GameRules::default(),
+            terrain: TerrainMap::default(),
+            assets: AssetCache::new(rt.handle().clone()),
+            ui_font_pending: assets::resolve(UI_FONT).is_some(),
+            spectating: None,
+            located: None,
+            muted_players: HashSet::new(),
+            player_stats: HashMap::new(),
+            player_names: HashMap::new(),
+            player_keys: HashMap::new(),
+            server_address: None,
+            friends: FriendList::load(paths::friends_file()),
+            servers: ServerList::load(paths::config_file(), paths::recent_servers_file()),
+            server_statuses: HashMap::new(),
+            status_query: None,
+            statuses_queried: None,
+            connection_unstable: false,
+            commands: Rc::new(Self::console_commands()),
+            player_name: None,
+            fps_max: 0,
             state_machine,
         })
     }
 
     fn run(&mut self, event_loop: &mut EventLoop<()>) {
-        // Frame-rate independent loop with fixed update, variable framerate.
-        //
-        // A naive calculation and passing of a deltaTime introduces floating point
-        // precision errors, leading to choppy camera movement and unstable logic
-        // even on high framerate. Here, think of it as renderer dictating time, and
-        // logic update adapting to it.
-        let mut previous_time = std::time::Instant::now();
-        // How much application "clock" is behind real time. Also known as
-        // "accumulator"
-        let mut lag: f32 = 0.0;
-        loop {
-            let current_time = std::time::Instant::now();
-            let elapsed_time = (current_time - previous_time).as_secs_f32();
-            previous_time = current_time;
-            lag += elapsed_time;
-
-            let _ = event_loop.pump_app_event(Some(Duration::ZERO), self);
-            if matches!(self.state_machine.peek().unwrap(), fsm::State::Quit) {
-                break;
-            }
-            if self.client_session.is_some() {
-                self.process_server_response();
-            }
+        GameLoop::new(RealTime).run(&mut WindowedApp {
+            app: self,
+            event_loop,
+        });
 
-            while lag >= globals::FIXED_UPDATE_TIMESTEP_SEC {
-                self.update();
-                lag -= globals::FIXED_UPDATE_TIMESTEP_SEC;
-            }
+        if let Some(client_session) = self.client_session.as_ref() {
+            client_session.leave_server(self.local_player.id);
+        }
 
-            self.window.as_ref().unwrap().request_redraw();
+        if let Some(task) = self.port_mapping_task.take() {
+            task.abort();
         }
-        let set = HashSet::new();
-        if self.client_session.is_some() {
-            self.client_session
-                .as_ref()
-                .unwrap()
-                .leave_server(self.local_player.id);
+        if let Some(port_mapping) = self.port_mapping.take() {
+            self.rt.block_on(port_mapping.remove());
         }
     }
 
     ////////////////////////////////////
 
     fn process_server_response(&mut self) {
-        while let Ok(msg) = self
-            .client_session
-            .as_mut()
-            .unwrap()
-            .receive_server_response()
-        {
-            message::trace(format!("Received: {}", msg));
-
-            match Message::deserialize(&msg) {
-                Ok(Message::Replicate(new_player)) => {
-                    if let Some(player) = self.remote_players.get_mut(&new_player.id) {
-                        // Update existing player based on sever's
-                        // simualtion
-                        player.pos = new_player.pos;
-                    } else {
+        while let Some(event) = self.client_session.as_mut().unwrap().poll_event() {
+            match event {
+                // Only sent when the server changed the local player's avatar, its position is
+                // predicted here
+                ClientEvent::PlayerUpdated(new_player) if new_player.id == self.local_player.id => {
+                    self.local_player.avatar = new_player.avatar;
+                }
+                ClientEvent::PlayerUpdated(new_player) => {
+                    // One replication per server tick, so the step since the previous one is
+                    // the velocity
+                    let tick_rate = self.client_session.as_ref().unwrap().server_tick_rate();
+                    let snapshots = self.remote_snapshots.entry(new_player.id).or_default();
+                    let velocity =
+                        snapshots
+                            .back()
+                            .map_or(Vector2::new(0.0, 0.0), |(_, previous)| {
+                                self.rules.world_delta(
+                                    previous.pos,
+                                    new_player.pos,
+                                    self.world_mode,
+                                ) * tick_rate as f32
+                            });
+                    self.remote_updates
+                        .insert(new_player.id, (Instant::now(), velocity));
+
+                    // Update existing player based on sever's simualtion, the displayed
+                    // position follows in interpolate_remote_players
+                    if snapshots.len() == SNAPSHOT_BUFFER_LEN {
+                        snapshots.pop_front();
+                    }
+                    snapshots.push_back((Instant::now(), new_player));
+
+                    if let Entry::Vacant(entry) = self.remote_players.entry(new_player.id) {
                         // On-demand remote player creation because
                         // replication does not fit into the handshake
                         // ACK message
-                        self.remote_players.insert(new_player.id, new_player);
+                        entry.insert(new_player);
+                        self.spawn_effect(Effect::Puff {
+                            pos: new_player.pos,
+                            color: new_player.color,
+                        });
 
                         // Add GUI
-                        self.gui
-                            .as_mut()
-                            .unwrap()
-                            .log(format!("Player {} has joined the server", new_player.id));
+                        let gui = self.gui.as_mut().unwrap();
+                        let msg = tr_args("log.player_joined", &[("id", &new_player.id)]);
+                        gui.toast(Severity::Join, msg.clone());
+                        gui.log(Severity::Join, msg);
                     }
                 }
-                Ok(Message::Leave(id)) => {
-                    self.remote_players.remove(&id);
+                ClientEvent::Dashed(id, from, facing) => {
+                    if let Some(player) = self.remote_players.get(&id) {
+                        // Same jump the dasher made, from where the server had it
+                        let mut dashed = Player {
+                            pos: from,
+                            facing,
+                            ..*player
+                        };
+                        simulation::dash(&mut dashed, self.world_mode, &self.rules);
+
+                        let color = player.color;
+                        self.trails.push(Trail {
+                            at: Instant::now(),
+                            from,
+                            to: dashed.pos,
+                            color,
+                        });
+                        self.spawn_effect(Effect::DashTrail {
+                            from,
+                            to: dashed.pos,
+                            color,
+                        });
+                    }
+                }
+                ClientEvent::KnockedBack(id, impulse) => {
+                    let friction = self.rules.movement.friction;
+                    let player = if id == self.local_player.id {
+                        // Predicted from here on like any other movement
+                        self.local_player.impulse += impulse;
+                        if self.render_settings.screen_shake > 0.0 && !self.reduced_motion() {
+                            self.shake = Some(Instant::now());
+                        }
+                        Some(&self.local_player)
+                    } else {
+                        let remote = self.remote_players.get(&id);
+                        let dealt = remote.is_some_and(|remote| {
+                            self.rules
+                                .world_delta(self.local_player.pos, remote.pos, self.world_mode)
+                                .magnitude()
+                                < self.rules.size_of(remote) * HIT_REACH
+                        });
+                        if dealt && self.render_settings.hit_stop && !self.reduced_motion() {
+                            self.hit_stop = Some(Instant::now());
+                        }
+                        remote
+                    };
+
+                    // Where the push alone takes the player, replication follows it there
+                    if let Some(&Player { pos, color, .. }) = player {
+                        self.trails.push(Trail {
+                            at: Instant::now(),
+                            from: pos,
+                            to: pos + impulse / friction,
+                            color,
+                        });
+                        self.spawn_effect(Effect::Sparks {
+                            pos,
+                            direction: impulse,
+                        });
+                    }
+                }
+                ClientEvent::PlayerLeft(id) => {
+                    self.remove_remote_player(id);
+                    let gui = self.gui.as_mut().unwrap();
+                    let msg = tr_args("log.player_left", &[("id", &id)]);
+                    gui.toast(Severity::Leave, msg.clone());
+                    gui.log(Severity::Leave, msg);
+                }
+
+                ClientEvent::Corrected(pos) => {
+                    // Carry on from the server's position, but keep drawing the player where it
+                    // was and close the gap over the next frames
+                    self.correction_offset +=
+                        self.rules
+                            .world_delta(pos, self.local_player.pos, self.world_mode);
+                    if self.correction_offset.magnitude() > CORRECTION_SNAP_DISTANCE {
+                        self.correction_offset = Vector2::new(0.0, 0.0);
+                    }
+                    self.local_player.pos = pos;
+                }
+
+                ClientEvent::Stats(id, distance, seconds) => {
+                    self.player_stats.insert(
+                        id,
+                        PlayerStats {
+                            distance,
+                            played: Duration::from_secs(seconds as u64),
+                        },
+                    );
+                }
+
+                ClientEvent::Named(id, name)
+                    if id != self.local_player.id && globals::is_valid_player_name(&name) =>
+                {
+                    self.player_names.insert(id, name);
+                    self.friend_seen(id);
+                }
+
+                ClientEvent::KeyAnnounced(id, key) if id != self.local_player.id => {
+                    self.player_keys.insert(id, key);
+
+                    if self.friends.contains(&key) {
+                        let msg = tr_args("log.friend_online", &[("name", &self.display_name(id))]);
+                        let gui = self.gui.as_mut().unwrap();
+                        gui.toast(Severity::Join, msg.clone());
+                        gui.log(Severity::Join, msg);
+
+                        self.friend_seen(id);
+                    }
+                }
+
+                ClientEvent::Chat(id, text) if !self.muted_players.contains(&id) => {
+                    let line = tr_args(
+                        "chat.line",
+                        &[("name", &self.display_name(id)), ("text", &text)],
+                    );
+                    self.gui.as_mut().unwrap().log(Severity::Chat, line);
+                }
+
+                ClientEvent::Whisper(id, text) if !self.muted_players.contains(&id) => {
+                    let line = tr_args(
+                        "chat.whisper_from",
+                        &[("name", &self.display_name(id)), ("text", &text)],
+                    );
+                    self.gui.as_mut().unwrap().log(Severity::Whisper, line);
+                }
+
+                ClientEvent::Notice(level, text) => {
+                    self.gui.as_mut().unwrap().log(level.into(), text);
+                }
+
+                ClientEvent::WhisperFailed(error, name) => {
+                    let key = match error {
+                        WhisperError::Unknown => "chat.whisper_unknown",
+                        WhisperError::Ambiguous => "chat.whisper_ambiguous",
+                    };
+                    self.gui
+                        .as_mut()
+                        .unwrap()
+                        .log(Severity::Error, tr_args(key, &[("name", &name)]));
+                }
+
+                ClientEvent::TickRateChanged(hz) => {
+                    self.gui.as_mut().unwrap().log(
+                        Severity::Info,
+                        tr_args("log.tick_rate_changed", &[("hz", &hz)]),
+                    );
+                }
+
+                ClientEvent::TimeScaleChanged(scale) => {
+                    self.replication_resumed = Instant::now();
+                    let line = match scale {
+                        0.0 => String::from(tr("log.paused")),
+                        scale => tr_args("log.time_scale", &[("scale", &scale)]),
+                    };
+                    self.gui.as_mut().unwrap().log(Severity::Info, line);
+                }
+
+                ClientEvent::Kicked(reason) => {
+                    let reason = match reason.as_deref() {
+                        Some(message::KICK_IDLE) => tr("dialog.kicked_idle"),
+                        _ => tr("dialog.kicked"),
+                    };
+                    self.gui
+                        .as_mut()
+                        .unwrap()
+                        .set_disconnect_reason(String::from(reason));
+                    self.disconnect();
+
+                    return;
+                }
+
+                ClientEvent::ServerShutdown => {
                     self.gui
                         .as_mut()
                         .unwrap()
-                        .log(format!("Player {} has left the server", id));
+                        .set_disconnect_reason(String::from(tr("dialog.server_shutdown")));
+                    self.disconnect();
+
+                    return;
                 }
 
+                ClientEvent::ResyncRequested => self.start_resync(),
+
                 _ => (),
             }
         }
     }
 
     fn update(&mut self) {
-        match self.state_machine.peek_mut() {
+        self.poll_ui_font();
+        self.poll_port_mapping();
+        self.poll_resync();
+        self.update_server_lists();
+        self.update_player_pings();
+        self.check_connection_quality();
+
+        // Dialogs over the game don't pause it, the server doesn't either
+        match self.state_machine.base_mut() {
             Some(fsm::State::Connecting {
-                server_address,
+                endpoint,
                 session_mode,
+                invite_code,
             }) => match self.connection_task.as_ref() {
                 Some(task) if task.is_finished() => {
                     if let Some(finished_task) = self.connection_task.take() {
@@ -204,10 +734,40 @@ impl<'a> App<'a> {
 
                         match self.rt.block_on(finished_task) {
                             Ok(result) => match result {
-                                Ok(client_session) => {
+                                Ok((client_session, hosted_server)) => {
+                                    if let Some(server) = hosted_server {
+                                        // Auto port may have bound another port than the one
+                                        // asked for
+                                        if let Ok(addr) = server.local_addr() {
+                                            endpoint.port = addr.port();
+                                        }
+                                        gui.set_hosting_port(Some(endpoint.port));
+
+                                        // Seen as changed, so the HUD shows it right away
+                                        let mut player_count = server.player_count();
+                                        player_count.mark_changed();
+                                        self.hosted_player_count = Some(player_count);
+                                        self.hosted_server = Some(server);
+
+                                        // Shown to the host to hand out, until the server stops
+                                        gui.set_invite_code(invite_code.clone());
+                                    }
+
+                                    self.server_address = Some(endpoint.to_string());
+
+                                    // Your own hosted server is no use in the list
+                                    if matches!(session_mode, fsm::SessionMode::ConnectAsClientOnly)
+                                    {
+                                        if let Err(e) = self.servers.joined(&endpoint.to_string()) {
+                                            eprintln!("Failed to save recent servers: {e}");
+                                        }
+                                    }
                                     self.local_player = client_session.get_session_player_data();
+                                    self.world_mode = client_session.world_mode();
+                                    self.rules = client_session.rules();
+                                    self.terrain = client_session.map().clone();
 
-                                    let window = self.window.as_mut().unwrap();
+                                    let window = self.window.as_ref().unwrap();
 
                                     window.set_title(&format!(
                                         "{} - Player {}",
@@ -215,10 +775,39 @@ impl<'a> App<'a> {
                                         self.local_player.id
                                     ));
 
+                                    // Names are sent on every join, the server forgets them
+                                    if let Some(name) = &self.player_name {
+                                        client_session.send_name(self.local_player.id, name);
+                                    }
                                     self.client_session = Some(client_session);
+
+                                    // Friends outside the local network need the router to
+                                    // forward the port, only needed once per hosted server
+                                    if matches!(session_mode, fsm::SessionMode::CreateServer)
+                                        && self.port_mapping.is_none()
+                                        && self.port_mapping_task.is_none()
+                                    {
+                                        self.port_mapping_task =
+                                            Some(self.rt.spawn(portmap::map_port(endpoint.port)));
+                                    }
+
                                     self.state_machine.change(fsm::State::Playing);
 
-                                    gui.log(format!("Welcome player {}", self.local_player.id));
+                                    gui.log(
+                                        Severity::Info,
+                                        tr_args("log.welcome", &[("id", &self.local_player.id)]),
+                                    );
+
+                                    let differences = self.rules.differences(&GameRules::default());
+                                    if !differences.is_empty() {
+                                        gui.log(
+                                            Severity::Warning,
+                                            tr_args(
+                                                "log.rules_differ",
+                                                &[("differences", &differences.join(", "))],
+                                            ),
+                                        );
+                                    }
                                 }
                                 Err(connection_err) => {
                                     gui.set_error_status(connection_err.to_string());
@@ -227,8 +816,9 @@ impl<'a> App<'a> {
                             },
 
                             Err(join_err) => {
-                                gui.set_error_status(format!(
-                                    "Connection taskl has aborted: {join_err}"
+                                gui.set_error_status(tr_args(
+                                    "status.connection_aborted",
+                                    &[("error", &join_err)],
                                 ));
 
                                 self.state_machine.change(fsm::State::Menu);
@@ -240,22 +830,57 @@ impl<'a> App<'a> {
                 Some(_) => (), // Task is still running -> Do nothing,
 
                 None => {
-                    let server_address = server_address.clone();
-                    let session_mode = session_mode.clone();
-                    self.connection_task = Some(self.rt.spawn(async move {
-                        if matches!(session_mode, fsm::SessionMode::CreateServer) {
-                            let parts: Vec<&str> = server_address.split(':').collect();
-                            let port: u16 = parts[1].parse().unwrap();
+                    let endpoint = endpoint.clone();
+                    let session_mode = *session_mode;
+                    let mut server_builder = self.server_builder.clone().port(endpoint.port);
+                    let mut client_config = self.client_config.clone();
+
+                    // A code from the menu or the console wins over the command line one
+                    if invite_code.is_some() {
+                        server_builder = server_builder.invite_code(invite_code.clone());
+                        client_config.invite_code = invite_code.clone();
+                    }
 
-                            server::start_server(port).await?;
+                    // Lets friends see it's us hosting
+                    server_builder = server_builder.host_key(client_config.identity.public_key());
+                    self.connection_task = Some(self.rt.spawn(async move {
+                        let hosted_server = match session_mode {
+                            fsm::SessionMode::CreateServer => Some(server_builder.start().await?),
+                            fsm::SessionMode::ConnectAsClientOnly => None,
+                        };
+                        let mut endpoint = endpoint;
+                        if let Some(server) = &hosted_server {
+                            endpoint.port = server.local_addr()?.port();
                         }
-                        ClientSession::new(server_address).await
+                        let client_session = match &hosted_server {
+                            Some(server) if client_config.in_process_host => {
+                                ClientSession::local(server.connect_local(), &client_config).await?
+                            }
+                            _ => {
+                                let server_address = endpoint.resolve().await?;
+                                ClientSession::new(server_address.to_string(), &client_config)
+                                    .await?
+                            }
+                        };
+
+                        Ok((client_session, hosted_server))
                     }));
                 }
             },
 
             Some(fsm::State::Playing) => {
-                let base_speed = 10.0;
+                if let Some(motd) = self.client_session.as_mut().unwrap().take_motd() {
+                    self.state_machine.push(fsm::State::Motd(motd));
+                    return;
+                }
+
+                // Keys go to the overlay meanwhile, the player stands still. Same while looking
+                // at the map.
+                if self.state_machine.overlay().is_some() || self.map_open {
+                    self.input_state = InputState::default();
+                    self.dash_requested = false;
+                }
+
                 let mut direction = cgmath::vec2(0.0, 0.0);
 
                 // Apply input
@@ -277,33 +902,80 @@ impl<'a> App<'a> {
                     direction = direction.normalize();
                 }
 
+                self.step = self.step.wrapping_add(1);
+
                 // Move player
-                self.local_player.velocity = direction * base_speed;
-                self.local_player.pos += self.local_player.velocity;
-                globals::clamp_player_to_bounds(&mut self.local_player);
+                let before = self.local_player;
+                simulation::step_player(
+                    &mut self.local_player,
+                    direction,
+                    &self.terrain,
+                    self.world_mode,
+                    &self.rules,
+                );
+                if self.client_config.check_determinism {
+                    simulation::check_step(
+                        &before,
+                        direction,
+                        &self.terrain,
+                        self.world_mode,
+                        &self.rules,
+                        &self.local_player,
+                    );
+                }
+
+                // Dash right away, the server checks the cooldown when the position arrives
+                let dashed =
+                    std::mem::take(&mut self.dash_requested) && self.dash_cooldown() == 0.0;
+                if dashed {
+                    let from = self.local_player.pos;
+                    simulation::dash(&mut self.local_player, self.world_mode, &self.rules);
+                    self.last_dash = Some(Instant::now());
+                    self.trails.push(Trail {
+                        at: Instant::now(),
+                        from,
+                        to: self.local_player.pos,
+                        color: self.local_player.color,
+                    });
+                    self.spawn_effect(Effect::DashTrail {
+                        from,
+                        to: self.local_player.pos,
+                        color: self.local_player.color,
+                    });
+
+                    self.client_session
+                        .as_ref()
+                        .unwrap()
+                        .send_ability(Ability::Dash);
+                }
+                self.trails
+                    .retain(|trail| trail.at.elapsed() < TRAIL_DURATION);
+                self.particles.update(globals::FIXED_UPDATE_TIMESTEP_SEC);
+                let dash_cooldown = self.dash_cooldown();
+                self.gui.as_mut().unwrap().set_dash_cooldown(dash_cooldown);
+                self.update_hosting_players();
+
+                self.smooth_correction();
+                self.drop_stale_remote_players();
+                self.interpolate_remote_players();
 
                 // Move camera
                 self.move_camera();
 
-                // Message server
-                if self.local_player.velocity != cgmath::vec2(0.0, 0.0) {
+                // Message server, also for moves from a dash or knockback alone
+                if self.local_player.pos != before.pos {
                     self.client_session
                         .as_ref()
                         .unwrap()
-                        .send_pos(&self.local_player);
+                        .send_pos(&self.local_player, self.step);
                 }
 
-                // Server healthcheck
-                if !self.client_session.as_ref().unwrap().is_server_alive() {
+                // Server healthcheck. A resync has a timeout of its own.
+                if self.resync_task.is_none()
+                    && !self.client_session.as_ref().unwrap().is_server_alive()
+                {
                     eprintln!("Connection to server was lost");
-                    self.client_session = None;
-                    self.window
-                        .as_mut()
-                        .unwrap()
-                        .set_title(globals::WINDOW_TITLE);
-                    self.input_state = InputState::default(); // Avoid keys being stuck
-                    self.remote_players.clear();
-                    self.state_machine.change(fsm::State::Disconnected);
+                    self.disconnect();
                 }
             }
 
@@ -311,19 +983,1079 @@ impl<'a> App<'a> {
         }
     }
 
+    /// Switch the GUI to the UI font from the asset files once it loaded
+    fn poll_ui_font(&mut self) {
+        let Some(gui) = self.gui.as_mut().filter(|_| self.ui_font_pending) else {
+            return;
+        };
+
+        if let Some(font) = self.assets.get(UI_FONT) {
+            gui.set_ui_font(&font);
+            self.ui_font_pending = false;
+        }
+    }
+
+    /// Pick up the outcome of the router port forwarding once it is done
+    fn poll_port_mapping(&mut self) {
+        if !self
+            .port_mapping_task
+            .as_ref()
+            .is_some_and(|task| task.is_finished())
+        {
+            return;
+        }
+        let Some(task) = self.port_mapping_task.take() else {
+            return;
+        };
+        let gui = self.gui.as_mut().unwrap();
+
+        match self.rt.block_on(task) {
+            Ok(Ok(port_mapping)) => {
+                let msg = tr_args("log.port_mapped", &[("address", &port_mapping.external)]);
+                gui.toast(Severity::Info, msg.clone());
+                gui.log(Severity::Info, msg);
+                gui.set_hosting_address(Some(port_mapping.external.to_string()));
+                self.port_mapping = Some(port_mapping);
+            }
+            Ok(Err(e)) => gui.log(
+                Severity::Error,
+                tr_args("log.port_mapping_failed", &[("error", &e)]),
+            ),
+            Err(e) => gui.log(
+                Severity::Error,
+                tr_args("log.port_mapping_failed", &[("error", &e)]),
+            ),
+        }
+    }
+
+    /// Warn once when the connection quality drops, and again only after it recovered
+    fn check_connection_quality(&mut self) {
+        let Some(report) = self.client_session.as_ref().map(|s| s.connection_quality()) else {
+            return;
+        };
+
+        if !self.connection_unstable && report.bars <= gui::UNSTABLE_CONNECTION_BARS {
+            self.connection_unstable = true;
+
+            let gui = self.gui.as_mut().unwrap();
+            let msg = String::from(tr("log.connection_unstable"));
+            gui.toast(Severity::Error, msg.clone());
+            gui.log(Severity::Error, msg);
+        } else if report.bars >= CONNECTION_RECOVERED_BARS {
+            self.connection_unstable = false;
+        }
+    }
+
+    /// Show the server list and the friends list in the menu. Every few seconds the listed
+    /// servers and the friends' last servers are asked how many play there and who hosts them.
+    fn update_server_lists(&mut self) {
+        if let Some(query) = self.status_query.take_if(|query| query.is_finished()) {
+            if let Ok(statuses) = self.rt.block_on(query) {
+                self.server_statuses = statuses;
+            }
+        }
+
+        if !matches!(self.state_machine.base(), Some(fsm::State::Menu)) {
+            return;
+        }
+
+        let due = self
+            .statuses_queried
+            .is_none_or(|at| at.elapsed() >= STATUS_QUERY_INTERVAL);
+        if due && self.status_query.is_none() {
+            self.statuses_queried = Some(Instant::now());
+
+            let mut addresses = self.servers.addresses();
+            for server in self.friends.iter().filter_map(|f| f.last_server.clone()) {
+                if !addresses.contains(&server) {
+                    addresses.push(server);
+                }
+            }
+
+            self.status_query = Some(self.rt.spawn(async move {
+                let mut queries = tokio::task::JoinSet::new();
+                for address in addresses {
+                    queries.spawn(async move {
+                        let status = client::query_status(&address).await.ok()?;
+                        Some((address, status))
+                    });
+                }
+
+                queries.join_all().await.into_iter().flatten().collect()
+            }));
+        }
+
+        let mut servers: Vec<ServerEntry> = self
+            .servers
+            .addresses()
+            .into_iter()
+            .map(|address| {
+                let status = self.server_statuses.get(&address);
+                ServerEntry {
+                    favorite: self.servers.is_favorite(&address),
+                    players: status.map(|status| status.players),
+                    rtt: status.map(|status| status.rtt),
+                    version: status.and_then(|status| status.version.clone()),
+                    address,
+                }
+            })
+            .collect();
+
+        // Closest first, the ones that didn't answer last
+        servers.sort_by_key(|server| (server.rtt.is_none(), server.rtt));
+
+        let friends = self
+            .friends
+            .iter()
+            .map(|friend| FriendEntry {
+                key: friend.key,
+                name: friend.name.clone(),
+                server: friend.last_server.clone(),
+                hosting: friend
+                    .last_server
+                    .as_ref()
+                    .and_then(|server| self.server_statuses.get(server))
+                    .is_some_and(|status| status.host == Some(friend.key)),
+            })
+            .collect();
+
+        let gui = self.gui.as_mut().unwrap();
+        gui.set_servers(servers);
+        gui.set_friends(friends);
+    }
+
+    /// Remember where a friend among the players was seen, and under which name
+    fn friend_seen(&mut self, id: PlayerId) {
+        let (Some(key), Some(server)) = (self.player_keys.get(&id), self.server_address.as_ref())
+        else {
+            return;
+        };
+
+        if let Err(e) =
+            self.friends
+                .seen(key, self.player_names.get(&id).map(String::as_str), server)
+        {
+            eprintln!("Failed to save the friends list: {e}");
+        }
+    }
+
+    /// Show how full the hosted server is, whenever someone joins or leaves
+    fn update_hosting_players(&mut self) {
+        let (Some(server), Some(player_count)) = (
+            self.hosted_server.as_ref(),
+            self.hosted_player_count.as_mut(),
+        ) else {
+            return;
+        };
+
+        if player_count.has_changed().unwrap_or(false) {
+            let count = *player_count.borrow_and_update();
+            self.gui
+                .as_mut()
+                .unwrap()
+                .set_hosting_players(Some((count, server.max_players())));
+        }
+    }
+
+    /// Refresh the round trip times shown in the player list, a few times per second is plenty
+    fn update_player_pings(&mut self) {
+        let Some(server) = self.hosted_server.as_ref() else {
+            return;
+        };
+        if self.player_pings_updated.elapsed() < PLAYER_PINGS_INTERVAL {
+            return;
+        }
+        self.player_pings_updated = Instant::now();
+
+        self.player_pings = self
+            .rt
+            .block_on(server.status())
+            .players
+            .iter()
+            .filter_map(|p| Some((p.player.id, p.rtt?)))
+            .collect();
+    }
+
+    /// Everything the player list panel shows
+    fn player_list(&self) -> PlayerList {
+        let local = &self.local_player;
+        let entry = |player: &Player| PlayerListEntry {
+            id: player.id,
+            name: if player.id == local.id {
+                self.player_name.clone()
+            } else {
+                self.player_names.get(&player.id).cloned()
+            },
+            is_local: player.id == local.id,
+            distance: self
+                .rules
+                .world_delta(local.pos, player.pos, self.world_mode)
+                .magnitude(),
+            ping: self.player_pings.get(&player.id).copied(),
+            muted: self.muted_players.contains(&player.id),
+            stats: self.player_stats.get(&player.id).copied(),
+            can_befriend: self
+                .player_keys
+                .get(&player.id)
+                .is_some_and(|key| !self.friends.contains(key)),
+        };
+
+        let mut remotes: Vec<&Player> = self.remote_players.values().collect();
+        remotes.sort_by_key(|p| p.id);
+
+        PlayerList {
+            entries: std::iter::once(local).chain(remotes).map(entry).collect(),
+            hosting: self.hosted_server.is_some(),
+            spectating: self.spectating,
+            marker: self.locate_marker(),
+        }
+    }
+
+    /// Velocities and update ages for the motion debug render mode
+    fn motion_debug(&self) -> MotionDebug {
+        let mut debug = MotionDebug::default();
+
+        debug.velocities.insert(
+            self.local_player.id,
+            self.local_player.velocity / globals::FIXED_UPDATE_TIMESTEP_SEC,
+        );
+        for (id, (received_at, velocity)) in &self.remote_updates {
+            debug.velocities.insert(*id, *velocity);
+            debug.update_ages.insert(*id, received_at.elapsed());
+        }
+
+        debug
+    }
+
+    fn debug_window_event(&mut self, event: WindowEvent) {
+        let Some(debug_window) = self.debug_window.as_mut() else {
+            return;
+        };
+
+        match event {
+            WindowEvent::CloseRequested => {
+                self.debug_window = None;
+                self.gui.as_mut().unwrap().attach_debug_overlay();
+            }
+            WindowEvent::RedrawRequested => {
+                let message_stats = self.client_session.as_ref().map(|s| s.message_stats());
+                let interpolation = self.client_session.as_ref().map(|s| s.interpolation());
+                let liveness = self.client_session.as_ref().map(|s| s.liveness());
+                debug_window.redraw(message_stats.as_ref(), interpolation, liveness);
+            }
+            event => debug_window.handle_event(&event),
+        }
+    }
+
+    /// Open or close the debug window to match the GUI
+    fn update_debug_window(&mut self, event_loop: &ActiveEventLoop) {
+        let gui = self.gui.as_mut().unwrap();
+
+        match (gui.debug_detached(), self.debug_window.is_some()) {
+            (true, false) => {
+                let attributes = WindowAttributes::default()
+                    .with_title(tr("debug.window_title"))
+                    .with_inner_size(PhysicalSize::new(DebugWindow::SIZE.0, DebugWindow::SIZE.1));
+
+                match self
+                    .renderer
+                    .as_mut()
+                    .unwrap()
+                    .open_window(event_loop, attributes)
+                {
+                    Some(target) => self.debug_window = Some(DebugWindow::new(event_loop, target)),
+                    None => {
+                        gui.attach_debug_overlay();
+                        gui.toast(Severity::Error, String::from(tr("debug.detach_failed")));
+                    }
+                }
+            }
+            (false, true) => self.debug_window = None,
+            _ => {}
+        }
+    }
+
+    /// Part of the dash cooldown still to go, 0 once the player can dash again
+    fn dash_cooldown(&self) -> f32 {
+        self.last_dash.map_or(0.0, |at| {
+            let remaining = globals::DASH_COOLDOWN.saturating_sub(at.elapsed());
+            remaining.as_secs_f32() / globals::DASH_COOLDOWN.as_secs_f32()
+        })
+    }
+
+    fn spawn_effect(&mut self, effect: Effect) {
+        if self.render_settings.particles && !self.reduced_motion() {
+            self.particles.spawn(effect);
+        }
+    }
+
+    /// Shake, hit-stop, particles and trails are all off
+    fn reduced_motion(&self) -> bool {
+        self.render_settings.accessibility.reduced_motion
+    }
+
+    /// Save settings changed in the menu and apply them from the next frame on
+    fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.render_settings.accessibility = accessibility;
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.set_accessibility(accessibility);
+        }
+        if accessibility.reduced_motion {
+            self.particles.clear();
+            self.shake = None;
+            self.hit_stop = None;
+        }
+
+        if let Err(e) = accessibility.save(&paths::config_file()) {
+            eprintln!("Failed to save the accessibility settings: {e}");
+        }
+    }
+
+    fn trail_streaks(&self) -> Vec<Streak> {
+        if self.reduced_motion() {
+            return Vec::new();
+        }
+
+        self.trails
+            .iter()
+            .map(|trail| Streak {
+                from: trail.from,
+                to: trail.to,
+                color: trail.color,
+                fade: (trail.at.elapsed().as_secs_f32() / TRAIL_DURATION.as_secs_f32()).min(1.0),
+            })
+            .collect()
+    }
+
+    /// World position under the mouse while the crosshair replaces the cursor
+    fn crosshair_pos(&self) -> Option<Vector2<f32>> {
+        let cursor = self.cursor_pos.filter(|_| self.cursor_captured)?;
+        let center = Vector2::new(
+            globals::WINDOW_SIZE.0 as f32 / 2.0,
+            globals::WINDOW_SIZE.1 as f32 / 2.0,
+        );
+
+        Some(self.camera_pos + Vector2::new(cursor.x as f32, cursor.y as f32) - center)
+    }
+
+    /// Hide the OS cursor over the game while playing, the crosshair takes its place. Over GUI
+    /// windows the cursor is back for clicking.
+    fn update_cursor(&mut self) {
+        let gui = self.gui.as_ref().unwrap();
+        let capture = self.render_settings.crosshair
+            && matches!(self.state_machine.peek(), Some(fsm::State::Playing))
+            && !gui.wants_pointer();
+        if capture == self.cursor_captured {
+            return;
+        }
+        self.cursor_captured = capture;
+
+        let window = self.window.as_ref().unwrap();
+        window.set_cursor_visible(!capture);
+
+        let grab = match (capture, self.render_settings.cursor_grab) {
+            (true, CursorGrab::Confined) => CursorGrabMode::Confined,
+            _ => CursorGrabMode::None,
+        };
+        if let Err(e) = window.set_cursor_grab(grab) {
+            eprintln!("Failed to grab the mouse cursor: {e}");
+        }
+    }
+
+    /// Names above every player's avatar
+    fn name_labels(&self, local_player: &Player) -> Vec<WorldLabel> {
+        std::iter::once(local_player)
+            .chain(self.remote_players.values())
+            .map(|player| WorldLabel {
+                pos: player.pos
+                    - Vector2::new(0.0, self.rules.size_of(player) / 2.0 + NAME_LABEL_MARGIN),
+                text: self.display_name(player.id),
+                color: Vector3::new(0.1, 0.1, 0.1),
+            })
+            .collect()
+    }
+
+    /// Name a player picked, or the player's number
+    fn display_name(&self, id: PlayerId) -> String {
+        let name = if id == self.local_player.id {
+            self.player_name.as_ref()
+        } else {
+            self.player_names.get(&id)
+        };
+
+        name.cloned()
+            .unwrap_or_else(|| tr_args("players.player", &[("id", &id)]))
+    }
+
+    /// Send a line typed into the chat, `/w <name> <message>` whispering to a single player
+    fn send_chat_line(&mut self, line: &str) {
+        let Some(session) = self.client_session.as_ref() else {
+            return;
+        };
+        let line = line.trim();
+
+        let whisper = line
+            .strip_prefix("/w")
+            .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace));
+
+        let (severity, echo) = match whisper {
+            Some(whisper) => match whisper.trim_start().split_once(char::is_whitespace) {
+                Some((name, text)) => {
+                    session.send_whisper(name, text.trim());
+                    (
+                        Severity::Whisper,
+                        tr_args(
+                            "chat.whisper_to",
+                            &[("name", &name), ("text", &text.trim())],
+                        ),
+                    )
+                }
+                None => (Severity::Error, String::from(tr("chat.whisper_usage"))),
+            },
+            None => {
+                session.send_chat(self.local_player.id, line);
+                (
+                    Severity::Chat,
+                    tr_args(
+                        "chat.line",
+                        &[
+                            ("name", &self.display_name(self.local_player.id)),
+                            ("text", &line),
+                        ],
+                    ),
+                )
+            }
+        };
+
+        self.gui.as_mut().unwrap().log(severity, echo);
+    }
+
+    /// Screen position of the located player, pulled inside the window when off screen
+    fn locate_marker(&self) -> Option<egui::Pos2> {
+        let (id, since) = self.located?;
+        if since.elapsed() > LOCATE_MARKER_DURATION {
+            return None;
+        }
+
+        let player = self.remote_players.get(&id)?;
+        let (width, height) = (globals::WINDOW_SIZE.0 as f32, globals::WINDOW_SIZE.1 as f32);
+        let on_screen = self
+            .rules
+            .world_delta(self.camera_pos, player.pos, self.world_mode)
+            + Vector2::new(width / 2.0, height / 2.0);
+
+        Some(egui::pos2(
+            on_screen
+                .x
+                .clamp(LOCATE_MARKER_MARGIN, width - LOCATE_MARKER_MARGIN),
+            on_screen
+                .y
+                .clamp(LOCATE_MARKER_MARGIN, height - LOCATE_MARKER_MARGIN),
+        ))
+    }
+
+    fn handle_player_action(&mut self, action: PlayerAction) {
+        match action {
+            PlayerAction::Spectate(id) => self.spectating = Some(id),
+            PlayerAction::StopSpectating => self.spectating = None,
+            PlayerAction::Locate(id) => self.located = Some((id, Instant::now())),
+            PlayerAction::ToggleMute(id) => {
+                if !self.muted_players.remove(&id) {
+                    self.muted_players.insert(id);
+                }
+            }
+            PlayerAction::Kick(id) => {
+                if let Some(server) = self.hosted_server.clone() {
+                    self.rt.spawn(async move { server.kick(id).await });
+                }
+            }
+            PlayerAction::AddFriend(id) => {
+                let Some(key) = self.player_keys.get(&id).copied() else {
+                    return;
+                };
+                let name = self.display_name(id);
+                let friend = Friend {
+                    key,
+                    name: name.clone(),
+                    last_server: self.server_address.clone(),
+                };
+
+                let gui = self.gui.as_mut().unwrap();
+                match self.friends.add(friend) {
+                    Ok(()) => gui.log(
+                        Severity::Info,
+                        tr_args("log.friend_added", &[("name", &name)]),
+                    ),
+                    Err(e) => gui.log(
+                        Severity::Error,
+                        format!("Failed to save the friends list: {e}"),
+                    ),
+                }
+            }
+            PlayerAction::RemoveFriend(key) => {
+                if let Err(e) = self.friends.remove(&key) {
+                    eprintln!("Failed to save the friends list: {e}");
+                }
+            }
+            PlayerAction::ToggleFavorite(address) => {
+                if let Err(e) = self.servers.toggle_favorite(&address) {
+                    eprintln!("Failed to save favorite servers: {e}");
+                }
+            }
+        }
+    }
+
+    /// A file dropped onto the window: a config to apply
+    fn open_dropped_file(&mut self, path: &Path) {
+        let file = path.file_name().unwrap_or_default().to_string_lossy();
+
+        let (severity, msg) = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml" | "json") => match self.apply_config_file(path) {
+                Ok(favorites) => (
+                    Severity::Info,
+                    tr_args(
+                        "drop.config_applied",
+                        &[("file", &file), ("favorites", &favorites)],
+                    ),
+                ),
+                Err(e) => (
+                    Severity::Error,
+                    tr_args("drop.config_failed", &[("file", &file), ("error", &e)]),
+                ),
+            },
+            _ => (Severity::Error, tr_args("drop.unknown", &[("file", &file)])),
+        };
+
+        self.gui.as_ref().unwrap().toast(severity, msg);
+    }
+
+    /// Apply the settings of a config file that are safe to change while running: the language
+    /// and the favorite servers. TOML, or JSON like the user config. Returns how many favorites
+    /// were new.
+    fn apply_config_file(&mut self, path: &Path) -> io::Result<usize> {
+        let config = servers::read_config_file(path)?;
+
+        if let Some(lang) = config["language"]
+            .as_str()
+            .and_then(|lang| Language::from_str(lang, true).ok())
+        {
+            i18n::set_language(lang);
+        }
+
+        let favorites = self.servers.import_favorites(&config)?;
+
+        // Have the new favorites answer soon rather than at the next refresh
+        if favorites > 0 {
+            self.statuses_queried = None;
+        }
+
+        Ok(favorites)
+    }
+
+    /// Commands of the in-game console
+    fn console_commands() -> CommandRegistry<Self> {
+        let mut commands = CommandRegistry::new();
+
+        commands.register(
+            "connect",
+            &[
+                Param::required("host[:port]", ParamKind::Word),
+                Param::optional("invite_code", ParamKind::Word),
+            ],
+            "Join a server, private ones need their invite code",
+            Permission::Player,
+            |app: &mut Self, args| {
+                let address: String = args.value("host[:port]")?;
+                let endpoint = addr::parse_endpoint(&address)?;
+                let invite_code: Option<String> = args.get("invite_code")?;
+                if invite_code
+                    .as_deref()
+                    .is_some_and(|code| !server::is_valid_invite_code(code))
+                {
+                    return Err(String::from(tr("status.invalid_invite_code")));
+                }
+
+                if app.client_session.is_some()
+                    || matches!(
+                        app.state_machine.peek(),
+                        Some(fsm::State::Connecting { .. })
+                    )
+                {
+                    return Err(String::from("Already connected, disconnect first"));
+                }
+
+                app.gui
+                    .as_mut()
+                    .unwrap()
+                    .set_status(String::from(tr("status.connecting")));
+                app.state_machine.change(fsm::State::Menu);
+                app.state_machine.push(fsm::State::Connecting {
+                    endpoint: endpoint.clone(),
+                    session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                    invite_code,
+                });
+
+                Ok(Some(format!("Connecting to {endpoint}")))
+            },
+        );
+
+        commands.register(
+            "disconnect",
+            &[],
+            "Leave the server",
+            Permission::Player,
+            |app: &mut Self, _| {
+                let Some(client_session) = app.client_session.as_ref() else {
+                    return Err(String::from("Not connected"));
+                };
+                client_session.leave_server(app.local_player.id);
+
+                app.gui
+                    .as_mut()
+                    .unwrap()
+                    .set_disconnect_reason(String::from(tr("dialog.left_server")));
+                app.disconnect();
+
+                Ok(None)
+            },
+        );
+
+        commands.register(
+            "name",
+            &[Param::optional("name", ParamKind::Text)],
+            "Show or set your name in the player list",
+            Permission::Player,
+            |app: &mut Self, args| {
+                let Some(name) = args.get::<String>("name")? else {
+                    return Ok(Some(format!(
+                        "Name: {}",
+                        app.player_name.as_deref().unwrap_or("(none)")
+                    )));
+                };
+                if !globals::is_valid_player_name(&name) {
+                    return Err(format!(
+                        "Names are at most {} characters and can't contain ':'",
+                        globals::MAX_PLAYER_NAME_LEN
+                    ));
+                }
+
+                if let Some(session) = app.client_session.as_ref() {
+                    session.send_name(app.local_player.id, &name);
+                }
+                app.player_name = Some(name);
+                Ok(None)
+            },
+        );
+
+        commands.register(
+            "fps_max",
+            &[Param::required("fps", ParamKind::Integer)],
+            "Limit the frame rate, 0 for no limit",
+            Permission::Player,
+            |app: &mut Self, args| {
+                app.fps_max = args.value("fps")?;
+
+                Ok(Some(match app.fps_max {
+                    0 => String::from("Frame rate unlimited"),
+                    fps => format!("Frame rate limited to {fps}"),
+                }))
+            },
+        );
+
+        commands.register(
+            "trace",
+            &[Param::required("on|off", ParamKind::Word)],
+            "Trace network messages to stdout",
+            Permission::Player,
+            |_, args| match args.value::<String>("on|off")?.as_str() {
+                "on" => {
+                    message::set_trace(true);
+                    Ok(Some(String::from("Tracing enabled")))
+                }
+                "off" => {
+                    message::set_trace(false);
+                    Ok(Some(String::from("Tracing disabled")))
+                }
+                _ => Err(String::from("Expected 'on' or 'off'")),
+            },
+        );
+
+        commands.register(
+            "debug_motion",
+            &[Param::required("on|off", ParamKind::Word)],
+            "Draw velocities and color remote players by the age of their last update (F4)",
+            Permission::Player,
+            |app: &mut Self, args| {
+                app.debug_motion = match args.value::<String>("on|off")?.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(String::from("Expected 'on' or 'off'")),
+                };
+                Ok(None)
+            },
+        );
+
+        commands.register(
+            "timescale",
+            &[Param::required("scale", ParamKind::Number)],
+            "Run the game you are hosting in slow motion, 1 for full speed",
+            Permission::Admin,
+            |app: &mut Self, args| {
+                let Some(server) = app.hosted_server.as_ref() else {
+                    return Err(String::from("Only the hosting player can change the time"));
+                };
+                let scale = server.set_time_scale(args.value("scale")?);
+
+                Ok(Some(format!("Time scale set to {scale}")))
+            },
+        );
+
+        commands.register(
+            "pause",
+            &[],
+            "Pause or resume the game you are hosting",
+            Permission::Admin,
+            |app: &mut Self, _| {
+                let Some(server) = app.hosted_server.clone() else {
+                    return Err(String::from("Only the hosting player can pause the game"));
+                };
+                let paused = !server.is_paused();
+                app.rt.spawn(async move { server.set_paused(paused).await });
+
+                Ok(None)
+            },
+        );
+
+        commands.register(
+            "kick",
+            &[Param::required("id", ParamKind::Integer)],
+            "Kick a player from the server you are hosting",
+            Permission::Admin,
+            |app: &mut Self, args| {
+                let id: PlayerId = args.value("id")?;
+                app.handle_player_action(PlayerAction::Kick(id));
+
+                Ok(None)
+            },
+        );
+
+        commands.register(
+            "avatar",
+            &[
+                Param::required("id", ParamKind::Integer),
+                Param::required("shape", ParamKind::Word),
+                Param::optional("size", ParamKind::Number),
+            ],
+            "Make a player on the server you are hosting a square, circle or triangle, \
+             optionally of another size",
+            Permission::Admin,
+            |app: &mut Self, args| {
+                let Some(server) = app.hosted_server.clone() else {
+                    return Err(String::from("Only the hosting player can change avatars"));
+                };
+                let id: PlayerId = args.value("id")?;
+                let avatar = admin::avatar_arg(args)?;
+                app.rt.block_on(server.set_avatar(id, avatar))?;
+
+                Ok(None)
+            },
+        );
+
+        client::register_console_commands(&mut commands);
+
+        commands
+    }
+
+    fn run_console_command(&mut self, line: &str) {
+        let commands = Rc::clone(&self.commands);
+
+        // Hosting from here makes you the server's admin
+        let permission = match self.hosted_server {
+            Some(_) => Permission::Admin,
+            None => Permission::Player,
+        };
+
+        let output = match commands.execute(self, line, permission) {
+            Ok(Executed::Ran(Ok(Some(output)))) | Ok(Executed::Help(output)) => output,
+            Ok(Executed::Ran(Err(e))) | Err(e) => e,
+            Ok(Executed::Ran(Ok(None))) | Ok(Executed::Nothing) => return,
+        };
+        self.gui.as_mut().unwrap().console_print(&output);
+    }
+
+    /// Drop the client session and show the Disconnected dialog
+    fn disconnect(&mut self) {
+        self.gui
+            .as_mut()
+            .unwrap()
+            .set_session_totals(self.player_stats.get(&self.local_player.id).copied());
+        self.server_address = None;
+        self.gui.as_mut().unwrap().close_chat();
+
+        self.client_session = None;
+        self.resync_task = None;
+        self.window
+            .as_mut()
+            .unwrap()
+            .set_title(globals::WINDOW_TITLE);
+        self.input_state = InputState::default(); // Avoid keys being stuck
+        self.forget_session();
+        self.connection_unstable = false;
+        self.state_machine.change(fsm::State::Disconnected);
+    }
+
+    /// Everything learned from the server during the session
+    fn forget_session(&mut self) {
+        self.player_stats.clear();
+        self.player_names.clear();
+        self.player_keys.clear();
+        self.remote_players.clear();
+        self.remote_snapshots.clear();
+        self.remote_updates.clear();
+        self.replication_resumed = Instant::now();
+        self.trails.clear();
+        self.particles.clear();
+        self.shake = None;
+        self.hit_stop = None;
+        self.map_open = false;
+        self.last_dash = None;
+        self.correction_offset = Vector2::new(0.0, 0.0);
+        self.step = 0;
+        self.spectating = None;
+        self.located = None;
+    }
+
+    /// The server lost the session, most likely it restarted. Join it again in the background,
+    /// the game goes on with the old session until then.
+    fn start_resync(&mut self) {
+        if self.resync_task.is_some() {
+            return;
+        }
+        let Some(server_address) = self.server_address.clone() else {
+            return;
+        };
+
+        self.gui
+            .as_mut()
+            .unwrap()
+            .log(Severity::Info, String::from(tr("log.resyncing")));
+
+        let client_config = self.client_config.clone();
+        self.resync_task = Some(
+            self.rt
+                .spawn(async move { ClientSession::new(server_address, &client_config).await }),
+        );
+    }
+
+    /// Switch to the new session once the resync went through
+    fn poll_resync(&mut self) {
+        let Some(task) = self.resync_task.take_if(|task| task.is_finished()) else {
+            return;
+        };
+
+        let client_session = match self.rt.block_on(task) {
+            Ok(Ok(client_session)) => client_session,
+            Ok(Err(e)) => return self.resync_failed(&*e),
+            Err(e) => return self.resync_failed(&e),
+        };
+
+        self.forget_session();
+        self.local_player = client_session.get_session_player_data();
+        self.world_mode = client_session.world_mode();
+        self.rules = client_session.rules();
+        self.terrain = client_session.map().clone();
+        self.window.as_ref().unwrap().set_title(&format!(
+            "{} - Player {}",
+            globals::WINDOW_TITLE,
+            self.local_player.id
+        ));
+
+        // The new session is a new join to the server, the name is sent again
+        if let Some(name) = &self.player_name {
+            client_session.send_name(self.local_player.id, name);
+        }
+        self.client_session = Some(client_session);
+
+        self.gui.as_mut().unwrap().log(
+            Severity::Info,
+            tr_args("log.resynced", &[("id", &self.local_player.id)]),
+        );
+    }
+
+    fn resync_failed(&mut self, e: &dyn Error) {
+        self.gui
+            .as_mut()
+            .unwrap()
+            .set_disconnect_reason(tr_args("dialog.resync_failed", &[("error", &e)]));
+        self.disconnect();
+    }
+
+    fn remove_remote_player(&mut self, id: PlayerId) {
+        if let Some(player) = self.remote_players.remove(&id) {
+            self.spawn_effect(Effect::Puff {
+                pos: player.pos,
+                color: player.color,
+            });
+        }
+        self.remote_snapshots.remove(&id);
+        self.remote_updates.remove(&id);
+        self.player_names.remove(&id);
+        self.player_keys.remove(&id);
+        if self.spectating == Some(id) {
+            self.spectating = None;
+        }
+    }
+
+    /// Remove remote players the server stopped replicating, as if they had left. Their LEAVE
+    /// may have been lost, the server has reaped them long ago then. Nobody is replicated while
+    /// the server is paused, so nobody times out then either.
+    fn drop_stale_remote_players(&mut self) {
+        let paused = self
+            .client_session
+            .as_ref()
+            .is_none_or(|s| s.time_scale() == 0.0);
+        if paused || self.replication_resumed.elapsed() < REMOTE_PLAYER_TIMEOUT {
+            return;
+        }
+
+        let stale: Vec<PlayerId> = self
+            .remote_updates
+            .iter()
+            .filter(|(_, (received_at, _))| received_at.elapsed() >= REMOTE_PLAYER_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in stale {
+            self.remove_remote_player(id);
+            self.gui.as_mut().unwrap().log(
+                Severity::Leave,
+                tr_args("log.player_timed_out", &[("id", &id)]),
+            );
+        }
+    }
+
+    /// Show remote players where they were the interpolation delay ago, between the two
+    /// snapshots around that time. With the delay tuned to the jitter the later one is usually
+    /// there already, otherwise they wait at the latest one. In a wrapping world they take the
+    /// short way across the seam, and they always turn the short way round.
+    fn interpolate_remote_players(&mut self) {
+        let Some(session) = self.client_session.as_ref() else {
+            return;
+        };
+        let now = Instant::now();
+        let shown_at = now
+            .checked_sub(session.interpolation().delay)
+            .unwrap_or(now);
+
+        for (id, player) in self.remote_players.iter_mut() {
+            let Some(snapshots) = self.remote_snapshots.get_mut(id) else {
+                continue;
+            };
+
+            // The older of the two snapshots around the shown time comes first
+            while snapshots.len() > 2 && snapshots[1].0 <= shown_at {
+                snapshots.pop_front();
+            }
+
+            let (from, to, t) = match (snapshots.front(), snapshots.get(1)) {
+                (Some((from_at, from)), Some((to_at, to))) => {
+                    let span = to_at.saturating_duration_since(*from_at).as_secs_f32();
+                    let elapsed = shown_at.saturating_duration_since(*from_at).as_secs_f32();
+                    let t = if span > 0.0 {
+                        (elapsed / span).min(1.0)
+                    } else {
+                        1.0
+                    };
+                    (from, to, t)
+                }
+                (Some((_, only)), None) => (only, only, 0.0),
+                _ => continue,
+            };
+
+            player.pos = from.pos + self.rules.world_delta(from.pos, to.pos, self.world_mode) * t;
+            player.facing = lerp_angle(from.facing, to.facing, t);
+            player.avatar = to.avatar;
+            self.rules.apply_world_bounds(player, self.world_mode);
+        }
+    }
+
+    /// Shrink the correction offset exponentially, but no faster than the max correction rate
+    fn smooth_correction(&mut self) {
+        let max_step =
+            self.render_settings.max_correction_rate * globals::FIXED_UPDATE_TIMESTEP_SEC;
+        let decay =
+            0.5f32.powf(globals::FIXED_UPDATE_TIMESTEP_SEC / CORRECTION_HALF_LIFE.as_secs_f32());
+
+        let step = self.correction_offset * (1.0 - decay);
+        match step.magnitude() {
+            m if m > max_step => self.correction_offset -= step * (max_step / m),
+            _ => self.correction_offset -= step,
+        }
+
+        // Done once it's below a pixel, the decay alone never gets to zero
+        if self.correction_offset.magnitude() < 1.0 || max_step == 0.0 {
+            self.correction_offset = Vector2::new(0.0, 0.0);
+        }
+    }
+
+    /// Local player where it is drawn, which lags behind server corrections for a moment
+    /// Random jolt of the camera, weaker as the shake wears off
+    fn shake_offset(&self) -> Vector2<f32> {
+        let Some(at) = self.shake else {
+            return Vector2::new(0.0, 0.0);
+        };
+        let remaining = 1.0 - at.elapsed().as_secs_f32() / SHAKE_DURATION.as_secs_f32();
+        if remaining <= 0.0 {
+            return Vector2::new(0.0, 0.0);
+        }
+
+        let mut rng = rand::thread_rng();
+        let strength = SHAKE_AMPLITUDE * self.render_settings.screen_shake * remaining * remaining;
+        Vector2::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0)) * strength
+    }
+
+    fn displayed_local_player(&self) -> Player {
+        Player {
+            pos: self.local_player.pos + self.correction_offset,
+            ..self.local_player
+        }
+    }
+
     fn move_camera(&mut self) {
+        self.camera_shake = self.shake_offset();
+
+        let followed = match self.spectating.and_then(|id| self.remote_players.get(&id)) {
+            Some(spectated) => spectated.pos,
+            None => self.displayed_local_player().pos,
+        };
+
+        // A wrapping world has no edge to stop at
+        if self.world_mode == WorldMode::Wrap {
+            self.camera_pos = followed;
+            return;
+        }
+
         let half_width = globals::WINDOW_SIZE.0 as f32 / 2.0;
         let half_height = globals::WINDOW_SIZE.1 as f32 / 2.0;
 
         // Calculate the camera's allowed range
-        let min_camera_x = globals::WORLD_BOUNDS.min_x + half_width;
-        let max_camera_x = globals::WORLD_BOUNDS.max_x - half_width;
-        let min_camera_y = globals::WORLD_BOUNDS.min_y + half_height;
-        let max_camera_y = globals::WORLD_BOUNDS.max_y - half_height;
+        let bounds = &self.rules.bounds;
+        let min_camera_x = bounds.min_x + half_width;
+        let max_camera_x = bounds.max_x - half_width;
+        let min_camera_y = bounds.min_y + half_height;
+        let max_camera_y = bounds.max_y - half_height;
 
-        // Update camera position, clamping to the allowed range
-        self.camera_pos.x = self.local_player.pos.x.clamp(min_camera_x, max_camera_x);
-        self.camera_pos.y = self.local_player.pos.y.clamp(min_camera_y, max_camera_y);
+        // Update camera position, clamping to the allowed range. A world smaller than the window
+        // stays centered.
+        let clamp = |value: f32, min: f32, max: f32| {
+            if min <= max {
+                value.clamp(min, max)
+            } else {
+                (min + max) / 2.0
+            }
+        };
+        self.camera_pos.x = clamp(followed.x, min_camera_x, max_camera_x);
+        self.camera_pos.y = clamp(followed.y, min_camera_y, max_camera_y);
     }
 }
 
@@ -332,7 +2064,10 @@ impl ApplicationHandler for App<'_> {
     // after the first WindowEvent::Resumed even is received. There are systems that won't allow
     // applications to create a renderer until that.
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let (window, renderer, gui) = Renderer::create_graphics(&event_loop);
+        let (window, renderer, mut gui) =
+            renderer::create_graphics(event_loop, self.render_settings);
+
+        gui.set_accessibility(self.render_settings.accessibility);
 
         self.window = Some(window);
         self.renderer = Some(renderer);
@@ -341,10 +2076,34 @@ impl ApplicationHandler for App<'_> {
 
     fn window_event(
         &mut self,
-        _event_loop: &ActiveEventLoop,
-        _window_id: winit::window::WindowId,
+        event_loop: &ActiveEventLoop,
+        window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
+        if self
+            .debug_window
+            .as_ref()
+            .is_some_and(|w| w.id() == window_id)
+        {
+            self.debug_window_event(event);
+            return;
+        }
+
+        if let WindowEvent::DroppedFile(path) = &event {
+            self.open_dropped_file(path);
+            return;
+        }
+
+        // Gathered up front, the GUI borrows the app for the rest of the event
+        let redraw = matches!(event, WindowEvent::RedrawRequested);
+        let player_list = redraw.then(|| self.player_list());
+        let motion_debug = (redraw && self.debug_motion).then(|| self.motion_debug());
+        let local_player = self.displayed_local_player();
+        let name_labels = redraw.then(|| self.name_labels(&local_player));
+        let crosshair = self.crosshair_pos();
+        let streaks = redraw.then(|| self.trail_streaks());
+        let mut player_actions = Vec::new();
+
         let window = self.window.as_ref().unwrap();
         let gui = self.gui.as_mut().unwrap();
 
@@ -362,6 +2121,76 @@ impl ApplicationHandler for App<'_> {
                 is_synthetic: false,
                 ..
             } => {
+                // Not forwarded, the key would end up typed into the console
+                if physical_key == KeyCode::Backquote {
+                    if state == ElementState::Pressed {
+                        gui.toggle_console();
+                        self.input_state = InputState::default(); // Avoid keys being stuck
+                    }
+                    return;
+                }
+
+                // Everything else is typed into the console while it is open
+                if gui.console_open() {
+                    if matches!(logical_key, Key::Named(NamedKey::Escape))
+                        && state == ElementState::Pressed
+                    {
+                        gui.toggle_console();
+                    }
+                    gui.handle_events(window, &event);
+                    return;
+                }
+
+                // Same for the chat line, which Enter sends and closes
+                if gui.chat_open() {
+                    if matches!(logical_key, Key::Named(NamedKey::Escape))
+                        && state == ElementState::Pressed
+                    {
+                        gui.close_chat();
+                    }
+                    gui.handle_events(window, &event);
+                    return;
+                }
+
+                // And for any other focused text field, e.g. the server address
+                if gui.wants_keyboard() {
+                    self.input_state = InputState::default(); // Avoid keys being stuck
+                    gui.handle_events(window, &event);
+                    return;
+                }
+
+                if physical_key == KeyCode::Enter
+                    && state == ElementState::Pressed
+                    && matches!(self.state_machine.peek(), Some(fsm::State::Playing))
+                {
+                    gui.open_chat();
+                    self.input_state = InputState::default(); // Avoid keys being stuck
+                    return;
+                }
+
+                if physical_key == KeyCode::F3 && state == ElementState::Pressed {
+                    gui.toggle_debug_overlay();
+                }
+
+                if physical_key == KeyCode::F4 && state == ElementState::Pressed {
+                    self.debug_motion = !self.debug_motion;
+                }
+
+                if physical_key == KeyCode::F10 && state == ElementState::Pressed {
+                    gui.toggle_trace_viewer();
+                }
+
+                if physical_key == KeyCode::Tab && state == ElementState::Pressed {
+                    gui.toggle_player_list();
+                }
+
+                if physical_key == KeyCode::KeyM
+                    && state == ElementState::Pressed
+                    && matches!(self.state_machine.peek(), Some(fsm::State::Playing))
+                {
+                    self.map_open = !self.map_open;
+                }
+
                 if matches!(logical_key, Key::Named(NamedKey::Escape)) &&
                 // Negation is an additional guard to avoid accidentally pushing duplicate states when someone holds down Esc key for too long
                 !matches!(self.state_machine.peek(), Some(fsm::State::QuitDialog))
@@ -370,6 +2199,13 @@ impl ApplicationHandler for App<'_> {
                 }
 
                 if matches!(self.state_machine.peek(), Some(fsm::State::Playing)) {
+                    if matches!(physical_key, KeyCode::ShiftLeft | KeyCode::ShiftRight)
+                        && state == ElementState::Pressed
+                    {
+                        self.dash_requested = true;
+                        return;
+                    }
+
                     let input_event = match physical_key {
                         KeyCode::ArrowUp | KeyCode::KeyW => InputEvent::MoveUp,
                         KeyCode::ArrowDown | KeyCode::KeyS => InputEvent::MoveDown,
@@ -380,27 +2216,75 @@ impl ApplicationHandler for App<'_> {
                     self.input_state[input_event] = state == ElementState::Pressed;
                 }
             }
+            WindowEvent::CursorMoved { position, .. } => self.cursor_pos = Some(position),
+            WindowEvent::CursorLeft { .. } => self.cursor_pos = None,
             WindowEvent::Focused(false) => {
                 // Avoid stuck keys when window loses focus
                 self.input_state = InputState::default();
             }
             WindowEvent::RedrawRequested => {
-                let renderer = self.renderer.as_ref().unwrap();
-
-                gui.prepare_frame(&window, &mut self.state_machine);
-                renderer.draw(
-                    &self.camera_pos,
-                    &self.local_player,
-                    &self.remote_players,
-                    self.state_machine.peek(),
+                let renderer = self.renderer.as_mut().unwrap();
+
+                let message_stats = self.client_session.as_ref().map(|s| s.message_stats());
+                let interpolation = self.client_session.as_ref().map(|s| s.interpolation());
+                let connection_quality =
+                    self.client_session.as_ref().map(|s| s.connection_quality());
+                player_actions = gui.prepare_frame(
+                    window,
+                    &mut self.state_machine,
+                    message_stats.as_ref(),
+                    interpolation,
+                    connection_quality,
+                    &player_list.unwrap_or_default(),
                 );
-                gui.draw(&window);
-                renderer.swap_buffers();
+                renderer.draw(&Scene {
+                    camera: self.camera_pos + self.camera_shake,
+                    local_player: &local_player,
+                    remote_players: &self.remote_players,
+                    state: self.state_machine.peek(),
+                    world: WorldView {
+                        world_mode: self.world_mode,
+                        rules: &self.rules,
+                        terrain: &self.terrain,
+                        time_of_day: self
+                            .client_session
+                            .as_ref()
+                            .map_or(0.5, |s| s.time_of_day()),
+                    },
+                    motion_debug: motion_debug.as_ref(),
+                    labels: name_labels.as_deref().unwrap_or_default(),
+                    crosshair,
+                    streaks: streaks.as_deref().unwrap_or_default(),
+                    particles: self.particles.particles(),
+                    map_overlay: self.map_open,
+                });
+                gui.draw(window);
+                renderer.present();
             }
             _ => (),
         }
 
         // Forward rest of events to GUI
-        gui.handle_events(&window, &event);
+        gui.handle_events(window, &event);
+
+        let console_commands = gui.take_console_commands();
+        let chat_lines = gui.take_chat_lines();
+        let accessibility = gui.take_accessibility_change();
+
+        for action in player_actions {
+            self.handle_player_action(action);
+        }
+        for line in console_commands {
+            self.run_console_command(&line);
+        }
+        for line in chat_lines {
+            self.send_chat_line(&line);
+        }
+        if let Some(accessibility) = accessibility {
+            self.set_accessibility(accessibility);
+        }
+
+        self.update_debug_window(event_loop);
+        self.update_cursor();
     }
 }
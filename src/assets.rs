@@ -0,0 +1,212 @@
+//! Files the game draws and plays, such as shaders, fonts, textures and sounds, looked up by a
+//! path like `shaders/quad.frag`. A file in the asset directory of the data dir overrides one
+//! next to the working directory, which overrides the copy built into the executable. Assets
+//! without a built-in copy are optional, whatever shows them keeps a placeholder until they
+//! loaded.
+
+use std::{borrow::Cow, collections::HashMap, fmt, fs, io, path::PathBuf, sync::Arc};
+
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use crate::paths;
+
+/// Built-in copies, for running without any asset files around
+const EMBEDDED: &[(&str, &[u8])] = &[
+    (
+        "shaders/grid.vert",
+        include_bytes!("../assets/shaders/grid.vert"),
+    ),
+    (
+        "shaders/grid.frag",
+        include_bytes!("../assets/shaders/grid.frag"),
+    ),
+    (
+        "shaders/quad.vert",
+        include_bytes!("../assets/shaders/quad.vert"),
+    ),
+    (
+        "shaders/quad.frag",
+        include_bytes!("../assets/shaders/quad.frag"),
+    ),
+    (
+        "shaders/text.vert",
+        include_bytes!("../assets/shaders/text.vert"),
+    ),
+    (
+        "shaders/text.frag",
+        include_bytes!("../assets/shaders/text.frag"),
+    ),
+    (
+        "shaders/particle.vert",
+        include_bytes!("../assets/shaders/particle.vert"),
+    ),
+    (
+        "shaders/particle.frag",
+        include_bytes!("../assets/shaders/particle.frag"),
+    ),
+];
+
+/// Assets next to the working directory, e.g. when started from the source tree
+pub const LOCAL_ASSET_DIR: &str = "assets";
+
+/// Where the bytes of an asset come from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    Embedded(&'static [u8]),
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    /// Neither a file nor a built-in copy
+    NotFound(String),
+
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::NotFound(name) => write!(f, "Asset {name} not found"),
+            AssetError::Io(path, e) => write!(f, "Failed to read {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+/// Where `name` would be loaded from, `None` for an asset that is nowhere to be found
+pub fn resolve(name: &str) -> Option<Source> {
+    resolve_in(&[paths::asset_dir(), PathBuf::from(LOCAL_ASSET_DIR)], name)
+}
+
+fn resolve_in(dirs: &[PathBuf], name: &str) -> Option<Source> {
+    dirs.iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+        .map(Source::File)
+        .or_else(|| embedded(name).map(Source::Embedded))
+}
+
+/// Built-in copy of `name`
+pub fn embedded(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED
+        .iter()
+        .find(|(embedded_name, _)| *embedded_name == name)
+        .map(|(_, bytes)| *bytes)
+}
+
+/// Load `name` right away. A file that can't be read falls back to the built-in copy, if there
+/// is one.
+pub fn read(name: &str) -> Result<Cow<'static, [u8]>, AssetError> {
+    match resolve(name) {
+        Some(Source::Embedded(bytes)) => Ok(Cow::Borrowed(bytes)),
+        Some(Source::File(path)) => match fs::read(&path) {
+            Ok(bytes) => Ok(Cow::Owned(bytes)),
+            Err(e) => match embedded(name) {
+                Some(bytes) => {
+                    eprintln!(
+                        "Failed to read {}, using the built-in copy: {e}",
+                        path.display()
+                    );
+                    Ok(Cow::Borrowed(bytes))
+                }
+                None => Err(AssetError::Io(path, e)),
+            },
+        },
+        None => Err(AssetError::NotFound(name.to_string())),
+    }
+}
+
+enum Slot {
+    Loading(JoinHandle<Result<Vec<u8>, AssetError>>),
+    Ready(Arc<[u8]>),
+
+    /// Not asked for again, so a broken file isn't read every frame
+    Failed,
+}
+
+/// Assets loaded in the background on the tokio runtime and kept once loaded. Asking for one
+/// that isn't loaded yet starts loading it and gets `None`, so the caller shows a placeholder
+/// for now.
+pub struct AssetCache {
+    rt: Handle,
+    slots: HashMap<String, Slot>,
+}
+
+impl AssetCache {
+    pub fn new(rt: Handle) -> Self {
+        Self {
+            rt,
+            slots: HashMap::new(),
+        }
+    }
+
+    /// Bytes of `name` once loaded. Load errors are reported once, the asset then stays `None`.
+    pub fn get(&mut self, name: &str) -> Option<Arc<[u8]>> {
+        let slot = self.slots.entry(name.to_string()).or_insert_with(|| {
+            let name = name.to_string();
+            Slot::Loading(
+                self.rt
+                    .spawn_blocking(move || read(&name).map(Cow::into_owned)),
+            )
+        });
+
+        if let Slot::Loading(task) = slot {
+            if !task.is_finished() {
+                return None;
+            }
+
+            *slot = match self.rt.block_on(task) {
+                Ok(Ok(bytes)) => Slot::Ready(bytes.into()),
+                Ok(Err(e)) => {
+                    eprintln!("{e}");
+                    Slot::Failed
+                }
+                Err(e) => {
+                    eprintln!("Loading {name} failed: {e}");
+                    Slot::Failed
+                }
+            };
+        }
+
+        match slot {
+            Slot::Ready(bytes) => Some(bytes.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_override_the_built_in_copy_in_order() {
+        let root = std::env::temp_dir().join(format!("assets-test-{}", std::process::id()));
+        let (user, local) = (root.join("user"), root.join("local"));
+        fs::create_dir_all(user.join("shaders")).unwrap();
+        fs::create_dir_all(local.join("shaders")).unwrap();
+        let dirs = [user.clone(), local.clone()];
+
+        assert_eq!(
+            resolve_in(&dirs, "shaders/quad.frag"),
+            embedded("shaders/quad.frag").map(Source::Embedded)
+        );
+        assert_eq!(resolve_in(&dirs, "fonts/ui.ttf"), None);
+
+        fs::write(local.join("shaders/quad.frag"), "local").unwrap();
+        assert_eq!(
+            resolve_in(&dirs, "shaders/quad.frag"),
+            Some(Source::File(local.join("shaders/quad.frag")))
+        );
+
+        fs::write(user.join("shaders/quad.frag"), "user").unwrap();
+        assert_eq!(
+            resolve_in(&dirs, "shaders/quad.frag"),
+            Some(Source::File(user.join("shaders/quad.frag")))
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
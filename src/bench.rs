@@ -0,0 +1,422 @@
+use std::{
+    error::Error,
+    hint::black_box,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use cgmath::{vec2, Vector2};
+use game_server_sample::{
+    globals, identity::Identity, message::Message, rules::GameRules, simulate_player, simulation,
+    ClientId, Palette, Player, WorldMode,
+};
+use rand::Rng;
+
+use crate::{
+    client::{self, ClientConfig, ClientSession},
+    server::{ServerBuilder, ServerHandle},
+};
+
+// Samples taken over a soak run, and the share of them skipped while the bots join for the
+// first time
+const SOAK_SAMPLES: u32 = 60;
+const SOAK_WARMUP_SAMPLES: usize = 6;
+const MIN_SOAK_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Bots play for a while, leave and join again, so whatever the server keeps per player gets
+// created and dropped over and over
+const BOT_LIFETIME_SEC: std::ops::Range<f32> = 10.0..60.0;
+const BOT_REJOIN_DELAY_SEC: std::ops::Range<f32> = 0.5..3.0;
+const BOT_TURN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Run the serializer, deserializer and simulation step over a synthetic workload of `players`
+/// players for `ticks` ticks and print throughput numbers. No sockets are involved, so the
+/// numbers only move when the code does.
+pub fn run(players: usize, ticks: u64) {
+    let mut rng = rand::thread_rng();
+    let rules = GameRules::default();
+    let bounds = &rules.bounds;
+
+    // Players start all over the world and keep moving, so some of them are always pushing
+    // against the bounds and get clamped
+    let mut world: Vec<Player> = (1..=players as u64)
+        .map(|id| {
+            let mut player = Player::new(id, Palette::Normal.player_color(id));
+            player.pos = vec2(
+                rng.gen_range(bounds.min_x..bounds.max_x),
+                rng.gen_range(bounds.min_y..bounds.max_y),
+            );
+            player.velocity = vec2(rng.gen_range(-600.0..600.0), rng.gen_range(-600.0..600.0));
+            player
+        })
+        .collect();
+
+    println!("Synthetic workload: {players} players, {ticks} ticks");
+
+    // Simulation step
+    let start = Instant::now();
+    let mut replication = Vec::with_capacity(players);
+    for _ in 0..ticks {
+        replication.clear();
+        for player in world.iter_mut() {
+            player.pos += player.velocity * globals::FIXED_UPDATE_TIMESTEP_SEC;
+            replication.push(simulate_player(player, WorldMode::Bounded, &rules));
+        }
+        black_box(&replication);
+    }
+    let simulation = start.elapsed();
+
+    // Serializer, over the replication messages of one tick again and again
+    let start = Instant::now();
+    let mut serialized_bytes = 0;
+    for _ in 0..ticks {
+        for msg in &replication {
+            serialized_bytes += black_box(msg.serialize()).len();
+        }
+    }
+    let serialize = start.elapsed();
+
+    // Deserializer, over the wire format of the same messages
+    let wire: Vec<String> = replication.iter().map(Message::serialize).collect();
+    let start = Instant::now();
+    for _ in 0..ticks {
+        for msg in &wire {
+            let _ = black_box(Message::deserialize(black_box(msg)));
+        }
+    }
+    let deserialize = start.elapsed();
+
+    let steps = players as u64 * ticks;
+    let tick_budget = Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC);
+    let tick_time = simulation.div_f64(ticks.max(1) as f64);
+
+    print_row("simulation", steps, "player steps", simulation);
+    println!(
+        "{:14}{:.3} ms per tick, {:.2}% of the {:.2} ms tick budget",
+        "",
+        tick_time.as_secs_f64() * 1000.0,
+        tick_time.as_secs_f64() / tick_budget.as_secs_f64() * 100.0,
+        tick_budget.as_secs_f64() * 1000.0
+    );
+
+    print_row("serialize", steps, "messages", serialize);
+    println!(
+        "{:14}{:.1} MB/s",
+        "",
+        serialized_bytes as f64 / serialize.as_secs_f64().max(f64::EPSILON) / 1_000_000.0
+    );
+
+    print_row("deserialize", steps, "messages", deserialize);
+}
+
+/// Duration of a soak run: a number of seconds, or a number followed by `s`, `m` or `h`
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, unit_sec) = match text.trim().char_indices().last() {
+        Some((i, 's')) => (&text[..i], 1.0),
+        Some((i, 'm')) => (&text[..i], 60.0),
+        Some((i, 'h')) => (&text[..i], 3600.0),
+        _ => (text, 1.0),
+    };
+
+    match number.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => {
+            Ok(Duration::from_secs_f64(number * unit_sec))
+        }
+        _ => Err(format!("{text:?} is not a duration like 90s, 30m or 1h")),
+    }
+}
+
+fn print_row(name: &str, count: u64, unit: &str, elapsed: Duration) {
+    let per_sec = count as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+
+    println!(
+        "{name:14}{count} {unit} in {:.1} ms, {:.2} M/s",
+        elapsed.as_secs_f64() * 1000.0,
+        per_sec / 1_000_000.0
+    );
+}
+
+/// Host a server, keep `bots` bots joining, wandering and leaving it for `duration` and watch
+/// the heap, the runtime's tasks and the server's per-player bookkeeping. A counter that only
+/// ever grows is a leak, like players that are never reaped or a queue nobody drains, and makes
+/// the process exit nonzero.
+pub fn soak(duration: Duration, bots: usize) -> Result<(), Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    println!(
+        "Soak test: {bots} bots for {} s",
+        duration.as_secs_f32().round()
+    );
+    if cfg!(not(feature = "soak")) {
+        println!("Heap growth is not checked, build with --features soak for that");
+    }
+
+    rt.block_on(async {
+        let server = ServerBuilder::new()
+            .max_players(bots)
+            .start()
+            .await
+            .map_err(|e| format!("Server did not start: {e}"))?;
+        server.set_log_echo(false);
+        let port = server.local_addr()?.port();
+        let server_address = SocketAddr::from((Ipv4Addr::LOCALHOST, port)).to_string();
+
+        let lost = Arc::new(AtomicUsize::new(0));
+        for _ in 0..bots {
+            tokio::spawn(run_bot(server_address.clone(), lost.clone()));
+        }
+
+        let counters = sample_counters(&server, duration).await;
+        server.shutdown().await;
+
+        let leaks = report(&counters);
+        let lost = lost.load(Ordering::Relaxed);
+        if lost > 0 {
+            println!("{lost} bot connections were lost or refused");
+        }
+
+        match (leaks.is_empty(), lost) {
+            (true, 0) => {
+                println!("Soak test passed");
+                Ok(())
+            }
+            (true, _) => Err("Soak test failed: bots lost their connection".into()),
+            (false, _) => {
+                Err(format!("Soak test failed, still growing: {}", leaks.join(", ")).into())
+            }
+        }
+    })
+}
+
+/// Values of every counter over the run, one entry per sample
+struct Counter {
+    name: &'static str,
+    values: Vec<usize>,
+}
+
+async fn sample_counters(server: &ServerHandle, duration: Duration) -> Vec<Counter> {
+    let mut counters: Vec<Counter> = Vec::new();
+    let mut interval =
+        tokio::time::interval((duration / SOAK_SAMPLES).max(MIN_SOAK_SAMPLE_INTERVAL));
+    let started = Instant::now();
+    interval.tick().await;
+
+    while started.elapsed() < duration {
+        interval.tick().await;
+
+        let mut sample = vec![(
+            "tasks",
+            tokio::runtime::Handle::current()
+                .metrics()
+                .num_alive_tasks(),
+        )];
+        #[cfg(feature = "soak")]
+        sample.insert(0, ("heap bytes", heap::HEAP_BYTES.load(Ordering::Relaxed)));
+        sample.extend(server.resource_counts().await);
+
+        println!(
+            "{:>6} s  {}",
+            started.elapsed().as_secs(),
+            sample
+                .iter()
+                .map(|(name, value)| format!("{name} {value}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        for (name, value) in sample {
+            match counters.iter_mut().find(|counter| counter.name == name) {
+                Some(counter) => counter.values.push(value),
+                None => counters.push(Counter {
+                    name,
+                    values: vec![value],
+                }),
+            }
+        }
+    }
+
+    counters
+}
+
+// Print where every counter started and ended up, and return the names of the growing ones
+fn report(counters: &[Counter]) -> Vec<&'static str> {
+    let mut leaks = Vec::new();
+
+    println!();
+    for counter in counters {
+        let values = counter
+            .values
+            .get(SOAK_WARMUP_SAMPLES..)
+            .unwrap_or_default();
+        let growing = keeps_growing(values);
+        if growing {
+            leaks.push(counter.name);
+        }
+
+        println!(
+            "{:18}{:>12} -> {:>12}, peak {:>12}{}",
+            counter.name,
+            values.first().copied().unwrap_or_default(),
+            values.last().copied().unwrap_or_default(),
+            values.iter().copied().max().unwrap_or_default(),
+            if growing { "  GROWING" } else { "" }
+        );
+    }
+
+    leaks
+}
+
+/// Whether a counter never went down and was still rising in the last third of the samples.
+/// Buffers that fill up to their limit early on and stay there don't count.
+fn keeps_growing(values: &[usize]) -> bool {
+    let Some(&last) = values.last() else {
+        return false;
+    };
+
+    let never_shrank = values.windows(2).all(|pair| pair[1] >= pair[0]);
+    never_shrank && last > values[values.len() * 2 / 3]
+}
+
+// One bot for the whole run: join, wander around, leave and join again
+async fn run_bot(server_address: String, lost: Arc<AtomicUsize>) {
+    let config = ClientConfig {
+        client_id: ClientId::random(),
+        identity: Identity::generate(),
+        relay: None,
+        check_determinism: false,
+        invite_code: None,
+        in_process_host: false,
+        interp_delay: client::DEFAULT_INTERP_DELAY,
+    };
+
+    loop {
+        match ClientSession::new(server_address.clone(), &config).await {
+            Ok(mut session) => {
+                if !play(&mut session).await {
+                    lost.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(_) => {
+                lost.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let delay = rand::thread_rng().gen_range(BOT_REJOIN_DELAY_SEC);
+        tokio::time::sleep(Duration::from_secs_f32(delay)).await;
+    }
+}
+
+// Wander in a random direction that changes every so often, `false` when the connection is lost
+async fn play(session: &mut ClientSession) -> bool {
+    let lifetime = Duration::from_secs_f32(rand::thread_rng().gen_range(BOT_LIFETIME_SEC));
+    let world_mode = session.world_mode();
+    let rules = session.rules();
+    let map = session.map().clone();
+    let mut player = session.get_session_player_data();
+    let mut direction = Vector2::new(0.0, 0.0);
+    let mut step: u32 = 0;
+
+    let mut tick =
+        tokio::time::interval(Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC));
+    let joined = Instant::now();
+    let mut turned = joined;
+
+    while joined.elapsed() < lifetime {
+        tick.tick().await;
+        step = step.wrapping_add(1);
+
+        if step == 1 || turned.elapsed() >= BOT_TURN_INTERVAL {
+            let angle = rand::thread_rng().gen_range(0.0..std::f32::consts::TAU);
+            direction = vec2(angle.cos(), angle.sin());
+            turned = Instant::now();
+        }
+
+        simulation::step_player(&mut player, direction, &map, world_mode, &rules);
+        session.send_pos(&player, step);
+        while session.poll_event().is_some() {}
+
+        if !session.is_server_alive() {
+            return false;
+        }
+    }
+
+    // Give the send task a moment with the leave message before the session is dropped
+    session.leave_server(player.id);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    true
+}
+
+/// Counts the heap bytes of the whole process for the soak test. Only in builds with the `soak`
+/// feature, it sits in front of every allocation the game makes.
+#[cfg(feature = "soak")]
+mod heap {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    pub static HEAP_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    struct CountingAllocator;
+
+    // Only counts, the system allocator does the work
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                HEAP_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            HEAP_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                HEAP_BYTES.fetch_add(new_size, Ordering::Relaxed);
+                HEAP_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_counters_still_rising_at_the_end_are_leaks() {
+        assert!(keeps_growing(&[10, 11, 11, 12, 14, 15]));
+
+        // Filled up to its limit and stayed there
+        assert!(!keeps_growing(&[10, 40, 64, 64, 64, 64]));
+
+        // Up and down with the players
+        assert!(!keeps_growing(&[10, 12, 11, 13, 14, 15]));
+        assert!(!keeps_growing(&[]));
+    }
+
+    #[test]
+    fn soak_durations_take_a_unit() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("").is_err());
+    }
+}
@@ -1,73 +1,457 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use cgmath::Vector2;
+use game_server_sample::{
+    codec::Codec,
+    globals,
+    identity::{Identity, PublicKey},
+    outbox::Outbox,
+    rules::GameRules,
+    terrain::TerrainMap,
+    version::Version,
+    ClientId, Player, PlayerId, SessionToken, WorldMode,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
 
-use game_server_sample::{globals, Player, PlayerId};
-use tokio::{
-    net::UdpSocket,
-    sync::mpsc::{self, error::TryRecvError},
-    task::JoinHandle,
+use crate::{
+    commands::{CommandRegistry, Param, ParamKind, Permission},
+    message::{
+        self, Ability, Direction, Message, MessageStats, NoticeLevel, SharedMessageStats,
+        WhisperError,
+    },
+    paths,
+    quality::{
+        ConnectionQuality, InterpolationDelay, InterpolationReport, Liveness, QualityReport,
+    },
+    transport::{LocalTransport, Transport, UdpTransport},
 };
 
-use crate::message::{self, Message};
+type ChannelSender<T> = mpsc::UnboundedSender<T>;
+type ChannelReceiver<T> = mpsc::UnboundedReceiver<T>;
+
+/// Latest state updates from the server waiting for the app, one per player and kind, see
+/// [`Message::superseded_by`]
+type GameplayQueue = Arc<Mutex<Outbox<(&'static str, PlayerId), Message>>>;
+
+// State updates waiting for the app beyond this push out the oldest ones
+const GAMEPLAY_QUEUE_LEN: usize = 256;
 
-type ChannelSender = mpsc::UnboundedSender<String>;
-type ChannelReceiver = mpsc::UnboundedReceiver<String>;
+/// What the network tasks measure of the link as messages come and go, read by the session
+struct LinkMeasurements {
+    /// Loss and jitter of the pings from the server
+    quality: Mutex<ConnectionQuality>,
 
+    /// Tuned as snapshots arrive
+    interpolation: Mutex<InterpolationDelay>,
+
+    /// Latency probes sent while tracing and not answered yet, with the time they went out
+    probes: Mutex<VecDeque<(u32, std::time::Instant)>>,
+
+    /// Arrival of the latest ping in microseconds after `created`, pings come too often to go
+    /// through a lock or the app
+    last_ping: AtomicU64,
+    created: std::time::Instant,
+}
+
+impl LinkMeasurements {
+    fn on_ping(&self) {
+        self.last_ping
+            .store(self.created.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Time since the latest ping, or since joining before the first one
+    fn since_last_ping(&self) -> Duration {
+        let last_ping = Duration::from_micros(self.last_ping.load(Ordering::Relaxed));
+        self.created.elapsed().saturating_sub(last_ping)
+    }
+}
+
+// While tracing, a latency probe breaks down one round trip this often
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+// Probes not answered by the time this many newer ones went out count as lost
+const PENDING_PROBES_LEN: usize = 8;
+
+/// Bounds of the interpolation delay unless configured otherwise, see [`InterpolationDelay`]
+pub const DEFAULT_INTERP_DELAY: RangeInclusive<Duration> =
+    Duration::from_millis(30)..=Duration::from_millis(250);
+
+/// Fraction of packets dropped on purpose in both directions, as `f32` bits
+static SIMULATED_LOSS: AtomicU32 = AtomicU32::new(0);
+
+/// Connection to a server. The session logic only reaches the server through the
+/// [`Transport`] its tasks hold, never the platform socket itself.
 pub struct ClientSession {
-    listen_rx: ChannelReceiver,
-    send_tx: ChannelSender,
+    /// Everything that has to arrive, such as chat, kicks and shutdown notices. Handed to the
+    /// app before any state update.
+    control_rx: ChannelReceiver<Message>,
+    gameplay: GameplayQueue,
+
+    send_tx: ChannelSender<Message>,
     listen_task: JoinHandle<()>,
     send_task: JoinHandle<()>,
 
     /// The local player associated to the client
     session_player: Player,
 
-    /// Last ping time used for initiating timeout when server is available
-    last_ping: std::time::Instant,
+    /// Handed out in the ACK, `None` from older servers
+    token: Option<SessionToken>,
+
+    /// ACKs that arrived after joining and belong to another session, see
+    /// [`Self::late_ack`]
+    foreign_acks: u32,
+
+    world_mode: WorldMode,
+    map: TerrainMap,
+
+    /// What the server plays by, prediction and clamping have to do the same
+    rules: GameRules,
+
+    /// Latest world clock from the server and when it arrived
+    world_clock: Option<(f32, std::time::Instant)>,
+
+    /// Simulation rate the server currently runs at in Hz
+    server_tick_rate: u32,
+
+    /// Speed of simulation time set by the server, 0 while paused
+    time_scale: f32,
+
+    /// Message of the day until the app takes it to show it. Handshake retries get it sent
+    /// again, only the first one counts.
+    motd: Option<String>,
+    motd_received: bool,
+
+    message_stats: SharedMessageStats,
+
+    link: Arc<LinkMeasurements>,
+}
+
+/// What the server told the client, for the app to act on. See [`ClientSession::poll_event`].
+#[derive(Clone, Debug)]
+pub enum ClientEvent {
+    /// Latest replicated state of a remote player, the first one means they joined
+    PlayerUpdated(Player),
+    PlayerLeft(PlayerId),
+
+    /// A player dashed from the given position in the given facing
+    Dashed(PlayerId, Vector2<f32>, f32),
+
+    /// A player was knocked back with the given impulse, the local player included
+    KnockedBack(PlayerId, Vector2<f32>),
+
+    /// Server's position of the local player after it refused part of a move
+    Corrected(Vector2<f32>),
+
+    /// Player's distance traveled and seconds played so far
+    Stats(PlayerId, f32, u32),
+    Named(PlayerId, String),
+    KeyAnnounced(PlayerId, PublicKey),
+    Chat(PlayerId, String),
+
+    /// Private chat line from the given player
+    Whisper(PlayerId, String),
+    WhisperFailed(WhisperError, String),
+    Notice(NoticeLevel, String),
+    TickRateChanged(u32),
+
+    /// Speed of simulation time, 0 while paused
+    TimeScaleChanged(f32),
+
+    /// Removed by the server, with a reason code such as [`message::KICK_IDLE`]
+    Kicked(Option<String>),
+    ServerShutdown,
+
+    /// The server lost the session and asks the client to join again
+    ResyncRequested,
+}
+
+/// How this client joins servers
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Identity sent with the handshake, kept across reconnects
+    pub client_id: ClientId,
+
+    /// Key pair the handshake proves the player's public key with
+    pub identity: Identity,
+
+    /// Relay to fall back to when the server can't be reached directly
+    pub relay: Option<String>,
+
+    /// Replay every predicted step and panic if it comes out different
+    pub check_determinism: bool,
+
+    /// Handshake code of a private server
+    pub invite_code: Option<String>,
+
+    /// Join a server hosted by this process in memory instead of over the loopback socket
+    pub in_process_host: bool,
+
+    /// Bounds the delay remote players are drawn behind the replication is tuned within
+    pub interp_delay: RangeInclusive<Duration>,
+}
+
+/// Joining failed because the server never answered, as opposed to turning the client away
+#[derive(Debug)]
+pub struct ConnectionTimeout;
+
+impl std::fmt::Display for ConnectionTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Connection timeout after {:?} seconds",
+            globals::CONNECTION_TIMEOUT_SEC
+        )
+    }
+}
+
+impl Error for ConnectionTimeout {}
+
+/// Joining failed because none of the handshakes was answered, see [`HANDSHAKE_ATTEMPTS`]
+#[derive(Debug)]
+pub struct HandshakeUnanswered;
+
+impl std::fmt::Display for HandshakeUnanswered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The server did not answer any of {HANDSHAKE_ATTEMPTS} handshakes"
+        )
+    }
+}
+
+impl Error for HandshakeUnanswered {}
+
+// Handshakes are sent again after a wait doubling from the first to the longest one, with up to
+// half of it taken off at random. Clients rejoining a restarted server all at once spread out
+// instead of hammering it in lockstep. All attempts together fit into the connection timeout.
+const HANDSHAKE_ATTEMPTS: u32 = 5;
+const HANDSHAKE_FIRST_WAIT: Duration = Duration::from_millis(200);
+const HANDSHAKE_LONGEST_WAIT: Duration = Duration::from_millis(1600);
+
+// Status replies come right away or not at all
+const STATUS_QUERY_WAIT: Duration = Duration::from_millis(300);
+
+/// Identity of this installation, created on first use. Every client started from the same data
+/// directory shares it, so the server sees them as the same player.
+pub fn stored_identity() -> ClientId {
+    let path = paths::identity_file();
+
+    if let Some(client_id) = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| ClientId::parse(text.trim()))
+    {
+        return client_id;
+    }
+
+    let client_id = ClientId::random();
+    if let Err(e) = paths::ensure_dir(paths::data_dir().to_path_buf())
+        .and_then(|_| std::fs::write(&path, client_id.to_string()))
+    {
+        eprintln!("Failed to store client identity in {}: {e}", path.display());
+    }
+
+    client_id
+}
+
+/// Key pair of this installation, created on first use like [`stored_identity`]
+pub fn stored_key() -> Identity {
+    let path = paths::identity_key_file();
+
+    if let Some(identity) = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| Identity::from_secret_hex(text.trim()))
+    {
+        return identity;
+    }
+
+    let identity = Identity::generate();
+    if let Err(e) = paths::ensure_dir(paths::data_dir().to_path_buf())
+        .and_then(|_| std::fs::write(&path, identity.secret_hex()))
+    {
+        eprintln!("Failed to store identity key in {}: {e}", path.display());
+    }
+
+    identity
+}
+
+/// Drop the given fraction of packets sent and received by all sessions, to see how the game
+/// copes with a bad network
+pub fn set_simulated_loss(fraction: f32) {
+    SIMULATED_LOSS.store(fraction.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+}
+
+fn simulate_loss() -> bool {
+    let loss = f32::from_bits(SIMULATED_LOSS.load(Ordering::Relaxed));
+    loss > 0.0 && rand::random::<f32>() < loss
+}
+
+/// Console commands tuning the client network
+pub fn register_console_commands<C>(commands: &mut CommandRegistry<C>) {
+    commands.register(
+        "net_sim_loss",
+        &[Param::required("fraction", ParamKind::Number)],
+        "Drop this fraction of packets, e.g. 0.1, 0 turns it off",
+        Permission::Player,
+        |_, args| {
+            let fraction: f32 = args.value("fraction")?;
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(String::from("Fraction must be between 0 and 1"));
+            }
+            set_simulated_loss(fraction);
+
+            Ok(Some(format!(
+                "Simulating {:.0}% packet loss",
+                fraction * 100.0
+            )))
+        },
+    );
 }
 
 pub type ClientSessionResult = Result<ClientSession, Box<dyn Error + Send + Sync>>;
 
 impl ClientSession {
-    pub async fn new(server_address: String) -> ClientSessionResult {
-        match tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
-            // Init client socket
-            let client_socket = UdpSocket::bind("0.0.0.0").await?;
-            let client_socket = Arc::new(client_socket);
+    /// Join the server over UDP, through the relay if the server does not answer directly
+    pub async fn new(server_address: String, config: &ClientConfig) -> ClientSessionResult {
+        let transport = UdpTransport::connect(&server_address).await?;
+        let result = ClientSession::with_transport(transport, server_address.clone(), config).await;
+
+        match (&config.relay, result) {
+            // Strict NATs on either side can block the direct path, while both can reach the
+            // relay
+            (Some(relay), Err(e))
+                if e.is::<ConnectionTimeout>() || e.is::<HandshakeUnanswered>() =>
+            {
+                println!("No direct connection to {server_address}, trying relay {relay}");
+
+                let transport = UdpTransport::via_relay(relay, &server_address).await?;
 
+                // The address shows up in traces, so relayed traffic is easy to tell apart
+                ClientSession::with_transport(
+                    transport,
+                    format!("{server_address} via relay {relay}"),
+                    config,
+                )
+                .await
+            }
+            (_, result) => result,
+        }
+    }
+
+    /// Join a server hosted by this process over an in-memory link, see
+    /// [`crate::server::ServerHandle::connect_local`]
+    pub async fn local(link: LocalTransport, config: &ClientConfig) -> ClientSessionResult {
+        ClientSession::with_transport(link, String::from("local server"), config).await
+    }
+
+    /// Join the server over an already connected transport. `server_address` is only used for
+    /// tracing and statistics.
+    pub async fn with_transport<T: Transport>(
+        transport: T,
+        server_address: String,
+        config: &ClientConfig,
+    ) -> ClientSessionResult {
+        match tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
+            let transport = Arc::new(transport);
+            let message_stats = SharedMessageStats::default();
             // Join server
-            let session_player = join_server(&client_socket, &server_address).await?;
+            let JoinInfo {
+                player: session_player,
+                world_mode,
+                map,
+                rules,
+                token,
+                motd,
+                codec,
+            } = join_server(
+                transport.as_ref(),
+                &server_address,
+                Message::Handshake(
+                    Some(config.client_id),
+                    config.invite_code.clone(),
+                    Some(Box::new(config.identity.prove(config.client_id))),
+                    transport.carries_binary().then_some(Codec::Binary),
+                ),
+                &message_stats,
+            )
+            .await?;
+
+            let differences = rules.differences(&GameRules::default());
+            if !differences.is_empty() {
+                println!("Server plays by other rules: {}", differences.join(", "));
+            }
+
+            let link = Arc::new(LinkMeasurements {
+                quality: Mutex::new(ConnectionQuality::new(rules.ping_interval)),
+                interpolation: Mutex::new(InterpolationDelay::new(
+                    config.interp_delay.clone(),
+                    rules.tick_rate,
+                )),
+                probes: Mutex::new(VecDeque::new()),
+                last_ping: AtomicU64::new(0),
+                created: std::time::Instant::now(),
+            });
 
             // Message handlers
-            let (listen_tx, listen_rx) = mpsc::unbounded_channel();
+            let (control_tx, control_rx) = mpsc::unbounded_channel();
+            let gameplay = Arc::new(Mutex::new(Outbox::new(GAMEPLAY_QUEUE_LEN)));
             let (send_tx, send_rx) = mpsc::unbounded_channel();
 
-            let listen_task = tokio::spawn(listen_handler(client_socket.clone(), listen_tx));
+            let listen_task = tokio::spawn(listen_handler(
+                transport.clone(),
+                server_address.clone(),
+                control_tx,
+                gameplay.clone(),
+                message_stats.clone(),
+                link.clone(),
+                codec,
+            ));
 
-            let send_task =
-                tokio::spawn(send_handler(client_socket.clone(), server_address, send_rx));
+            let send_task = tokio::spawn(send_handler(
+                transport.clone(),
+                server_address,
+                send_rx,
+                token,
+                message_stats.clone(),
+                link.clone(),
+                codec,
+            ));
 
             println!("Connected to server");
             Ok(Self {
-                listen_rx,
+                control_rx,
+                gameplay,
                 send_tx,
                 listen_task,
                 send_task,
                 session_player,
-                last_ping: std::time::Instant::now(),
+                token,
+                foreign_acks: 0,
+                world_mode,
+                map,
+                rules,
+                world_clock: None,
+                server_tick_rate: rules.tick_rate,
+                time_scale: 1.0,
+                motd_received: motd.is_some(),
+                motd,
+                message_stats,
+                link,
             })
         })
         .await
         {
-            Ok(client_session) => {
-                return client_session;
-            }
-            Err(_) => {
-                return Err(format!(
-                    "Connection timeout after {:?} seconds",
-                    globals::CONNECTION_TIMEOUT_SEC
-                )
-                .into());
-            }
+            Ok(client_session) => client_session,
+            Err(_) => Err(ConnectionTimeout.into()),
         }
     }
 
@@ -75,33 +459,213 @@ impl ClientSession {
         self.session_player
     }
 
-    pub fn receive_server_response(&mut self) -> Result<String, TryRecvError> {
-        match self.listen_rx.try_recv() {
-            Ok(response) => {
-                if let Ok(Message::Ping) = Message::deserialize(&response) {
-                    self.last_ping = std::time::Instant::now();
+    /// Next thing the server told the client, state updates only once nothing else is waiting.
+    /// `None` once everything received so far was handled.
+    pub fn poll_event(&mut self) -> Option<ClientEvent> {
+        loop {
+            let msg = match self.control_rx.try_recv() {
+                Ok(msg) => msg,
+                Err(_) => self.gameplay.lock().unwrap().pop()?,
+            };
+
+            if let Some(event) = self.handle_message(msg) {
+                return Some(event);
+            }
+        }
+    }
+
+    // Keep what the session tracks itself, the rest becomes an event for the app
+    fn handle_message(&mut self, msg: Message) -> Option<ClientEvent> {
+        match msg {
+            Message::TickRateChange(hz) => {
+                self.server_tick_rate = hz;
+                return Some(ClientEvent::TickRateChanged(hz));
+            }
+            Message::TimeScale(scale) => {
+                // Restart the extrapolation from where the old speed got it
+                if self.world_clock.is_some() {
+                    self.world_clock = Some((self.time_of_day(), std::time::Instant::now()));
                 }
+                self.time_scale = scale;
+                return Some(ClientEvent::TimeScaleChanged(scale));
+            }
+            Message::Map(map) => self.map = map,
+            Message::Rules(rules) => self.rules = rules,
+            Message::Motd(motd) => {
+                if !self.motd_received {
+                    self.motd = Some(motd);
+                    self.motd_received = true;
+                }
+            }
+            Message::WorldClock(time_of_day) => {
+                self.world_clock = Some((time_of_day, std::time::Instant::now()))
+            }
+            // Once joined, the only ACKs still coming are answers to handshake retries
+            Message::Ack(player_id, _, _, token, _) => self.late_ack(player_id, token),
 
-                Ok(response)
+            Message::Replicate(player) => return Some(ClientEvent::PlayerUpdated(player)),
+            Message::Leave(player_id) => return Some(ClientEvent::PlayerLeft(player_id)),
+            Message::Dash(player_id, from, facing) => {
+                return Some(ClientEvent::Dashed(player_id, from, facing))
+            }
+            Message::Knockback(player_id, impulse) => {
+                return Some(ClientEvent::KnockedBack(player_id, impulse))
+            }
+            Message::Correction(pos) => return Some(ClientEvent::Corrected(pos)),
+            Message::Stats(player_id, distance, seconds) => {
+                return Some(ClientEvent::Stats(player_id, distance, seconds))
+            }
+            Message::Name(player_id, name) => return Some(ClientEvent::Named(player_id, name)),
+            Message::PlayerKey(player_id, key) => {
+                return Some(ClientEvent::KeyAnnounced(player_id, key))
+            }
+            Message::Chat(player_id, text) => return Some(ClientEvent::Chat(player_id, text)),
+            Message::WhisperFrom(player_id, text) => {
+                return Some(ClientEvent::Whisper(player_id, text))
+            }
+            Message::WhisperFailed(error, name) => {
+                return Some(ClientEvent::WhisperFailed(error, name))
             }
-            Err(e) => Err(e),
+            Message::ServerNotice(level, text) => return Some(ClientEvent::Notice(level, text)),
+            Message::Kick(reason) => return Some(ClientEvent::Kicked(reason)),
+            Message::ServerShutdown => return Some(ClientEvent::ServerShutdown),
+            Message::Resync => return Some(ClientEvent::ResyncRequested),
+
+            // Sent by clients, or only answered while joining or querying a status
+            Message::Handshake(..)
+            | Message::Pong(..)
+            | Message::Position(..)
+            | Message::KeepAlive(_)
+            | Message::Whisper(..)
+            | Message::Ability(_)
+            | Message::StatusQuery
+            | Message::Status(..)
+            | Message::Probe(_) => (),
+
+            // Handled by the listen task as soon as they arrive
+            Message::Ping(_) | Message::ProbeReply(..) => (),
+        }
+
+        None
+    }
+
+    // The server answers every handshake retry, the ones answered after joining show up here.
+    // Anything but the joined session's player and token means a confused server or a stray
+    // packet, noted and ignored all the same.
+    fn late_ack(&mut self, player_id: PlayerId, token: Option<SessionToken>) {
+        if player_id == self.session_player.id && token == self.token {
+            message::trace(format!("Duplicate ACK for player {player_id} ignored"));
+            return;
         }
+
+        self.foreign_acks += 1;
+        eprintln!(
+            "Ignored ACK for player {player_id}, this session joined as player {}",
+            self.session_player.id
+        );
     }
 
-    pub fn send_pos(&self, player: &Player) {
+    /// Report the position reached at simulation `step`, counted in fixed update steps
+    pub fn send_pos(&self, player: &Player, step: u32) {
         // TODO: avoid position self-reporting
         let _ = self
             .send_tx
-            .send(Message::Position(player.id, player.pos).serialize());
+            .send(Message::Position(player.id, player.pos, Some(step)));
+    }
+
+    pub fn send_name(&self, player_id: PlayerId, name: &str) {
+        let _ = self
+            .send_tx
+            .send(Message::Name(player_id, name.to_string()));
+    }
+
+    pub fn send_ability(&self, ability: Ability) {
+        let _ = self.send_tx.send(Message::Ability(ability));
+    }
+
+    pub fn send_chat(&self, player_id: PlayerId, text: &str) {
+        let _ = self
+            .send_tx
+            .send(Message::Chat(player_id, text.to_string()));
+    }
+
+    /// Private line for the player called `target`, the server reports back if there is none
+    pub fn send_whisper(&self, target: &str, text: &str) {
+        let _ = self
+            .send_tx
+            .send(Message::Whisper(target.to_string(), text.to_string()));
+    }
+
+    /// Pings missed since the last one, the server counts as gone after as many as its rules
+    /// allow
+    pub fn liveness(&self) -> Liveness {
+        Liveness::after(self.link.since_last_ping(), &self.rules)
     }
 
     pub fn is_server_alive(&self) -> bool {
-        // No need for separate timeout countdown timer
-        self.last_ping.elapsed() < globals::CONNECTION_TIMEOUT_SEC
+        self.liveness().is_alive()
+    }
+
+    /// How the server treats the world edges, so prediction matches the server simulation
+    pub fn world_mode(&self) -> WorldMode {
+        self.world_mode
+    }
+
+    /// Terrain of the joined server, for client prediction and rendering
+    pub fn map(&self) -> &TerrainMap {
+        &self.map
+    }
+
+    /// What the joined server plays by, for prediction, clamping and rendering
+    pub fn rules(&self) -> GameRules {
+        self.rules
+    }
+
+    /// Server's time of day between 0 and 1, run forward from the last update. Noon until the
+    /// first update arrives.
+    pub fn time_of_day(&self) -> f32 {
+        match self.world_clock {
+            Some((time_of_day, received_at)) => {
+                let elapsed = received_at.elapsed().as_secs_f32() * self.time_scale;
+
+                (time_of_day + elapsed / globals::DAY_CYCLE_SEC).fract()
+            }
+            None => 0.5,
+        }
+    }
+
+    /// Rate at which replication arrives, for interpolating remote players
+    pub fn server_tick_rate(&self) -> u32 {
+        self.server_tick_rate
+    }
+
+    /// Speed the server runs simulation time at, 0 while paused
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Message of the day to show, once
+    pub fn take_motd(&mut self) -> Option<String> {
+        self.motd.take()
+    }
+
+    /// Copy of the traffic counters per message type
+    pub fn message_stats(&self) -> MessageStats {
+        self.message_stats.lock().unwrap().clone()
+    }
+
+    /// Packet loss and jitter of the pings from the server, rated in bars
+    pub fn connection_quality(&self) -> QualityReport {
+        self.link.quality.lock().unwrap().report(self.liveness())
+    }
+
+    /// Delay remote players are drawn behind the replication, and how it got there
+    pub fn interpolation(&self) -> InterpolationReport {
+        self.link.interpolation.lock().unwrap().report()
     }
 
     pub fn leave_server(&self, player_id: PlayerId) {
-        let _ = self.send_tx.send(Message::Leave(player_id).serialize());
+        let _ = self.send_tx.send(Message::Leave(player_id));
     }
 }
 
@@ -109,7 +673,6 @@ impl Drop for ClientSession {
     fn drop(&mut self) {
         self.listen_task.abort();
         self.send_task.abort();
-        self.listen_task.abort();
     }
 }
 
@@ -117,83 +680,667 @@ impl Drop for ClientSession {
 
 // Utility functions
 
-/// Join UDP server
+/// What the server tells a client when it joins
+struct JoinInfo {
+    player: Player,
+    world_mode: WorldMode,
+    map: TerrainMap,
+    rules: GameRules,
+
+    /// Older servers don't hand out a session token
+    token: Option<SessionToken>,
+
+    motd: Option<String>,
+
+    /// What the ACK came in, the server speaks it from then on and expects it back
+    codec: Codec,
+}
+
+/// Join UDP server. Joining is complete once the ACK, the game rules and the MAP arrived,
+/// if any of them gets lost the handshake is sent again.
 async fn join_server(
-    client_socket: &UdpSocket,
+    transport: &impl Transport,
     server_address: &String,
-) -> Result<Player, Box<dyn Error + Send + Sync>> {
-    let handshake_msg = Message::Handshake.serialize();
+    handshake_msg: Message,
+    message_stats: &SharedMessageStats,
+) -> Result<JoinInfo, Box<dyn Error + Send + Sync>> {
+    for attempt in 0..HANDSHAKE_ATTEMPTS {
+        let wait = handshake_wait(attempt, rand::random());
+        let deadline = tokio::time::Instant::now() + wait;
 
-    loop {
-        client_socket
-            .send_to(handshake_msg.as_bytes(), server_address)
-            .await?;
+        // A refused datagram means nobody listens on the port yet, the server may still be
+        // starting. Back off like for an unanswered attempt instead of hammering it.
+        let len = match transport.send(handshake_msg.serialize().as_bytes()).await {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                tokio::time::sleep_until(deadline).await;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        message::record_msg(
+            message_stats,
+            Direction::Sent,
+            server_address,
+            &handshake_msg,
+            len,
+        );
 
-        message::trace(format!("Sent: {handshake_msg}"));
+        let mut ack = None;
+        let mut map = None;
+        let mut rules = None;
+        let mut motd = None;
 
-        // Wait for ACK
-        match receive_with_retry_timeout(client_socket).await {
-            Ok(response) => {
-                if let Ok(Message::Ack(new_id, new_color)) = Message::deserialize(&response) {
-                    message::trace(format!("Handshake result: {response}"));
+        // Wait for ACK, rules and MAP, the MOTD comes in between if the server has one. A late one
+        // arrives through the listen task instead.
+        loop {
+            let response = match receive_with_retry_timeout(transport, wait).await {
+                Ok(response) => response,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    tokio::time::sleep_until(deadline).await;
+                    break;
+                }
+                Err(_) => break,
+            };
 
-                    return Ok(Player::new(new_id, new_color));
+            let codec = Codec::of(&response);
+            let msg = match codec.decode(&response) {
+                Ok(
+                    msg @ (Message::Ack(..)
+                    | Message::Map(_)
+                    | Message::Rules(_)
+                    | Message::Motd(_)
+                    | Message::Kick(_)),
+                ) => msg,
+                _ => {
+                    message::trace(format!(
+                        "Invalid handshake response: {}",
+                        String::from_utf8_lossy(&response)
+                    ));
+                    continue;
                 }
+            };
+
+            message::record_msg(
+                message_stats,
+                Direction::Received,
+                server_address,
+                &msg,
+                response.len(),
+            );
 
-                message::trace(format!("Invalid handshake response: {response}"));
+            match msg {
+                Message::Ack(_, _, _, _, Some(version)) if !version.is_compatible() => {
+                    return Err(format!(
+                        "The server runs version {version}, this client {}",
+                        Version::current()
+                    )
+                    .into())
+                }
+                // Answers to earlier retries of the same handshake, the first one counts
+                Message::Ack(new_id, new_color, world_mode, token, _) if ack.is_none() => {
+                    ack = Some((Player::new(new_id, new_color), world_mode, token, codec))
+                }
+                Message::Map(new_map) => map = Some(new_map),
+                Message::Rules(new_rules) => rules = Some(new_rules),
+                Message::Motd(text) => motd = Some(text),
+                Message::Kick(Some(reason)) if reason == message::KICK_INVITE => {
+                    return Err("The server is private, ask the host for the invite code".into())
+                }
+                Message::Kick(Some(reason)) if reason == message::KICK_FULL => {
+                    return Err("The server is full, try again later".into())
+                }
+                Message::Kick(_) => {
+                    return Err(
+                        "The server refused the connection, this client is already playing \
+                                there"
+                            .into(),
+                    )
+                }
+                _ => (),
             }
 
-            Err(_) => continue,
+            if let (Some((player, world_mode, token, codec)), Some(rules)) = (ack, rules) {
+                if let Some(map) = map.take() {
+                    return Ok(JoinInfo {
+                        player,
+                        world_mode,
+                        map,
+                        rules,
+                        token,
+                        motd,
+                        codec,
+                    });
+                }
+            }
         }
     }
+
+    Err(HandshakeUnanswered.into())
+}
+
+/// How long to wait for an answer to the handshake of `attempt`, counted from 0. `jitter`
+/// between 0 and 1 takes up to half of it off.
+fn handshake_wait(attempt: u32, jitter: f64) -> Duration {
+    let wait = HANDSHAKE_FIRST_WAIT
+        .saturating_mul(1 << attempt.min(16))
+        .min(HANDSHAKE_LONGEST_WAIT);
+
+    wait - wait.mul_f64(jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// What a server tells about itself without being joined
+#[derive(Clone, Debug)]
+pub struct ServerStatus {
+    pub players: u32,
+
+    /// Public key of the player hosting it, `None` for dedicated servers
+    pub host: Option<PublicKey>,
+
+    /// `None` for servers older than versioned status replies
+    pub version: Option<Version>,
+    pub rtt: std::time::Duration,
+}
+
+// Queries are cheap, a few lost ones shouldn't make a server look offline
+const STATUS_QUERY_ATTEMPTS: usize = 3;
+
+/// Ask a server for its [`ServerStatus`]
+pub async fn query_status(
+    server_address: &str,
+) -> Result<ServerStatus, Box<dyn Error + Send + Sync>> {
+    let transport = UdpTransport::connect(server_address).await?;
+
+    for _ in 0..STATUS_QUERY_ATTEMPTS {
+        let sent_at = std::time::Instant::now();
+        transport
+            .send(Message::StatusQuery.serialize().as_bytes())
+            .await?;
+
+        while let Ok(response) = receive_with_retry_timeout(&transport, STATUS_QUERY_WAIT).await {
+            if let Ok(Message::Status(players, host, version)) =
+                Codec::of(&response).decode(&response)
+            {
+                return Ok(ServerStatus {
+                    players,
+                    host,
+                    version,
+                    rtt: sent_at.elapsed(),
+                });
+            }
+        }
+    }
+
+    Err("Server did not answer the status query".into())
 }
 
 /// Receive message
 async fn receive_with_retry_timeout(
-    socket: &UdpSocket,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let retry_timeout = std::time::Duration::from_millis(300);
-
-    let mut buf = [0u8; 32];
+    transport: &impl Transport,
+    retry_timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let mut buf = [0u8; globals::MAX_CLIENT_DATAGRAM_LEN];
 
     // Consider non-blocking UDP I/O - Using try_revc_from
-    match tokio::time::timeout(retry_timeout, socket.recv_from(&mut buf)).await {
+    match tokio::time::timeout(retry_timeout, transport.recv(&mut buf)).await {
         Ok(result) => {
-            let (len, _) = result?;
-            Ok(String::from_utf8_lossy(&buf[..len]).to_string())
+            let len = result?;
+            Ok(buf[..len].to_vec())
         }
 
         Err(_) => {
             message::trace("No response (sender or reciever package lost)".to_string());
-            Err("Receive operation time out".into())
+            Err(io::ErrorKind::TimedOut.into())
         }
     }
 }
 
-/// Listen handler
-async fn listen_handler(socket: Arc<UdpSocket>, listen_tx: ChannelSender) {
-    let mut buf = [0u8; 1024];
+/// Listen handler. State updates go to the gameplay queue, replacing older ones of the same
+/// player, everything else to the control channel. Pongs go back in `codec`.
+async fn listen_handler<T: Transport>(
+    transport: Arc<T>,
+    server: String,
+    control_tx: ChannelSender<Message>,
+    gameplay: GameplayQueue,
+    message_stats: SharedMessageStats,
+    link: Arc<LinkMeasurements>,
+    codec: Codec,
+) {
+    let mut buf = [0u8; globals::MAX_CLIENT_DATAGRAM_LEN];
 
     loop {
-        match socket.recv_from(&mut buf).await {
-            Ok((len, _)) => {
-                if let Ok(msg) = std::str::from_utf8(&buf[..len]) {
-                    if listen_tx.send(msg.to_string()).is_err() {
-                        break;
-                    }
+        let len = match transport.recv(&mut buf).await {
+            Ok(len) => len,
+
+            // The connected UDP socket reports an ICMP port unreachable for an earlier datagram
+            // here, the server may just be restarting. Only a closed transport ends the session.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+            Err(_) => break,
+        };
+
+        if simulate_loss() {
+            continue;
+        }
+
+        let datagram = &buf[..len];
+        let deserialized = match Codec::of(datagram).decode(datagram) {
+            Ok(deserialized) => deserialized,
+            Err(e) => {
+                message::trace(format!(
+                    "<- {server} invalid message ({e}): {}",
+                    String::from_utf8_lossy(datagram)
+                ));
+                continue;
+            }
+        };
+
+        message::record_msg(
+            &message_stats,
+            Direction::Received,
+            &server,
+            &deserialized,
+            len,
+        );
+
+        // Answer pings right away instead of waiting for the next frame, so the server measures
+        // network round trip rather than client frame time. They only keep the connection
+        // alive, so the app never sees them.
+        if let Message::Ping(seq) = deserialized {
+            link.on_ping();
+            link.quality
+                .lock()
+                .unwrap()
+                .on_ping(seq, std::time::Instant::now());
+
+            // The server rewinds the world this far back when checking what the player hit
+            let interp_delay = link.interpolation.lock().unwrap().report().delay;
+            let pong = Message::Pong(seq, Some(interp_delay));
+            if let Ok(len) = transport.send(&codec.encode(&pong)).await {
+                message::record_msg(&message_stats, Direction::Sent, &server, &pong, len);
+            }
+            continue;
+        }
+
+        // Only clients send these, a stray one has nothing to update
+        if let Message::Pong(..) = deserialized {
+            continue;
+        }
+
+        if let Message::ProbeReply(seq, queued, tick_wait) = deserialized {
+            let sent_at = {
+                let mut probes = link.probes.lock().unwrap();
+                let index = probes.iter().position(|(sent_seq, _)| *sent_seq == seq);
+                index.and_then(|index| probes.remove(index))
+            };
+            if let Some((_, sent_at)) = sent_at {
+                trace_round_trip(seq, sent_at.elapsed(), queued, tick_wait);
+            }
+            continue;
+        }
+
+        // Arrival times go straight from the socket, the app only sees snapshots once per frame
+        match &deserialized {
+            Message::Replicate(player) => link
+                .interpolation
+                .lock()
+                .unwrap()
+                .on_snapshot(player.id, std::time::Instant::now()),
+            Message::TickRateChange(hz) => link.interpolation.lock().unwrap().set_tick_rate(*hz),
+            Message::Leave(player_id) => link.interpolation.lock().unwrap().forget(*player_id),
+            _ => (),
+        }
+
+        // Updates of a player who left would bring them back
+        if let Message::Leave(player_id) = deserialized {
+            gameplay
+                .lock()
+                .unwrap()
+                .retain_keyed(|(_, id)| *id != player_id);
+        }
+
+        match deserialized.superseded_by() {
+            Some(key) => gameplay.lock().unwrap().push(Some(key), deserialized),
+            None => {
+                if control_tx.send(deserialized).is_err() {
+                    break;
                 }
             }
-            Err(_) => {
-                break;
+        }
+    }
+}
+
+/// Break a latency probe's round trip down into the time it spent on the server and the rest,
+/// which is the network both ways plus the send queues on either end
+fn trace_round_trip(seq: u32, round_trip: Duration, queued: Duration, tick_wait: Duration) {
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let elsewhere = round_trip.saturating_sub(queued + tick_wait);
+
+    message::trace(format!(
+        "Probe {seq}: {:.1} ms round trip = {:.1} ms network and send queues + {:.1} ms server \
+         receive queue + {:.1} ms waiting for the server tick",
+        ms(round_trip),
+        ms(elsewhere),
+        ms(queued),
+        ms(tick_wait)
+    ));
+}
+
+/// Send handler. Also sends a KEEPALIVE with the session token every
+/// [`globals::KEEP_ALIVE_INTERVAL`], so the NAT mapping stays open while the player idles and the
+/// server finds the client again if the mapping changes anyway. While tracing, a PROBE goes out
+/// every [`PROBE_INTERVAL`] to see where the lag of a round trip comes from.
+async fn send_handler<T: Transport>(
+    transport: Arc<T>,
+    server_address: String,
+    mut rx: ChannelReceiver<Message>,
+    token: Option<SessionToken>,
+    message_stats: SharedMessageStats,
+    link: Arc<LinkMeasurements>,
+    codec: Codec,
+) {
+    let mut keep_alive = tokio::time::interval(globals::KEEP_ALIVE_INTERVAL);
+    keep_alive.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut probe = tokio::time::interval(PROBE_INTERVAL);
+    probe.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut probe_seq = 0u32;
+
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = keep_alive.tick(), if token.is_some() => {
+                Message::KeepAlive(token.unwrap_or_default())
+            }
+            _ = probe.tick(), if message::trace_enabled() => {
+                probe_seq = probe_seq.wrapping_add(1);
+
+                let mut probes = link.probes.lock().unwrap();
+                if probes.len() == PENDING_PROBES_LEN {
+                    probes.pop_front();
+                }
+                probes.push_back((probe_seq, std::time::Instant::now()));
+
+                Message::Probe(probe_seq)
             }
+        };
+
+        if simulate_loss() {
+            continue;
+        }
+
+        if let Ok(len) = transport.send(&codec.encode(&msg)).await {
+            message::record_msg(&message_stats, Direction::Sent, &server_address, &msg, len);
         }
     }
 }
 
-/// Send handler
-async fn send_handler(socket: Arc<UdpSocket>, server_address: String, mut rx: ChannelReceiver) {
-    while let Some(msg) = rx.recv().await {
-        let _ = socket.send_to(&msg.as_bytes(), &server_address).await;
-        message::trace(format!("Sent: {msg}"));
+#[cfg(test)]
+mod tests {
+    use cgmath::vec3;
+
+    use super::*;
+
+    /// Server end of an in-memory link, sending only what the test tells it to
+    struct FakeServer {
+        from_client: mpsc::Receiver<Vec<u8>>,
+        to_client: mpsc::Sender<Vec<u8>>,
+        refusals: Arc<AtomicU32>,
+    }
+
+    impl FakeServer {
+        async fn send(&self, msg: Message) {
+            self.to_client
+                .send(msg.serialize().into_bytes())
+                .await
+                .unwrap();
+        }
+
+        /// Fail the client's next `count` socket calls as refused, like a connected UDP socket
+        /// does after one of its datagrams hit the port of a server that is restarting
+        fn refuse(&self, count: u32) {
+            self.refusals.store(count, Ordering::Relaxed);
+        }
+    }
+
+    /// Client end of the link to a [`FakeServer`]
+    struct FakeLink {
+        link: LocalTransport,
+        refusals: Arc<AtomicU32>,
+    }
+
+    impl FakeLink {
+        fn refused(&self) -> io::Result<()> {
+            match self
+                .refusals
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            {
+                Ok(_) => Err(io::ErrorKind::ConnectionRefused.into()),
+                Err(_) => Ok(()),
+            }
+        }
+    }
+
+    impl Transport for FakeLink {
+        async fn send(&self, data: &[u8]) -> io::Result<usize> {
+            self.refused()?;
+            self.link.send(data).await
+        }
+
+        async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            self.refused()?;
+            self.link.recv(buf).await
+        }
+    }
+
+    fn ack(player_id: PlayerId, token: SessionToken) -> Message {
+        Message::Ack(
+            player_id,
+            vec3(1.0, 0.0, 0.0),
+            WorldMode::default(),
+            Some(token),
+            Some(Version::current()),
+        )
+    }
+
+    /// Session joined as player 3 with token 7, the fake server answering the handshake with
+    /// `acks` ACKs before the map
+    async fn joined(acks: usize) -> (ClientSession, FakeServer) {
+        joined_after_refusals(0, acks).await
+    }
+
+    /// Like [`joined`], with the first `refusals` socket calls of the client refused
+    async fn joined_after_refusals(refusals: u32, acks: usize) -> (ClientSession, FakeServer) {
+        let (to_server, from_client) = mpsc::channel(64);
+        let (to_client, from_server) = mpsc::channel(64);
+        let refusals = Arc::new(AtomicU32::new(refusals));
+        let mut server = FakeServer {
+            from_client,
+            to_client,
+            refusals: refusals.clone(),
+        };
+
+        let config = ClientConfig {
+            client_id: ClientId::random(),
+            identity: Identity::generate(),
+            relay: None,
+            check_determinism: false,
+            invite_code: None,
+            in_process_host: true,
+            interp_delay: DEFAULT_INTERP_DELAY,
+        };
+        let link = FakeLink {
+            link: LocalTransport::new(to_server, from_server),
+            refusals,
+        };
+        let join = tokio::spawn(async move {
+            ClientSession::with_transport(link, String::from("fake server"), &config).await
+        });
+
+        // Offers binary, the fake server answers in text like servers from before it
+        let handshake = server.from_client.recv().await.unwrap();
+        assert!(matches!(
+            Message::deserialize(&String::from_utf8(handshake).unwrap()),
+            Ok(Message::Handshake(.., Some(Codec::Binary)))
+        ));
+        for _ in 0..acks {
+            server.send(ack(3, 7)).await;
+        }
+        server.send(Message::Rules(GameRules::default())).await;
+        server.send(Message::Map(TerrainMap::builtin())).await;
+
+        (join.await.unwrap().unwrap(), server)
+    }
+
+    /// Everything the app gets to see up to the next chat line
+    async fn events_until_chat(session: &mut ClientSession) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+
+        while !matches!(events.last(), Some(ClientEvent::Chat(..))) {
+            match session.poll_event() {
+                Some(event) => events.push(event),
+                None => tokio::time::sleep(Duration::from_millis(5)).await,
+            }
+        }
+
+        events
+    }
+
+    #[tokio::test]
+    async fn duplicate_acks_of_handshake_retries_join_once() {
+        let (session, _server) = joined(3).await;
+
+        assert_eq!(session.get_session_player_data().id, 3);
+        assert_eq!(session.token, Some(7));
+    }
+
+    #[tokio::test]
+    async fn late_duplicate_ack_is_dropped() {
+        let (mut session, server) = joined(1).await;
+        server.send(ack(3, 7)).await;
+        server.send(Message::Chat(5, String::from("hi"))).await;
+
+        let events = events_until_chat(&mut session).await;
+        assert!(
+            matches!(&events[..], [ClientEvent::Chat(5, _)]),
+            "{events:?}"
+        );
+        assert_eq!(session.foreign_acks, 0);
+    }
+
+    #[tokio::test]
+    async fn control_messages_overtake_coalesced_state_updates() {
+        let (mut session, server) = joined(1).await;
+
+        let mut player = Player::new(5, vec3(0.0, 1.0, 0.0));
+        for x in [1.0, 2.0, 3.0] {
+            player.pos.x = x;
+            server.send(Message::Replicate(player)).await;
+        }
+        server
+            .send(Message::Replicate(Player::new(6, vec3(0.0, 0.0, 1.0))))
+            .await;
+        server.send(Message::Leave(6)).await;
+        server.send(Message::Chat(5, String::from("hi"))).await;
+
+        // Wait for the listen task to get through all of it
+        while session.gameplay.lock().unwrap().len() != 1 || session.control_rx.len() != 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let events: Vec<ClientEvent> = std::iter::from_fn(|| session.poll_event()).collect();
+        assert!(
+            matches!(
+                &events[..],
+                [ClientEvent::PlayerLeft(6), ClientEvent::Chat(5, _), ClientEvent::PlayerUpdated(latest)]
+                    if latest.pos.x == 3.0
+            ),
+            "{events:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered_without_reaching_the_app() {
+        let (mut session, mut server) = joined(1).await;
+        server.send(Message::Ping(1)).await;
+        server.send(Message::Ping(2)).await;
+        server.send(Message::Chat(5, String::from("hi"))).await;
+
+        let mut pongs = Vec::new();
+        while pongs.len() < 2 {
+            let datagram = server.from_client.recv().await.unwrap();
+            if let Ok(Message::Pong(seq, _)) =
+                Message::deserialize(&String::from_utf8(datagram).unwrap())
+            {
+                pongs.push(seq);
+            }
+        }
+        assert_eq!(pongs, [1, 2]);
+        assert!(session.is_server_alive());
+
+        // Only the chat line is queued for the app
+        while session.control_rx.is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(session.control_rx.len(), 1);
+        assert!(matches!(
+            session.poll_event(),
+            Some(ClientEvent::Chat(5, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn refused_handshake_backs_off_and_retries() {
+        let started = std::time::Instant::now();
+        let (session, _server) = joined_after_refusals(2, 1).await;
+
+        assert_eq!(session.get_session_player_data().id, 3);
+        assert!(started.elapsed() >= handshake_wait(0, 1.0) + handshake_wait(1, 1.0));
+    }
+
+    #[tokio::test]
+    async fn refused_datagrams_do_not_end_the_session() {
+        let (mut session, server) = joined(1).await;
+        server.refuse(3);
+        server.send(Message::Chat(5, String::from("hi"))).await;
+
+        let events = tokio::time::timeout(Duration::from_secs(1), events_until_chat(&mut session))
+            .await
+            .expect("the listen task stopped");
+        assert!(
+            matches!(&events[..], [ClientEvent::Chat(5, _)]),
+            "{events:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn ack_of_another_session_is_dropped_and_counted() {
+        let (mut session, server) = joined(1).await;
+        server.send(ack(4, 7)).await;
+        server.send(ack(3, 9)).await;
+        server.send(Message::Chat(5, String::from("hi"))).await;
+
+        let events = events_until_chat(&mut session).await;
+        assert!(
+            matches!(&events[..], [ClientEvent::Chat(5, _)]),
+            "{events:?}"
+        );
+        assert_eq!(session.foreign_acks, 2);
+        assert_eq!(session.get_session_player_data().id, 3);
+    }
+
+    #[test]
+    fn handshake_waits_back_off_within_the_connection_timeout() {
+        assert_eq!(handshake_wait(0, 0.0), HANDSHAKE_FIRST_WAIT);
+        assert_eq!(handshake_wait(1, 0.0), HANDSHAKE_FIRST_WAIT * 2);
+        assert_eq!(handshake_wait(0, 1.0), HANDSHAKE_FIRST_WAIT / 2);
+        assert_eq!(handshake_wait(30, 0.0), HANDSHAKE_LONGEST_WAIT);
+
+        let longest: Duration = (0..HANDSHAKE_ATTEMPTS)
+            .map(|attempt| handshake_wait(attempt, 0.0))
+            .sum();
+        assert!(longest < globals::CONNECTION_TIMEOUT_SEC, "{longest:?}");
     }
 }
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Slowest simulation speed, below that the game looks paused anyway
+pub const MIN_TIME_SCALE: f32 = 0.05;
+
+/// Fastest simulation speed. Faster than real time would look like speed hacking to the
+/// server's cheat checks.
+pub const MAX_TIME_SCALE: f32 = 1.0;
+
+/// Simulation time as opposed to real time, able to stand still and to run in slow motion. The
+/// server's clock is authoritative, clients follow it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimClock {
+    scale: f32,
+    paused: bool,
+    elapsed: Duration,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            paused: false,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let `real` time pass, returns how much simulation time passed along with it
+    pub fn advance(&mut self, real: Duration) -> Duration {
+        let passed = match (self.paused, self.scale) {
+            (true, _) => Duration::ZERO,
+            // Real time as it is, so full speed stays exact
+            (false, 1.0) => real,
+            (false, scale) => real.mul_f64(scale as f64),
+        };
+        self.elapsed += passed;
+
+        passed
+    }
+
+    /// Simulation time passed since the clock started
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Clamped to [`MIN_TIME_SCALE`]..=[`MAX_TIME_SCALE`], NaN is ignored
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale.is_nan() {
+            return;
+        }
+
+        self.scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Speed as sent to clients, 0 while paused
+    pub fn wire_scale(&self) -> f32 {
+        if self.paused {
+            0.0
+        } else {
+            self.scale
+        }
+    }
+
+    /// Follow the speed the server sent, see [`SimClock::wire_scale`]
+    pub fn set_wire_scale(&mut self, scale: f32) {
+        self.paused = scale <= 0.0;
+        if !self.paused {
+            self.set_scale(scale);
+        }
+    }
+}
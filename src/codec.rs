@@ -0,0 +1,125 @@
+//! Encodings of [`Message`] sharing the serde definitions of the message types. The wire speaks
+//! `Text`, the colon separated form older clients and the relay understand, unless client and
+//! server agree on `Binary` in the handshake. JSON is for tools.
+
+use std::io::{Error, ErrorKind};
+
+use cgmath::{Vector2, Vector3};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::message::Message;
+
+/// First byte of every binary message. No UTF-8 string starts with it, so a receiver tells
+/// binary messages from text ones without knowing what the sender picked.
+pub const BINARY_MARKER: u8 = 0xff;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Codec {
+    /// Colon separated text, see [`Message::serialize`]
+    #[default]
+    Text,
+
+    /// For people and other tools to read
+    Json,
+
+    /// Postcard behind [`BINARY_MARKER`], a fraction of the size of text for positions and maps
+    Binary,
+}
+
+impl Codec {
+    /// Codec a message from the wire is in, the marker byte or else text
+    pub fn of(bytes: &[u8]) -> Self {
+        match bytes.first() {
+            Some(&BINARY_MARKER) => Codec::Binary,
+            _ => Codec::Text,
+        }
+    }
+
+    pub fn encode(self, msg: &Message) -> Vec<u8> {
+        match self {
+            Codec::Text => msg.serialize().into_bytes(),
+            Codec::Json => serde_json::to_vec(msg).expect("messages always serialize"),
+            Codec::Binary => {
+                postcard::to_extend(msg, vec![BINARY_MARKER]).expect("messages always serialize")
+            }
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Result<Message, Error> {
+        match self {
+            Codec::Text => {
+                let text = std::str::from_utf8(bytes)
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "Message is not UTF-8"))?;
+
+                Message::deserialize(text)
+            }
+            Codec::Json => serde_json::from_slice(bytes).map_err(Error::from),
+            Codec::Binary => match bytes.split_first() {
+                Some((&BINARY_MARKER, body)) => postcard::from_bytes(body)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string())),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Message lacks the binary marker",
+                )),
+            },
+        }
+    }
+
+    /// Name in the handshake
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Codec::Text => "text",
+            Codec::Json => "json",
+            Codec::Binary => "binary",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Codec::Text),
+            "json" => Some(Codec::Json),
+            "binary" => Some(Codec::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Serde definition of cgmath's [`Vector2`], for `#[serde(with = "Vector2Def")]`
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Vector2")]
+pub struct Vector2Def<S> {
+    pub x: S,
+    pub y: S,
+}
+
+/// Serde definition of cgmath's [`Vector3`], for `#[serde(with = "Vector3Def")]`
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Vector3")]
+pub struct Vector3Def<S> {
+    pub x: S,
+    pub y: S,
+    pub z: S,
+}
+
+/// Byte arrays longer than serde's built-in 32, such as signatures
+pub(crate) mod byte_array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+
+        bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::invalid_length(len, &"a fixed size byte array"))
+    }
+}
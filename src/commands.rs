@@ -0,0 +1,257 @@
+use std::{collections::BTreeMap, fmt::Write as _, str::FromStr};
+
+/// Text printed back to whoever ran the command, or what went wrong
+pub type CommandResult = Result<Option<String>, String>;
+
+type Handler<C, R> = Box<dyn Fn(&mut C, &Args) -> R + Send + Sync>;
+
+/// Who may run a command. Higher levels may run everything lower levels may.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Player,
+
+    /// Server operators and players hosting the server themselves
+    Admin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamKind {
+    Integer,
+    Number,
+    Word,
+
+    /// Everything left on the line, spaces included. Only valid as the last parameter.
+    Text,
+}
+
+/// Argument a command takes, checked before the handler runs
+#[derive(Clone, Copy, Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    pub required: bool,
+}
+
+impl Param {
+    pub const fn required(name: &'static str, kind: ParamKind) -> Self {
+        Self {
+            name,
+            kind,
+            required: true,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ParamKind) -> Self {
+        Self {
+            name,
+            kind,
+            required: false,
+        }
+    }
+
+    fn usage(&self) -> String {
+        if self.required {
+            format!("<{}>", self.name)
+        } else {
+            format!("[{}]", self.name)
+        }
+    }
+
+    fn check(&self, value: &str) -> Result<(), String> {
+        let valid = match self.kind {
+            ParamKind::Integer => value.parse::<i64>().is_ok(),
+            ParamKind::Number => value.parse::<f64>().is_ok(),
+            ParamKind::Word | ParamKind::Text => true,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("Invalid {} '{value}'", self.usage()))
+        }
+    }
+}
+
+/// Arguments of a command, already checked against its parameters
+pub struct Args<'a> {
+    params: &'a [Param],
+    values: Vec<&'a str>,
+}
+
+impl Args<'_> {
+    /// Parse a parameter into the type the handler wants, `None` when an optional one is
+    /// missing
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        let index = self
+            .params
+            .iter()
+            .position(|param| param.name == name)
+            .expect("Command handler asked for an undeclared parameter");
+
+        self.values
+            .get(index)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid {} '{value}'", self.params[index].usage()))
+            })
+            .transpose()
+    }
+
+    /// Same as `get`, for parameters declared as required
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<T, String> {
+        self.get(name)?.ok_or_else(|| format!("Missing <{name}>"))
+    }
+}
+
+/// What a console line turned out to be
+pub enum Executed<R> {
+    /// Empty line
+    Nothing,
+
+    /// Built-in help listing the commands
+    Help(String),
+
+    /// Result of the command's handler
+    Ran(R),
+}
+
+struct Command<C, R> {
+    params: Vec<Param>,
+    help: &'static str,
+    permission: Permission,
+    handler: Handler<C, R>,
+}
+
+impl<C, R> Command<C, R> {
+    fn usage(&self, name: &str) -> String {
+        self.params.iter().fold(name.to_string(), |usage, param| {
+            usage + " " + &param.usage()
+        })
+    }
+
+    /// Split the line into one value per parameter and check them
+    fn parse_args<'a>(&'a self, name: &str, line: &'a str) -> Result<Args<'a>, String> {
+        let mut values = Vec::with_capacity(self.params.len());
+        let mut rest = line.trim_start();
+
+        for param in &self.params {
+            if rest.is_empty() {
+                if param.required {
+                    return Err(format!(
+                        "Missing {}\nUsage: {}",
+                        param.usage(),
+                        self.usage(name)
+                    ));
+                }
+                break;
+            }
+
+            let value = match param.kind {
+                ParamKind::Text => std::mem::take(&mut rest).trim_end(),
+                _ => {
+                    let (value, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    rest = tail.trim_start();
+                    value
+                }
+            };
+
+            param.check(value)?;
+            values.push(value);
+        }
+
+        if !rest.is_empty() {
+            return Err(format!("Too many arguments\nUsage: {}", self.usage(name)));
+        }
+
+        Ok(Args {
+            params: &self.params,
+            values,
+        })
+    }
+}
+
+/// Commands typed into a console, run against a context `C` such as the GUI app or the server.
+/// Handlers return `R`, which lets async consoles hand back a future to await.
+///
+/// Modules register their own commands. Handlers which don't need the context can stay generic
+/// over it and be shared between consoles.
+pub struct CommandRegistry<C, R = CommandResult> {
+    // Sorted, so help lists the commands alphabetically
+    commands: BTreeMap<&'static str, Command<C, R>>,
+}
+
+impl<C, R> Default for CommandRegistry<C, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, R> CommandRegistry<C, R> {
+    pub fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Add a command, replacing any earlier one of the same name
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        params: &[Param],
+        help: &'static str,
+        permission: Permission,
+        handler: impl Fn(&mut C, &Args) -> R + Send + Sync + 'static,
+    ) {
+        self.commands.insert(
+            name,
+            Command {
+                params: params.to_vec(),
+                help,
+                permission,
+                handler: Box::new(handler),
+            },
+        );
+    }
+
+    /// Parse one console line and run its command. Commands above `permission` are treated as
+    /// unknown. Errors are about the line itself, the handler reports its own through `R`.
+    pub fn execute(
+        &self,
+        context: &mut C,
+        line: &str,
+        permission: Permission,
+    ) -> Result<Executed<R>, String> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+        let command = match name {
+            "" => return Ok(Executed::Nothing),
+            "help" => return Ok(Executed::Help(self.help(permission))),
+            _ => self
+                .commands
+                .get(name)
+                .filter(|command| command.permission <= permission)
+                .ok_or_else(|| format!("Unknown command '{name}', type 'help' for a list"))?,
+        };
+
+        let args = command.parse_args(name, rest)?;
+        Ok(Executed::Ran((command.handler)(context, &args)))
+    }
+
+    /// Usage and description of every command available at `permission`
+    pub fn help(&self, permission: Permission) -> String {
+        let mut help = String::from("Commands:");
+        let available = self
+            .commands
+            .iter()
+            .filter(|(_, command)| command.permission <= permission)
+            .map(|(name, command)| (command.usage(name), command.help));
+
+        for (usage, text) in available.chain([(String::from("help"), "Show this help")]) {
+            let _ = write!(help, "\n  {usage:<24} {text}");
+        }
+
+        help
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fmt::Write as _,
+    fs, io,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::paths;
+
+/// Log lines kept for the crash report
+const LOG_LINES: usize = 200;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Graphics driver description, known once the renderer is up
+static GL_INFO: OnceLock<String> = OnceLock::new();
+
+// Written next to the reports on a crash, removed once the GUI showed the report
+const UNSEEN_FILE: &str = "unseen";
+
+/// Keep a log line for the report of a crash happening later
+pub fn record_log(line: &str) {
+    // Never wait here, the panic hook may be holding the lock on this very thread
+    let Ok(mut log) = LOG.try_lock() else {
+        return;
+    };
+
+    if log.len() == LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(line.to_string());
+}
+
+pub fn set_gl_info(info: String) {
+    let _ = GL_INFO.set(info);
+}
+
+/// Write a report to the crash directory on every panic before the previous hook runs. The
+/// report's path goes to stderr, where server logs end up.
+pub fn install_hook() {
+    let previous_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        match write_report(&report(info)) {
+            Ok(path) => eprintln!("Crash report written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write crash report: {e}"),
+        }
+
+        previous_hook(info);
+    }));
+}
+
+/// Report of a crash the GUI did not show yet. Only returned once.
+pub fn take_unseen_report() -> Option<PathBuf> {
+    let unseen = paths::crash_dir().join(UNSEEN_FILE);
+    let path = PathBuf::from(fs::read_to_string(&unseen).ok()?.trim());
+    let _ = fs::remove_file(unseen);
+
+    path.is_file().then_some(path)
+}
+
+/// Show the report in the platform's default text viewer
+pub fn open_report(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "windows")]
+    let opener = "explorer";
+    #[cfg(target_os = "macos")]
+    let opener = "open";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let opener = "xdg-open";
+
+    std::process::Command::new(opener).arg(path).spawn()?;
+
+    Ok(())
+}
+
+fn report(info: &PanicHookInfo) -> String {
+    let mut report = String::new();
+    let thread = std::thread::current();
+
+    let _ = writeln!(
+        report,
+        "{} {} crashed",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(
+        report,
+        "OS: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let _ = writeln!(
+        report,
+        "GL: {}",
+        GL_INFO.get().map_or("not initialized", String::as_str)
+    );
+    let _ = writeln!(report, "Thread: {}", thread.name().unwrap_or("unnamed"));
+    let _ = writeln!(report, "\n{info}");
+    let _ = writeln!(report, "\nBacktrace:\n{}", Backtrace::force_capture());
+
+    report.push_str("\nLast log lines:\n");
+    if let Ok(log) = LOG.try_lock() {
+        for line in log.iter() {
+            let _ = writeln!(report, "{line}");
+        }
+    }
+
+    report
+}
+
+fn write_report(report: &str) -> io::Result<PathBuf> {
+    let dir = paths::ensure_dir(paths::crash_dir())?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let path = dir.join(format!("crash-{secs}.txt"));
+    fs::write(&path, report)?;
+
+    // Absolute, so the GUI finds it again even when started from elsewhere
+    let path = path.canonicalize().unwrap_or(path);
+    fs::write(dir.join(UNSEEN_FILE), path.to_string_lossy().as_bytes())?;
+
+    Ok(path)
+}
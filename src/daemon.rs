@@ -0,0 +1,292 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::Notify;
+
+/// Process exit codes of the dedicated server, so service managers can tell a misconfiguration
+/// apart from a crash
+pub mod exit_code {
+    /// Socket could not be bound, usually because the port is already taken. Restarting will not
+    /// help until the configuration is fixed.
+    pub const BIND_FAILURE: i32 = 2;
+
+    /// Server panicked at runtime. Safe to restart.
+    pub const PANIC: i32 = 3;
+}
+
+const LOG_MAX_BYTES: u64 = 1024 * 1024;
+const LOG_KEEP_FILES: usize = 3;
+
+/// File holding the server's process id while it runs, removed again on drop
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+////////////////////////////////////////////////////
+
+/// Append-only log file rotated by size. Once the active file grows past `LOG_MAX_BYTES` it is
+/// renamed to `<path>.1`, older files shift up by one and the oldest one is deleted.
+pub struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl RotatingLogFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+        })
+    }
+
+    /// Write a single line prefixed with a UNIX timestamp
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.written >= LOG_MAX_BYTES {
+            self.rotate()?;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let entry = format!("[{timestamp:.3}] {line}\n");
+
+        self.file.write_all(entry.as_bytes())?;
+        self.written += entry.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..LOG_KEEP_FILES).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+
+    PathBuf::from(rotated)
+}
+
+////////////////////////////////////////////////////
+
+static PANICKED: Notify = Notify::const_new();
+
+/// The server was stopped by a panic, see [`stop_on_panic`]
+#[derive(Debug)]
+pub struct Panicked;
+
+impl std::fmt::Display for Panicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Server stopped after a panic")
+    }
+}
+
+impl std::error::Error for Panicked {}
+
+/// Panics inside tokio tasks only kill the task, leaving a zombie server behind. In daemon mode
+/// they wake [`panicked`] instead, so the server shuts down and exits with
+/// [`exit_code::PANIC`] for the service manager to restart it.
+pub fn stop_on_panic() {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        PANICKED.notify_one();
+    }));
+}
+
+/// Resolves once something panicked after [`stop_on_panic`]
+pub async fn panicked() {
+    PANICKED.notified().await;
+}
+
+/// Wait until the service manager or user asks the server to stop: SIGTERM or SIGINT on Unix,
+/// Ctrl + C, Ctrl + Break or a stop from the service control manager on Windows
+pub async fn wait_for_shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let mut ctrl_break = tokio::signal::windows::ctrl_break()?;
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = ctrl_break.recv() => {}
+            _ = service::STOP.notified() => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer the Windows service control manager when started as a service, so stopping the
+/// service shuts the server down gracefully. Nothing happens when started any other way.
+pub fn serve_service_control() {
+    #[cfg(windows)]
+    service::serve();
+}
+
+/// Tell the Windows service control manager the server stopped, with the code the process is
+/// about to exit with. Nothing happens unless running as a service.
+pub fn report_stopped(_exit_code: i32) {
+    #[cfg(windows)]
+    service::report_stopped(_exit_code);
+}
+
+#[cfg(windows)]
+mod service {
+    use std::{ffi::OsString, sync::OnceLock, time::Duration};
+
+    use tokio::sync::Notify;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+    };
+
+    // Ignored for services running in a process of their own, which this one is
+    const SERVICE_NAME: &str = "game-server-sample";
+
+    // ERROR_FAILED_SERVICE_CONTROLLER_CONNECT, the process wasn't started as a service
+    const NOT_A_SERVICE: i32 = 1063;
+
+    // Time the service control manager gives the server to shut down before it complains
+    const STOP_WAIT_HINT: Duration = Duration::from_secs(10);
+
+    /// The service control manager asked the service to stop
+    pub(super) static STOP: Notify = Notify::const_new();
+
+    static STATUS: OnceLock<ServiceStatusHandle> = OnceLock::new();
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// The dispatcher blocks its thread for as long as the service runs, so it gets one of its
+    /// own and the server keeps running on the main thread
+    pub(super) fn serve() {
+        std::thread::spawn(|| {
+            if let Err(e) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+                let not_a_service = matches!(
+                    &e,
+                    windows_service::Error::Winapi(e) if e.raw_os_error() == Some(NOT_A_SERVICE)
+                );
+                if !not_a_service {
+                    eprintln!("Failed to connect to the service control manager: {e}");
+                }
+            }
+        });
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        // The handler is released after the first stop, later controls must not reach it
+        let handler = |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                set_status(ServiceState::StopPending, ServiceExitCode::NO_ERROR);
+                STOP.notify_one();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+
+        match service_control_handler::register(SERVICE_NAME, handler) {
+            Ok(status) => {
+                let _ = STATUS.set(status);
+                set_status(ServiceState::Running, ServiceExitCode::NO_ERROR);
+            }
+            Err(e) => eprintln!("Failed to register with the service control manager: {e}"),
+        }
+    }
+
+    pub(super) fn report_stopped(exit_code: i32) {
+        let exit_code = if exit_code == 0 {
+            ServiceExitCode::NO_ERROR
+        } else {
+            ServiceExitCode::ServiceSpecific(exit_code as u32)
+        };
+
+        set_status(ServiceState::Stopped, exit_code);
+    }
+
+    fn set_status(state: ServiceState, exit_code: ServiceExitCode) {
+        let Some(status) = STATUS.get() else {
+            return;
+        };
+
+        let controls_accepted = if state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        };
+        let wait_hint = if state == ServiceState::StopPending {
+            STOP_WAIT_HINT
+        } else {
+            Duration::ZERO
+        };
+
+        let _ = status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint,
+            process_id: None,
+        });
+    }
+}
@@ -0,0 +1,180 @@
+//! Read-only web page of the dedicated server for watching a match from a browser without the
+//! game client. The page polls a JSON snapshot of the world and draws it on a canvas. Anyone who
+//! can reach the address sees every player's position, so bind it to a private interface.
+//! `/metrics.csv` has the load samples of the last hour for looking into load tests.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+
+use crate::server::{metrics_csv, ServerHandle};
+
+const PAGE: &str = include_str!("../assets/dashboard/index.html");
+
+// Requests are a line and a few headers, anything longer isn't a browser asking for the page
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+// A browser sends its request right away, a connection that stays silent is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Connections served at once, more wait in the listen backlog until one finishes
+const MAX_CONNECTIONS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    Page,
+    Snapshot,
+    Metrics,
+    NotFound,
+    MethodNotAllowed,
+}
+
+/// Serve the dashboard on `addr` until the process exits. Returns the address it listens on, with
+/// the actual port when asked for port 0.
+pub async fn start(addr: SocketAddr, server: ServerHandle) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+
+        loop {
+            let permit = connections
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let server = server.clone();
+                    tokio::spawn(async move {
+                        handle_connection(stream, server).await;
+                        drop(permit);
+                    });
+                }
+                Err(e) => eprintln!("Dashboard failed to accept a connection: {e}"),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// One request per connection, the page asks again for every snapshot
+async fn handle_connection(mut stream: TcpStream, server: ServerHandle) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+
+    let (status, content_type, body) = match route(&request) {
+        Route::Page => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+        Route::Snapshot => (
+            "200 OK",
+            "application/json",
+            server.observer_snapshot().await.to_string(),
+        ),
+        Route::Metrics => ("200 OK", "text/csv", metrics_csv(&server.metrics_history())),
+        Route::NotFound => ("404 Not Found", "text/plain", String::from("Not found")),
+        Route::MethodNotAllowed => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("Only GET"),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = tokio::time::timeout(REQUEST_TIMEOUT, stream.write_all(response.as_bytes())).await;
+}
+
+/// Request line and headers up to the blank line, `None` for a connection that closed early,
+/// sent too much or took too long
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+
+    let read = async {
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let len = stream.read(&mut buf).await.ok()?;
+            if len == 0 || request.len() + len > MAX_REQUEST_LEN {
+                return None;
+            }
+            request.extend_from_slice(&buf[..len]);
+        }
+
+        Some(())
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read).await.ok()??;
+
+    String::from_utf8(request).ok()
+}
+
+fn route(request: &str) -> Route {
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (request_line.next(), request_line.next().unwrap_or_default());
+
+    // The query string is only there to get past caches
+    let path = target.split('?').next().unwrap_or_default();
+
+    match (method, path) {
+        (Some("GET"), "/" | "/index.html") => Route::Page,
+        (Some("GET"), "/snapshot.json") => Route::Snapshot,
+        (Some("GET"), "/metrics.csv") => Route::Metrics,
+        (Some("GET"), _) => Route::NotFound,
+        _ => Route::MethodNotAllowed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::server::ServerBuilder;
+
+    #[test]
+    fn requests_are_routed_by_method_and_path() {
+        assert_eq!(route("GET / HTTP/1.1\r\nHost: x\r\n\r\n"), Route::Page);
+        assert_eq!(
+            route("GET /snapshot.json?t=17 HTTP/1.1\r\n\r\n"),
+            Route::Snapshot
+        );
+        assert_eq!(route("GET /metrics.csv HTTP/1.1\r\n\r\n"), Route::Metrics);
+        assert_eq!(route("GET /../etc HTTP/1.1\r\n\r\n"), Route::NotFound);
+        assert_eq!(
+            route("POST /snapshot.json HTTP/1.1\r\n\r\n"),
+            Route::MethodNotAllowed
+        );
+        assert_eq!(route(""), Route::MethodNotAllowed);
+    }
+
+    #[tokio::test]
+    async fn snapshot_is_served_as_json() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let addr = start((Ipv4Addr::LOCALHOST, 0).into(), server)
+            .await
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /snapshot.json HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        let snapshot: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(snapshot["players"], serde_json::json!([]));
+        assert!(snapshot["bounds"]["max_x"].is_number());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{io, path::PathBuf};
+
+use game_server_sample::identity::PublicKey;
+use serde_json::{json, Value};
+
+/// Player added from the player list, recognized by their public key on any server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Friend {
+    pub key: PublicKey,
+
+    /// Name they went by when last seen, or their player number
+    pub name: String,
+
+    /// Address of the server they were last seen on, where to look for them hosting
+    pub last_server: Option<String>,
+}
+
+/// Friends list kept in the data directory as JSON
+pub struct FriendList {
+    path: PathBuf,
+    friends: Vec<Friend>,
+}
+
+impl FriendList {
+    /// Start empty if the file is missing or broken, it is rewritten on the next change
+    pub fn load(path: PathBuf) -> Self {
+        let friends = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+            .and_then(|value| value.as_array().map(|entries| parse_friends(entries)))
+            .unwrap_or_default();
+
+        Self { path, friends }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Friend> {
+        self.friends.iter()
+    }
+
+    pub fn contains(&self, key: &PublicKey) -> bool {
+        self.friends.iter().any(|friend| friend.key == *key)
+    }
+
+    pub fn add(&mut self, friend: Friend) -> io::Result<()> {
+        self.friends.retain(|f| f.key != friend.key);
+        self.friends.push(friend);
+        self.save()
+    }
+
+    pub fn remove(&mut self, key: &PublicKey) -> io::Result<()> {
+        self.friends.retain(|friend| friend.key != *key);
+        self.save()
+    }
+
+    /// Note where a friend was just seen. Only writes the file when something changed.
+    pub fn seen(&mut self, key: &PublicKey, name: Option<&str>, server: &str) -> io::Result<()> {
+        let Some(friend) = self.friends.iter_mut().find(|friend| friend.key == *key) else {
+            return Ok(());
+        };
+
+        let name = name.unwrap_or(&friend.name).to_string();
+        if friend.name == name && friend.last_server.as_deref() == Some(server) {
+            return Ok(());
+        }
+        friend.name = name;
+        friend.last_server = Some(server.to_string());
+
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let entries: Vec<Value> = self
+            .friends
+            .iter()
+            .map(|friend| {
+                json!({
+                    "key": friend.key.to_string(),
+                    "name": friend.name,
+                    "last_server": friend.last_server,
+                })
+            })
+            .collect();
+
+        std::fs::write(&self.path, serde_json::to_string_pretty(&entries)?)
+    }
+}
+
+// Entries with a bad key are skipped, the rest of the list still loads
+fn parse_friends(entries: &[Value]) -> Vec<Friend> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            Some(Friend {
+                key: PublicKey::parse(entry["key"].as_str()?)?,
+                name: entry["name"].as_str().unwrap_or_default().to_string(),
+                last_server: entry["last_server"].as_str().map(String::from),
+            })
+        })
+        .collect()
+}
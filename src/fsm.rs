@@ -1,3 +1,7 @@
+use std::path::PathBuf;
+
+use crate::net::addr::Endpoint;
+
 #[derive(Clone, Copy)]
 
 pub enum SessionMode {
@@ -10,20 +14,48 @@ pub enum SessionMode {
 pub enum State {
     Menu,
     Connecting {
-        server_address: String,
+        /// Server to join, its port is also the one a hosted server listens on
+        endpoint: Endpoint,
         session_mode: SessionMode,
+
+        /// Code to join with, or to make a created server private with
+        invite_code: Option<String>,
     },
 
     Playing,
+
+    /// Message of the day over the game, until the player dismisses it
+    Motd(String),
+
+    /// The game crashed last time, offers to open the report
+    CrashReport(PathBuf),
+
     Disconnected,
     QuitDialog,
     Quit,
 }
 
+impl State {
+    /// Dialogs shown over another state. The state underneath keeps running, the overlay only
+    /// takes the input.
+    pub fn is_overlay(&self) -> bool {
+        matches!(
+            self,
+            State::Motd(_) | State::QuitDialog | State::CrashReport(_)
+        )
+    }
+}
+
 pub struct StateMachine {
     state_stack: Vec<State>,
 }
 
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StateMachine {
     pub fn new() -> Self {
         Self {
@@ -51,4 +83,24 @@ impl StateMachine {
     pub fn peek_mut(&mut self) -> Option<&mut State> {
         self.state_stack.last_mut()
     }
+
+    /// Topmost state that is not an overlay, the one whose logic runs
+    pub fn base(&self) -> Option<&State> {
+        self.state_stack
+            .iter()
+            .rev()
+            .find(|state| !state.is_overlay())
+    }
+
+    pub fn base_mut(&mut self) -> Option<&mut State> {
+        self.state_stack
+            .iter_mut()
+            .rev()
+            .find(|state| !state.is_overlay())
+    }
+
+    /// Overlay on top taking the input, if any
+    pub fn overlay(&self) -> Option<&State> {
+        self.peek().filter(|state| state.is_overlay())
+    }
 }
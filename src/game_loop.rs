@@ -0,0 +1,403 @@
+use std::time::{Duration, Instant};
+
+use game_server_sample::{clock::SimClock, globals};
+
+// Most fixed updates run to catch up in one frame. After a longer stall (window dragged, debugger
+// break) the rest of the backlog is dropped, instead of each frame taking longer to catch up
+// than the last one (spiral of death).
+const MAX_UPDATES_PER_FRAME: u32 = 8;
+
+pub trait TimeSource {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Window and input events
+pub trait EventSource {
+    /// Handle everything that arrived since the last frame. Returns false once the app should
+    /// quit.
+    fn pump_events(&mut self) -> bool;
+}
+
+pub trait Network {
+    /// Handle messages that arrived since the last frame
+    fn poll(&mut self);
+}
+
+/// Game logic, stepped at a fixed rate
+pub trait Simulation {
+    fn update(&mut self);
+
+    /// Speed of simulation time as set by the server, 0 while paused
+    fn time_scale(&self) -> f32 {
+        1.0
+    }
+}
+
+pub trait FrameRenderer {
+    fn render(&mut self);
+
+    /// Frame rate cap, 0 for none
+    fn fps_max(&self) -> u32 {
+        0
+    }
+}
+
+pub struct RealTime;
+
+impl TimeSource for RealTime {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Frame-rate independent loop with fixed update, variable framerate.
+///
+/// A naive calculation and passing of a deltaTime introduces floating point precision errors,
+/// leading to choppy camera movement and unstable logic even on high framerate. Here, think of it
+/// as renderer dictating time, and logic update adapting to it.
+pub struct GameLoop<T: TimeSource> {
+    time: T,
+    step: Duration,
+    previous_time: Instant,
+
+    /// Turns the real time between frames into simulation time owed to the updates
+    pub clock: SimClock,
+
+    /// How much application "clock" is behind real time. Also known as "accumulator"
+    lag: Duration,
+
+    /// Time dropped by the spiral of death guard
+    pub dropped: Duration,
+}
+
+impl<T: TimeSource> GameLoop<T> {
+    pub fn new(time: T) -> Self {
+        Self {
+            previous_time: time.now(),
+            time,
+            step: Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC),
+            clock: SimClock::new(),
+            lag: Duration::ZERO,
+            dropped: Duration::ZERO,
+        }
+    }
+
+    /// Run frames until the app quits
+    pub fn run<A>(&mut self, app: &mut A)
+    where
+        A: EventSource + Network + Simulation + FrameRenderer,
+    {
+        while self.frame(app) {}
+    }
+
+    /// One frame: events, network, as many fixed updates as time has passed, render. Returns
+    /// false once the app should quit.
+    pub fn frame<A>(&mut self, app: &mut A) -> bool
+    where
+        A: EventSource + Network + Simulation + FrameRenderer,
+    {
+        let current_time = self.time.now();
+        self.clock.set_wire_scale(app.time_scale());
+        self.lag += self
+            .clock
+            .advance(current_time.saturating_duration_since(self.previous_time));
+        self.previous_time = current_time;
+
+        if !app.pump_events() {
+            return false;
+        }
+        app.poll();
+
+        let mut updates = 0;
+        while self.lag >= self.step {
+            if updates == MAX_UPDATES_PER_FRAME {
+                // Keep the fraction of a step, so the pace stays even after the stall
+                let backlog = self.lag.as_nanos() / self.step.as_nanos();
+                let dropped = self.step * backlog as u32;
+                self.lag -= dropped;
+                self.dropped += dropped;
+                break;
+            }
+
+            app.update();
+            self.lag -= self.step;
+            updates += 1;
+        }
+
+        app.render();
+
+        if app.fps_max() > 0 {
+            let frame_time = Duration::from_secs_f32(1.0 / app.fps_max() as f32);
+            let spent = self.time.now().saturating_duration_since(current_time);
+            if let Some(remaining) = frame_time.checked_sub(spent) {
+                self.time.sleep(remaining);
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// Time that only moves when told to, shared with the app so it can take time too
+    #[derive(Clone)]
+    struct ManualTime {
+        start: Instant,
+        elapsed: Rc<Cell<Duration>>,
+    }
+
+    impl ManualTime {
+        fn new() -> Self {
+            Self {
+                start: Instant::now(),
+                elapsed: Rc::new(Cell::new(Duration::ZERO)),
+            }
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.elapsed.set(self.elapsed.get() + duration);
+        }
+    }
+
+    impl TimeSource for ManualTime {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Call {
+        Events,
+        Poll,
+        Update,
+        Render,
+    }
+
+    #[derive(Default)]
+    struct MockApp {
+        calls: Vec<Call>,
+        quit: bool,
+        fps_max: u32,
+
+        /// Time each render takes
+        render_time: Option<(ManualTime, Duration)>,
+
+        /// Full speed unless set
+        time_scale: Option<f32>,
+    }
+
+    impl MockApp {
+        fn updates(&self) -> usize {
+            self.calls.iter().filter(|c| **c == Call::Update).count()
+        }
+
+        fn take_calls(&mut self) -> Vec<Call> {
+            std::mem::take(&mut self.calls)
+        }
+    }
+
+    impl EventSource for MockApp {
+        fn pump_events(&mut self) -> bool {
+            self.calls.push(Call::Events);
+            !self.quit
+        }
+    }
+
+    impl Network for MockApp {
+        fn poll(&mut self) {
+            self.calls.push(Call::Poll);
+        }
+    }
+
+    impl Simulation for MockApp {
+        fn update(&mut self) {
+            self.calls.push(Call::Update);
+        }
+
+        fn time_scale(&self) -> f32 {
+            self.time_scale.unwrap_or(1.0)
+        }
+    }
+
+    impl FrameRenderer for MockApp {
+        fn render(&mut self) {
+            self.calls.push(Call::Render);
+            if let Some((time, duration)) = &self.render_time {
+                time.advance(*duration);
+            }
+        }
+
+        fn fps_max(&self) -> u32 {
+            self.fps_max
+        }
+    }
+
+    fn step() -> Duration {
+        Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC)
+    }
+
+    #[test]
+    fn updates_follow_elapsed_time_whatever_the_frame_rate() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp::default();
+
+        // Uneven frames, from well above to below the update rate
+        let frame_times = [1, 3, 7, 16, 17, 25, 33, 2, 40, 9];
+        let mut total = Duration::ZERO;
+        for i in 0..1000 {
+            let frame_time = Duration::from_millis(frame_times[i % frame_times.len()]);
+            time.advance(frame_time);
+            total += frame_time;
+
+            assert!(game_loop.frame(&mut app));
+            assert_eq!(
+                app.updates() as u128,
+                total.as_nanos() / step().as_nanos(),
+                "after {total:?}"
+            );
+        }
+        assert_eq!(game_loop.dropped, Duration::ZERO);
+    }
+
+    #[test]
+    fn no_update_before_a_full_step() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp::default();
+
+        time.advance(step() - Duration::from_nanos(1));
+        game_loop.frame(&mut app);
+        assert_eq!(app.updates(), 0);
+
+        time.advance(Duration::from_nanos(1));
+        game_loop.frame(&mut app);
+        assert_eq!(app.updates(), 1);
+    }
+
+    #[test]
+    fn frame_order() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp::default();
+
+        time.advance(step() * 2);
+        game_loop.frame(&mut app);
+
+        assert_eq!(
+            app.take_calls(),
+            [
+                Call::Events,
+                Call::Poll,
+                Call::Update,
+                Call::Update,
+                Call::Render
+            ]
+        );
+    }
+
+    #[test]
+    fn updates_follow_the_time_scale() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp {
+            time_scale: Some(0.5),
+            ..Default::default()
+        };
+
+        time.advance(step() * 8);
+        game_loop.frame(&mut app);
+        assert_eq!(app.updates(), 4);
+
+        // Paused, frames keep coming but nothing moves
+        app.take_calls();
+        app.time_scale = Some(0.0);
+        time.advance(step() * 8);
+        game_loop.frame(&mut app);
+        assert_eq!(app.take_calls(), [Call::Events, Call::Poll, Call::Render]);
+    }
+
+    #[test]
+    fn quitting_stops_before_updating() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp {
+            quit: true,
+            ..Default::default()
+        };
+
+        time.advance(step() * 3);
+        game_loop.run(&mut app);
+
+        assert_eq!(app.take_calls(), [Call::Events]);
+    }
+
+    #[test]
+    fn long_stall_drops_the_backlog() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp::default();
+
+        time.advance(step() * 300 + step() / 2);
+        game_loop.frame(&mut app);
+        assert_eq!(app.updates(), MAX_UPDATES_PER_FRAME as usize);
+        assert!(game_loop.dropped > Duration::from_secs(4));
+
+        // Back to one update per step right away, the leftover fraction is kept
+        app.take_calls();
+        time.advance(step() - step() / 2);
+        game_loop.frame(&mut app);
+        assert_eq!(app.updates(), 1);
+    }
+
+    #[test]
+    fn slow_frames_never_run_away() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+
+        // Every update takes longer than a step, the lag would grow forever without the guard
+        let mut app = MockApp {
+            render_time: Some((time.clone(), step() * 20)),
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            app.take_calls();
+            game_loop.frame(&mut app);
+            assert!(app.updates() <= MAX_UPDATES_PER_FRAME as usize);
+        }
+        assert!(game_loop.lag < game_loop.step);
+    }
+
+    #[test]
+    fn frame_cap_sleeps_the_rest_of_the_frame() {
+        let time = ManualTime::new();
+        let mut game_loop = GameLoop::new(time.clone());
+        let mut app = MockApp {
+            fps_max: 50,
+            render_time: Some((time.clone(), Duration::from_millis(5))),
+            ..Default::default()
+        };
+
+        let start = time.now();
+        for _ in 0..50 {
+            game_loop.frame(&mut app);
+        }
+
+        assert_eq!(time.now() - start, Duration::from_secs(1));
+    }
+}
@@ -1,85 +1,814 @@
-use std::{net::IpAddr, sync::Arc, u16};
+use std::{
+    collections::{HashSet, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use egui::{
-    Align2, Button, CentralPanel, Color32, Frame, Grid, Rounding, Shadow, TextEdit, Vec2, Visuals,
-    Window,
+    Align2, Button, CentralPanel, Color32, ComboBox, Frame, Grid, Id, LayerId, Order, Pos2,
+    Rounding, Shadow, SidePanel, Stroke, TextEdit, TextStyle, Vec2, Visuals, Window,
 };
 use egui_glow::EguiGlow;
-use game_server_sample::globals;
-use winit::{event::WindowEvent, event_loop::ActiveEventLoop};
+use game_server_sample::{
+    globals,
+    identity::PublicKey,
+    version::{self, Version},
+    PlayerId,
+};
+use winit::{event::WindowEvent, event_loop::ActiveEventLoop, window::WindowId};
+
+use crate::{
+    accessibility::Accessibility,
+    crash,
+    daemon::RotatingLogFile,
+    fsm,
+    i18n::{self, tr, tr_args, Language},
+    message::{self, MessageStats, NoticeLevel, TraceLine, MESSAGE_NAMES},
+    net::addr,
+    paths,
+    quality::{InterpolationReport, Liveness, QualityReport},
+    renderer::SecondaryWindow,
+    server,
+};
 
-use crate::fsm;
+#[cfg(feature = "wgpu")]
+use crate::renderer::wgpu_backend::EguiWgpu;
 
 pub struct Gui {
-    egui_glow: EguiGlow,
-    log_messages: String,
+    /// `None` when frames are laid out on a bare egui context, as in tests
+    egui: Option<EguiBackend>,
+    log: VecDeque<LogEntry>,
+
+    /// Kinds of log lines filtered out of the log window
+    log_hidden: HashSet<Severity>,
+
+    /// Everything logged this session, with timestamps. `None` if it couldn't be created.
+    session_log: Option<RotatingLogFile>,
+
     server_hostname: String,
     server_port: String,
+
+    /// "Paste address" was clicked, the clipboard is read once egui let go of it
+    paste_address: bool,
+
+    invite: InviteForm,
     status_text: String,
     status_color: Color32,
+
+    /// Shown in the Disconnected dialog instead of the generic connection lost message
+    disconnect_reason: Option<String>,
+
+    /// Your own stats of the session that just ended, for the Disconnected dialog
+    session_totals: Option<PlayerStats>,
+
+    debug_overlay: bool,
+
+    /// Debug overlay moved to a window of its own, see [`DebugWindow`]
+    debug_detached: bool,
+
+    /// Part of the dash cooldown still to go, 0 when the player can dash
+    dash_cooldown: f32,
+
+    /// Forwarded router address of a server hosted from here, for sharing with friends
+    hosting_address: Option<String>,
+
+    /// Port the hosted server got, not always the one asked for with auto port
+    hosting_port: Option<u16>,
+
+    /// Players on the server this process hosts and how many fit
+    hosting_players: Option<(usize, usize)>,
+
+    /// Code friends need to join the private server hosted from here
+    hosting_invite_code: Option<String>,
+
+    /// Shown in the menu, with the servers friends are hosting
+    friends: Vec<FriendEntry>,
+
+    /// Favorite and recent servers in the menu, closest first
+    servers: Vec<ServerEntry>,
+
+    player_list_open: bool,
+
+    console: Console,
+    chat: Chat,
+    trace_viewer: TraceViewer,
+
+    toasts: Toasts,
+    visible_toasts: VecDeque<VisibleToast>,
+
+    accessibility: Accessibility,
+
+    /// Accessibility settings were changed in the menu and not taken by the app yet
+    accessibility_changed: bool,
+}
+
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+const TOAST_FADE_OUT: Duration = Duration::from_millis(500);
+
+// Older toasts make room when more pile up at once
+const MAX_VISIBLE_TOASTS: usize = 4;
+
+/// Queue of toast notifications, short messages shown in a corner for a few seconds. Cheap to
+/// clone and usable from any thread, so any module can be handed one.
+#[derive(Clone, Default)]
+pub struct Toasts {
+    queue: Arc<Mutex<Vec<(Severity, String)>>>,
+}
+
+impl Toasts {
+    pub fn push(&self, severity: Severity, text: String) {
+        self.queue.lock().unwrap().push((severity, text));
+    }
+
+    fn take(&self) -> Vec<(Severity, String)> {
+        std::mem::take(&mut *self.queue.lock().unwrap())
+    }
+}
+
+struct VisibleToast {
+    severity: Severity,
+    text: String,
+    shown_at: Instant,
+}
+
+/// Invite code fields of the menu
+#[derive(Default)]
+struct InviteForm {
+    /// Code to join a private server with
+    code: String,
+
+    /// Create a private server with a fresh code
+    private: bool,
+}
+
+/// Kind of a log line, deciding its color and which filter toggle hides it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Info,
+    Join,
+    Leave,
+    Error,
+    Chat,
+    Whisper,
+
+    /// Server notices, by their level
+    Notice,
+    Warning,
+}
+
+impl Severity {
+    const ALL: [Severity; 8] = [
+        Severity::Info,
+        Severity::Join,
+        Severity::Leave,
+        Severity::Error,
+        Severity::Chat,
+        Severity::Whisper,
+        Severity::Notice,
+        Severity::Warning,
+    ];
+
+    /// `None` keeps the regular text color
+    fn color(self) -> Option<Color32> {
+        match self {
+            Severity::Info => None,
+            Severity::Join => Some(Color32::from_rgb(0, 140, 0)),
+            Severity::Leave => Some(Color32::from_rgb(190, 140, 0)),
+            Severity::Error => Some(Color32::RED),
+            Severity::Chat => Some(Color32::from_rgb(60, 120, 220)),
+            Severity::Whisper => Some(Color32::from_rgb(190, 80, 210)),
+            Severity::Notice => Some(Color32::from_rgb(0, 150, 150)),
+            Severity::Warning => Some(Color32::from_rgb(230, 100, 0)),
+        }
+    }
+
+    fn filter_label(self) -> &'static str {
+        match self {
+            Severity::Info => tr("log.filter_info"),
+            Severity::Join => tr("log.filter_joins"),
+            Severity::Leave => tr("log.filter_leaves"),
+            Severity::Error => tr("log.filter_errors"),
+            Severity::Chat => tr("log.filter_chat"),
+            Severity::Whisper => tr("log.filter_whispers"),
+            Severity::Notice => tr("log.filter_notices"),
+            Severity::Warning => tr("log.filter_warnings"),
+        }
+    }
+}
+
+impl From<NoticeLevel> for Severity {
+    fn from(level: NoticeLevel) -> Self {
+        match level {
+            NoticeLevel::Info => Severity::Notice,
+            NoticeLevel::Warning => Severity::Warning,
+            NoticeLevel::Error => Severity::Error,
+        }
+    }
+}
+
+pub struct LogEntry {
+    pub severity: Severity,
+    pub time: SystemTime,
+    pub text: String,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", clock_time(self.time), self.text)
+    }
+}
+
+/// At or below this many bars the connection indicator turns red
+pub const UNSTABLE_CONNECTION_BARS: u8 = 1;
+
+// Older log lines are dropped from the log window, the session log file keeps them
+const LOG_HISTORY_LINES: usize = 500;
+
+// Older console lines are dropped
+const CONSOLE_HISTORY_LINES: usize = 200;
+
+/// Quake style drop-down console. Submitted lines are picked up and run by the app.
+#[derive(Default)]
+struct Console {
+    open: bool,
+    input: String,
+    output: VecDeque<String>,
+    submitted: Vec<String>,
+}
+
+impl Console {
+    fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.output.len() == CONSOLE_HISTORY_LINES {
+                self.output.pop_front();
+            }
+            self.output.push_back(line.to_string());
+        }
+    }
+}
+
+/// Chat line typed into the log window. Submitted lines are picked up and sent by the app.
+#[derive(Default)]
+struct Chat {
+    open: bool,
+    input: String,
+    submitted: Vec<String>,
+}
+
+// Older trace lines are dropped from the trace viewer
+const TRACE_VIEWER_LINES: usize = 500;
+
+/// Live view of the message trace, which can be switched on and off from here
+#[derive(Default)]
+struct TraceViewer {
+    open: bool,
+    lines: VecDeque<TraceLine>,
+
+    /// Message types filtered out, `None` standing for free-form lines
+    hidden: HashSet<Option<&'static str>>,
+}
+
+impl TraceViewer {
+    fn collect(&mut self) {
+        for line in message::take_trace_lines() {
+            if self.lines.len() == TRACE_VIEWER_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line);
+        }
+    }
+}
+
+/// What the player list panel shows, gathered by the app every frame
+#[derive(Default)]
+pub struct PlayerList {
+    /// Local player first
+    pub entries: Vec<PlayerListEntry>,
+
+    /// Whether the server is hosted from here, so players can be kicked
+    pub hosting: bool,
+    pub spectating: Option<PlayerId>,
+
+    /// Screen position of the player picked with Locate, kept on screen
+    pub marker: Option<Pos2>,
+}
+
+pub struct PlayerListEntry {
+    pub id: PlayerId,
+
+    /// Shown instead of the id when set
+    pub name: Option<String>,
+    pub is_local: bool,
+
+    /// World units away from the local player
+    pub distance: f32,
+
+    /// Only known to the host
+    pub ping: Option<Duration>,
+    pub muted: bool,
+
+    /// Scoreboard totals, once the server sent them
+    pub stats: Option<PlayerStats>,
+
+    /// Joined with a public key that isn't on the friends list yet
+    pub can_befriend: bool,
+}
+
+/// Row of the friends list in the menu
+pub struct FriendEntry {
+    pub key: PublicKey,
+    pub name: String,
+
+    /// Server they were last seen on
+    pub server: Option<String>,
+
+    /// Whether that server answered with them as its host
+    pub hosting: bool,
+}
+
+/// Totals the server keeps for each player
+#[derive(Clone, Copy, Debug)]
+pub struct PlayerStats {
+    /// World units traveled
+    pub distance: f32,
+    pub played: Duration,
+}
+
+/// Row of the server list in the menu
+pub struct ServerEntry {
+    pub address: String,
+    pub favorite: bool,
+
+    /// `None` until the server answered a status query
+    pub players: Option<u32>,
+    pub rtt: Option<Duration>,
+    pub version: Option<Version>,
+}
+
+/// Player list, friends list or server list button the app has to act on
+pub enum PlayerAction {
+    Spectate(PlayerId),
+    StopSpectating,
+    Locate(PlayerId),
+    ToggleMute(PlayerId),
+    Kick(PlayerId),
+    AddFriend(PlayerId),
+    RemoveFriend(PublicKey),
+    ToggleFavorite(String),
 }
 
 impl Gui {
     pub fn new(event_loop: &ActiveEventLoop, gl: Arc<glow::Context>) -> Self {
         let egui_glow = EguiGlow::new(event_loop, gl, None, None, true);
 
-        egui_glow.egui_ctx.style_mut(|style| {
-            style.visuals = Visuals::light();
-            style.visuals.window_shadow = Shadow::NONE;
-            style.visuals.window_rounding = Rounding::ZERO;
-        });
+        Self::painted_by(EguiBackend::Glow(egui_glow))
+    }
+
+    /// GUI painted over the world of the wgpu renderer
+    #[cfg(feature = "wgpu")]
+    pub fn with_wgpu(egui_wgpu: EguiWgpu) -> Self {
+        Self::painted_by(EguiBackend::Wgpu(egui_wgpu))
+    }
+
+    fn painted_by(egui: EguiBackend) -> Self {
+        set_style(egui.ctx());
 
+        Self::with_egui(Some(egui), open_session_log())
+    }
+
+    fn with_egui(egui: Option<EguiBackend>, session_log: Option<RotatingLogFile>) -> Self {
         Self {
-            egui_glow,
-            log_messages: String::new(),
+            egui,
+            log: VecDeque::new(),
+            log_hidden: HashSet::new(),
+            session_log,
             server_hostname: String::from(globals::LOCAL_HOST),
             server_port: globals::DEFAULT_PORT.to_string(),
-            status_text: String::from("Ready."),
+            paste_address: false,
+            invite: InviteForm::default(),
+            status_text: String::from(tr("status.ready")),
             status_color: Color32::BLACK,
+            disconnect_reason: None,
+            session_totals: None,
+            debug_overlay: false,
+            debug_detached: false,
+            dash_cooldown: 0.0,
+            hosting_address: None,
+            hosting_port: None,
+            hosting_players: None,
+            hosting_invite_code: None,
+            friends: Vec::new(),
+            servers: Vec::new(),
+            player_list_open: false,
+            console: Console::default(),
+            chat: Chat::default(),
+            trace_viewer: TraceViewer::default(),
+            toasts: Toasts::default(),
+            visible_toasts: VecDeque::new(),
+            accessibility: Accessibility::default(),
+            accessibility_changed: false,
         }
     }
 
     pub fn handle_events(&mut self, window: &winit::window::Window, event: &WindowEvent) {
-        let _ = self.egui_glow.on_window_event(&window, &event);
+        if let Some(egui) = self.egui.as_mut() {
+            egui.on_window_event(window, event);
+        }
     }
 
     pub fn prepare_frame(
         &mut self,
         window: &winit::window::Window,
         state_machine: &mut fsm::StateMachine,
-    ) {
-        self.egui_glow
-            .run(&window, |ctx| match state_machine.peek() {
-                Some(fsm::State::Menu) | Some(fsm::State::Connecting { .. }) => show_menu(
+        message_stats: Option<&MessageStats>,
+        interpolation: Option<InterpolationReport>,
+        connection_quality: Option<QualityReport>,
+        player_list: &PlayerList,
+    ) -> Vec<PlayerAction> {
+        self.update_toasts();
+        if self.trace_viewer.open {
+            self.trace_viewer.collect();
+        }
+
+        let Some(mut egui) = self.egui.take() else {
+            return Vec::new();
+        };
+
+        let mut actions = Vec::new();
+        egui.run(window, |ctx| {
+            actions.extend(self.show(
+                ctx,
+                state_machine,
+                message_stats,
+                interpolation,
+                connection_quality,
+                player_list,
+            ));
+        });
+
+        if std::mem::take(&mut self.paste_address) {
+            match egui.clipboard_text() {
+                Some(text) => self.paste_address(&text),
+                None => self.set_error_status(String::from(tr("status.clipboard_empty"))),
+            }
+        }
+        self.egui = Some(egui);
+
+        actions
+    }
+
+    /// Lay out one frame for the current state. Returns the player list buttons clicked.
+    fn show(
+        &mut self,
+        ctx: &egui::Context,
+        state_machine: &mut fsm::StateMachine,
+        message_stats: Option<&MessageStats>,
+        interpolation: Option<InterpolationReport>,
+        connection_quality: Option<QualityReport>,
+        player_list: &PlayerList,
+    ) -> Vec<PlayerAction> {
+        let mut actions = Vec::new();
+
+        match state_machine.base() {
+            Some(fsm::State::Menu) | Some(fsm::State::Connecting { .. }) => {
+                self.paste_address |= show_menu(
                     ctx,
                     state_machine,
                     &mut self.server_hostname,
                     &mut self.server_port,
+                    &mut self.invite,
                     &mut self.status_text,
                     &mut self.status_color,
-                ),
+                );
 
-                Some(fsm::State::Playing) => show_log(ctx, &self.log_messages),
+                show_version_label(ctx);
 
-                Some(fsm::State::Disconnected) => show_disconnected_dialog(
-                    ctx,
-                    state_machine,
-                    &mut self.log_messages,
-                    &mut self.status_text,
-                    &mut self.status_color,
-                ),
+                if show_accessibility(ctx, &mut self.accessibility) {
+                    set_text_scale(ctx, self.accessibility.text_scale());
+                    self.accessibility_changed = true;
+                }
+
+                if !self.servers.is_empty() {
+                    show_servers(
+                        ctx,
+                        state_machine,
+                        &self.servers,
+                        &mut self.status_text,
+                        &mut self.status_color,
+                        &mut actions,
+                    );
+                }
+
+                if !self.friends.is_empty() {
+                    show_friends(
+                        ctx,
+                        state_machine,
+                        &self.friends,
+                        &mut self.status_text,
+                        &mut self.status_color,
+                        &mut actions,
+                    );
+                }
+            }
+
+            Some(fsm::State::Playing) => {
+                if self.player_list_open {
+                    show_player_list(ctx, player_list, &mut actions);
+                }
+                if let Some(marker) = player_list.marker {
+                    show_locate_marker(ctx, marker);
+                }
+
+                show_log(ctx, &self.log, &mut self.log_hidden, &mut self.chat);
+
+                if self.hosting_players.is_some()
+                    || self.hosting_address.is_some()
+                    || self.hosting_port.is_some()
+                    || self.hosting_invite_code.is_some()
+                {
+                    show_hosting_info(
+                        ctx,
+                        self.hosting_players,
+                        self.hosting_address.as_deref(),
+                        self.hosting_port,
+                        self.hosting_invite_code.as_deref(),
+                    );
+                }
+
+                if let Some(report) = connection_quality {
+                    show_connection_quality(ctx, report);
+                }
+
+                show_dash_cooldown(ctx, self.dash_cooldown);
+            }
+
+            Some(fsm::State::Disconnected) => show_disconnected_dialog(
+                ctx,
+                state_machine,
+                &mut self.log,
+                &mut self.status_text,
+                &mut self.status_color,
+                &mut self.disconnect_reason,
+                self.session_totals,
+            ),
+
+            _ => {}
+        }
+
+        // Dialogs go over whatever the state underneath shows
+        match state_machine.overlay() {
+            Some(fsm::State::QuitDialog) => show_quit_dialog(ctx, state_machine),
+
+            Some(fsm::State::Motd(motd)) => {
+                let motd = motd.clone();
+                show_motd_dialog(ctx, state_machine, &motd);
+            }
+
+            Some(fsm::State::CrashReport(path)) => {
+                let path = path.clone();
+                show_crash_dialog(ctx, state_machine, &path);
+            }
+
+            _ => {}
+        }
+
+        if self.debug_overlay && !self.debug_detached {
+            show_debug_overlay(
+                ctx,
+                message_stats,
+                interpolation,
+                connection_quality.map(|report| report.liveness),
+                &mut self.debug_detached,
+            );
+        }
+
+        if self.trace_viewer.open {
+            show_trace_viewer(ctx, &mut self.trace_viewer);
+        }
 
-                _ => {}
+        if self.console.open {
+            show_console(ctx, &mut self.console);
+        }
+
+        show_toasts(ctx, &self.visible_toasts);
+
+        actions
+    }
+
+    /// Whether the mouse is over a GUI window or dragging something in it
+    pub fn wants_pointer(&self) -> bool {
+        self.egui.as_ref().is_some_and(|egui| {
+            egui.ctx().is_pointer_over_area() || egui.ctx().wants_pointer_input()
+        })
+    }
+
+    /// Whether key presses belong to the GUI rather than the game: the chat line or console is
+    /// open, or a text field such as the server address has focus
+    pub fn wants_keyboard(&self) -> bool {
+        self.egui
+            .as_ref()
+            .is_some_and(|egui| self.keyboard_captured(egui.ctx()))
+    }
+
+    fn keyboard_captured(&self, ctx: &egui::Context) -> bool {
+        self.chat.open || self.console.open || ctx.wants_keyboard_input()
+    }
+
+    /// Show or hide the network debug overlay (F3), hiding it closes its detached window too
+    pub fn toggle_debug_overlay(&mut self) {
+        self.debug_overlay = !self.debug_overlay && !self.debug_detached;
+        self.debug_detached = false;
+    }
+
+    /// Whether the debug overlay wants a window of its own
+    pub fn debug_detached(&self) -> bool {
+        self.debug_overlay && self.debug_detached
+    }
+
+    /// Put the debug overlay back over the game, e.g. when its window was closed
+    pub fn attach_debug_overlay(&mut self) {
+        self.debug_detached = false;
+    }
+
+    /// Show or hide the live message trace (F10)
+    pub fn toggle_trace_viewer(&mut self) {
+        self.trace_viewer.open = !self.trace_viewer.open;
+        message::set_trace_capture(self.trace_viewer.open);
+    }
+
+    /// Show or hide the player list side panel (Tab)
+    pub fn toggle_player_list(&mut self) {
+        self.player_list_open = !self.player_list_open;
+    }
+
+    /// Handle for showing toasts from anywhere
+    pub fn toasts(&self) -> Toasts {
+        self.toasts.clone()
+    }
+
+    pub fn toast(&self, severity: Severity, text: String) {
+        self.toasts.push(severity, text);
+    }
+
+    fn update_toasts(&mut self) {
+        self.visible_toasts
+            .retain(|toast| toast.shown_at.elapsed() < TOAST_DURATION);
+
+        for (severity, text) in self.toasts.take() {
+            if self.visible_toasts.len() == MAX_VISIBLE_TOASTS {
+                self.visible_toasts.pop_front();
+            }
+            self.visible_toasts.push_back(VisibleToast {
+                severity,
+                text,
+                shown_at: Instant::now(),
             });
+        }
+    }
+
+    /// Open or close the console (~)
+    pub fn toggle_console(&mut self) {
+        self.console.open = !self.console.open;
+    }
+
+    pub fn console_open(&self) -> bool {
+        self.console.open
     }
+
+    /// Print command output in the console
+    pub fn console_print(&mut self, text: &str) {
+        self.console.print(text);
+    }
+
+    /// Lines entered in the console since the last call
+    /// Settings loaded from the config, to show in the menu and apply to the text size
+    pub fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.accessibility = accessibility;
+        if let Some(egui) = &self.egui {
+            set_text_scale(egui.ctx(), accessibility.text_scale());
+        }
+    }
+
+    /// Accessibility settings changed in the menu since the last call, for the app to save and
+    /// pass on to the renderer
+    pub fn take_accessibility_change(&mut self) -> Option<Accessibility> {
+        std::mem::take(&mut self.accessibility_changed).then_some(self.accessibility)
+    }
+
+    pub fn take_console_commands(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.console.submitted)
+    }
+
+    /// Start typing a chat line (Enter)
+    pub fn open_chat(&mut self) {
+        self.chat.open = true;
+    }
+
+    /// Stop typing, dropping the unsent line
+    pub fn close_chat(&mut self) {
+        self.chat.open = false;
+        self.chat.input.clear();
+    }
+
+    pub fn chat_open(&self) -> bool {
+        self.chat.open
+    }
+
+    /// Chat lines entered since the last call
+    pub fn take_chat_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.chat.submitted)
+    }
+
     /// Issue batched draw call
     pub fn draw(&mut self, window: &winit::window::Window) {
-        self.egui_glow.paint(&window);
+        if let Some(egui) = self.egui.as_mut() {
+            egui.paint(window);
+        }
     }
 
     /// Redirect message to gameplay log window
-    pub fn log(&mut self, msg: String) {
-        self.log_messages += &format!("{msg}\n");
+    pub fn log(&mut self, severity: Severity, msg: String) {
+        crash::record_log(&msg);
+
+        if let Some(session_log) = self.session_log.as_mut() {
+            if let Err(e) = session_log.write_line(&msg) {
+                eprintln!("Failed to write session log: {e}");
+                self.session_log = None;
+            }
+        }
+
+        if self.log.len() == LOG_HISTORY_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(LogEntry {
+            severity,
+            time: SystemTime::now(),
+            text: msg,
+        });
+    }
+
+    /// Draw text in `font` ahead of egui's built-in ones, which still cover what it lacks
+    pub fn set_ui_font(&mut self, font: &[u8]) {
+        let Some(egui) = &self.egui else {
+            return;
+        };
+
+        let mut fonts = egui::FontDefinitions::default();
+        fonts.font_data.insert(
+            String::from("ui"),
+            egui::FontData::from_owned(font.to_vec()),
+        );
+        fonts
+            .families
+            .entry(egui::FontFamily::Proportional)
+            .or_default()
+            .insert(0, String::from("ui"));
+
+        egui.ctx().set_fonts(fonts);
+    }
+
+    pub fn set_hosting_address(&mut self, address: Option<String>) {
+        self.hosting_address = address;
+    }
+
+    pub fn set_hosting_port(&mut self, port: Option<u16>) {
+        self.hosting_port = port;
+    }
+
+    pub fn set_hosting_players(&mut self, players: Option<(usize, usize)>) {
+        self.hosting_players = players;
+    }
+
+    pub fn set_invite_code(&mut self, invite_code: Option<String>) {
+        self.hosting_invite_code = invite_code;
+    }
+
+    pub fn set_friends(&mut self, friends: Vec<FriendEntry>) {
+        self.friends = friends;
+    }
+
+    pub fn set_servers(&mut self, servers: Vec<ServerEntry>) {
+        self.servers = servers;
+    }
+
+    /// Part of the dash cooldown still to go, between 0 and 1
+    pub fn set_dash_cooldown(&mut self, remaining: f32) {
+        self.dash_cooldown = remaining.clamp(0.0, 1.0);
+    }
+
+    /// Informational status on the connection menu
+    pub fn set_status(&mut self, msg: String) {
+        self.status_color = Color32::BLACK;
+        self.status_text = msg;
+    }
+
+    pub fn set_session_totals(&mut self, totals: Option<PlayerStats>) {
+        self.session_totals = totals;
+    }
+
+    pub fn set_disconnect_reason(&mut self, reason: String) {
+        self.disconnect_reason = Some(reason);
     }
 
     /// Error status on connection menu and Disconnected message dialog
@@ -87,160 +816,1129 @@ impl Gui {
         self.status_color = Color32::RED;
         self.status_text = msg;
     }
+
+    /// Fill the address fields from pasted text, splitting "host:port" into both
+    fn paste_address(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            self.set_error_status(String::from(tr("status.clipboard_empty")));
+            return;
+        }
+
+        match addr::split_host_port(text) {
+            Some((host, port)) => {
+                self.server_hostname = host.to_string();
+                self.server_port = port.to_string();
+            }
+            None => self.server_hostname = text.to_string(),
+        }
+    }
+}
+
+////////////////////////////////////////////////
+
+/// Returns whether "Paste address" was clicked
+fn show_menu(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    server_hostname: &mut String,
+    server_port: &mut String,
+    invite: &mut InviteForm,
+    status_text: &mut String,
+    status_color: &mut Color32,
+) -> bool {
+    let mut paste_address = false;
+
+    Window::new("join_server_menu")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            Grid::new("join_server_grid")
+                .num_columns(2)
+                .spacing([10.0, 10.0])
+                .show(ui, |ui| {
+                    // Server address textbox
+                    ui.label(tr("menu.server_address"));
+                    let address_edit = ui
+                        .horizontal(|ui| {
+                            let edit =
+                                ui.add(TextEdit::singleline(server_hostname).desired_width(150.0));
+                            if ui.small_button(tr("menu.paste_address")).clicked() {
+                                paste_address = true;
+                            }
+                            edit
+                        })
+                        .inner;
+                    ui.end_row();
+
+                    // A full "host:port" address fills in both fields once it is pasted or
+                    // the field is left, not while a port is still being typed
+                    let pasted = address_edit.has_focus()
+                        && ui
+                            .input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Paste(_))));
+                    if pasted || address_edit.lost_focus() {
+                        split_address_field(server_hostname, server_port);
+                    }
+
+                    // Sever port number textbox
+                    ui.label(tr("menu.port"));
+                    ui.add(TextEdit::singleline(server_port).desired_width(150.0));
+                    ui.end_row();
+
+                    // Needed to join private servers
+                    ui.label(tr("menu.invite_code"));
+                    ui.add(TextEdit::singleline(&mut invite.code).desired_width(150.0));
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.checkbox(&mut invite.private, tr("menu.private_server"));
+                    ui.end_row();
+
+                    // Language picker
+                    ui.label(tr("menu.language"));
+                    show_language_picker(ui, status_text);
+                    ui.end_row();
+
+                    // Disable "Connect" button while client is trying to
+                    // connect
+                    let connect_button_enabled =
+                        !matches!(state_machine.peek(), Some(fsm::State::Connecting { .. }));
+
+                    // Create server button
+                    let create_button = ui.add_enabled(
+                        connect_button_enabled,
+                        Button::new(tr("menu.create_server")),
+                    );
+
+                    if create_button.clicked() {
+                        split_address_field(server_hostname, server_port);
+                        match addr::parse_host_and_port(server_hostname, server_port) {
+                            Ok(endpoint) => {
+                                *status_text = String::from(tr("status.connecting"));
+
+                                *status_color = Color32::BLACK;
+
+                                state_machine.push(fsm::State::Connecting {
+                                    endpoint,
+                                    session_mode: fsm::SessionMode::CreateServer,
+                                    invite_code: invite.private.then(server::generate_invite_code),
+                                });
+                            }
+
+                            Err(address_parse_err) => {
+                                *status_text = address_parse_err.to_string();
+                                *status_color = Color32::RED;
+                            }
+                        }
+                    }
+
+                    // Join server button
+                    let join_button =
+                        ui.add_enabled(connect_button_enabled, Button::new(tr("menu.join_server")));
+
+                    if join_button.clicked() {
+                        let invite_code = match invite.code.trim() {
+                            "" => Ok(None),
+                            code if server::is_valid_invite_code(code) => {
+                                Ok(Some(code.to_string()))
+                            }
+                            _ => Err(tr("status.invalid_invite_code").to_string()),
+                        };
+
+                        split_address_field(server_hostname, server_port);
+
+                        match addr::parse_host_and_port(server_hostname, server_port)
+                            .map_err(|e| e.to_string())
+                            .and_then(|endpoint| Ok((endpoint, invite_code?)))
+                        {
+                            Ok((endpoint, invite_code)) => {
+                                *status_text = String::from(tr("status.connecting"));
+
+                                *status_color = Color32::BLACK;
+
+                                state_machine.push(fsm::State::Connecting {
+                                    endpoint,
+                                    session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                                    invite_code,
+                                });
+                            }
+
+                            Err(address_parse_err) => {
+                                *status_text = address_parse_err.to_string();
+
+                                *status_color = Color32::RED;
+                            }
+                        }
+                    }
+
+                    // STATUS LABEL
+                    ui.colored_label(*status_color, status_text);
+                    ui.end_row();
+
+                    // Quit button
+                    if ui.button(tr("menu.quit")).clicked() {
+                        state_machine.push(fsm::State::QuitDialog);
+                    }
+
+                    ui.end_row();
+                })
+        });
+
+    paste_address
+}
+
+/// Move the port of a "host:port" typed into the address field to the port field
+fn split_address_field(server_hostname: &mut String, server_port: &mut String) {
+    if let Some((host, port)) = addr::split_host_port(server_hostname) {
+        *server_port = port.to_string();
+        *server_hostname = host.to_string();
+    }
+}
+
+/// Build of the game in a corner of the menu, for bug reports
+fn show_version_label(ctx: &egui::Context) {
+    egui::Area::new(Id::new("version_label"))
+        .anchor(Align2::RIGHT_BOTTOM, Vec2::new(-6.0, -4.0))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.small(format!("v{}", version::LONG_VERSION));
+        });
+}
+
+/// Accessibility options in a corner of the menu. Returns whether any changed.
+fn show_accessibility(ctx: &egui::Context, accessibility: &mut Accessibility) -> bool {
+    let mut changed = false;
+
+    Window::new(tr("accessibility.title"))
+        .default_open(false)
+        .resizable(false)
+        .anchor(Align2::LEFT_BOTTOM, Vec2::new(10.0, -10.0))
+        .show(ctx, |ui| {
+            changed |= ui
+                .checkbox(
+                    &mut accessibility.reduced_motion,
+                    tr("accessibility.reduced_motion"),
+                )
+                .changed();
+            changed |= ui
+                .checkbox(
+                    &mut accessibility.high_contrast,
+                    tr("accessibility.high_contrast"),
+                )
+                .changed();
+            changed |= ui
+                .checkbox(
+                    &mut accessibility.large_text,
+                    tr("accessibility.large_text"),
+                )
+                .changed();
+        });
+
+    changed
+}
+
+fn show_servers(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    servers: &[ServerEntry],
+    status_text: &mut String,
+    status_color: &mut Color32,
+    actions: &mut Vec<PlayerAction>,
+) {
+    let connecting = matches!(state_machine.peek(), Some(fsm::State::Connecting { .. }));
+
+    Window::new(tr("servers.title"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::LEFT_TOP, Vec2::new(10.0, 10.0))
+        .show(ctx, |ui| {
+            Grid::new("servers_grid")
+                .num_columns(5)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("");
+                    ui.strong(tr("servers.address"));
+                    ui.strong(tr("servers.players"));
+                    ui.strong(tr("servers.ping"));
+                    ui.label("");
+                    ui.end_row();
+
+                    for server in servers {
+                        let star = if server.favorite { "★" } else { "☆" };
+                        if ui
+                            .selectable_label(server.favorite, star)
+                            .on_hover_text(tr("servers.favorite"))
+                            .clicked()
+                        {
+                            actions.push(PlayerAction::ToggleFavorite(server.address.clone()));
+                        }
+
+                        match &server.version {
+                            Some(version) if !version.is_compatible() => ui
+                                .colored_label(Color32::RED, &server.address)
+                                .on_hover_text(tr_args(
+                                    "servers.incompatible",
+                                    &[("version", version)],
+                                )),
+                            Some(version) => {
+                                ui.label(&server.address).on_hover_text(version.to_string())
+                            }
+                            None => ui.label(&server.address),
+                        };
+                        ui.label(
+                            server
+                                .players
+                                .map_or_else(|| String::from("-"), |players| players.to_string()),
+                        );
+                        ui.label(
+                            server
+                                .rtt
+                                .map(|rtt| format!("{:.0} ms", rtt.as_secs_f32() * 1000.0))
+                                .unwrap_or_else(|| String::from("-")),
+                        );
+
+                        let join = ui.add_enabled(!connecting, Button::new(tr("servers.join")));
+                        if join.clicked() {
+                            match addr::parse_endpoint(&server.address) {
+                                Ok(endpoint) => {
+                                    *status_text = String::from(tr("status.connecting"));
+                                    *status_color = Color32::BLACK;
+
+                                    state_machine.push(fsm::State::Connecting {
+                                        endpoint,
+                                        session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                                        invite_code: None,
+                                    });
+                                }
+                                Err(e) => {
+                                    *status_text = e.to_string();
+                                    *status_color = Color32::RED;
+                                }
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+fn show_friends(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    friends: &[FriendEntry],
+    status_text: &mut String,
+    status_color: &mut Color32,
+    actions: &mut Vec<PlayerAction>,
+) {
+    let connecting = matches!(state_machine.peek(), Some(fsm::State::Connecting { .. }));
+
+    Window::new(tr("friends.title"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::RIGHT_TOP, Vec2::new(-10.0, 10.0))
+        .show(ctx, |ui| {
+            Grid::new("friends_grid")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for friend in friends {
+                        // Names aren't unique, the start of the key tells them apart
+                        ui.label(format!("{} ({})", friend.name, friend.key.short()))
+                            .on_hover_text(friend.server.as_deref().unwrap_or_default());
+
+                        if friend.hosting {
+                            ui.label(tr("friends.hosting"));
+                        } else {
+                            ui.label("-");
+                        }
+
+                        ui.horizontal(|ui| {
+                            let join = ui.add_enabled(
+                                friend.hosting && !connecting,
+                                Button::new(tr("friends.join")),
+                            );
+                            if join.clicked() {
+                                match addr::parse_endpoint(
+                                    friend.server.as_deref().unwrap_or_default(),
+                                ) {
+                                    Ok(endpoint) => {
+                                        *status_text = String::from(tr("status.connecting"));
+                                        *status_color = Color32::BLACK;
+
+                                        state_machine.push(fsm::State::Connecting {
+                                            endpoint,
+                                            session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                                            invite_code: None,
+                                        });
+                                    }
+                                    Err(e) => {
+                                        *status_text = e.to_string();
+                                        *status_color = Color32::RED;
+                                    }
+                                }
+                            }
+
+                            if ui.small_button(tr("friends.remove")).clicked() {
+                                actions.push(PlayerAction::RemoveFriend(friend.key));
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+fn show_language_picker(ui: &mut egui::Ui, status_text: &mut String) {
+    let current = i18n::language();
+
+    ComboBox::from_id_salt("language_picker")
+        .selected_text(current.native_name())
+        .show_ui(ui, |ui| {
+            for lang in Language::ALL {
+                if ui
+                    .selectable_label(lang == current, lang.native_name())
+                    .clicked()
+                    && lang != current
+                {
+                    // Idle status is re-translated right away, anything else is left as is
+                    let was_ready = status_text == tr("status.ready");
+                    i18n::set_language(lang);
+                    if was_ready {
+                        *status_text = String::from(tr("status.ready"));
+                    }
+                }
+            }
+        });
+}
+
+//-----------------------------------------------
+
+fn show_log(
+    ctx: &egui::Context,
+    log: &VecDeque<LogEntry>,
+    hidden: &mut HashSet<Severity>,
+    chat: &mut Chat,
+) {
+    let style = (*ctx.style()).clone();
+    ctx.style_mut(|style| {
+        style.visuals.window_fill = Color32::from_rgba_unmultiplied(255, 255, 255, 32);
+    });
+
+    Window::new("log")
+        .title_bar(false)
+        .anchor(Align2::LEFT_TOP, egui::Vec2::ZERO)
+        .fixed_size([240.0, 100.0])
+        .show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for severity in Severity::ALL {
+                    let shown = !hidden.contains(&severity);
+                    if ui
+                        .selectable_label(shown, severity.filter_label())
+                        .clicked()
+                    {
+                        if shown {
+                            hidden.insert(severity);
+                        } else {
+                            hidden.remove(&severity);
+                        }
+                    }
+                }
+
+                if ui.small_button(tr("log.copy")).clicked() {
+                    let text: Vec<String> = log.iter().map(LogEntry::to_string).collect();
+                    ctx.copy_text(text.join("\n"));
+                }
+            });
+
+            // Leave room for the chat line below
+            let log_height = if chat.open {
+                ui.available_height() - ui.spacing().interact_size.y
+            } else {
+                ui.available_height()
+            };
+
+            egui::ScrollArea::vertical()
+                .auto_shrink(false)
+                .stick_to_bottom(true)
+                .max_height(log_height)
+                .show(ui, |ui| {
+                    for entry in log.iter().filter(|e| !hidden.contains(&e.severity)) {
+                        let mut text = egui::RichText::new(&entry.text);
+                        if let Some(color) = entry.severity.color() {
+                            text = text.color(color);
+                        }
+                        ui.label(text).on_hover_text(clock_time(entry.time));
+                    }
+                });
+
+            if chat.open {
+                let input = ui.add(
+                    TextEdit::singleline(&mut chat.input)
+                        .hint_text(tr("chat.hint"))
+                        .char_limit(globals::MAX_CHAT_LEN)
+                        .desired_width(f32::INFINITY),
+                );
+
+                // Enter sends the line and hands the keys back to the game
+                if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    let line = std::mem::take(&mut chat.input);
+                    if !line.trim().is_empty() {
+                        chat.submitted.push(line);
+                    }
+                    chat.open = false;
+                } else {
+                    input.request_focus();
+                }
+            }
+        });
+
+    // reset style for other dialog widgets
+    ctx.set_style(style);
+}
+
+fn show_player_list(
+    ctx: &egui::Context,
+    player_list: &PlayerList,
+    actions: &mut Vec<PlayerAction>,
+) {
+    SidePanel::right("player_list")
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.heading(tr("players.title"));
+
+            if player_list.spectating.is_some()
+                && ui.button(tr("players.stop_spectating")).clicked()
+            {
+                actions.push(PlayerAction::StopSpectating);
+            }
+
+            Grid::new("player_list_grid")
+                .num_columns(6)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong(tr("players.name"));
+                    ui.strong(tr("players.ping"));
+                    ui.strong(tr("players.distance"));
+                    ui.strong(tr("players.traveled"));
+                    ui.strong(tr("players.played"));
+                    ui.strong("");
+                    ui.end_row();
+
+                    for entry in &player_list.entries {
+                        let name = entry
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| tr_args("players.player", &[("id", &entry.id)]));
+                        if entry.is_local {
+                            ui.label(format!("{name} ({})", tr("players.you")));
+                        } else {
+                            ui.label(name);
+                        }
+
+                        ui.label(
+                            entry
+                                .ping
+                                .map(|ping| format!("{:.0} ms", ping.as_secs_f32() * 1000.0))
+                                .unwrap_or_else(|| String::from("-")),
+                        );
+                        ui.label(format!("{:.0}", entry.distance));
+                        match entry.stats {
+                            Some(stats) => {
+                                ui.label(format!("{:.0}", stats.distance));
+                                ui.label(format_played(stats.played));
+                            }
+                            None => {
+                                ui.label("-");
+                                ui.label("-");
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            if entry.is_local {
+                                return;
+                            }
+
+                            let spectating = player_list.spectating == Some(entry.id);
+                            if ui
+                                .selectable_label(spectating, tr("players.spectate"))
+                                .clicked()
+                            {
+                                actions.push(if spectating {
+                                    PlayerAction::StopSpectating
+                                } else {
+                                    PlayerAction::Spectate(entry.id)
+                                });
+                            }
+
+                            if ui.small_button(tr("players.locate")).clicked() {
+                                actions.push(PlayerAction::Locate(entry.id));
+                            }
+
+                            if ui
+                                .selectable_label(entry.muted, tr("players.mute"))
+                                .clicked()
+                            {
+                                actions.push(PlayerAction::ToggleMute(entry.id));
+                            }
+
+                            if player_list.hosting && ui.small_button(tr("players.kick")).clicked()
+                            {
+                                actions.push(PlayerAction::Kick(entry.id));
+                            }
+
+                            if entry.can_befriend
+                                && ui.small_button(tr("players.add_friend")).clicked()
+                            {
+                                actions.push(PlayerAction::AddFriend(entry.id));
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+/// Log file of this GUI session in the logs folder of the data directory, named after the start
+/// time
+fn set_style(ctx: &egui::Context) {
+    ctx.style_mut(|style| {
+        style.visuals = Visuals::light();
+        style.visuals.window_shadow = Shadow::NONE;
+        style.visuals.window_rounding = Rounding::ZERO;
+    });
+}
+
+/// Size every kind of GUI text at `scale` times egui's default
+fn set_text_scale(ctx: &egui::Context, scale: f32) {
+    let defaults = egui::Style::default().text_styles;
+    ctx.style_mut(|style| {
+        for (text_style, font) in &mut style.text_styles {
+            if let Some(default) = defaults.get(text_style) {
+                font.size = default.size * scale;
+            }
+        }
+    });
+}
+
+fn open_session_log() -> Option<RotatingLogFile> {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let result = paths::ensure_dir(paths::log_dir())
+        .and_then(|dir| RotatingLogFile::open(&dir.join(format!("session-{started}.log"))));
+
+    match result {
+        Ok(session_log) => Some(session_log),
+        Err(e) => {
+            eprintln!("Failed to create session log: {e}");
+            None
+        }
+    }
+}
+
+/// Newest toast at the bottom right, older ones stacked above it
+fn show_toasts(ctx: &egui::Context, toasts: &VecDeque<VisibleToast>) {
+    let mut offset = Vec2::new(-10.0, -10.0);
+
+    for (i, toast) in toasts.iter().rev().enumerate() {
+        let remaining = TOAST_DURATION.saturating_sub(toast.shown_at.elapsed());
+        let opacity = (remaining.as_secs_f32() / TOAST_FADE_OUT.as_secs_f32()).min(1.0);
+
+        let response = egui::Area::new(Id::new("toast").with(i))
+            .order(Order::Foreground)
+            .anchor(Align2::RIGHT_BOTTOM, offset)
+            .interactable(false)
+            .show(ctx, |ui| {
+                ui.set_opacity(opacity);
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    let mut text = egui::RichText::new(&toast.text);
+                    if let Some(color) = toast.severity.color() {
+                        text = text.color(color);
+                    }
+                    ui.label(text);
+                });
+            })
+            .response;
+
+        offset.y -= response.rect.height() + 6.0;
+    }
+
+    if !toasts.is_empty() {
+        ctx.request_repaint();
+    }
+}
+
+/// Ring around a located player, pulsing so it catches the eye
+fn show_locate_marker(ctx: &egui::Context, pos: Pos2) {
+    let pulse = (ctx.input(|i| i.time) * 6.0).sin() as f32 * 4.0;
+    let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("locate_marker")));
+
+    painter.circle_stroke(
+        pos,
+        28.0 + pulse,
+        Stroke::new(3.0, Color32::from_rgb(255, 200, 0)),
+    );
+    ctx.request_repaint();
+}
+
+/// Signal bars in the top right corner, details on hover
+fn show_connection_quality(ctx: &egui::Context, report: QualityReport) {
+    const BAR_WIDTH: f32 = 4.0;
+    const BAR_STEP: f32 = 4.0;
+    const BAR_SPACING: f32 = 2.0;
+
+    let color = if report.bars <= UNSTABLE_CONNECTION_BARS {
+        Color32::RED
+    } else {
+        Color32::from_rgb(0, 160, 0)
+    };
+
+    egui::Area::new(Id::new("connection_quality"))
+        .order(Order::Foreground)
+        .anchor(Align2::RIGHT_TOP, Vec2::new(-8.0, 8.0))
+        .show(ctx, |ui| {
+            let size = Vec2::new(4.0 * (BAR_WIDTH + BAR_SPACING), 4.0 * BAR_STEP);
+            let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+
+            for i in 0..4u8 {
+                let height = BAR_STEP * (i + 1) as f32;
+                let left = rect.left() + i as f32 * (BAR_WIDTH + BAR_SPACING);
+                let bar = egui::Rect::from_min_max(
+                    egui::pos2(left, rect.bottom() - height),
+                    egui::pos2(left + BAR_WIDTH, rect.bottom()),
+                );
+                let fill = if i < report.bars {
+                    color
+                } else {
+                    Color32::from_gray(160)
+                };
+                ui.painter().rect_filled(bar, 0.0, fill);
+            }
+
+            response.on_hover_text(tr_args(
+                "hud.connection_quality",
+                &[
+                    ("loss", &format!("{:.1}", report.loss * 100.0)),
+                    (
+                        "jitter",
+                        &format!("{:.1}", report.jitter.as_secs_f32() * 1000.0),
+                    ),
+                ],
+            ));
+        });
+}
+
+fn show_dash_cooldown(ctx: &egui::Context, remaining: f32) {
+    let text = match remaining {
+        0.0 => tr("hud.dash_ready"),
+        _ => tr("hud.dash"),
+    };
+
+    egui::Area::new(Id::new("dash_cooldown"))
+        .order(Order::Foreground)
+        .anchor(Align2::CENTER_BOTTOM, Vec2::new(0.0, -16.0))
+        .show(ctx, |ui| {
+            ui.add(
+                egui::ProgressBar::new(1.0 - remaining)
+                    .desired_width(120.0)
+                    .text(text),
+            );
+        });
+}
+
+fn show_hosting_info(
+    ctx: &egui::Context,
+    players: Option<(usize, usize)>,
+    address: Option<&str>,
+    port: Option<u16>,
+    invite_code: Option<&str>,
+) {
+    Window::new("hosting_address")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(Align2::LEFT_BOTTOM, Vec2::ZERO)
+        .show(ctx, |ui| {
+            if let Some((count, max)) = players {
+                ui.label(tr_args(
+                    "hosting.players",
+                    &[("count", &count), ("max", &max)],
+                ));
+            }
+
+            if let Some(port) = port {
+                ui.label(tr_args("hosting.port", &[("port", &port)]));
+            }
+
+            // Selectable so they can be copied and sent to friends
+            if let Some(mut address) = address {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", tr("hosting.internet_address")));
+                    ui.add(TextEdit::singleline(&mut address).desired_width(160.0));
+                });
+            }
+            if let Some(mut invite_code) = invite_code {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", tr("hosting.invite_code")));
+                    ui.add(TextEdit::singleline(&mut invite_code).desired_width(80.0));
+                });
+            }
+        });
+}
+
+// -------------------------------------------------
+
+fn show_disconnected_dialog(
+    ctx: &egui::Context,
+    state_machine: &mut fsm::StateMachine,
+    log: &mut VecDeque<LogEntry>,
+    status_text: &mut String,
+    status_color: &mut Color32,
+    disconnect_reason: &mut Option<String>,
+    session_totals: Option<PlayerStats>,
+) {
+    CentralPanel::default()
+        .frame(Frame::none().fill(Color32::from_black_alpha(192)))
+        .show(ctx, |_| {});
+
+    Window::new("disconnected_dialog")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+        .fixed_size([300.0, 100.0])
+        .show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.label(
+                    disconnect_reason
+                        .as_deref()
+                        .unwrap_or(tr("dialog.connection_lost")),
+                );
+                if let Some(totals) = session_totals {
+                    ui.label(tr_args(
+                        "dialog.session_totals",
+                        &[
+                            ("distance", &format!("{:.0}", totals.distance)),
+                            ("time", &format_played(totals.played)),
+                        ],
+                    ));
+                }
+                if ui.button(tr("dialog.ok")).clicked() {
+                    *disconnect_reason = None;
+                    state_machine.change(fsm::State::Menu);
+                    log.clear();
+                    *status_text = String::from(tr("status.ready"));
+                    *status_color = Color32::BLACK;
+                }
+            });
+        });
+}
+
+fn show_trace_viewer(ctx: &egui::Context, viewer: &mut TraceViewer) {
+    let mut open = viewer.open;
+
+    Window::new(tr("trace.title"))
+        .id(Id::new("trace_viewer"))
+        .open(&mut open)
+        .default_size([560.0, 320.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let mut enabled = message::trace_enabled();
+                if ui.checkbox(&mut enabled, tr("trace.enabled")).changed() {
+                    message::set_trace(enabled);
+                }
+                if ui.button(tr("trace.clear")).clicked() {
+                    viewer.lines.clear();
+                }
+            });
+
+            ui.horizontal_wrapped(|ui| {
+                let filters = MESSAGE_NAMES
+                    .iter()
+                    .map(|name| (Some(*name), *name))
+                    .chain([(None, tr("trace.other"))]);
+
+                for (name, label) in filters {
+                    let shown = !viewer.hidden.contains(&name);
+                    if ui.selectable_label(shown, label).clicked() {
+                        if shown {
+                            viewer.hidden.insert(name);
+                        } else {
+                            viewer.hidden.remove(&name);
+                        }
+                    }
+                }
+            });
+
+            ui.separator();
+
+            egui::ScrollArea::both()
+                .auto_shrink(false)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    let shown = viewer
+                        .lines
+                        .iter()
+                        .filter(|line| !viewer.hidden.contains(&line.name));
+
+                    for line in shown {
+                        ui.label(egui::RichText::new(&line.text).text_style(TextStyle::Monospace));
+                    }
+                });
+        });
+
+    // Closed through the window's close button
+    if !open {
+        viewer.open = false;
+        message::set_trace_capture(false);
+    }
 }
 
-////////////////////////////////////////////////
-
-fn show_menu(
+fn show_debug_overlay(
     ctx: &egui::Context,
-    state_machine: &mut fsm::StateMachine,
-    server_hostname: &mut String,
-    server_port: &mut String,
-    status_text: &mut String,
-    status_color: &mut Color32,
+    message_stats: Option<&MessageStats>,
+    interpolation: Option<InterpolationReport>,
+    liveness: Option<Liveness>,
+    detached: &mut bool,
 ) {
-    Window::new("join_server_menu")
+    Window::new("debug_overlay")
         .title_bar(false)
-        .collapsible(false)
         .resizable(false)
-        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        // Below the connection quality indicator
+        .anchor(Align2::RIGHT_TOP, Vec2::new(0.0, 32.0))
         .show(ctx, |ui| {
-            Grid::new("join_server_grid")
-                .num_columns(2)
-                .spacing([10.0, 10.0])
-                .show(ui, |ui| {
-                    // Server address textbox
-                    ui.label("Server address:");
-                    ui.add(TextEdit::singleline(server_hostname).desired_width(150.0));
-                    ui.end_row();
+            if ui.small_button(tr("debug.detach")).clicked() {
+                *detached = true;
+            }
 
-                    // Sever port number textbox
-                    ui.label("Port:");
-                    ui.add(TextEdit::singleline(server_port).desired_width(150.0));
-                    ui.end_row();
+            show_liveness(ui, liveness);
+            show_interpolation(ui, interpolation);
+            show_message_stats(ui, message_stats);
+        });
+}
 
-                    // Disable "Connect" button while client is trying to
-                    // connect
-                    let connect_button_enabled =
-                        !matches!(state_machine.peek(), Some(fsm::State::Connecting { .. }));
+/// Pings missed in a row, against what the server allows before the connection counts as lost
+fn show_liveness(ui: &mut egui::Ui, liveness: Option<Liveness>) {
+    let Some(liveness) = liveness else {
+        return;
+    };
 
-                    // Create server button
-                    let create_button =
-                        ui.add_enabled(connect_button_enabled, Button::new("Create server"));
+    let text = |key| {
+        tr_args(
+            key,
+            &[("missed", &liveness.missed), ("max", &liveness.max_missed)],
+        )
+    };
+    if liveness.is_alive() {
+        ui.label(text("debug.liveness"));
+    } else {
+        ui.colored_label(Color32::RED, text("debug.liveness_lost"));
+    }
+}
 
-                    if create_button.clicked() {
-                        match verify_address_format(server_hostname, server_port) {
-                            Ok(_) => {
-                                *status_text = String::from("Connecting");
+/// Current interpolation delay, with the jitter it was tuned to and how often it changed
+fn show_interpolation(ui: &mut egui::Ui, report: Option<InterpolationReport>) {
+    let Some(report) = report else {
+        return;
+    };
 
-                                *status_color = Color32::BLACK;
+    let ms = |duration: Duration| format!("{:.1}", duration.as_secs_f32() * 1000.0);
+    ui.label(tr_args(
+        "debug.interpolation",
+        &[
+            ("delay", &ms(report.delay)),
+            ("jitter", &ms(report.jitter)),
+            ("adjustments", &report.adjustments),
+        ],
+    ));
+}
 
-                                state_machine.push(fsm::State::Connecting {
-                                    server_address: format!("{server_hostname}:{server_port}"),
-                                    session_mode: fsm::SessionMode::CreateServer,
-                                });
-                            }
+fn show_message_stats(ui: &mut egui::Ui, message_stats: Option<&MessageStats>) {
+    let Some(message_stats) = message_stats else {
+        ui.label(tr("debug.not_connected"));
+        return;
+    };
 
-                            Err(address_parse_err) => {
-                                *status_text = address_parse_err;
-                                *status_color = Color32::RED;
-                            }
-                        }
-                    }
+    Grid::new("debug_message_stats")
+        .num_columns(5)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("");
+            ui.strong(tr("debug.message_type"));
+            ui.strong(tr("debug.count"));
+            ui.strong(tr("debug.bytes"));
+            ui.strong(tr("debug.last_seen"));
+            ui.end_row();
 
-                    // Join server button
-                    let join_button =
-                        ui.add_enabled(connect_button_enabled, Button::new("Join server"));
+            for (direction, name, stats) in message_stats.iter() {
+                ui.label(direction.arrow());
+                ui.label(name);
+                ui.label(stats.count.to_string());
+                ui.label(stats.bytes.to_string());
+                ui.label(
+                    stats
+                        .last_seen
+                        .map(|t| format!("{:.1} s", t.elapsed().as_secs_f32()))
+                        .unwrap_or_default(),
+                );
+                ui.end_row();
+            }
+        });
+}
 
-                    if join_button.clicked() {
-                        match verify_address_format(server_hostname, server_port) {
-                            Ok(_) => {
-                                *status_text = String::from("Connecting");
+/// egui on the game window, painted by the backend that draws the world
+enum EguiBackend {
+    Glow(EguiGlow),
+    #[cfg(feature = "wgpu")]
+    Wgpu(EguiWgpu),
+}
 
-                                *status_color = Color32::BLACK;
+impl EguiBackend {
+    fn ctx(&self) -> &egui::Context {
+        match self {
+            EguiBackend::Glow(egui_glow) => &egui_glow.egui_ctx,
+            #[cfg(feature = "wgpu")]
+            EguiBackend::Wgpu(egui_wgpu) => &egui_wgpu.egui_ctx,
+        }
+    }
 
-                                state_machine.push(fsm::State::Connecting {
-                                    server_address: format!("{server_hostname}:{server_port}"),
-                                    session_mode: fsm::SessionMode::ConnectAsClientOnly,
-                                });
-                            }
+    fn on_window_event(&mut self, window: &winit::window::Window, event: &WindowEvent) {
+        match self {
+            EguiBackend::Glow(egui_glow) => {
+                let _ = egui_glow.on_window_event(window, event);
+            }
+            #[cfg(feature = "wgpu")]
+            EguiBackend::Wgpu(egui_wgpu) => egui_wgpu.on_window_event(window, event),
+        }
+    }
 
-                            Err(address_parse_err) => {
-                                *status_text = address_parse_err;
+    fn run(&mut self, window: &winit::window::Window, run_ui: impl FnMut(&egui::Context)) {
+        match self {
+            EguiBackend::Glow(egui_glow) => egui_glow.run(window, run_ui),
+            #[cfg(feature = "wgpu")]
+            EguiBackend::Wgpu(egui_wgpu) => egui_wgpu.run(window, run_ui),
+        }
+    }
 
-                                *status_color = Color32::RED;
-                            }
-                        }
-                    }
+    fn clipboard_text(&mut self) -> Option<String> {
+        match self {
+            EguiBackend::Glow(egui_glow) => egui_glow.egui_winit.clipboard_text(),
+            #[cfg(feature = "wgpu")]
+            EguiBackend::Wgpu(egui_wgpu) => egui_wgpu.egui_winit.clipboard_text(),
+        }
+    }
 
-                    // STATUS LABEL
-                    ui.colored_label(*status_color, status_text);
-                    ui.end_row();
+    fn paint(&mut self, window: &winit::window::Window) {
+        match self {
+            EguiBackend::Glow(egui_glow) => egui_glow.paint(window),
+            #[cfg(feature = "wgpu")]
+            EguiBackend::Wgpu(egui_wgpu) => egui_wgpu.paint(window),
+        }
+    }
+}
 
-                    // Quit button
-                    if ui.button("Quit").clicked() {
-                        state_machine.push(fsm::State::QuitDialog);
-                    }
+/// The debug overlay in a window of its own, so it doesn't cover the game on small screens. Has
+/// its own egui context, drawing goes through the renderer's OpenGL context.
+pub struct DebugWindow {
+    target: SecondaryWindow,
+    egui_glow: EguiGlow,
+}
 
-                    ui.end_row();
-                })
+impl DebugWindow {
+    pub const SIZE: (u32, u32) = (420, 480);
+
+    pub fn new(event_loop: &ActiveEventLoop, target: SecondaryWindow) -> Self {
+        target.make_current();
+        let egui_glow = EguiGlow::new(event_loop, target.gl.clone(), None, None, true);
+        set_style(&egui_glow.egui_ctx);
+
+        Self { target, egui_glow }
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.target.window.id()
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        let _ = self.egui_glow.on_window_event(&self.target.window, event);
+    }
+
+    pub fn request_redraw(&self) {
+        self.target.window.request_redraw();
+    }
+
+    pub fn redraw(
+        &mut self,
+        message_stats: Option<&MessageStats>,
+        interpolation: Option<InterpolationReport>,
+        liveness: Option<Liveness>,
+    ) {
+        let window = &self.target.window;
+
+        self.target.make_current();
+        self.egui_glow.run(window, |ctx| {
+            CentralPanel::default().show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    show_liveness(ui, liveness);
+                    show_interpolation(ui, interpolation);
+                    show_message_stats(ui, message_stats);
+                });
+            });
         });
+        self.egui_glow.paint(window);
+        self.target.swap_buffers();
+    }
 }
 
-//-----------------------------------------------
-
-fn show_log(ctx: &egui::Context, log_messages: &String) {
-    let style = (*ctx.style()).clone();
-    ctx.style_mut(|style| {
-        style.visuals.window_fill = Color32::from_rgba_unmultiplied(255, 255, 255, 32);
-    });
+impl Drop for DebugWindow {
+    fn drop(&mut self) {
+        // Textures and buffers live in the shared context, which outlives this window
+        self.target.make_current();
+        self.egui_glow.destroy();
+    }
+}
 
-    Window::new("log")
+fn show_console(ctx: &egui::Context, console: &mut Console) {
+    Window::new("console")
         .title_bar(false)
-        .anchor(Align2::LEFT_TOP, egui::Vec2::ZERO)
-        .fixed_size([200.0, 80.0])
-        .show(&ctx, |ui| {
+        .resizable(false)
+        .order(Order::Foreground)
+        .anchor(Align2::CENTER_TOP, Vec2::ZERO)
+        .fixed_size([globals::WINDOW_SIZE.0 as f32, 200.0])
+        .frame(Frame::window(&ctx.style()).fill(Color32::from_black_alpha(224)))
+        .show(ctx, |ui| {
             egui::ScrollArea::vertical()
                 .auto_shrink(false)
                 .stick_to_bottom(true)
+                .max_height(170.0)
                 .show(ui, |ui| {
-                    ui.label(log_messages);
+                    for line in &console.output {
+                        ui.label(
+                            egui::RichText::new(line)
+                                .text_style(TextStyle::Monospace)
+                                .color(Color32::LIGHT_GRAY),
+                        );
+                    }
                 });
-        });
-
-    // reset style for other dialog widgets
-    ctx.set_style(style);
-}
-
-// -------------------------------------------------
 
-fn show_disconnected_dialog(
-    ctx: &egui::Context,
-    state_machine: &mut fsm::StateMachine,
-    log_messages: &mut String,
-    status_text: &mut String,
-    status_color: &mut Color32,
-) {
-    CentralPanel::default()
-        .frame(Frame::none().fill(Color32::from_black_alpha(192)))
-        .show(ctx, |_| {});
+            let input = ui.add(
+                TextEdit::singleline(&mut console.input)
+                    .font(TextStyle::Monospace)
+                    .desired_width(f32::INFINITY),
+            );
 
-    Window::new("disconnected_dialog")
-        .title_bar(false)
-        .collapsible(false)
-        .resizable(false)
-        .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
-        .fixed_size([300.0, 100.0])
-        .show(ctx, |ui| {
-            ui.vertical_centered(|ui| {
-                ui.label("Connection to server was lost");
-                if ui.button("Ok").clicked() {
-                    state_machine.change(fsm::State::Menu);
-                    log_messages.clear();
-                    *status_text = String::from("Ready.");
-                    *status_color = Color32::BLACK;
+            // Typing goes to the console for as long as it is open
+            if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let line = std::mem::take(&mut console.input);
+                if !line.trim().is_empty() {
+                    console.print(&format!("> {line}"));
+                    console.submitted.push(line);
                 }
-            });
+            }
+            input.request_focus();
         });
 }
 
@@ -257,7 +1955,7 @@ fn show_quit_dialog(ctx: &egui::Context, state_machine: &mut fsm::StateMachine)
         .fixed_size([300.0, 100.0])
         .show(ctx, |ui| {
             ui.vertical_centered(|ui| {
-                ui.label("Are you sure would like to quit");
+                ui.label(tr("dialog.quit_confirm"));
             });
 
             let window_rect = ui.max_rect();
@@ -279,32 +1977,469 @@ fn show_quit_dialog(ctx: &egui::Context, state_machine: &mut fsm::StateMachine)
                 egui::vec2(button_width, button_height),
             );
 
-            if ui.put(yes_button_rect, egui::Button::new("Yes")).clicked() {
+            if ui
+                .put(yes_button_rect, egui::Button::new(tr("dialog.yes")))
+                .clicked()
+            {
                 state_machine.change(fsm::State::Quit);
             }
 
-            if ui.put(no_button_rect, egui::Button::new("No")).clicked() {
+            if ui
+                .put(no_button_rect, egui::Button::new(tr("dialog.no")))
+                .clicked()
+            {
                 state_machine.pop();
             }
         });
 }
 
+/// Message of the day over the game, dismissed with the button or Enter
+fn show_motd_dialog(ctx: &egui::Context, state_machine: &mut fsm::StateMachine, motd: &str) {
+    Window::new(tr("motd.title"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .default_width(400.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| ui.label(motd));
+
+            ui.separator();
+            ui.vertical_centered(|ui| {
+                if ui.button(tr("motd.continue")).clicked()
+                    || ui.input(|i| i.key_pressed(egui::Key::Enter))
+                {
+                    state_machine.pop();
+                }
+            });
+        });
+}
+
+fn show_crash_dialog(ctx: &egui::Context, state_machine: &mut fsm::StateMachine, path: &Path) {
+    Window::new(tr("crash.title"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .default_width(400.0)
+        .show(ctx, |ui| {
+            ui.label(tr_args("crash.text", &[("path", &path.display())]));
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button(tr("crash.open")).clicked() {
+                    if let Err(e) = crash::open_report(path) {
+                        eprintln!("Failed to open crash report: {e}");
+                    }
+                    state_machine.pop();
+                }
+
+                if ui.button(tr("crash.close")).clicked() {
+                    state_machine.pop();
+                }
+            });
+        });
+}
+
 //////////////////////////////////////////////////
 
-fn verify_address_format(address: &str, port: &str) -> Result<(), String> {
-    match address.parse::<IpAddr>() {
-        Ok(_) => {}
+/// Time played as M:SS
+fn format_played(played: Duration) -> String {
+    let secs = played.as_secs();
+
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Time of day as HH:MM:SS in UTC
+fn clock_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % 86400;
+
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use egui::{epaint::ClippedShape, Event, Modifiers, PointerButton, RawInput, Rect, Shape};
+
+    use super::*;
+    use crate::net::addr::{Endpoint, Host};
+
+    /// Lays out GUI frames on a bare egui context and plays back pointer input
+    struct Harness {
+        ctx: egui::Context,
+        gui: Gui,
+        state_machine: fsm::StateMachine,
+        shapes: Vec<ClippedShape>,
+    }
+
+    impl Harness {
+        /// GUI showing `state`, on top of the menu like in the app
+        fn new(state: fsm::State) -> Self {
+            let ctx = egui::Context::default();
+            set_style(&ctx);
+
+            let mut state_machine = fsm::StateMachine::new();
+            state_machine.push(fsm::State::Menu);
+            if !matches!(state, fsm::State::Menu) {
+                state_machine.push(state);
+            }
+
+            let mut harness = Self {
+                ctx,
+                gui: Gui::with_egui(None, None),
+                state_machine,
+                shapes: Vec::new(),
+            };
+
+            harness.settle();
+
+            harness
+        }
+
+        /// Lay out frames until nothing moves any more. Windows are measured in their first
+        /// frame, then anchored ones move into place over the next few.
+        fn settle(&mut self) {
+            let mut previous = None;
+            for _ in 0..20 {
+                self.frame(Vec::new());
+
+                let layout = self.text_rects();
+                if previous.as_ref() == Some(&layout) {
+                    return;
+                }
+                previous = Some(layout);
+            }
+
+            panic!("GUI layout never settled");
+        }
+
+        fn frame(&mut self, events: Vec<Event>) {
+            let input = RawInput {
+                screen_rect: Some(Rect::from_min_size(
+                    Pos2::ZERO,
+                    Vec2::new(globals::WINDOW_SIZE.0 as f32, globals::WINDOW_SIZE.1 as f32),
+                )),
+                events,
+                ..Default::default()
+            };
+
+            let Self {
+                ctx,
+                gui,
+                state_machine,
+                ..
+            } = self;
+            let output = ctx.run(input, |ctx| {
+                gui.show(ctx, state_machine, None, None, None, &PlayerList::default());
+            });
+
+            self.shapes = output.shapes;
+        }
+
+        /// Every text drawn in the last frame and where
+        fn text_rects(&self) -> Vec<(String, Rect)> {
+            fn collect(shape: &Shape, out: &mut Vec<(String, Rect)>) {
+                match shape {
+                    Shape::Text(shape) => out.push((
+                        shape.galley.text().to_string(),
+                        shape.galley.rect.translate(shape.pos.to_vec2()),
+                    )),
+                    Shape::Vec(shapes) => shapes.iter().for_each(|shape| collect(shape, out)),
+                    _ => (),
+                }
+            }
+
+            let mut out = Vec::new();
+            for clipped in &self.shapes {
+                collect(&clipped.shape, &mut out);
+            }
+
+            out
+        }
+
+        fn find_text(&self, text: &str) -> Option<Rect> {
+            self.text_rects()
+                .into_iter()
+                .find_map(|(drawn, rect)| (drawn == text).then_some(rect))
+        }
+
+        /// Press and release the primary button over the widget labeled `text`
+        fn click(&mut self, text: &str) {
+            let pos = self
+                .find_text(text)
+                .unwrap_or_else(|| panic!("{text:?} is not on screen"))
+                .center();
+            let button = |pressed| Event::PointerButton {
+                pos,
+                button: PointerButton::Primary,
+                pressed,
+                modifiers: Modifiers::NONE,
+            };
+
+            self.frame(vec![Event::PointerMoved(pos)]);
+            self.frame(vec![button(true)]);
+            self.frame(vec![button(false)]);
+        }
+    }
+
+    fn localhost(port: u16) -> Endpoint {
+        Endpoint {
+            host: Host::Ip(Ipv4Addr::LOCALHOST.into()),
+            port,
+        }
+    }
+
+    #[test]
+    fn join_pushes_connecting() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.click(tr("menu.join_server"));
+
+        match harness.state_machine.peek() {
+            Some(fsm::State::Connecting {
+                endpoint,
+                session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                invite_code: None,
+            }) => assert_eq!(*endpoint, localhost(globals::DEFAULT_PORT)),
+            _ => panic!("Join did not start connecting"),
+        }
+        assert_eq!(harness.gui.status_text, tr("status.connecting"));
+    }
+
+    #[test]
+    fn pasted_address_fills_both_fields() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.click(tr("menu.paste_address"));
+        assert!(harness.gui.paste_address);
+
+        harness.gui.paste_address(" [::1]:9100\n");
+        assert_eq!(harness.gui.server_hostname, "::1");
+        assert_eq!(harness.gui.server_port, "9100");
+
+        // A bare host keeps the port already there
+        harness.gui.paste_address("example.com");
+        assert_eq!(harness.gui.server_hostname, "example.com");
+        assert_eq!(harness.gui.server_port, "9100");
+    }
+
+    #[test]
+    fn create_server_pushes_connecting() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.gui.server_port = String::from("9100");
+        harness.click(tr("menu.create_server"));
+
+        match harness.state_machine.peek() {
+            Some(fsm::State::Connecting {
+                endpoint,
+                session_mode: fsm::SessionMode::CreateServer,
+                invite_code: None,
+            }) => assert_eq!(*endpoint, localhost(9100)),
+            _ => panic!("Create server did not start connecting"),
+        }
+    }
+
+    #[test]
+    fn private_server_gets_an_invite_code() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.click(tr("menu.private_server"));
+        harness.click(tr("menu.create_server"));
+
+        match harness.state_machine.peek() {
+            Some(fsm::State::Connecting {
+                session_mode: fsm::SessionMode::CreateServer,
+                invite_code: Some(code),
+                ..
+            }) => assert!(server::is_valid_invite_code(code), "{code}"),
+            _ => panic!("Private server did not start with an invite code"),
+        }
+    }
+
+    #[test]
+    fn invalid_invite_code_stays_in_menu() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.gui.invite.code = String::from("AB:CD");
+        harness.click(tr("menu.join_server"));
 
-        Err(_) => return Err("Error: Invalid Ip Adrress format".to_string()),
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Menu)
+        ));
+        assert_eq!(harness.gui.status_text, tr("status.invalid_invite_code"));
     }
 
-    match port.parse::<u16>() {
-        Ok(_) => {}
+    #[test]
+    fn hosting_friend_can_be_joined() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.gui.set_friends(vec![FriendEntry {
+            key: PublicKey([7; 32]),
+            name: String::from("khoi"),
+            server: Some(String::from("127.0.0.1:9200")),
+            hosting: true,
+        }]);
+        harness.settle();
+        harness.click(tr("friends.join"));
 
-        Err(_) => {
-            return Err("Error: Invalid port number. Must be between 0 and 65535".to_string());
+        match harness.state_machine.peek() {
+            Some(fsm::State::Connecting {
+                endpoint,
+                session_mode: fsm::SessionMode::ConnectAsClientOnly,
+                ..
+            }) => assert_eq!(*endpoint, localhost(9200)),
+            _ => panic!("Join did not start connecting to the friend's server"),
         }
     }
 
-    Ok(())
+    #[test]
+    fn invalid_port_stays_in_menu() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        harness.gui.server_port = String::from("0");
+        harness.click(tr("menu.join_server"));
+
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Menu)
+        ));
+        assert_eq!(harness.gui.status_text, tr("error.invalid_port"));
+        assert_eq!(harness.gui.status_color, Color32::RED);
+    }
+
+    #[test]
+    fn disconnected_dialog_resets_status() {
+        let mut harness = Harness::new(fsm::State::Disconnected);
+        harness
+            .gui
+            .set_error_status(String::from("Connection timeout"));
+        harness.gui.disconnect_reason = Some(String::from("Kicked"));
+        harness.gui.log.push_back(LogEntry {
+            severity: Severity::Info,
+            time: SystemTime::now(),
+            text: String::from("Welcome"),
+        });
+
+        harness.frame(Vec::new());
+        assert!(harness.find_text("Kicked").is_some());
+        harness.click(tr("dialog.ok"));
+
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Menu)
+        ));
+        assert_eq!(harness.gui.status_text, tr("status.ready"));
+        assert_eq!(harness.gui.status_color, Color32::BLACK);
+        assert!(harness.gui.disconnect_reason.is_none());
+        assert!(harness.gui.log.is_empty());
+    }
+
+    #[test]
+    fn quit_dialog_yes_quits() {
+        let mut harness = Harness::new(fsm::State::QuitDialog);
+        harness.click(tr("dialog.yes"));
+
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Quit)
+        ));
+    }
+
+    #[test]
+    fn quit_dialog_no_goes_back() {
+        let mut harness = Harness::new(fsm::State::QuitDialog);
+        harness.click(tr("dialog.no"));
+
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Menu)
+        ));
+    }
+
+    #[test]
+    fn game_stays_on_screen_under_the_quit_dialog() {
+        let mut harness = Harness::new(fsm::State::Playing);
+        harness
+            .gui
+            .log(Severity::Info, String::from("Player 2 joined"));
+        harness.state_machine.push(fsm::State::QuitDialog);
+        harness.settle();
+
+        assert!(harness.find_text("Player 2 joined").is_some());
+        assert!(matches!(
+            harness.state_machine.base(),
+            Some(fsm::State::Playing)
+        ));
+
+        harness.click(tr("dialog.no"));
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Playing)
+        ));
+    }
+
+    #[test]
+    fn open_chat_keeps_keys_from_the_game() {
+        let mut harness = Harness::new(fsm::State::Playing);
+        assert!(!harness.gui.keyboard_captured(&harness.ctx));
+
+        harness.gui.open_chat();
+        harness.settle();
+        assert!(harness.gui.keyboard_captured(&harness.ctx));
+
+        harness.gui.close_chat();
+        harness.settle();
+        assert!(!harness.gui.keyboard_captured(&harness.ctx));
+    }
+
+    #[test]
+    fn focused_address_field_keeps_keys_from_the_game() {
+        let mut harness = Harness::new(fsm::State::Menu);
+        assert!(!harness.gui.keyboard_captured(&harness.ctx));
+
+        harness.click(globals::LOCAL_HOST);
+        assert!(harness.gui.keyboard_captured(&harness.ctx));
+    }
+
+    #[test]
+    fn motd_is_dismissed_back_to_the_game() {
+        let mut harness = Harness::new(fsm::State::Playing);
+        harness
+            .state_machine
+            .push(fsm::State::Motd("Be nice: no spawn camping".to_string()));
+        harness.settle();
+        assert!(harness.find_text("Be nice: no spawn camping").is_some());
+
+        harness.click(tr("motd.continue"));
+        assert!(matches!(
+            harness.state_machine.peek(),
+            Some(fsm::State::Playing)
+        ));
+    }
+
+    #[test]
+    fn debug_overlay_detaches_and_hides() {
+        let mut harness = Harness::new(fsm::State::Playing);
+        harness.gui.toggle_debug_overlay();
+        harness.settle();
+        assert!(harness.find_text(tr("debug.not_connected")).is_some());
+
+        harness.click(tr("debug.detach"));
+        harness.settle();
+        assert!(harness.gui.debug_detached());
+        assert!(harness.find_text(tr("debug.not_connected")).is_none());
+
+        // Hiding the overlay closes its window, showing it again puts it back over the game
+        harness.gui.toggle_debug_overlay();
+        assert!(!harness.gui.debug_detached());
+        harness.gui.toggle_debug_overlay();
+        harness.settle();
+        assert!(harness.find_text(tr("debug.not_connected")).is_some());
+    }
 }
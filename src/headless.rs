@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Write as _,
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use cgmath::vec2;
+use game_server_sample::{globals, terrain::Terrain, Player, PlayerId};
+
+use crate::{
+    client::{ClientConfig, ClientEvent, ClientSession},
+    message::Direction,
+};
+
+const PRINT_INTERVAL: Duration = Duration::from_millis(500);
+
+// Size of the ASCII map in characters, the whole world is squeezed into it
+const MAP_WIDTH: usize = 48;
+const MAP_HEIGHT: usize = 20;
+
+/// Client without a window for SSH sessions and CI smoke tests. Joins the server, keeps the
+/// connection alive and periodically prints an ASCII map and player table to the terminal.
+pub struct HeadlessClient {
+    session: ClientSession,
+    local_player: Player,
+    remote_players: HashMap<PlayerId, Player>,
+    server_address: String,
+    joined_at: Instant,
+}
+
+impl HeadlessClient {
+    pub async fn connect(
+        server_address: String,
+        config: &ClientConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let session = ClientSession::new(server_address.clone(), config).await?;
+        let local_player = session.get_session_player_data();
+
+        Ok(Self {
+            session,
+            local_player,
+            remote_players: HashMap::new(),
+            server_address,
+            joined_at: Instant::now(),
+        })
+    }
+
+    /// Run until the server goes away or `duration` has passed. Losing the connection is an
+    /// error, so scripts can tell a healthy run apart from a broken one by the exit code.
+    pub async fn run(mut self, duration: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        let mut tick =
+            tokio::time::interval(Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC));
+        let mut last_print = Instant::now();
+        let mut step: u32 = 0;
+
+        loop {
+            tick.tick().await;
+            step = step.wrapping_add(1);
+
+            match self.process_server_response() {
+                Some(ClientEvent::ServerShutdown) => {
+                    println!("Server shut down");
+                    return Ok(());
+                }
+                Some(ClientEvent::Kicked(Some(reason))) => {
+                    return Err(format!("Kicked by the server: {reason}").into())
+                }
+                Some(_) => return Err("Kicked by the server".into()),
+                None => (),
+            }
+
+            // Report the position every tick like the graphical client, so the server keeps
+            // replicating this player to everyone else
+            self.session.send_pos(&self.local_player, step);
+
+            if !self.session.is_server_alive() {
+                return Err("Connection to server was lost".into());
+            }
+
+            if last_print.elapsed() >= PRINT_INTERVAL {
+                self.print_state();
+                last_print = Instant::now();
+            }
+
+            if duration.is_some_and(|duration| self.joined_at.elapsed() >= duration) {
+                self.session.leave_server(self.local_player.id);
+
+                // Give the send task a moment to get LEAVE out before the session is dropped
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Apply queued server events. Returns the event ending the session, if any.
+    fn process_server_response(&mut self) -> Option<ClientEvent> {
+        while let Some(event) = self.session.poll_event() {
+            match event {
+                ClientEvent::PlayerUpdated(player) => {
+                    self.remote_players.insert(player.id, player);
+                }
+
+                ClientEvent::PlayerLeft(id) => {
+                    self.remote_players.remove(&id);
+                }
+
+                event @ (ClientEvent::ServerShutdown | ClientEvent::Kicked(_)) => {
+                    return Some(event)
+                }
+
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    fn print_state(&self) {
+        let mut out = String::new();
+
+        // Redraw in place on a terminal, plain appended frames are easier to read in CI logs
+        if std::io::stdout().is_terminal() {
+            out.push_str("\x1B[2J\x1B[H");
+        }
+
+        let _ = writeln!(
+            out,
+            "Connected to {} as player {} for {:.0} s, server at {} Hz, world time {}",
+            self.server_address,
+            self.local_player.id,
+            self.joined_at.elapsed().as_secs_f32(),
+            self.session.server_tick_rate(),
+            format_time_of_day(self.session.time_of_day())
+        );
+
+        out.push_str(&self.render_map());
+
+        let mut players: Vec<&Player> = self.remote_players.values().collect();
+        players.sort_by_key(|p| p.id);
+
+        let _ = writeln!(out, "{:>4}  {:>16}", "Id", "Position");
+        for player in std::iter::once(&self.local_player).chain(players) {
+            let _ = writeln!(
+                out,
+                "{:>4}  {:>16}",
+                player.id,
+                format!("{:.0}, {:.0}", player.pos.x, player.pos.y)
+            );
+        }
+
+        let received: u64 = self
+            .session
+            .message_stats()
+            .iter()
+            .filter(|(direction, _, _)| *direction == Direction::Received)
+            .map(|(_, _, stats)| stats.count)
+            .sum();
+        let _ = writeln!(out, "{received} messages received");
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    /// Local player is drawn as @, remote players as the last digit of their id. Mud shows as
+    /// `,` and ice as `~`.
+    fn render_map(&self) -> String {
+        let bounds = self.session.rules().bounds;
+        let cell_size = vec2(
+            (bounds.max_x - bounds.min_x) / MAP_WIDTH as f32,
+            (bounds.max_y - bounds.min_y) / MAP_HEIGHT as f32,
+        );
+
+        let mut grid = [[b'.'; MAP_WIDTH]; MAP_HEIGHT];
+        for (row, cells) in grid.iter_mut().enumerate() {
+            for (column, cell) in cells.iter_mut().enumerate() {
+                let center = vec2(
+                    bounds.min_x + (column as f32 + 0.5) * cell_size.x,
+                    bounds.min_y + (row as f32 + 0.5) * cell_size.y,
+                );
+
+                *cell = match self.session.map().terrain_at(center) {
+                    Terrain::Normal => b'.',
+                    Terrain::Mud => b',',
+                    Terrain::Ice => b'~',
+                };
+            }
+        }
+
+        let mut plot = |player: &Player, symbol: u8| {
+            let x = (player.pos.x - bounds.min_x) / (bounds.max_x - bounds.min_x);
+            let y = (player.pos.y - bounds.min_y) / (bounds.max_y - bounds.min_y);
+
+            let column = ((x * MAP_WIDTH as f32) as usize).min(MAP_WIDTH - 1);
+            // World y points down like on screen in the graphical client
+            let row = ((y * MAP_HEIGHT as f32) as usize).min(MAP_HEIGHT - 1);
+
+            grid[row][column] = symbol;
+        };
+
+        for player in self.remote_players.values() {
+            plot(player, b'0' + (player.id % 10) as u8);
+        }
+        plot(&self.local_player, b'@');
+
+        let mut map = String::with_capacity((MAP_WIDTH + 3) * (MAP_HEIGHT + 2));
+        let border = format!("+{}+\n", "-".repeat(MAP_WIDTH));
+
+        map.push_str(&border);
+        for row in grid {
+            map.push('|');
+            map.push_str(std::str::from_utf8(&row).unwrap_or_default());
+            map.push_str("|\n");
+        }
+        map.push_str(&border);
+
+        map
+    }
+}
+
+/// World clock as a 24 hour time
+fn format_time_of_day(time_of_day: f32) -> String {
+    let minutes = (time_of_day * 24.0 * 60.0) as u32;
+
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
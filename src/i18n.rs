@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+/// Languages the GUI ships with. Locale tables are embedded into the binary at compile time from
+/// `assets/locales`, so no files have to be installed next to the executable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Language {
+    #[value(name = "en")]
+    English,
+
+    #[value(name = "vi")]
+    Vietnamese,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Vietnamese];
+
+    /// Language name written in the language itself, as shown in the language picker
+    pub fn native_name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Vietnamese => "Tiếng Việt",
+        }
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Language::English => include_str!("../assets/locales/en.json"),
+            Language::Vietnamese => include_str!("../assets/locales/vi.json"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////
+
+type LocaleTable = HashMap<String, String>;
+
+static LOCALES: OnceLock<Vec<LocaleTable>> = OnceLock::new();
+static CURRENT_LANGUAGE: AtomicUsize = AtomicUsize::new(Language::English as usize);
+
+fn locales() -> &'static [LocaleTable] {
+    LOCALES.get_or_init(|| {
+        Language::ALL
+            .iter()
+            .map(|lang| {
+                serde_json::from_str(lang.source())
+                    .unwrap_or_else(|e| panic!("Malformed locale table for {lang:?}: {e}"))
+            })
+            .collect()
+    })
+}
+
+pub fn set_language(lang: Language) {
+    CURRENT_LANGUAGE.store(lang as usize, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    Language::ALL[CURRENT_LANGUAGE.load(Ordering::Relaxed)]
+}
+
+/// Look up a GUI string in the active language. Missing translations fall back to English, and
+/// missing keys to the key itself so they stand out on screen instead of crashing.
+pub fn tr(key: &'static str) -> &'static str {
+    let locales = locales();
+
+    locales[language() as usize]
+        .get(key)
+        .or_else(|| locales[Language::English as usize].get(key))
+        .map(|s| s.as_str())
+        .unwrap_or(key)
+}
+
+/// Same as `tr`, replacing each `{name}` placeholder with its argument
+pub fn tr_args(key: &'static str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = tr(key).to_string();
+
+    for (name, value) in args {
+        text = text.replace(&format!("{{{name}}}"), &value.to_string());
+    }
+
+    text
+}
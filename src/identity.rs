@@ -0,0 +1,135 @@
+use std::fmt::Write as _;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{codec::byte_array, ClientId};
+
+// Signed along with the client id, so a handshake signature can't pass for anything else
+const HANDSHAKE_CONTEXT: &[u8] = b"game-server-sample handshake:";
+
+/// Public half of a player's identity key. Unlike the player id it stays the same across
+/// servers, which is how friends recognize each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PublicKey(pub [u8; 32]);
+
+impl PublicKey {
+    pub fn parse(s: &str) -> Option<Self> {
+        decode_hex(s)?.try_into().ok().map(Self)
+    }
+
+    /// First few hex digits, enough to tell friends with the same name apart
+    pub fn short(&self) -> String {
+        encode_hex(&self.0[..4])
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&encode_hex(&self.0))
+    }
+}
+
+/// Key pair generated on the first run and kept in the data directory
+#[derive(Clone)]
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    /// Identity stored with [`Identity::secret_hex`]
+    pub fn from_secret_hex(s: &str) -> Option<Self> {
+        let secret: [u8; 32] = decode_hex(s)?.try_into().ok()?;
+
+        Some(Self {
+            key: SigningKey::from_bytes(&secret),
+        })
+    }
+
+    pub fn secret_hex(&self) -> String {
+        encode_hex(&self.key.to_bytes())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.key.verifying_key().to_bytes())
+    }
+
+    /// Public key for the handshake, signed together with the client id
+    pub fn prove(&self, client_id: ClientId) -> KeyProof {
+        KeyProof {
+            key: self.public_key(),
+            signature: self.key.sign(&handshake_payload(client_id)).to_bytes(),
+        }
+    }
+}
+
+// Never print the secret half
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Public key a client joins with and the signature showing it holds the private half
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyProof {
+    pub key: PublicKey,
+    #[serde(with = "byte_array")]
+    pub signature: [u8; 64],
+}
+
+impl KeyProof {
+    pub fn verify(&self, client_id: ClientId) -> bool {
+        VerifyingKey::from_bytes(&self.key.0).is_ok_and(|key| {
+            key.verify(
+                &handshake_payload(client_id),
+                &Signature::from_bytes(&self.signature),
+            )
+            .is_ok()
+        })
+    }
+
+    /// Wire form: key and signature in hex, separated by `,`
+    pub fn serialize(&self) -> String {
+        format!("{},{}", self.key, encode_hex(&self.signature))
+    }
+
+    pub fn deserialize(s: &str) -> Option<Self> {
+        let (key, signature) = s.split_once(',')?;
+
+        Some(Self {
+            key: PublicKey::parse(key)?,
+            signature: decode_hex(signature)?.try_into().ok()?,
+        })
+    }
+}
+
+fn handshake_payload(client_id: ClientId) -> Vec<u8> {
+    [HANDSHAKE_CONTEXT, client_id.to_string().as_bytes()].concat()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,114 @@
+use std::{collections::VecDeque, time::Instant};
+
+use cgmath::Vector2;
+use game_server_sample::globals;
+
+// Client steps the playout runs behind the stamps, absorbing that much arrival jitter
+const PLAYOUT_DELAY_STEPS: u32 = 3;
+
+// Further off than this and the playout clock is set again, e.g. after the client stalled or its
+// clock drifted
+const RESYNC_STEPS: u32 = 30;
+
+/// Jitter buffer for the positions a client reports, stamped with the client's simulation step.
+/// Positions are held back and released at the pace the client produced them, a few steps late,
+/// so bursts and gaps on the link don't turn into jerky movement on the server.
+pub struct InputBuffer {
+    queue: VecDeque<(u32, Vector2<f32>)>,
+
+    /// Client step due at the given time, the playout clock runs at the client's step rate
+    clock: Option<(u32, Instant)>,
+
+    /// Newest step released, older inputs arriving afterwards are stale
+    released: Option<u32>,
+
+    /// Inputs which arrived after their step was due
+    pub late: u64,
+
+    /// Times the playout clock was set again
+    pub resyncs: u64,
+    pub max_depth: usize,
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputBuffer {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            clock: None,
+            released: None,
+            late: 0,
+            resyncs: 0,
+            max_depth: 0,
+        }
+    }
+
+    pub fn push(&mut self, step: u32, pos: Vector2<f32>, now: Instant) {
+        if self.released.is_some_and(|released| step <= released) {
+            self.late += 1;
+            return;
+        }
+
+        match self.due_step(now) {
+            None => self.sync(step, now),
+            Some(due) if step.abs_diff(due) > RESYNC_STEPS => {
+                self.sync(step, now);
+                self.resyncs += 1;
+            }
+            Some(due) if step <= due => self.late += 1,
+            Some(_) => (),
+        }
+
+        // Usually in order already, UDP may swap a few
+        let index = self.queue.partition_point(|(queued, _)| *queued < step);
+        if self
+            .queue
+            .get(index)
+            .is_some_and(|(queued, _)| *queued == step)
+        {
+            return;
+        }
+        self.queue.insert(index, (step, pos));
+        self.max_depth = self.max_depth.max(self.queue.len());
+    }
+
+    /// Inputs due by `now`, oldest first
+    pub fn pop_due(&mut self, now: Instant) -> Vec<Vector2<f32>> {
+        let Some(due) = self.due_step(now) else {
+            return Vec::new();
+        };
+
+        let mut inputs = Vec::new();
+        while let Some((step, pos)) = self.queue.front().copied() {
+            if step > due {
+                break;
+            }
+            self.queue.pop_front();
+            self.released = Some(step);
+            inputs.push(pos);
+        }
+
+        inputs
+    }
+
+    /// Inputs waiting for their step
+    pub fn depth(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn due_step(&self, now: Instant) -> Option<u32> {
+        let (step, at) = self.clock?;
+        let elapsed = now.saturating_duration_since(at).as_secs_f32();
+
+        Some(step + (elapsed * globals::MAX_LOGIC_UPDATE_PER_SEC) as u32)
+    }
+
+    fn sync(&mut self, step: u32, now: Instant) {
+        self.clock = Some((step.saturating_sub(PLAYOUT_DELAY_STEPS), now));
+    }
+}
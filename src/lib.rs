@@ -1,6 +1,23 @@
-use cgmath::{Vector2, Vector3};
+use cgmath::{InnerSpace, Vector2, Vector3};
 use rand::Rng;
 
+use codec::{Vector2Def, Vector3Def};
+use message::Message;
+use rules::GameRules;
+use serde::{Deserialize, Serialize};
+
+pub mod clock;
+pub mod codec;
+pub mod identity;
+pub mod message;
+pub mod outbox;
+pub mod rules;
+pub mod simulation;
+pub mod terrain;
+pub mod udp_batch;
+pub mod version;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct WorldBounds {
     pub min_x: f32,
     pub min_y: f32,
@@ -8,11 +25,45 @@ pub struct WorldBounds {
     pub max_y: f32,
 }
 
+impl WorldBounds {
+    pub fn size(&self) -> Vector2<f32> {
+        Vector2::new(self.max_x - self.min_x, self.max_y - self.min_y)
+    }
+}
+
+/// What happens to players crossing the world bounds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum WorldMode {
+    /// Players stop at the edge
+    #[default]
+    Bounded,
+
+    /// Toroidal world, players leaving one edge come back in on the opposite one
+    Wrap,
+}
+
+impl WorldMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WorldMode::Bounded => "bounded",
+            WorldMode::Wrap => "wrap",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "bounded" => Some(WorldMode::Bounded),
+            "wrap" => Some(WorldMode::Wrap),
+            _ => None,
+        }
+    }
+}
+
 ////////////////////////////////////////////////////
 
 // REUSABLE GLOBAL CONSTANTS
 pub mod globals {
-    use crate::{Player, WorldBounds};
+    use crate::WorldBounds;
 
     // SERVER CONSTANTS
     pub const LOCAL_HOST: &str = "127.0.0.1";
@@ -20,6 +71,20 @@ pub mod globals {
     pub const CONNECTION_TIMEOUT_SEC: std::time::Duration = std::time::Duration::from_secs(5);
     pub const PING_INTERVAL_MS: std::time::Duration = std::time::Duration::from_millis(20);
 
+    /// How often clients send a KEEPALIVE, well below common NAT mapping timeouts. Also bounds
+    /// how long a client stays unreachable after its NAT changed the port.
+    pub const KEEP_ALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+    /// Longest player name in characters. Names can't contain `:`, the message separator.
+    pub const MAX_PLAYER_NAME_LEN: usize = 16;
+
+    /// Longer chat lines are cut off by the server
+    pub const MAX_CHAT_LEN: usize = 200;
+
+    /// Receive buffer of clients. Every message the server sends has to fit, the MAP message
+    /// included.
+    pub const MAX_CLIENT_DATAGRAM_LEN: usize = 1024;
+
     // CLIENT CONSTANTS
     pub const WINDOW_SIZE: (u16, u16) = (800, 600);
     pub const WINDOW_TITLE: &str = "Multiplayer game demo sample";
@@ -27,6 +92,15 @@ pub mod globals {
     pub const MAX_LOGIC_UPDATE_PER_SEC: f32 = 60.0;
     pub const FIXED_UPDATE_TIMESTEP_SEC: f32 = 1.0 / MAX_LOGIC_UPDATE_PER_SEC;
 
+    /// Server simulation rates in Hz, highest first. The server steps down when ticks keep
+    /// blowing their budget and back up once load drops.
+    pub const SERVER_TICK_RATES: [u32; 3] = [60, 30, 20];
+
+    /// Length of a full day/night cycle of the world clock
+    pub const DAY_CYCLE_SEC: f32 = 120.0;
+
+    /// Defaults of [`crate::rules::GameRules`], what a server plays by unless told otherwise.
+    /// Clients go by what the server they joined sent instead.
     pub const WORLD_BOUNDS: WorldBounds = WorldBounds {
         min_x: -1200.0,
         min_y: -1200.0,
@@ -36,16 +110,39 @@ pub mod globals {
 
     pub const PLAYER_QUAD_SIZE: f32 = 24.0;
 
-    pub fn clamp_player_to_bounds(player: &mut Player) {
-        player.pos.x = player.pos.x.clamp(
-            WORLD_BOUNDS.min_x + (PLAYER_QUAD_SIZE / 2.0),
-            WORLD_BOUNDS.max_x - (PLAYER_QUAD_SIZE / 2.0),
-        );
+    /// Top player movement per fixed update step on normal ground. This and the next two are the
+    /// defaults of [`crate::simulation::MovementConfig`], servers may pick others.
+    pub const PLAYER_SPEED: f32 = 10.0;
 
-        player.pos.y = player.pos.y.clamp(
-            WORLD_BOUNDS.min_y + (PLAYER_QUAD_SIZE / 2.0),
-            WORLD_BOUNDS.max_y - (PLAYER_QUAD_SIZE / 2.0),
-        );
+    /// Velocity a player gains per fixed update step towards where it wants to go, full speed
+    /// takes five steps
+    pub const PLAYER_ACCELERATION: f32 = 2.0;
+
+    /// Share of its velocity a player loses per fixed update step once it lets go of the keys
+    pub const PLAYER_FRICTION: f32 = 0.25;
+
+    /// Moving slower than this per step counts as standing still
+    pub const PLAYER_MIN_SPEED: f32 = 0.05;
+
+    /// How far a dash takes a player, and how long until it can dash again
+    pub const DASH_DISTANCE: f32 = 120.0;
+    pub const DASH_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(2);
+
+    /// Push two colliding players get away from each other, as movement per fixed update step.
+    /// A knocked back player can't be knocked back again for a moment.
+    pub const KNOCKBACK_IMPULSE: f32 = 12.0;
+    pub const KNOCKBACK_COOLDOWN: std::time::Duration = std::time::Duration::from_millis(500);
+
+    /// Radians a player turns per fixed update step towards where it's moving, a half turn
+    /// takes about a quarter of a second
+    pub const PLAYER_TURN_SPEED: f32 = std::f32::consts::PI / 15.0;
+
+    /// Names travel as a message field, so they can't contain the separator
+    pub fn is_valid_player_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.trim() == name
+            && !name.contains(':')
+            && name.chars().count() <= MAX_PLAYER_NAME_LEN
     }
 }
 
@@ -53,12 +150,96 @@ pub mod globals {
 
 pub type PlayerId = u64;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Random identity a client picks once and sends with every handshake, so the server can tell a
+/// reconnect from the same client apart from a new player
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ClientId(pub u128);
+
+impl ClientId {
+    pub fn random() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.len() != 32 {
+            return None;
+        }
+
+        u128::from_str_radix(s, 16).ok().map(Self)
+    }
+}
+
+impl std::fmt::Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// Secret the server hands out with the ACK. Only the client holding it can move its session to
+/// a new address.
+pub type SessionToken = u64;
+
+/// Outline a player is drawn with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum Shape {
+    #[default]
+    Square,
+    Circle,
+
+    /// Pointing the way the player faces
+    Triangle,
+}
+
+impl Shape {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Shape::Square => "square",
+            Shape::Circle => "circle",
+            Shape::Triangle => "triangle",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "square" => Some(Shape::Square),
+            "circle" => Some(Shape::Circle),
+            "triangle" => Some(Shape::Triangle),
+            _ => None,
+        }
+    }
+}
+
+/// Look the server gave a player, replicated along with its position
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Avatar {
+    pub shape: Shape,
+
+    /// Width the player is drawn and collides with, `None` for the player size of the game
+    /// rules. See [`rules::GameRules::size_of`].
+    pub size: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Player {
     pub id: PlayerId,
+    #[serde(with = "Vector2Def")]
     pub pos: Vector2<f32>,
+    #[serde(with = "Vector2Def")]
     pub velocity: Vector2<f32>,
+    #[serde(with = "Vector3Def")]
     pub color: Vector3<f32>,
+
+    /// Direction the player faces in radians, 0 along +x and turning towards +y
+    pub facing: f32,
+
+    /// Push from a knockback, moving the player on top of its own velocity until friction
+    /// wears it off
+    #[serde(with = "Vector2Def")]
+    pub impulse: Vector2<f32>,
+
+    /// Saves from before avatars existed have none
+    #[serde(default)]
+    pub avatar: Avatar,
 }
 
 impl Default for Player {
@@ -68,20 +249,127 @@ impl Default for Player {
             pos: Vector2::new(0.0, 0.0),
             velocity: Vector2::new(0.0, 0.0),
             color: Vector3::new(0.0, 0.0, 0.0),
+            facing: 0.0,
+            impulse: Vector2::new(0.0, 0.0),
+            avatar: Avatar::default(),
         }
     }
 }
 
 impl Player {
     pub fn new(id: PlayerId, color: Vector3<f32>) -> Self {
-        let mut player = Player::default();
-        player.id = id;
-        player.color = color;
+        Player {
+            id,
+            color,
+            ..Default::default()
+        }
+    }
+
+    /// Turn towards `direction` by at most [`globals::PLAYER_TURN_SPEED`], the short way round.
+    /// Standing still keeps the current facing.
+    pub fn turn_towards(&mut self, direction: Vector2<f32>) {
+        if direction.x == 0.0 && direction.y == 0.0 {
+            return;
+        }
 
-        player
+        let turn = angle_difference(self.facing, direction.y.atan2(direction.x));
+        self.facing = normalize_angle(
+            self.facing + turn.clamp(-globals::PLAYER_TURN_SPEED, globals::PLAYER_TURN_SPEED),
+        );
     }
 }
 
+/// Angle in the range (-PI, PI]
+pub fn normalize_angle(angle: f32) -> f32 {
+    let angle = angle.rem_euclid(std::f32::consts::TAU);
+
+    if angle > std::f32::consts::PI {
+        angle - std::f32::consts::TAU
+    } else {
+        angle
+    }
+}
+
+/// Shortest turn from `from` to `to`, negative when turning towards -y
+pub fn angle_difference(from: f32, to: f32) -> f32 {
+    normalize_angle(to - from)
+}
+
+/// Blend between two angles the short way round, so e.g. 170 and -170 degrees meet at 180
+/// instead of sweeping through 0
+pub fn lerp_angle(from: f32, to: f32, alpha: f32) -> f32 {
+    normalize_angle(from + angle_difference(from, to) * alpha)
+}
+
+/// Simulation of a single player for one server tick. Returns the gameplay state replication
+/// message for everyone else.
+pub fn simulate_player(player: &mut Player, world_mode: WorldMode, rules: &GameRules) -> Message {
+    // Bound checking
+    rules.apply_world_bounds(player, world_mode);
+
+    Message::Replicate(*player)
+}
+
+////////////////////////////////////////////////////
+
+/// Player color presets. The color-blind friendly presets replace random RGB with a fixed set of
+/// hues that stay distinguishable under the given color vision deficiency.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Palette {
+    #[default]
+    Normal,
+    Deuteranopia,
+    Protanopia,
+}
+
+// Based on the IBM Design color-blind safe palette
+const DEUTERANOPIA_COLORS: [u32; 6] = [0x648FFF, 0x785EF0, 0xDC267F, 0xFE6100, 0xFFB000, 0x000000];
+
+// Based on the Okabe-Ito palette
+const PROTANOPIA_COLORS: [u32; 7] = [
+    0xE69F00, 0x56B4E9, 0x009E73, 0xF0E442, 0x0072B2, 0xCC79A7, 0x000000,
+];
+
+impl Palette {
+    fn colors(self) -> &'static [u32] {
+        match self {
+            Palette::Normal => &[],
+            Palette::Deuteranopia => &DEUTERANOPIA_COLORS,
+            Palette::Protanopia => &PROTANOPIA_COLORS,
+        }
+    }
+
+    /// Color allocated by the server to a newly joined player
+    pub fn player_color(self, id: PlayerId) -> Vector3<f32> {
+        match self.colors() {
+            [] => generate_color(),
+            colors => hex_to_rgb(colors[id as usize % colors.len()]),
+        }
+    }
+
+    /// Snap a color to the closest palette entry. Used on the client, because the server may have
+    /// allocated colors with a different palette.
+    pub fn remap(self, color: Vector3<f32>) -> Vector3<f32> {
+        self.colors()
+            .iter()
+            .map(|hex| hex_to_rgb(*hex))
+            .min_by(|a, b| {
+                let dist_a = (a - color).magnitude2();
+                let dist_b = (b - color).magnitude2();
+                dist_a.total_cmp(&dist_b)
+            })
+            .unwrap_or(color)
+    }
+}
+
+fn hex_to_rgb(hex: u32) -> Vector3<f32> {
+    Vector3::new(
+        ((hex >> 16) & 0xFF) as f32 / 255.0,
+        ((hex >> 8) & 0xFF) as f32 / 255.0,
+        (hex & 0xFF) as f32 / 255.0,
+    )
+}
+
 pub fn generate_color() -> Vector3<f32> {
     let mut rng = rand::thread_rng();
     // Avoid generating white color
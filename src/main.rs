@@ -1,19 +1,68 @@
-use clap::Parser;
-use std::error::Error;
+use accessibility::Accessibility;
+use anticheat::CheatConfig;
+use clap::{Parser, Subcommand};
+use client::ClientConfig;
+use daemon::{exit_code, PidFile};
+use game_server_sample::{
+    codec::Codec,
+    globals,
+    identity::Identity,
+    message,
+    rules::{self, GameRules},
+    simulation::MovementConfig,
+    terrain::TerrainMap,
+    version, ClientId, Palette, WorldMode,
+};
+use headless::HeadlessClient;
+use net::addr;
+use renderer::{CursorGrab, RenderSettings, RendererBackend};
+use server::{BindFailure, DuplicateIdentity, ServerBuilder, ServerConfig, WorldSnapshot};
+use std::{error::Error, net::SocketAddr, path::PathBuf, time::Duration};
 
+pub mod accessibility;
+pub mod admin;
+pub mod anticheat;
 pub mod app;
+pub mod assets;
+pub mod bench;
 pub mod client;
+pub mod commands;
+pub mod crash;
+pub mod daemon;
+pub mod dashboard;
+pub mod friends;
 pub mod fsm;
+pub mod game_loop;
 pub mod gui;
-pub mod message;
+pub mod headless;
+pub mod i18n;
+pub mod jitter;
+pub mod net;
+pub mod particles;
+pub mod paths;
+pub mod portmap;
+pub mod quality;
+pub mod relay;
 pub mod renderer;
+pub mod rewind;
+pub mod selftest;
 pub mod server;
+pub mod servers;
+pub mod shaders;
+pub mod tools;
+pub mod transport;
+pub mod tui;
 
 #[derive(Parser)]
 #[command(
     about = "Networked multiplayer game demo with client-server architecture. Run with GUI by default in headless server mode."
 )]
+#[command(version, long_version = version::LONG_VERSION)]
+#[command(subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(
         long,
         help = "Starts a server only in headless mode without graphical user interface. Used for creating dedicated servers."
@@ -21,21 +70,533 @@ struct Cli {
     #[arg(long)]
     server_only: bool,
 
-    #[arg(short, long)]
-    port: u16,
+    #[arg(short, long, required = true)]
+    port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "When the port of a server is taken, use the first free one of the following ports instead. The dedicated server logs the port it got, hosting players see it in the HUD."
+    )]
+    auto_port: bool,
 
     #[arg(long)]
     trace: bool,
+
+    #[arg(
+        long,
+        requires = "trace",
+        value_delimiter = ',',
+        help = "Only trace the given message types, e.g. REPL,POS."
+    )]
+    trace_filter: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "server_only",
+        conflicts_with = "trace",
+        help = "Show an interactive console with players, tick timings and log in headless mode."
+    )]
+    tui: bool,
+
+    #[arg(
+        long,
+        requires = "server_only",
+        conflicts_with = "tui",
+        help = "Run as a system service under systemd or the Windows service control manager: write a pid file, log to a rotating file and exit on panic."
+    )]
+    daemon: bool,
+
+    #[arg(
+        long,
+        requires = "server_only",
+        help = "Serve a web page showing the players live on this address, e.g. 127.0.0.1:8081. Anyone reaching it can watch the match."
+    )]
+    dashboard: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        requires = "daemon",
+        help = "Pid file of the daemon. Defaults to game-server-sample.pid in the data directory."
+    )]
+    pid_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "daemon",
+        help = "Log file of the daemon. Defaults to server.log in the logs folder of the data directory."
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Directory for user configuration instead of the platform default."
+    )]
+    config_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Directory for logs, dumps, saves and crash reports instead of the platform default."
+    )]
+    data_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "server_only",
+        help = "Join a server without opening a window and print the game state to the terminal. Useful over SSH and for smoke tests."
+    )]
+    no_gui: bool,
+
+    #[arg(long, requires = "no_gui", default_value = globals::LOCAL_HOST)]
+    host: String,
+
+    #[arg(
+        long,
+        value_name = "HOST[:PORT]",
+        conflicts_with_all = ["server_only", "host"],
+        help = "Join this server right away instead of showing the menu. IPv6 addresses need brackets when followed by a port, the port defaults to 8080."
+    )]
+    connect: Option<String>,
+
+    #[arg(
+        long,
+        requires = "no_gui",
+        help = "Leave the server after the given number of seconds instead of running until stopped."
+    )]
+    duration: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "en",
+        help = "Language used for the graphical user interface."
+    )]
+    lang: i18n::Language,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "normal",
+        help = "Color-blind friendly player color palette. Applies to colors handed out by a hosted server and to rendering."
+    )]
+    palette: Palette,
+
+    #[arg(
+        long,
+        default_value_t = server::DEFAULT_BANDWIDTH_LIMIT / 1024,
+        help = "Outgoing bandwidth cap per client in KB/s, 0 for no limit. Clients over budget skip stale replication updates."
+    )]
+    bandwidth_limit: u32,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "bounded",
+        help = "What happens at the world edges of a hosted server: players stop there, or wrap around to the opposite edge."
+    )]
+    world: WorldMode,
+
+    #[arg(
+        long,
+        help = "Terrain map file of a hosted server, one zone per line: <mud|ice|normal> <min_x> <min_y> <max_x> <max_y>. Uses a built-in map by default."
+    )]
+    map: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "gl",
+        help = "Graphics backend of the graphical client. wgpu needs a build with --features wgpu."
+    )]
+    renderer: RendererBackend,
+
+    #[arg(long, help = "Draw a high-contrast outline around player quads.")]
+    outline: bool,
+
+    #[arg(
+        long,
+        help = "Turn off particle effects for dashes, collisions and players joining or leaving."
+    )]
+    no_particles: bool,
+
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        help = "Strength of the camera shake when knocked back. 0 turns it off."
+    )]
+    screen_shake: f32,
+
+    #[arg(
+        long,
+        help = "Slow the game down for a moment when knocking another player back."
+    )]
+    hit_stop: bool,
+
+    #[arg(
+        long,
+        help = "Keep the regular mouse cursor over the game instead of drawing a crosshair."
+    )]
+    no_crosshair: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "none",
+        help = "Keep the mouse cursor inside the window while playing."
+    )]
+    cursor_grab: CursorGrab,
+
+    #[arg(
+        long,
+        default_value_t = 1200.0,
+        help = "World units per second the local player is moved when the server corrects its position, so corrections don't look like teleports. 0 jumps right away."
+    )]
+    max_correction_rate: f32,
+
+    #[arg(
+        long,
+        default_value_t = client::DEFAULT_INTERP_DELAY.start().as_millis() as u64,
+        help = "Shortest delay in milliseconds remote players are drawn behind the server's updates. The delay is tuned to the measured update jitter, between this and --interp-delay-max."
+    )]
+    interp_delay_min: u64,
+
+    #[arg(
+        long,
+        default_value_t = client::DEFAULT_INTERP_DELAY.end().as_millis() as u64,
+        help = "Longest delay in milliseconds remote players are drawn behind the server's updates."
+    )]
+    interp_delay_max: u64,
+
+    #[arg(
+        long,
+        default_value_t = CheatConfig::default().speed_tolerance,
+        help = "Multiple of the regular player speed a hosted server accepts before counting a speed violation."
+    )]
+    cheat_speed_tolerance: f32,
+
+    #[arg(
+        long,
+        default_value_t = CheatConfig::default().max_messages_per_sec,
+        help = "Messages per second a client may send to a hosted server before counting a rate violation."
+    )]
+    cheat_max_rate: u32,
+
+    #[arg(
+        long,
+        default_value_t = CheatConfig::default().flag_score,
+        help = "Suspicion score at which a player is reported to the admin. Every violation adds 1, the score halves every 30 seconds."
+    )]
+    cheat_flag_score: f32,
+
+    #[arg(
+        long,
+        help = "Suspicion score at which a player is kicked automatically. Players are only reported by default."
+    )]
+    cheat_kick_score: Option<f32>,
+
+    #[arg(
+        long,
+        default_value_t = globals::PLAYER_SPEED,
+        help = "Top player speed on a hosted server, in world units per simulation step. Sent to joining clients, and what the speed check allows for."
+    )]
+    player_speed: f32,
+
+    #[arg(
+        long,
+        default_value_t = globals::PLAYER_ACCELERATION,
+        help = "Speed a player gains per simulation step on a hosted server."
+    )]
+    player_acceleration: f32,
+
+    #[arg(
+        long,
+        default_value_t = globals::PLAYER_FRICTION,
+        help = "Share of their speed players lose per simulation step on a hosted server once they let go of the keys, above 0 and at most 1."
+    )]
+    player_friction: f32,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = globals::PING_INTERVAL_MS.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(5..=10_000),
+        help = "How often a hosted server pings its players, in milliseconds. Longer intervals save traffic, loss and jitter estimates get slower."
+    )]
+    ping_interval: u64,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Pings in a row players of a hosted server may miss before they count the server as gone. Defaults to as many as fit in the connection timeout at --ping-interval."
+    )]
+    max_missed_pings: Option<u32>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "migrate",
+        help = "What a hosted server does when a client joins with the identity of a connected player: move that player to the new address, or refuse the new connection."
+    )]
+    duplicate_identity: DuplicateIdentity,
+
+    #[arg(
+        long,
+        help = "Join with a throwaway identity instead of the one stored in the data directory, e.g. to run several clients on one machine."
+    )]
+    new_identity: bool,
+
+    #[arg(
+        long,
+        help = "Relay server, <host>:<port>. Clients join through it when the server does not answer directly, hosted servers register with it so such clients can reach them."
+    )]
+    relay: Option<String>,
+
+    #[arg(
+        long,
+        help = "Let the dedicated server forward traffic between players and hosts that can't reach each other directly."
+    )]
+    relay_service: bool,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..=64),
+        help = "Number of UDP sockets the server listens on, sharing the port through SO_REUSEPORT. More than one spreads the receiving of very high packet rates over several tasks (Unix only)."
+    )]
+    sockets: u16,
+
+    #[arg(
+        long,
+        default_value_t = server::DEFAULT_MAX_PLAYERS as u16,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Players a server takes at most, anyone beyond is turned away until someone leaves."
+    )]
+    max_players: u16,
+
+    #[arg(
+        long,
+        default_value_t = globals::SERVER_TICK_RATES[0],
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Simulation rate of a server in Hz, rounded down to 60, 30 or 20. The server steps down from it when it can't keep up."
+    )]
+    tick_rate: u32,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Start the dedicated server with a world saved by the save admin command, its players get their place back when they join again."
+    )]
+    load_world: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Append a JSON summary of every finished player session to this file, one per line."
+    )]
+    session_summaries: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        help = "Kick players of a hosted server who haven't moved for the given number of minutes."
+    )]
+    idle_kick: Option<u64>,
+
+    #[arg(
+        long,
+        conflicts_with = "motd_file",
+        help = "Message of the day shown to players when they join a hosted server, e.g. the server rules."
+    )]
+    motd: Option<String>,
+
+    #[arg(long, help = "Read the message of the day from this file instead.")]
+    motd_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Make the server private: only players giving this code get in. Also the code --connect joins with."
+    )]
+    invite_code: Option<String>,
+
+    #[arg(
+        long,
+        help = "Debug mode: replay every predicted movement step of the client and panic if the result differs in any bit."
+    )]
+    check_determinism: bool,
+
+    #[arg(
+        long,
+        help = "When hosting from the GUI, join the own server through memory instead of the loopback socket."
+    )]
+    in_process_client: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Measure serializer, deserializer and simulation throughput on a synthetic workload
+    Bench {
+        #[arg(long, default_value_t = 64)]
+        players: usize,
+
+        #[arg(long, default_value_t = 10_000)]
+        ticks: u64,
+
+        /// Instead, host a server with bots joining and leaving for this long, like 30m or 1h,
+        /// and fail when tasks or the server's per-player state keep growing. Builds with the
+        /// soak feature check the heap as well.
+        #[arg(long, value_parser = bench::parse_duration)]
+        soak: Option<Duration>,
+
+        /// Bots playing during the soak test
+        #[arg(long, default_value_t = 50, requires = "soak")]
+        bots: usize,
+    },
+
+    /// Host a server on a free port, join it with two clients and check they see each other.
+    /// Exits nonzero when anything fails.
+    Selftest {
+        /// Seconds to exchange movement for
+        #[arg(long, default_value_t = 3)]
+        duration: u64,
+    },
+
+    /// Helpers for debugging the protocol
+    Tools {
+        #[command(subcommand)]
+        tool: Tool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Tool {
+    /// Print captured datagrams as JSON. Takes the hex dump of one datagram, or a capture file
+    /// with one datagram per line as hex or text.
+    Decode {
+        input: String,
+
+        #[arg(long, value_enum, default_value_t)]
+        codec: Codec,
+    },
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
 
+    match cli.command {
+        Some(Command::Bench {
+            soak: Some(duration),
+            bots,
+            ..
+        }) => return bench::soak(duration, bots),
+        Some(Command::Bench { players, ticks, .. }) => {
+            bench::run(players, ticks);
+            return Ok(());
+        }
+        Some(Command::Selftest { duration }) => {
+            return selftest::run(Duration::from_secs(duration));
+        }
+        Some(Command::Tools {
+            tool: Tool::Decode { input, codec },
+        }) => return Ok(tools::decode(&input, codec)?),
+        None => {}
+    }
+
+    // Only optional when running a subcommand
+    let port = cli.port.ok_or("--port is required")?;
+
+    let map = match &cli.map {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read map {}: {e}", path.display()))?;
+            TerrainMap::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?
+        }
+        None => TerrainMap::builtin(),
+    };
+
+    if cli
+        .invite_code
+        .as_deref()
+        .is_some_and(|code| !server::is_valid_invite_code(code))
+    {
+        return Err("--invite-code may only contain letters and digits".into());
+    }
+
+    if cli.interp_delay_min > cli.interp_delay_max {
+        return Err("--interp-delay-min may not be above --interp-delay-max".into());
+    }
+
+    let motd = match &cli.motd_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read MOTD {}: {e}", path.display()))?
+                .trim_end()
+                .to_string(),
+        ),
+        None => cli.motd.clone(),
+    };
+
+    let ping_interval = Duration::from_millis(cli.ping_interval);
+    let rules = GameRules {
+        movement: MovementConfig {
+            speed: cli.player_speed,
+            acceleration: cli.player_acceleration,
+            friction: cli.player_friction,
+        },
+        ping_interval,
+        max_missed_pings: cli.max_missed_pings.unwrap_or_else(|| {
+            rules::missed_pings_within(globals::CONNECTION_TIMEOUT_SEC, ping_interval)
+        }),
+        ..GameRules::default()
+    };
+    if !rules.movement.is_valid() {
+        return Err("--player-speed and --player-acceleration have to be above 0, --player-friction above 0 and at most 1".into());
+    }
+
+    // Used by the dedicated server and by servers hosted from the GUI
+    let server_config = ServerConfig {
+        palette: cli.palette,
+        bandwidth_limit: (cli.bandwidth_limit > 0).then(|| cli.bandwidth_limit * 1024),
+        world_mode: cli.world,
+        map,
+        rules,
+        cheat: CheatConfig {
+            speed_tolerance: cli.cheat_speed_tolerance,
+            max_messages_per_sec: cli.cheat_max_rate,
+            flag_score: cli.cheat_flag_score,
+            kick_score: cli.cheat_kick_score,
+        },
+        duplicate_identity: cli.duplicate_identity,
+        relay_service: cli.relay_service,
+        session_summaries: cli.session_summaries.clone(),
+        idle_kick: cli
+            .idle_kick
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+        motd,
+        // Filled in by the GUI for servers hosted by a player
+        host_key: None,
+        ..ServerConfig::default()
+    };
+    let server_builder = ServerBuilder::new()
+        .config(server_config)
+        .tick_rate(cli.tick_rate)
+        .max_players(cli.max_players as usize)
+        .sockets(cli.sockets as usize)
+        .relay(cli.relay.clone())
+        .invite_code(cli.invite_code.clone())
+        .auto_port(cli.auto_port);
+
     if cli.trace {
         println!("Message tracking enabled");
         message::set_trace(true);
+
+        if let Err(e) = message::set_trace_filter(&cli.trace_filter) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
     }
 
+    i18n::set_language(cli.lang);
+    paths::init(cli.config_dir.clone(), cli.data_dir.clone());
+    crash::install_hook();
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(4)
         .enable_all()
@@ -44,30 +605,185 @@ fn main() -> Result<(), Box<dyn Error>> {
     if cli.server_only {
         //cargo run -- --port 8080 --server-only --trace
 
-        print!("Starting server in headless mode");
-        rt.block_on(async {
-            match server::start_server(cli.port).await {
-                Ok(_) => {
-                    println!("Server started successfully. Press ctrl + C to shutdown the server");
-
-                    match tokio::signal::ctrl_c().await {
-                        Ok(_) => {
-                            println!("\nCtrl + C signal received. Shutting down gracefully...")
-                        }
+        // The pid file is gone by the time the exit code is picked
+        return match run_dedicated_server(&cli, &rt, server_builder, port) {
+            Err(e) if e.is::<BindFailure>() => {
+                eprintln!("Server failed to start: {e}");
+                daemon::report_stopped(exit_code::BIND_FAILURE);
+                std::process::exit(exit_code::BIND_FAILURE);
+            }
+            Err(e) if e.is::<daemon::Panicked>() => {
+                eprintln!("{e}");
+                daemon::report_stopped(exit_code::PANIC);
+                std::process::exit(exit_code::PANIC);
+            }
+            result => {
+                daemon::report_stopped(if result.is_ok() { 0 } else { 1 });
+                result
+            }
+        };
+    }
 
-                        Err(e) => eprint!("Failed to listen for ctrl + C"),
-                    }
-                }
+    let client_config = ClientConfig {
+        client_id: if cli.new_identity {
+            ClientId::random()
+        } else {
+            client::stored_identity()
+        },
+        identity: if cli.new_identity {
+            Identity::generate()
+        } else {
+            client::stored_key()
+        },
+        relay: cli.relay.clone(),
+        check_determinism: cli.check_determinism,
+        invite_code: cli.invite_code.clone(),
+        in_process_host: cli.in_process_client,
+        interp_delay: Duration::from_millis(cli.interp_delay_min)
+            ..=Duration::from_millis(cli.interp_delay_max),
+    };
 
-                Err(e) => {
-                    eprint!("Server failed to start: {}", e);
+    let connect = cli
+        .connect
+        .as_deref()
+        .map(addr::parse_endpoint)
+        .transpose()
+        .map_err(String::from)?;
 
-                    std::process::exit(1);
-                }
+    if cli.no_gui {
+        let endpoint = match connect {
+            Some(endpoint) => endpoint,
+            None => {
+                addr::parse_host_and_port(&cli.host, &port.to_string()).map_err(String::from)?
             }
-        })
+        };
+        let duration = cli.duration.map(Duration::from_secs);
+
+        return rt.block_on(async {
+            let server_address = endpoint.resolve().await.map_err(String::from)?.to_string();
+            let client = HeadlessClient::connect(server_address, &client_config)
+                .await
+                .map_err(|e| e as Box<dyn Error>)?;
+
+            client.run(duration).await
+        });
     }
 
     // Run graphical client otherwise.
-    app::run_app(&rt)
+    app::run_app(
+        &rt,
+        RenderSettings {
+            backend: cli.renderer,
+            crosshair: !cli.no_crosshair,
+            cursor_grab: cli.cursor_grab,
+            palette: cli.palette,
+            player_outline: cli.outline,
+            particles: !cli.no_particles,
+            screen_shake: cli.screen_shake.max(0.0),
+            hit_stop: cli.hit_stop,
+            max_correction_rate: cli.max_correction_rate,
+            accessibility: Accessibility::load(&paths::config_file()),
+        },
+        server_builder,
+        client_config,
+        connect,
+    )
+}
+
+/// Run the server without a window until it is asked to stop. In daemon mode a pid file exists
+/// for as long as this runs.
+fn run_dedicated_server(
+    cli: &Cli,
+    rt: &tokio::runtime::Runtime,
+    server_builder: ServerBuilder,
+    port: u16,
+) -> Result<(), Box<dyn Error>> {
+    let mut _pid_file = None;
+    if cli.daemon {
+        daemon::stop_on_panic();
+        daemon::serve_service_control();
+        let pid_file = match &cli.pid_file {
+            Some(path) => path.clone(),
+            None => {
+                paths::ensure_dir(paths::data_dir().to_path_buf())?.join("game-server-sample.pid")
+            }
+        };
+        _pid_file = Some(PidFile::create(&pid_file)?);
+    }
+
+    println!("Starting server {} in headless mode", version::LONG_VERSION);
+
+    let mut server_builder = server_builder.port(port);
+    if cli.daemon {
+        let log_file = match &cli.log_file {
+            Some(path) => path.clone(),
+            None => paths::ensure_dir(paths::log_dir())?.join("server.log"),
+        };
+        server_builder = server_builder.log_file(log_file);
+    }
+    if let Some(path) = &cli.load_world {
+        server_builder = server_builder.world(WorldSnapshot::load(path)?);
+    }
+
+    let server = rt.block_on(server_builder.start()).map_err(|e| {
+        if e.is::<BindFailure>() {
+            e as Box<dyn Error>
+        } else {
+            format!("Server failed to start: {e}").into()
+        }
+    })?;
+    let port = server.local_addr()?.port();
+
+    if let Some(addr) = cli.dashboard {
+        match rt.block_on(dashboard::start(addr, server.clone())) {
+            Ok(addr) => println!("Dashboard on http://{addr}"),
+            Err(e) => eprintln!("Failed to start the dashboard on {addr}: {e}"),
+        }
+    }
+
+    if cli.tui {
+        let result = tui::run(rt, &server, port);
+        rt.block_on(server.shutdown());
+
+        return result;
+    }
+
+    rt.block_on(async {
+        server
+            .log(format!(
+                "Server started successfully on port {port}. Press ctrl + C to shutdown the \
+                 server"
+            ))
+            .await;
+
+        tokio::spawn(admin::run_console(server.clone()));
+
+        let panicked = tokio::select! {
+            signal = daemon::wait_for_shutdown_signal() => {
+                match signal {
+                    Ok(_) => {
+                        server
+                            .log(String::from(
+                                "Shutdown signal received. Shutting down gracefully...",
+                            ))
+                            .await
+                    }
+
+                    Err(_) => eprint!("Failed to listen for shutdown signals"),
+                }
+
+                false
+            }
+
+            _ = daemon::panicked() => true,
+        };
+
+        server.shutdown().await;
+
+        if panicked {
+            Err(daemon::Panicked.into())
+        } else {
+            Ok(())
+        }
+    })
 }
@@ -1,48 +1,486 @@
 use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{Display, Write as _},
     io::Error,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
+use crate::{
+    codec::{Codec, Vector2Def, Vector3Def},
+    globals,
+    identity::{KeyProof, PublicKey},
+    normalize_angle,
+    rules::{self, GameRules},
+    simulation::MovementConfig,
+    terrain::TerrainMap,
+    version::Version,
+    Avatar, ClientId, Player, PlayerId, SessionToken, Shape, WorldBounds, WorldMode,
+};
 use cgmath::{Vector2, Vector3};
-use game_server_sample::{Player, PlayerId};
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub enum Message {
-    /// Period ping message for server healthcheck
+    /// Period ping message for server healthcheck, carrying a sequence number
     // TODO: extend for client disconnect check
-    Ping,
+    Ping(u32),
+
+    /// Client reply to a ping with the same sequence number, used for round trip time and packet
+    /// loss measurement. Also carries how far behind the client draws remote players, for lag
+    /// compensation. Older clients don't send it.
+    Pong(u32, Option<Duration>),
 
-    /// Init handshake when client join, retry on udp packet loss until timeout
-    Handshake,
+    /// Init handshake when client join, retry on udp packet loss until timeout. Carries the
+    /// client's identity, older clients don't send one, the invite code of private servers, the
+    /// player's public key and the codec the client would rather speak than text. The key proof
+    /// is boxed, it would triple the size of every message.
+    Handshake(
+        Option<ClientId>,
+        Option<String>,
+        Option<Box<KeyProof>>,
+        Option<Codec>,
+    ),
 
-    /// Server response to receive handshake
-    Ack(PlayerId, Vector3<f32>),
+    /// Server response to receive handshake with the player's color, the world mode, the
+    /// session token the client proves its identity with after an address change and the
+    /// server's version. Older servers send neither token nor version.
+    Ack(
+        PlayerId,
+        #[serde(with = "Vector3Def")] Vector3<f32>,
+        WorldMode,
+        Option<SessionToken>,
+        Option<Version>,
+    ),
 
     /// Notify all users still playing about the user exit so they can update their state
     Leave(PlayerId),
 
-    /// Server's world replication of a single player position
+    /// Server's world replication of a single player position and facing
     Replicate(Player),
 
-    /// Player's position response after movement change
+    /// Server is shutting down gracefully, clients should leave right away
+    ServerShutdown,
+
+    /// Server removed the client, e.g. for cheating. The optional reason is a short code such as
+    /// [`KICK_IDLE`] the client can explain to the player.
+    Kick(Option<String>),
+
+    /// Terrain layout of the world, sent by the server after the ACK
+    Map(TerrainMap),
+
+    /// Server's world clock as time of day between 0 and 1, 0 being midnight
+    WorldClock(f32),
+
+    /// Server changed its simulation rate in Hz, so clients can adapt their interpolation
+    TickRateChange(u32),
+
+    /// Server changed the speed of simulation time, 0 while paused. Clients run their own
+    /// simulation at the same speed, see [`crate::clock::SimClock`].
+    TimeScale(f32),
+
+    /// Player's totals so far: distance traveled in world units and seconds played, sent by the
+    /// server every second for the scoreboard
+    Stats(PlayerId, f32, u32),
+
+    /// Periodic client traffic keeping NAT mappings open, lets the server follow the client to a
+    /// new address
+    KeepAlive(SessionToken),
+
+    /// Name a player picked, sent by the player and relayed by the server to everyone
+    Name(PlayerId, String),
+
+    /// Chat line of a player, sent by the player and relayed by the server to everyone
+    Chat(PlayerId, String),
+
+    /// Private chat line for the player of the given name, the server only routes it to them
+    Whisper(String, String),
+
+    /// Private chat line the server delivers to its target, from the given player
+    WhisperFrom(PlayerId, String),
+
+    /// Server couldn't route a whisper to the given name
+    WhisperFailed(WhisperError, String),
+
+    /// Server's position of the receiving player after refusing part of a reported move. The
+    /// client moves there, smoothing the jump out over a few frames.
+    Correction(#[serde(with = "Vector2Def")] Vector2<f32>),
+
+    /// Player's position response after movement change, stamped with the client's simulation
+    /// step so the server can play positions back at the pace they were produced. Older clients
+    /// don't stamp them.
     // TODO: Avoid clients self-reporting their exact own position and opt for sending input
     // action instead
-    Position(PlayerId, Vector2<f32>),
+    Position(
+        PlayerId,
+        #[serde(with = "Vector2Def")] Vector2<f32>,
+        Option<u32>,
+    ),
+
+    /// Client used an ability. It already acted on it, the server checks the cooldown before
+    /// accepting the movement that follows.
+    Ability(Ability),
+
+    /// Server accepted a player's dash from the given position in the given facing, for other
+    /// clients to show it
+    Dash(PlayerId, #[serde(with = "Vector2Def")] Vector2<f32>, f32),
+
+    /// Server knocked a player back with the given impulse, see
+    /// [`crate::simulation::knockback`]. The player's own client applies it, everyone else
+    /// shows it.
+    Knockback(PlayerId, #[serde(with = "Vector2Def")] Vector2<f32>),
+
+    /// Line from the server for every client's log, such as kicks or admin announcements
+    ServerNotice(NoticeLevel, String),
+
+    /// Message of the day, sent to joining players right after the ACK
+    Motd(String),
+
+    /// What this server plays by, sent to joining players with the map
+    Rules(GameRules),
+
+    /// Public key a player joined with, for adding them as a friend
+    PlayerKey(PlayerId, PublicKey),
+
+    /// Asks a server how it's doing without joining it
+    StatusQuery,
+
+    /// Answer to a status query: number of players, the key of the player hosting the server,
+    /// if any, and the server's version
+    Status(u32, Option<PublicKey>, Option<Version>),
+
+    /// Server doesn't know the session the client talks from, e.g. after a restart. The client
+    /// joins again.
+    Resync,
+
+    /// Latency probe a tracing client sends, answered with a [`Message::ProbeReply`] of the
+    /// same sequence number
+    Probe(u32),
+
+    /// Where a probe spent its time on the server: waiting in the receive queue, then waiting
+    /// for the simulation tick that answered it. Travels in microseconds.
+    ProbeReply(u32, Duration, Duration),
 }
 
 const PING: &str = "PING";
+const PONG: &str = "PONG";
 const HANDSHAKE: &str = "HANDSHAKE";
 const ACK: &str = "ACK";
 const LEAVE: &str = "LEAVE";
 const REPL: &str = "REPL";
 const POS: &str = "POS";
+const SHUTDOWN: &str = "SHUTDOWN";
+const TICKRATE: &str = "TICKRATE";
+const TIMESCALE: &str = "TIMESCALE";
+const MAP: &str = "MAP";
+const CLOCK: &str = "CLOCK";
+const KICK: &str = "KICK";
+const KEEPALIVE: &str = "KEEPALIVE";
+const STATS: &str = "STATS";
+const NAME: &str = "NAME";
+const CHAT: &str = "CHAT";
+const WHISPER: &str = "WHISPER";
+const WHISPER_FROM: &str = "WHISPFROM";
+const WHISPER_FAILED: &str = "WHISPFAIL";
+const CORRECTION: &str = "CORRECT";
+const ABILITY: &str = "ABILITY";
+const DASH: &str = "DASH";
+const KNOCKBACK: &str = "KNOCK";
+const NOTICE: &str = "NOTICE";
+const MOTD: &str = "MOTD";
+const PLAYER_KEY: &str = "KEY";
+const STATUS_QUERY: &str = "STATUSQ";
+const STATUS: &str = "STATUS";
+const RESYNC: &str = "RESYNC";
+const PROBE: &str = "PROBE";
+const PROBE_REPLY: &str = "PROBEREP";
+const RULES: &str = "RULES";
+
+/// Kick reason of players removed for not moving for too long
+pub const KICK_IDLE: &str = "idle";
+
+/// Kick reason of handshakes without the right invite code of a private server
+pub const KICK_INVITE: &str = "invite";
+
+/// Kick reason of players turned away because the server has no room left
+pub const KICK_FULL: &str = "full";
+
+/// Wire names of all message types, as accepted by the trace filter
+pub const MESSAGE_NAMES: [&str; 33] = [
+    PING,
+    PONG,
+    HANDSHAKE,
+    ACK,
+    LEAVE,
+    REPL,
+    POS,
+    SHUTDOWN,
+    TICKRATE,
+    TIMESCALE,
+    MAP,
+    CLOCK,
+    KICK,
+    KEEPALIVE,
+    STATS,
+    NAME,
+    CHAT,
+    WHISPER,
+    WHISPER_FROM,
+    WHISPER_FAILED,
+    CORRECTION,
+    ABILITY,
+    DASH,
+    KNOCKBACK,
+    NOTICE,
+    MOTD,
+    PLAYER_KEY,
+    STATUS_QUERY,
+    STATUS,
+    RESYNC,
+    PROBE,
+    PROBE_REPLY,
+    RULES,
+];
+
+/// Action a player triggers on top of moving
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ability {
+    /// Short jump in the facing direction, see [`crate::simulation::dash`]
+    Dash,
+}
+
+impl Ability {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Ability::Dash => "dash",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "dash" => Some(Ability::Dash),
+            _ => None,
+        }
+    }
+}
+
+/// How much a server notice matters, deciding how clients show it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NoticeLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl NoticeLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NoticeLevel::Info => "info",
+            NoticeLevel::Warning => "warning",
+            NoticeLevel::Error => "error",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "info" => Some(NoticeLevel::Info),
+            "warning" => Some(NoticeLevel::Warning),
+            "error" => Some(NoticeLevel::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Why a whisper didn't reach anyone
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WhisperError {
+    /// No player goes by the name
+    Unknown,
+
+    /// Several players match the name
+    Ambiguous,
+}
+
+impl WhisperError {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WhisperError::Unknown => "unknown",
+            WhisperError::Ambiguous => "ambiguous",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "unknown" => Some(WhisperError::Unknown),
+            "ambiguous" => Some(WhisperError::Ambiguous),
+            _ => None,
+        }
+    }
+}
 
 impl Message {
     pub fn serialize(&self) -> String {
         match self {
-            Message::Ping | Message::Handshake => self.name().to_string(),
+            Message::Handshake(None, ..)
+            | Message::ServerShutdown
+            | Message::Kick(None)
+            | Message::StatusQuery
+            | Message::Resync => self.name().to_string(),
+
+            Message::Kick(Some(reason)) => format!("{}:{}", self.name(), reason),
+
+            Message::Name(player_id, text)
+            | Message::Chat(player_id, text)
+            | Message::WhisperFrom(player_id, text) => {
+                format!("{}:{}:{}", self.name(), player_id, text)
+            }
+
+            Message::Whisper(target, text) => format!("{}:{}:{}", self.name(), target, text),
+
+            Message::WhisperFailed(error, target) => {
+                format!("{}:{}:{}", self.name(), error.as_str(), target)
+            }
+
+            Message::Motd(text) => format!("{}:{}", self.name(), text),
+
+            // The connection timeout is still there for clients from before the ping policy
+            Message::Rules(rules) => format!(
+                "{}:{},{},{}:{}:{},{},{},{}:{}:{}:{},{}",
+                self.name(),
+                rules.movement.speed,
+                rules.movement.acceleration,
+                rules.movement.friction,
+                rules.player_size,
+                rules.bounds.min_x,
+                rules.bounds.min_y,
+                rules.bounds.max_x,
+                rules.bounds.max_y,
+                rules.tick_rate,
+                rules.connection_timeout().as_millis(),
+                rules.ping_interval.as_millis(),
+                rules.max_missed_pings
+            ),
+
+            Message::ServerNotice(level, text) => {
+                format!("{}:{}:{}", self.name(), level.as_str(), text)
+            }
+
+            Message::Handshake(Some(client_id), None, None, None) => {
+                format!("{}:{}", self.name(), client_id)
+            }
+
+            Message::Handshake(Some(client_id), Some(invite_code), None, None) => {
+                format!("{}:{}:{}", self.name(), client_id, invite_code)
+            }
+
+            Message::Handshake(Some(client_id), invite_code, Some(proof), None) => format!(
+                "{}:{}:{}:{}",
+                self.name(),
+                client_id,
+                invite_code.as_deref().unwrap_or(""),
+                KeyProof::serialize(proof)
+            ),
 
-            Message::Ack(player_id, color) => {
-                format!("{}:{}:{}", self.name(), player_id, serialize_color(&color))
+            // An empty invite code or proof stands for none. Older servers ignore the codec.
+            Message::Handshake(Some(client_id), invite_code, proof, Some(codec)) => format!(
+                "{}:{}:{}:{}:{}",
+                self.name(),
+                client_id,
+                invite_code.as_deref().unwrap_or(""),
+                proof
+                    .as_deref()
+                    .map(KeyProof::serialize)
+                    .unwrap_or_default(),
+                codec.as_str()
+            ),
+
+            Message::PlayerKey(player_id, key) => format!("{}:{}:{}", self.name(), player_id, key),
+
+            Message::Status(players, None, None) => format!("{}:{}", self.name(), players),
+
+            Message::Status(players, Some(host), None) => {
+                format!("{}:{}:{}", self.name(), players, host)
+            }
+
+            // An empty host key stands for none
+            Message::Status(players, host, Some(version)) => format!(
+                "{}:{}:{}:{}",
+                self.name(),
+                players,
+                host.map(|host| host.to_string()).unwrap_or_default(),
+                version.serialize()
+            ),
+
+            Message::Ability(ability) => format!("{}:{}", self.name(), ability.as_str()),
+
+            Message::Dash(player_id, from, facing) => format!(
+                "{}:{}:{}:{}",
+                self.name(),
+                player_id,
+                serialize_position(*from),
+                serialize_facing(*facing)
+            ),
+
+            Message::Knockback(player_id, impulse) => format!(
+                "{}:{}:{}",
+                self.name(),
+                player_id,
+                serialize_position(*impulse)
+            ),
+
+            Message::Ping(seq) | Message::Probe(seq) => format!("{}:{}", self.name(), seq),
+
+            Message::Pong(seq, interp_delay) => {
+                let mut msg = format!("{}:{}", self.name(), seq);
+                if let Some(interp_delay) = interp_delay {
+                    let _ = write!(msg, ":{}", interp_delay.as_micros());
+                }
+
+                msg
+            }
+
+            Message::ProbeReply(seq, queued, tick_wait) => format!(
+                "{}:{}:{}:{}",
+                self.name(),
+                seq,
+                queued.as_micros(),
+                tick_wait.as_micros()
+            ),
+
+            Message::TickRateChange(hz) => format!("{}:{}", self.name(), hz),
+
+            Message::TimeScale(scale) => format!("{}:{:.3}", self.name(), scale),
+
+            Message::KeepAlive(token) => format!("{}:{}", self.name(), token),
+
+            Message::Map(map) => format!("{}:{}", self.name(), map.serialize()),
+
+            Message::WorldClock(time_of_day) => format!("{}:{:.4}", self.name(), time_of_day),
+
+            Message::Ack(player_id, color, world_mode, token, version) => {
+                let mut ack = format!(
+                    "{}:{}:{}:{}",
+                    self.name(),
+                    player_id,
+                    serialize_color(color),
+                    world_mode.as_str()
+                );
+                // An empty token stands for none when a version follows
+                match (token, version) {
+                    (Some(token), None) => {
+                        let _ = write!(ack, ":{token}");
+                    }
+                    (token, Some(version)) => {
+                        let token = token.map(|token| token.to_string()).unwrap_or_default();
+                        let _ = write!(ack, ":{token}:{}", version.serialize());
+                    }
+                    (None, None) => (),
+                }
+
+                ack
             }
 
             Message::Leave(player_id) => {
@@ -50,30 +488,131 @@ impl Message {
             }
 
             Message::Replicate(player_state) => format!(
-                "{}:{}:{},{},{}",
+                "{}:{}:{},{},{}{}",
                 self.name(),
                 player_state.id,
-                player_state.pos.x as i32,
-                player_state.pos.y as i32,
-                serialize_color(&player_state.color)
+                serialize_position(player_state.pos),
+                serialize_color(&player_state.color),
+                serialize_facing(player_state.facing),
+                serialize_avatar(&player_state.avatar)
             ),
 
-            Message::Position(player_id, pos) => format!(
+            Message::Stats(player_id, distance, seconds) => format!(
                 "{}:{}:{},{}",
                 self.name(),
                 player_id,
-                pos.x as i32,
-                pos.y as i32
+                *distance as u32,
+                seconds
             ),
+
+            Message::Correction(pos) => {
+                format!("{}:{}", self.name(), serialize_position(*pos))
+            }
+
+            Message::Position(player_id, pos, step) => {
+                let mut msg = format!("{}:{}:{}", self.name(), player_id, serialize_position(*pos));
+                if let Some(step) = step {
+                    let _ = write!(msg, ":{step}");
+                }
+
+                msg
+            }
         }
     }
 
     pub fn deserialize(msg: &str) -> Result<Message, Error> {
         let parts: Vec<&str> = msg.split(':').collect();
-        match parts.get(0).map(|s| *s) {
-            Some(PING) => Ok(Message::Ping),
-            Some(HANDSHAKE) => Ok(Message::Handshake),
-            Some(ACK) if parts.len() == 3 => {
+        match parts.first().copied() {
+            Some(PING) if parts.len() == 2 => {
+                let seq = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid ping sequence")
+                })?;
+
+                Ok(Message::Ping(seq))
+            }
+            Some(PONG) if matches!(parts.len(), 2 | 3) => {
+                let seq = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid ping sequence")
+                })?;
+
+                let interp_delay = match parts.get(2) {
+                    Some(micros) => Some(Duration::from_micros(micros.parse().map_err(|_| {
+                        Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Invalid interpolation delay",
+                        )
+                    })?)),
+                    None => None,
+                };
+
+                Ok(Message::Pong(seq, interp_delay))
+            }
+            Some(TICKRATE) if parts.len() == 2 => {
+                let hz = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid tick rate")
+                })?;
+
+                Ok(Message::TickRateChange(hz))
+            }
+            Some(TIMESCALE) if parts.len() == 2 => {
+                let scale = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid time scale")
+                })?;
+
+                Ok(Message::TimeScale(scale))
+            }
+            Some(MAP) if parts.len() == 2 => Ok(Message::Map(TerrainMap::deserialize(parts[1])?)),
+            Some(CLOCK) if parts.len() == 2 => {
+                let time_of_day = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid world clock")
+                })?;
+
+                Ok(Message::WorldClock(time_of_day))
+            }
+            Some(HANDSHAKE) => match parts.get(1) {
+                Some(client_id) => {
+                    let client_id = ClientId::parse(client_id).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid client id")
+                    })?;
+
+                    let invite_code = parts
+                        .get(2)
+                        .filter(|code| !code.is_empty())
+                        .map(|code| code.to_string());
+
+                    let proof = match parts.get(3).filter(|proof| !proof.is_empty()) {
+                        Some(proof) => {
+                            Some(Box::new(KeyProof::deserialize(proof).ok_or_else(|| {
+                                Error::new(std::io::ErrorKind::InvalidData, "Invalid key proof")
+                            })?))
+                        }
+                        None => None,
+                    };
+
+                    // Codecs of newer clients are nothing to fall out over, text always works
+                    let codec = parts.get(4).and_then(|codec| Codec::parse(codec));
+
+                    Ok(Message::Handshake(
+                        Some(client_id),
+                        invite_code,
+                        proof,
+                        codec,
+                    ))
+                }
+                None => Ok(Message::Handshake(None, None, None, None)),
+            },
+            Some(SHUTDOWN) => Ok(Message::ServerShutdown),
+            Some(KICK) => Ok(Message::Kick(parts.get(1).map(|reason| reason.to_string()))),
+            Some(KEEPALIVE) if parts.len() == 2 => {
+                let token = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid session token")
+                })?;
+
+                Ok(Message::KeepAlive(token))
+            }
+            // World mode and session token were added later, servers not sending the world mode
+            // run a bounded world
+            Some(ACK) if (3..=6).contains(&parts.len()) => {
                 let player_id = parts[1]
                     .parse()
                     .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
@@ -81,7 +620,28 @@ impl Message {
                 let color = deserialize_color(parts[2])
                     .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
 
-                Ok(Message::Ack(player_id, color))
+                let world_mode = match parts.get(3) {
+                    Some(mode) => WorldMode::parse(mode).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid world mode")
+                    })?,
+                    None => WorldMode::Bounded,
+                };
+
+                let token = match parts.get(4).filter(|token| !token.is_empty()) {
+                    Some(token) => Some(token.parse().map_err(|_| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid session token")
+                    })?),
+                    None => None,
+                };
+
+                let version = match parts.get(5) {
+                    Some(version) => Some(Version::parse(version).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid version")
+                    })?),
+                    None => None,
+                };
+
+                Ok(Message::Ack(player_id, color, world_mode, token, version))
             }
             Some(LEAVE) if parts.len() == 2 => {
                 let player_id = parts[1].parse().map_err(|_| {
@@ -98,42 +658,67 @@ impl Message {
 
                 let data_parts: Vec<&str> = parts[2].split(',').collect();
 
-                if data_parts.len() != 3 {
+                // Older servers don't send the facing, players with the default avatar go without
+                // shape and size
+                if !(3..=6).contains(&data_parts.len()) {
                     return Err(Error::new(
                         std::io::ErrorKind::InvalidData,
                         "Invalid format",
                     ));
                 }
 
-                let x = data_parts[0].parse().map_err(|_| {
-                    return Error::new(
+                let x = deserialize_coordinate(data_parts[0]).ok_or_else(|| {
+                    Error::new(
                         std::io::ErrorKind::InvalidData,
                         "Invalid format x coordinate",
-                    );
+                    )
                 })?;
 
-                let y = data_parts[1].parse().map_err(|_| {
-                    return Error::new(
+                let y = deserialize_coordinate(data_parts[1]).ok_or_else(|| {
+                    Error::new(
                         std::io::ErrorKind::InvalidData,
                         "Invalid format y coordinate",
-                    );
+                    )
                 })?;
 
                 let color = deserialize_color(data_parts[2])
                     .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
+                let facing = match data_parts.get(3) {
+                    Some(facing) => deserialize_facing(facing).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid facing")
+                    })?,
+                    None => 0.0,
+                };
+
+                let avatar = match data_parts.get(4..) {
+                    Some([shape, size]) => deserialize_avatar(shape, size).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid avatar")
+                    })?,
+                    Some([]) | None => Avatar::default(),
+                    Some(_) => {
+                        return Err(Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Invalid avatar",
+                        ))
+                    }
+                };
+
                 Ok(Message::Replicate(Player {
                     id: player_id,
                     pos: Vector2::new(x, y),
                     velocity: Vector2::new(0.0, 0.0),
                     color,
+                    facing,
+                    avatar,
+                    ..Default::default()
                 }))
             }
 
-            Some(POS) if parts.len() == 3 => {
+            Some(POS) if (3..=4).contains(&parts.len()) => {
                 let player_id = parts[1]
                     .parse()
-                    .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
 
                 let pos_parts: Vec<&str> = parts[2].split(',').collect();
 
@@ -144,15 +729,270 @@ impl Message {
                     ));
                 }
 
-                let x = pos_parts[0].parse().map_err(|_| {
+                let x = deserialize_coordinate(pos_parts[0]).ok_or_else(|| {
                     Error::new(std::io::ErrorKind::InvalidData, "Invalid x coordinator")
                 })?;
 
-                let y = pos_parts[1].parse().map_err(|_| {
+                let y = deserialize_coordinate(pos_parts[1]).ok_or_else(|| {
                     Error::new(std::io::ErrorKind::InvalidData, "Invalid y coordinator")
                 })?;
 
-                Ok(Message::Position(player_id, Vector2::new(x, y)))
+                let step = match parts.get(3) {
+                    Some(step) => Some(step.parse().map_err(|_| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid simulation step")
+                    })?),
+                    None => None,
+                };
+
+                Ok(Message::Position(player_id, Vector2::new(x, y), step))
+            }
+
+            Some(ABILITY) if parts.len() == 2 => Ability::parse(parts[1])
+                .map(Message::Ability)
+                .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Unknown ability")),
+
+            Some(DASH) if parts.len() == 4 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+
+                let from = parts[2]
+                    .split_once(',')
+                    .and_then(|(x, y)| {
+                        Some(Vector2::new(
+                            deserialize_coordinate(x)?,
+                            deserialize_coordinate(y)?,
+                        ))
+                    })
+                    .ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid dash position")
+                    })?;
+
+                let facing = deserialize_facing(parts[3])
+                    .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "Invalid facing"))?;
+
+                Ok(Message::Dash(player_id, from, facing))
+            }
+
+            Some(KNOCKBACK) if parts.len() == 3 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+
+                let impulse = parts[2]
+                    .split_once(',')
+                    .and_then(|(x, y)| {
+                        Some(Vector2::new(
+                            deserialize_coordinate(x)?,
+                            deserialize_coordinate(y)?,
+                        ))
+                    })
+                    .ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid knockback impulse")
+                    })?;
+
+                Ok(Message::Knockback(player_id, impulse))
+            }
+
+            Some(CORRECTION) if parts.len() == 2 => {
+                let (x, y) = parts[1]
+                    .split_once(',')
+                    .and_then(|(x, y)| {
+                        Some((deserialize_coordinate(x)?, deserialize_coordinate(y)?))
+                    })
+                    .ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid correction format")
+                    })?;
+
+                Ok(Message::Correction(Vector2::new(x, y)))
+            }
+
+            Some(STATS) if parts.len() == 3 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+
+                let (distance, seconds) = parts[2]
+                    .split_once(',')
+                    .and_then(|(distance, seconds)| {
+                        Some((distance.parse().ok()?, seconds.parse().ok()?))
+                    })
+                    .ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid stats format")
+                    })?;
+
+                Ok(Message::Stats(player_id, distance, seconds))
+            }
+
+            Some(NAME) if parts.len() == 3 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+
+                Ok(Message::Name(player_id, parts[2].to_string()))
+            }
+
+            // Chat text may contain the separator itself
+            Some(CHAT) if parts.len() >= 3 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+
+                Ok(Message::Chat(player_id, parts[2..].join(":")))
+            }
+
+            Some(WHISPER) if parts.len() >= 3 => {
+                Ok(Message::Whisper(parts[1].to_string(), parts[2..].join(":")))
+            }
+
+            Some(WHISPER_FROM) if parts.len() >= 3 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+
+                Ok(Message::WhisperFrom(player_id, parts[2..].join(":")))
+            }
+
+            Some(WHISPER_FAILED) if parts.len() == 3 => {
+                let error = WhisperError::parse(parts[1]).ok_or_else(|| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid whisper error")
+                })?;
+
+                Ok(Message::WhisperFailed(error, parts[2].to_string()))
+            }
+
+            Some(MOTD) if parts.len() >= 2 => Ok(Message::Motd(parts[1..].join(":"))),
+
+            Some(RULES) if (6..=7).contains(&parts.len()) => {
+                let invalid =
+                    || Error::new(std::io::ErrorKind::InvalidData, "Invalid game rules format");
+                let floats = |field: &str| -> Result<Vec<f32>, Error> {
+                    field
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid())
+                };
+
+                let (movement, bounds) = match (&floats(parts[1])?[..], &floats(parts[3])?[..]) {
+                    (&[speed, acceleration, friction], &[min_x, min_y, max_x, max_y]) => (
+                        MovementConfig {
+                            speed,
+                            acceleration,
+                            friction,
+                        },
+                        WorldBounds {
+                            min_x,
+                            min_y,
+                            max_x,
+                            max_y,
+                        },
+                    ),
+                    _ => return Err(invalid()),
+                };
+
+                // Older servers ping at the default interval and only send the timeout
+                let connection_timeout =
+                    Duration::from_millis(parts[5].parse().map_err(|_| invalid())?);
+                let (ping_interval, max_missed_pings) = match parts.get(6) {
+                    Some(policy) => match policy.split_once(',') {
+                        Some((interval, missed)) => (
+                            Duration::from_millis(interval.parse().map_err(|_| invalid())?),
+                            missed.parse().map_err(|_| invalid())?,
+                        ),
+                        None => return Err(invalid()),
+                    },
+                    None => (
+                        globals::PING_INTERVAL_MS,
+                        rules::missed_pings_within(connection_timeout, globals::PING_INTERVAL_MS),
+                    ),
+                };
+
+                let rules = GameRules {
+                    bounds,
+                    player_size: parts[2].parse().map_err(|_| invalid())?,
+                    movement,
+                    tick_rate: parts[4].parse().map_err(|_| invalid())?,
+                    ping_interval,
+                    max_missed_pings,
+                };
+
+                if rules.is_valid() {
+                    Ok(Message::Rules(rules))
+                } else {
+                    Err(Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Invalid game rules",
+                    ))
+                }
+            }
+
+            Some(PLAYER_KEY) if parts.len() == 3 => {
+                let player_id = parts[1]
+                    .parse()
+                    .map_err(|_| Error::new(std::io::ErrorKind::InvalidData, "Invalid PlayerId"))?;
+                let key = PublicKey::parse(parts[2]).ok_or_else(|| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid public key")
+                })?;
+
+                Ok(Message::PlayerKey(player_id, key))
+            }
+
+            Some(STATUS_QUERY) => Ok(Message::StatusQuery),
+
+            Some(RESYNC) => Ok(Message::Resync),
+
+            Some(PROBE) if parts.len() == 2 => {
+                let seq = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid probe sequence")
+                })?;
+
+                Ok(Message::Probe(seq))
+            }
+
+            Some(PROBE_REPLY) if parts.len() == 4 => {
+                let seq = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid probe sequence")
+                })?;
+                let micros = |part: &str| {
+                    part.parse().map(Duration::from_micros).map_err(|_| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid probe timing")
+                    })
+                };
+
+                Ok(Message::ProbeReply(
+                    seq,
+                    micros(parts[2])?,
+                    micros(parts[3])?,
+                ))
+            }
+
+            Some(STATUS) if (2..=4).contains(&parts.len()) => {
+                let players = parts[1].parse().map_err(|_| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid player count")
+                })?;
+                let host = match parts.get(2).filter(|host| !host.is_empty()) {
+                    Some(host) => Some(PublicKey::parse(host).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid host key")
+                    })?),
+                    None => None,
+                };
+                let version = match parts.get(3) {
+                    Some(version) => Some(Version::parse(version).ok_or_else(|| {
+                        Error::new(std::io::ErrorKind::InvalidData, "Invalid version")
+                    })?),
+                    None => None,
+                };
+
+                Ok(Message::Status(players, host, version))
+            }
+
+            Some(NOTICE) if parts.len() >= 3 => {
+                let level = NoticeLevel::parse(parts[1]).ok_or_else(|| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid notice level")
+                })?;
+
+                Ok(Message::ServerNotice(level, parts[2..].join(":")))
             }
 
             _ => Err(Error::new(
@@ -164,29 +1004,150 @@ impl Message {
 
     /////////////////////////////////////////////////
 
-    // Helper function
-    fn name(&self) -> &'static str {
+    /// Wire name of the message type
+    pub fn name(&self) -> &'static str {
         match self {
-            Message::Ping => PING,
-            Message::Handshake => HANDSHAKE,
-            Message::Ack(_, _) => ACK,
+            Message::Ping(_) => PING,
+            Message::Pong(..) => PONG,
+            Message::Handshake(..) => HANDSHAKE,
+            Message::Ack(..) => ACK,
             Message::Leave(_) => LEAVE,
             Message::Replicate(_) => REPL,
-            Message::Position(_, _) => POS,
+            Message::Correction(_) => CORRECTION,
+            Message::Position(..) => POS,
+            Message::ServerShutdown => SHUTDOWN,
+            Message::Kick(_) => KICK,
+            Message::TickRateChange(_) => TICKRATE,
+            Message::TimeScale(_) => TIMESCALE,
+            Message::Map(_) => MAP,
+            Message::WorldClock(_) => CLOCK,
+            Message::KeepAlive(_) => KEEPALIVE,
+            Message::Stats(..) => STATS,
+            Message::Name(..) => NAME,
+            Message::Chat(..) => CHAT,
+            Message::Whisper(..) => WHISPER,
+            Message::WhisperFrom(..) => WHISPER_FROM,
+            Message::WhisperFailed(..) => WHISPER_FAILED,
+            Message::Ability(_) => ABILITY,
+            Message::Dash(..) => DASH,
+            Message::Knockback(..) => KNOCKBACK,
+            Message::ServerNotice(..) => NOTICE,
+            Message::Motd(_) => MOTD,
+            Message::Rules(_) => RULES,
+            Message::PlayerKey(..) => PLAYER_KEY,
+            Message::StatusQuery => STATUS_QUERY,
+            Message::Status(..) => STATUS,
+            Message::Resync => RESYNC,
+            Message::Probe(_) => PROBE,
+            Message::ProbeReply(..) => PROBE_REPLY,
+        }
+    }
+
+    /// Whether the message may be dropped when a client runs out of bandwidth. Replication, the
+    /// world clock and stats are superseded by the next update anyway, everything else has to
+    /// arrive.
+    pub fn is_droppable(&self) -> bool {
+        self.superseded_by().is_some()
+    }
+
+    /// Key shared with the newer messages that make this one stale, `None` for messages that
+    /// don't go stale
+    pub fn superseded_by(&self) -> Option<(&'static str, PlayerId)> {
+        match self {
+            Message::Replicate(player) => Some((REPL, player.id)),
+            Message::WorldClock(_) => Some((CLOCK, 0)),
+            Message::Stats(player_id, ..) => Some((STATS, *player_id)),
+            _ => None,
         }
     }
 }
 
 ////////////////////////////////////////////////////
 
+// Position process
+
+/// Positions travel as fixed point numbers, in steps of `1 / POSITION_SCALE` world units. Client
+/// and server keep positions on that grid, so what arrives is exactly what was sent.
+pub const POSITION_SCALE: f32 = 64.0;
+
+/// Fixed point wire form of a position, rounded to the nearest step
+pub fn quantize_position(pos: Vector2<f32>) -> Vector2<i32> {
+    pos.map(|v| (v * POSITION_SCALE).round() as i32)
+}
+
+pub fn dequantize_position(pos: Vector2<i32>) -> Vector2<f32> {
+    pos.map(|v| v as f32 / POSITION_SCALE)
+}
+
+/// Nearest position the wire can carry
+pub fn snap_position(pos: Vector2<f32>) -> Vector2<f32> {
+    dequantize_position(quantize_position(pos))
+}
+
+fn serialize_position(pos: Vector2<f32>) -> String {
+    let pos = quantize_position(pos);
+
+    format!("{},{}", pos.x, pos.y)
+}
+
+fn deserialize_coordinate(s: &str) -> Option<f32> {
+    s.parse::<i32>().ok().map(|v| v as f32 / POSITION_SCALE)
+}
+
+// Facing travels in 1/256 turns, finer than a turn step
+const FACING_STEPS: f32 = 256.0;
+
+fn serialize_facing(facing: f32) -> String {
+    let steps = (facing / std::f32::consts::TAU * FACING_STEPS).round() as i32;
+
+    steps.rem_euclid(FACING_STEPS as i32).to_string()
+}
+
+fn deserialize_facing(s: &str) -> Option<f32> {
+    let steps = s.parse::<u8>().ok()?;
+
+    Some(normalize_angle(
+        steps as f32 / FACING_STEPS * std::f32::consts::TAU,
+    ))
+}
+
+////////////////////////////////////////////////////
+
 // Color process
 
+/// Shape and size after the facing, nothing for the default avatar. A player without a size of
+/// its own has the size left empty.
+fn serialize_avatar(avatar: &Avatar) -> String {
+    if *avatar == Avatar::default() {
+        return String::new();
+    }
+
+    let size = avatar.size.map(|size| size.to_string()).unwrap_or_default();
+    format!(",{},{size}", avatar.shape.as_str())
+}
+
+fn deserialize_avatar(shape: &str, size: &str) -> Option<Avatar> {
+    let size = match size {
+        "" => None,
+        size => Some(
+            size.parse::<f32>()
+                .ok()
+                .filter(|s| s.is_finite() && *s > 0.0)?,
+        ),
+    };
+
+    Some(Avatar {
+        shape: Shape::parse(shape)?,
+        size,
+    })
+}
+
 fn serialize_color(color: &Vector3<f32>) -> String {
     let r = (color[0] * 255.0).round() as u8;
     let g = (color[1] * 255.0).round() as u8;
     let b = (color[2] * 255.0).round() as u8;
 
-    String::from(format!("#{:02X}{:02X}{:02X}", r, g, b))
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
 }
 
 fn deserialize_color(color_hex: &str) -> Result<Vector3<f32>, String> {
@@ -217,12 +1178,185 @@ fn deserialize_color(color_hex: &str) -> Result<Vector3<f32>, String> {
 
 static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
 
+/// Message types let through by `trace_msg`, `None` lets everything through
+static TRACE_FILTER: RwLock<Option<Vec<&'static str>>> = RwLock::new(None);
+
+/// Trace lines are also kept for `take_trace_lines` while set
+static TRACE_CAPTURE: AtomicBool = AtomicBool::new(false);
+
+// Captured lines nobody took yet, oldest dropped first
+static TRACE_CAPTURED: Mutex<VecDeque<TraceLine>> = Mutex::new(VecDeque::new());
+const TRACE_CAPTURE_LEN: usize = 1000;
+
+/// Single trace line as printed, for showing traces in the GUI
+#[derive(Clone, Debug)]
+pub struct TraceLine {
+    /// Message type, `None` for free-form lines
+    pub name: Option<&'static str>,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        }
+    }
+}
+
 pub fn set_trace(enabled: bool) {
     TRACE_ENABLED.store(enabled, Ordering::Relaxed);
 }
 
+pub fn trace_enabled() -> bool {
+    TRACE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Keep trace lines for `take_trace_lines` in addition to printing them
+pub fn set_trace_capture(enabled: bool) {
+    TRACE_CAPTURE.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        TRACE_CAPTURED.lock().unwrap().clear();
+    }
+}
+
+/// Trace lines captured since the last call, oldest first
+pub fn take_trace_lines() -> Vec<TraceLine> {
+    TRACE_CAPTURED.lock().unwrap().drain(..).collect()
+}
+
+fn emit_trace(name: Option<&'static str>, text: String) {
+    println!("[TRACE] {text}");
+
+    if TRACE_CAPTURE.load(Ordering::Relaxed) {
+        let mut captured = TRACE_CAPTURED.lock().unwrap();
+        if captured.len() == TRACE_CAPTURE_LEN {
+            captured.pop_front();
+        }
+        captured.push_back(TraceLine { name, text });
+    }
+}
+
+/// Only trace the given message types, e.g. `["REPL", "POS"]`. An empty list clears the filter.
+pub fn set_trace_filter(names: &[String]) -> Result<(), String> {
+    let mut filter = Vec::with_capacity(names.len());
+
+    for name in names {
+        let name = name.trim().to_uppercase();
+        match MESSAGE_NAMES.iter().find(|known| **known == name) {
+            Some(known) => filter.push(*known),
+            None => {
+                return Err(format!(
+                    "Unknown message type '{name}', expected one of {}",
+                    MESSAGE_NAMES.join(",")
+                ))
+            }
+        }
+    }
+
+    *TRACE_FILTER.write().unwrap() = (!filter.is_empty()).then_some(filter);
+
+    Ok(())
+}
+
+/// Free-form trace line for events which are not a single message
 pub fn trace(s: String) {
     if TRACE_ENABLED.load(Ordering::Relaxed) {
-        println!("[TRACE] {s}");
+        emit_trace(None, s);
     }
 }
+
+/// Trace a message going over the wire together with the remote peer and its size in bytes
+pub fn trace_msg(direction: Direction, peer: impl Display, msg: &Message, bytes: usize) {
+    if !TRACE_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let Some(filter) = TRACE_FILTER.read().unwrap().as_ref() {
+        if !filter.contains(&msg.name()) {
+            return;
+        }
+    }
+
+    emit_trace(
+        Some(msg.name()),
+        format!(
+            "{} {peer} {:<9} {bytes:>4} B  {}",
+            direction.arrow(),
+            msg.name(),
+            msg.serialize()
+        ),
+    );
+}
+
+//////////////////////////////////////////////////////////
+
+/// Traffic counters of a single message type in one direction
+#[derive(Clone, Copy, Default)]
+pub struct TypeStats {
+    pub count: u64,
+    pub bytes: u64,
+    pub last_seen: Option<Instant>,
+}
+
+/// Traffic counters per message type and direction, to find out which messages eat the bandwidth
+#[derive(Clone, Default)]
+pub struct MessageStats {
+    types: BTreeMap<(Direction, &'static str), TypeStats>,
+}
+
+/// Message statistics shared between the network tasks and their owner. Only ever locked for a
+/// single update or copy, never across an await.
+pub type SharedMessageStats = Arc<Mutex<MessageStats>>;
+
+impl MessageStats {
+    pub fn record(&mut self, direction: Direction, msg: &Message, bytes: usize) {
+        let stats = self.types.entry((direction, msg.name())).or_default();
+        stats.count += 1;
+        stats.bytes += bytes as u64;
+        stats.last_seen = Some(Instant::now());
+    }
+
+    /// Counters sorted by direction, then message type
+    pub fn iter(&self) -> impl Iterator<Item = (Direction, &'static str, &TypeStats)> {
+        self.types
+            .iter()
+            .map(|((direction, name), stats)| (*direction, *name, stats))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let entries: Vec<serde_json::Value> = self
+            .iter()
+            .map(|(direction, name, stats)| {
+                serde_json::json!({
+                    "direction": format!("{direction:?}"),
+                    "type": name,
+                    "count": stats.count,
+                    "bytes": stats.bytes,
+                    "last_seen_sec_ago": stats.last_seen.map(|t| t.elapsed().as_secs_f64()),
+                })
+            })
+            .collect();
+
+        serde_json::Value::Array(entries)
+    }
+}
+
+/// Count a message going over the wire and trace it
+pub fn record_msg(
+    stats: &SharedMessageStats,
+    direction: Direction,
+    peer: impl Display,
+    msg: &Message,
+    bytes: usize,
+) {
+    stats.lock().unwrap().record(direction, msg, bytes);
+    trace_msg(direction, peer, msg, bytes);
+}
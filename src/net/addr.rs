@@ -0,0 +1,294 @@
+use std::{
+    fmt::{self, Display},
+    net::{IpAddr, SocketAddr},
+};
+
+use game_server_sample::globals;
+
+use crate::i18n::{tr, tr_args};
+
+/// Server address as typed in by the player, the host is only looked up when connecting
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: Host,
+    pub port: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Host {
+    Ip(IpAddr),
+    Name(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddrError {
+    InvalidPort,
+    InvalidHost,
+
+    /// The host name did not resolve to any address
+    Unresolved(String),
+}
+
+impl Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddrError::InvalidPort => f.write_str(tr("error.invalid_port")),
+            AddrError::InvalidHost => f.write_str(tr("error.invalid_address")),
+            AddrError::Unresolved(host) => {
+                f.write_str(&tr_args("error.unresolved_host", &[("host", host)]))
+            }
+        }
+    }
+}
+
+impl std::error::Error for AddrError {}
+
+impl From<AddrError> for String {
+    fn from(e: AddrError) -> Self {
+        e.to_string()
+    }
+}
+
+impl Display for Endpoint {
+    /// Form the socket APIs accept, with brackets around IPv6 addresses
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.host {
+            Host::Ip(ip) => write!(f, "{}", SocketAddr::new(*ip, self.port)),
+            Host::Name(name) => write!(f, "{name}:{}", self.port),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Look up the host, IP addresses are taken as they are
+    pub async fn resolve(&self) -> Result<SocketAddr, AddrError> {
+        match &self.host {
+            Host::Ip(ip) => Ok(SocketAddr::new(*ip, self.port)),
+            Host::Name(name) => tokio::net::lookup_host((name.as_str(), self.port))
+                .await
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| AddrError::Unresolved(name.clone())),
+        }
+    }
+}
+
+/// Parse an address typed in one go, e.g. in the console or on the command line: an IPv4
+/// address, an IPv6 address or a host name, with an optional port. IPv6 addresses need brackets
+/// when followed by a port.
+pub fn parse_endpoint(input: &str) -> Result<Endpoint, AddrError> {
+    match split_host_port(input) {
+        Some((host, port)) => parse_host_and_port(host, port),
+        None => parse_host_and_port(input, &globals::DEFAULT_PORT.to_string()),
+    }
+}
+
+/// Validate the address and port typed into separate fields
+pub fn parse_host_and_port(host: &str, port: &str) -> Result<Endpoint, AddrError> {
+    let host = host.trim();
+
+    let port = match port.trim().parse::<u16>() {
+        Ok(port) if port != 0 => port,
+
+        _ => return Err(AddrError::InvalidPort),
+    };
+
+    let host = match host.parse::<IpAddr>() {
+        Ok(ip) => Host::Ip(ip),
+
+        Err(_) if is_valid_hostname(host) => Host::Name(host.to_ascii_lowercase()),
+
+        Err(_) => return Err(AddrError::InvalidHost),
+    };
+
+    Ok(Endpoint { host, port })
+}
+
+/// Split "host:port" or "[ipv6]:port" into its parts. Bare IPv6 addresses are left alone.
+pub fn split_host_port(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim();
+
+    let (host, port) = match input.strip_prefix('[') {
+        Some(rest) => rest.split_once("]:")?,
+        None => match input.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') => return None,
+            Some(parts) => parts,
+            None => return None,
+        },
+    };
+
+    if host.is_empty() || port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((host, port))
+}
+
+fn is_valid_hostname(hostname: &str) -> bool {
+    // An all numeric last label is a mistyped IPv4 address rather than a name
+    let numeric = |label: &str| label.chars().all(|c| c.is_ascii_digit());
+
+    !hostname.is_empty()
+        && hostname.len() <= 253
+        && !hostname.rsplit('.').next().is_some_and(numeric)
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn ip(ip: impl Into<IpAddr>, port: u16) -> Endpoint {
+        Endpoint {
+            host: Host::Ip(ip.into()),
+            port,
+        }
+    }
+
+    fn name(name: &str, port: u16) -> Endpoint {
+        Endpoint {
+            host: Host::Name(String::from(name)),
+            port,
+        }
+    }
+
+    #[test]
+    fn ipv4() {
+        assert_eq!(
+            parse_endpoint("192.168.1.20:9000"),
+            Ok(ip(Ipv4Addr::new(192, 168, 1, 20), 9000))
+        );
+        assert_eq!(
+            parse_endpoint("127.0.0.1"),
+            Ok(ip(Ipv4Addr::LOCALHOST, globals::DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_endpoint("  10.0.0.1:1  "),
+            Ok(ip(Ipv4Addr::new(10, 0, 0, 1), 1))
+        );
+    }
+
+    #[test]
+    fn ipv6() {
+        assert_eq!(
+            parse_endpoint("[::1]:8081"),
+            Ok(ip(Ipv6Addr::LOCALHOST, 8081))
+        );
+        assert_eq!(
+            parse_endpoint("::1"),
+            Ok(ip(Ipv6Addr::LOCALHOST, globals::DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_endpoint("fe80::1:2"),
+            Ok(ip(
+                "fe80::1:2".parse::<Ipv6Addr>().unwrap(),
+                globals::DEFAULT_PORT
+            ))
+        );
+        assert_eq!(parse_endpoint("[2001:db8::7]"), Err(AddrError::InvalidHost));
+    }
+
+    #[test]
+    fn hostnames() {
+        assert_eq!(
+            parse_endpoint("play.example.com:7000"),
+            Ok(name("play.example.com", 7000))
+        );
+        assert_eq!(
+            parse_endpoint("localhost"),
+            Ok(name("localhost", globals::DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_endpoint("My-Server.LAN:8080"),
+            Ok(name("my-server.lan", 8080))
+        );
+    }
+
+    #[test]
+    fn invalid_hosts() {
+        for input in [
+            "",
+            ":8080",
+            "bad host",
+            "under_score.com",
+            "-leading.com",
+            "trailing-.com",
+            "double..dot",
+            "[::1",
+            "300.1.1.1.1:80",
+            &"a".repeat(64),
+        ] {
+            assert_eq!(
+                parse_endpoint(input),
+                Err(AddrError::InvalidHost),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_ports() {
+        for port in ["0", "65536", "-1", "", "http", "80.5"] {
+            assert_eq!(
+                parse_host_and_port("127.0.0.1", port),
+                Err(AddrError::InvalidPort),
+                "{port:?}"
+            );
+        }
+
+        assert_eq!(parse_endpoint("127.0.0.1:0"), Err(AddrError::InvalidPort));
+        assert_eq!(
+            parse_endpoint("127.0.0.1:99999"),
+            Err(AddrError::InvalidPort)
+        );
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_host_port("a:1"), Some(("a", "1")));
+        assert_eq!(split_host_port("[::1]:80"), Some(("::1", "80")));
+        assert_eq!(split_host_port("::1"), None);
+        assert_eq!(split_host_port("host"), None);
+        assert_eq!(split_host_port("host:"), None);
+        assert_eq!(split_host_port("host:port"), None);
+        assert_eq!(split_host_port(":80"), None);
+    }
+
+    #[test]
+    fn display_is_connectable() {
+        assert_eq!(ip(Ipv6Addr::LOCALHOST, 8080).to_string(), "[::1]:8080");
+        assert_eq!(ip(Ipv4Addr::LOCALHOST, 8080).to_string(), "127.0.0.1:8080");
+        assert_eq!(name("example.com", 80).to_string(), "example.com:80");
+
+        for input in ["[fe80::2]:9", "10.1.2.3:4", "host.lan:5"] {
+            let endpoint = parse_endpoint(input).unwrap();
+            assert_eq!(parse_endpoint(&endpoint.to_string()), Ok(endpoint));
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve() {
+        assert_eq!(
+            ip(Ipv4Addr::LOCALHOST, 8080).resolve().await,
+            Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, 8080)))
+        );
+        assert!(name("localhost", 8080)
+            .resolve()
+            .await
+            .is_ok_and(|addr| addr.ip().is_loopback() && addr.port() == 8080));
+        assert_eq!(
+            name("does-not-exist.invalid", 1).resolve().await,
+            Err(AddrError::Unresolved(String::from(
+                "does-not-exist.invalid"
+            )))
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Bounded queue of what is still to be sent to one client, or still to be handled by the client
+//! app. State updates carry a key and a newer update with the same key replaces the queued one,
+//! so a slow client gets the latest state instead of a growing backlog. Messages without a key
+//! are never dropped.
+
+use std::collections::VecDeque;
+
+pub struct Outbox<K, T> {
+    entries: VecDeque<(Option<K>, T)>,
+    capacity: usize,
+
+    /// State updates replaced by a newer one or pushed out of a full queue
+    dropped: u64,
+}
+
+impl<K: PartialEq, T> Outbox<K, T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Queue `item`, keyed if it is a state update that a newer one supersedes
+    pub fn push(&mut self, key: Option<K>, item: T) {
+        let Some(key) = key else {
+            self.entries.push_back((None, item));
+            return;
+        };
+
+        // Latest state wins, and keeps the place in line of the one it replaces
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|(queued, _)| queued.as_ref() == Some(&key))
+        {
+            entry.1 = item;
+            self.dropped += 1;
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.dropped += 1;
+
+            // Make room by dropping the oldest update. A queue full of messages that have to
+            // arrive drops the new update instead.
+            match self.entries.iter().position(|(queued, _)| queued.is_some()) {
+                Some(oldest) => {
+                    self.entries.remove(oldest);
+                }
+                None => return,
+            }
+        }
+
+        self.entries.push_back((Some(key), item));
+    }
+
+    /// Drop the queued state updates whose key `keep` turns down
+    pub fn retain_keyed(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.entries
+            .retain(|(key, _)| key.as_ref().is_none_or(&mut keep));
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.entries.pop_front().map(|(_, item)| item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
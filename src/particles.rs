@@ -0,0 +1,188 @@
+use std::f32::consts::TAU;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use rand::Rng;
+
+/// Particles alive at once. Effects spawned beyond this push out the oldest particles.
+pub const MAX_PARTICLES: usize = 600;
+
+// Particles along every world unit of a dash trail
+const TRAIL_DENSITY: f32 = 0.15;
+
+const SPARK_COUNT: usize = 12;
+const PUFF_COUNT: usize = 20;
+
+/// Share of its velocity a particle keeps per second
+const DRAG: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub pos: Vector2<f32>,
+
+    /// World units per second
+    pub velocity: Vector2<f32>,
+
+    pub color: Vector3<f32>,
+
+    /// Side length of the particle's quad
+    pub size: f32,
+
+    /// Seconds lived and seconds to live
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+impl Particle {
+    /// 1 when spawned, fading to 0 as the particle dies
+    pub fn opacity(&self) -> f32 {
+        (1.0 - self.age / self.lifetime).clamp(0.0, 1.0)
+    }
+}
+
+/// Something happening in the world that gets a burst of particles
+pub enum Effect {
+    /// Dust left behind along a dash
+    DashTrail {
+        from: Vector2<f32>,
+        to: Vector2<f32>,
+        color: Vector3<f32>,
+    },
+
+    /// Sparks flying off where two players collided, away along `direction`
+    Sparks {
+        pos: Vector2<f32>,
+        direction: Vector2<f32>,
+    },
+
+    /// A ring of smoke where a player joined or left
+    Puff {
+        pos: Vector2<f32>,
+        color: Vector3<f32>,
+    },
+}
+
+/// Purely visual particles simulated on the CPU. The renderer draws all of them in one batch.
+#[derive(Default)]
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+}
+
+impl ParticleSystem {
+    pub fn spawn(&mut self, effect: Effect) {
+        let mut rng = rand::thread_rng();
+
+        match effect {
+            Effect::DashTrail { from, to, color } => {
+                let count = ((to - from).magnitude() * TRAIL_DENSITY).ceil() as usize;
+                for i in 0..count {
+                    let t = i as f32 / count as f32;
+                    self.particles.push(Particle {
+                        pos: from + (to - from) * t,
+                        velocity: random_direction(&mut rng) * rng.gen_range(5.0..25.0),
+                        color,
+                        size: rng.gen_range(3.0..6.0),
+                        age: 0.0,
+                        // Older along the trail, so it fades from the start towards the end
+                        lifetime: rng.gen_range(0.3..0.5) * (0.5 + t),
+                    });
+                }
+            }
+            Effect::Sparks { pos, direction } => {
+                let direction = match direction.magnitude() {
+                    0.0 => random_direction(&mut rng),
+                    magnitude => direction / magnitude,
+                };
+                for _ in 0..SPARK_COUNT {
+                    let spread = rng.gen_range(-0.8..0.8_f32);
+                    let (sin, cos) = spread.sin_cos();
+                    let heading = Vector2::new(
+                        direction.x * cos - direction.y * sin,
+                        direction.x * sin + direction.y * cos,
+                    );
+                    self.particles.push(Particle {
+                        pos,
+                        velocity: heading * rng.gen_range(150.0..350.0),
+                        color: Vector3::new(1.0, rng.gen_range(0.6..0.9), 0.2),
+                        size: rng.gen_range(2.0..4.0),
+                        age: 0.0,
+                        lifetime: rng.gen_range(0.15..0.35),
+                    });
+                }
+            }
+            Effect::Puff { pos, color } => {
+                // Pale version of the player's color, smoke rather than paint
+                let color = color * 0.4 + Vector3::new(0.6, 0.6, 0.6);
+                for i in 0..PUFF_COUNT {
+                    let angle = i as f32 / PUFF_COUNT as f32 * TAU;
+                    self.particles.push(Particle {
+                        pos,
+                        velocity: Vector2::new(angle.cos(), angle.sin())
+                            * rng.gen_range(40.0..80.0),
+                        color,
+                        size: rng.gen_range(6.0..10.0),
+                        age: 0.0,
+                        lifetime: rng.gen_range(0.5..0.8),
+                    });
+                }
+            }
+        }
+
+        if self.particles.len() > MAX_PARTICLES {
+            let excess = self.particles.len() - MAX_PARTICLES;
+            self.particles.drain(..excess);
+        }
+    }
+
+    /// Move every particle on by `dt` seconds and drop the dead ones
+    pub fn update(&mut self, dt: f32) {
+        let drag = DRAG.powf(dt);
+
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            particle.pos += particle.velocity * dt;
+            particle.velocity *= drag;
+
+            particle.age < particle.lifetime
+        });
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+}
+
+fn random_direction(rng: &mut impl Rng) -> Vector2<f32> {
+    let angle = rng.gen_range(0.0..TAU);
+
+    Vector2::new(angle.cos(), angle.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn particles_are_capped_and_die_off() {
+        let mut system = ParticleSystem::default();
+        for _ in 0..100 {
+            system.spawn(Effect::Puff {
+                pos: Vector2::new(0.0, 0.0),
+                color: Vector3::new(1.0, 0.0, 0.0),
+            });
+        }
+        assert_eq!(system.particles().len(), MAX_PARTICLES);
+
+        system.update(0.4);
+        assert!(system
+            .particles()
+            .iter()
+            .all(|particle| particle.opacity() < 1.0 && particle.pos != Vector2::new(0.0, 0.0)));
+
+        system.update(1.0);
+        assert!(system.particles().is_empty());
+    }
+}
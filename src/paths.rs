@@ -0,0 +1,110 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use directories::ProjectDirs;
+
+/// Where the game keeps its files. Follows the platform conventions unless overridden on the
+/// command line:
+///
+/// - Linux: `$XDG_CONFIG_HOME/game-server-sample` and `$XDG_DATA_HOME/game-server-sample`
+/// - Windows: `%APPDATA%\game-server-sample\config` and `%APPDATA%\game-server-sample\data`
+/// - macOS: `~/Library/Application Support/game-server-sample`
+struct Dirs {
+    config: PathBuf,
+    data: PathBuf,
+}
+
+static DIRS: OnceLock<Dirs> = OnceLock::new();
+
+/// Set up the directories, with optional overrides from the command line. Has no effect once any
+/// path has been looked up.
+pub fn init(config_override: Option<PathBuf>, data_override: Option<PathBuf>) {
+    let _ = DIRS.set(resolve(config_override, data_override));
+}
+
+fn resolve(config_override: Option<PathBuf>, data_override: Option<PathBuf>) -> Dirs {
+    // Without a home directory, e.g. for some service accounts, fall back to the working
+    // directory like before
+    let project = ProjectDirs::from("", "", env!("CARGO_PKG_NAME"));
+
+    let config = config_override
+        .or_else(|| project.as_ref().map(|p| p.config_dir().to_path_buf()))
+        .unwrap_or_else(|| PathBuf::from("."));
+    let data = data_override
+        .or_else(|| project.as_ref().map(|p| p.data_dir().to_path_buf()))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    Dirs { config, data }
+}
+
+fn dirs() -> &'static Dirs {
+    DIRS.get_or_init(|| resolve(None, None))
+}
+
+////////////////////////////////////////////////////
+
+// Configuration written by the user
+
+pub fn config_dir() -> &'static Path {
+    &dirs().config
+}
+
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.json")
+}
+
+////////////////////////////////////////////////////
+
+// Files produced by the game
+
+pub fn data_dir() -> &'static Path {
+    &dirs().data
+}
+
+pub fn log_dir() -> PathBuf {
+    data_dir().join("logs")
+}
+
+pub fn dump_dir() -> PathBuf {
+    data_dir().join("dumps")
+}
+
+pub fn save_dir() -> PathBuf {
+    data_dir().join("saves")
+}
+
+/// Files here replace the game's own shaders, fonts, textures and sounds
+pub fn asset_dir() -> PathBuf {
+    data_dir().join("assets")
+}
+
+pub fn crash_dir() -> PathBuf {
+    data_dir().join("crashes")
+}
+
+pub fn identity_file() -> PathBuf {
+    data_dir().join("identity")
+}
+
+/// Secret half of the player's key pair
+pub fn identity_key_file() -> PathBuf {
+    data_dir().join("identity_key")
+}
+
+pub fn friends_file() -> PathBuf {
+    data_dir().join("friends.json")
+}
+
+pub fn recent_servers_file() -> PathBuf {
+    data_dir().join("recent_servers.json")
+}
+
+/// Create the directory and its parents if missing, so callers can write into it right away
+pub fn ensure_dir(dir: PathBuf) -> io::Result<PathBuf> {
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
@@ -0,0 +1,312 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use igd_next::{
+    aio::{tokio::Tokio, Gateway},
+    PortMappingProtocol, SearchOptions,
+};
+use tokio::{net::UdpSocket, task::AbortHandle};
+
+const MAPPING_DESCRIPTION: &str = env!("CARGO_PKG_NAME");
+
+const UPNP_SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Removal runs while the app exits, so don't wait long for a router that went away
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const NAT_PMP_PORT: u16 = 5351;
+
+// Recommended by RFC 6886, renewed at half of what the router grants and removed on shutdown
+const NAT_PMP_LIFETIME_SEC: u32 = 7200;
+
+// Wait before trying again after a failed renewal, well within the remaining half lifetime
+const NAT_PMP_RENEW_RETRY: Duration = Duration::from_secs(60);
+
+// First retry delay, doubled after every attempt as RFC 6886 asks for
+const NAT_PMP_RETRY: Duration = Duration::from_millis(250);
+const NAT_PMP_ATTEMPTS: u32 = 4;
+
+type PortMapResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Router port forwarding for a server hosted from the GUI, so players outside the local network
+/// can join
+pub struct PortMapping {
+    /// Address friends connect to over the internet
+    pub external: SocketAddr,
+    local_port: u16,
+    router: Router,
+
+    /// Task keeping a NAT-PMP mapping from expiring, stopped when the mapping goes away
+    renewal: Option<AbortHandle>,
+}
+
+enum Router {
+    Upnp(Gateway<Tokio>),
+    NatPmp(SocketAddrV4),
+}
+
+/// Forward UDP `port` on the router to this machine, trying UPnP first and NAT-PMP second
+pub async fn map_port(port: u16) -> PortMapResult<PortMapping> {
+    let upnp_err = match map_upnp(port).await {
+        Ok(mapping) => return Ok(mapping),
+        Err(e) => e,
+    };
+
+    map_nat_pmp(port)
+        .await
+        .map_err(|nat_pmp_err| format!("UPnP: {upnp_err}, NAT-PMP: {nat_pmp_err}").into())
+}
+
+impl PortMapping {
+    /// Remove the forwarding again. Best effort, the router may be gone already.
+    pub async fn remove(self) {
+        // A renewal in flight would add the mapping right back
+        if let Some(renewal) = &self.renewal {
+            renewal.abort();
+        }
+
+        let result = tokio::time::timeout(REMOVE_TIMEOUT, async {
+            match &self.router {
+                Router::Upnp(gateway) => gateway
+                    .remove_port(PortMappingProtocol::UDP, self.external.port())
+                    .await
+                    .map_err(Into::into),
+                Router::NatPmp(gateway) => {
+                    // Lifetime 0 deletes the mapping of the internal port
+                    nat_pmp_request(*gateway, &nat_pmp_map_request(self.local_port, 0, 0))
+                        .await
+                        .map(|_| ())
+                }
+            }
+        })
+        .await;
+
+        match result {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => eprintln!("Failed to remove port mapping: {e}"),
+            Err(_) => eprintln!("Failed to remove port mapping: router did not answer"),
+        }
+    }
+}
+
+impl Drop for PortMapping {
+    fn drop(&mut self) {
+        if let Some(renewal) = &self.renewal {
+            renewal.abort();
+        }
+    }
+}
+
+////////////////////////////////////////////////////
+
+// UPnP
+
+async fn map_upnp(port: u16) -> PortMapResult<PortMapping> {
+    let gateway = igd_next::aio::tokio::search_gateway(SearchOptions {
+        timeout: Some(UPNP_SEARCH_TIMEOUT),
+        ..Default::default()
+    })
+    .await?;
+
+    let local_ip = local_ip_towards(gateway.addr).await?;
+    let external_ip = gateway.get_external_ip().await?;
+
+    // Lease 0 keeps the mapping until it is removed, hosting may last longer than any lease
+    gateway
+        .add_port(
+            PortMappingProtocol::UDP,
+            port,
+            SocketAddr::new(local_ip, port),
+            0,
+            MAPPING_DESCRIPTION,
+        )
+        .await?;
+
+    Ok(PortMapping {
+        external: SocketAddr::new(external_ip, port),
+        local_port: port,
+        router: Router::Upnp(gateway),
+        renewal: None,
+    })
+}
+
+/// Local address the OS would use to reach `peer`. Connecting a UDP socket sends nothing.
+async fn local_ip_towards(peer: SocketAddr) -> PortMapResult<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(peer).await?;
+
+    Ok(socket.local_addr()?.ip())
+}
+
+////////////////////////////////////////////////////
+
+// NAT-PMP, RFC 6886
+
+async fn map_nat_pmp(port: u16) -> PortMapResult<PortMapping> {
+    let gateway = SocketAddrV4::new(default_gateway()?, NAT_PMP_PORT);
+
+    // External address: version 0, opcode 0
+    let response = nat_pmp_request(gateway, &[0, 0]).await?;
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let response = nat_pmp_request(
+        gateway,
+        &nat_pmp_map_request(port, port, NAT_PMP_LIFETIME_SEC),
+    )
+    .await?;
+    // The router may hand out another external port and lifetime than the ones asked for
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = nat_pmp_lifetime(&response);
+
+    let renewal = tokio::spawn(renew_nat_pmp(gateway, port, external_port, lifetime));
+
+    Ok(PortMapping {
+        external: SocketAddr::new(IpAddr::V4(external_ip), external_port),
+        local_port: port,
+        router: Router::NatPmp(gateway),
+        renewal: Some(renewal.abort_handle()),
+    })
+}
+
+/// Ask for the mapping again at half its lifetime, as RFC 6886 recommends, for as long as the
+/// server is hosted
+async fn renew_nat_pmp(gateway: SocketAddrV4, port: u16, external_port: u16, lifetime: Duration) {
+    let mut wait = lifetime / 2;
+
+    loop {
+        tokio::time::sleep(wait).await;
+
+        let request = nat_pmp_map_request(port, external_port, NAT_PMP_LIFETIME_SEC);
+        wait = match nat_pmp_request(gateway, &request).await {
+            Ok(response) => {
+                let renewed_port = u16::from_be_bytes([response[10], response[11]]);
+                if renewed_port != external_port {
+                    eprintln!("NAT-PMP renewal moved the external port to {renewed_port}");
+                }
+
+                nat_pmp_lifetime(&response) / 2
+            }
+            Err(e) => {
+                eprintln!("Failed to renew port mapping: {e}");
+                NAT_PMP_RENEW_RETRY
+            }
+        };
+    }
+}
+
+/// Lifetime the router granted in a mapping response. At least a second, so a router granting
+/// none doesn't get renewals in a tight loop.
+fn nat_pmp_lifetime(response: &[u8]) -> Duration {
+    let lifetime_sec = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+
+    Duration::from_secs(lifetime_sec.max(1).into())
+}
+
+/// UDP mapping request: version 0, opcode 1, reserved, internal port, suggested external port
+/// and lifetime in seconds
+fn nat_pmp_map_request(internal_port: u16, external_port: u16, lifetime_sec: u32) -> Vec<u8> {
+    let mut request = vec![0, 1, 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&lifetime_sec.to_be_bytes());
+
+    request
+}
+
+/// Send a request to the gateway and wait for a successful response, retrying on packet loss
+async fn nat_pmp_request(gateway: SocketAddrV4, request: &[u8]) -> PortMapResult<Vec<u8>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(gateway).await?;
+
+    // External address responses end with the address, mapping responses with the lifetime
+    let response_len = if request[1] == 0 { 12 } else { 16 };
+
+    let mut buf = [0u8; 16];
+    let mut retry = NAT_PMP_RETRY;
+
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send(request).await?;
+
+        if let Ok(result) = tokio::time::timeout(retry, socket.recv(&mut buf)).await {
+            let len = result?;
+
+            // Version 0, opcode of the request + 128, result code
+            if len < response_len || buf[0] != 0 || buf[1] != request[1] + 128 {
+                return Err("Invalid NAT-PMP response".into());
+            }
+            let result_code = u16::from_be_bytes([buf[2], buf[3]]);
+            if result_code != 0 {
+                return Err(
+                    format!("NAT-PMP request refused with result code {result_code}").into(),
+                );
+            }
+
+            return Ok(buf[..len].to_vec());
+        }
+
+        retry *= 2;
+    }
+
+    Err("No NAT-PMP gateway answered".into())
+}
+
+/// NAT-PMP has no discovery, requests go to the default gateway
+#[cfg(target_os = "linux")]
+fn default_gateway() -> PortMapResult<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route")?;
+
+    // Columns: Iface Destination Gateway ..., addresses as hex of the raw network order bytes
+    routes
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(1) == Some(&"00000000"))
+        .and_then(|fields| u32::from_str_radix(fields.get(2)?, 16).ok())
+        .map(|gateway| Ipv4Addr::from(gateway.to_ne_bytes()))
+        .ok_or_else(|| "No default gateway".into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> PortMapResult<Ipv4Addr> {
+    Err("Default gateway lookup is not supported on this platform".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn nat_pmp_mapping_is_renewed_at_half_its_lifetime() {
+        let router = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let SocketAddr::V4(gateway) = router.local_addr().unwrap() else {
+            unreachable!()
+        };
+
+        let renewal = tokio::spawn(renew_nat_pmp(gateway, 8080, 8080, Duration::from_secs(1)));
+        let started = std::time::Instant::now();
+
+        // Grant a second each time, so the next renewal follows half a second later
+        let mut buf = [0u8; 16];
+        for _ in 0..2 {
+            let (len, client) =
+                tokio::time::timeout(Duration::from_secs(3), router.recv_from(&mut buf))
+                    .await
+                    .expect("Mapping was not renewed")
+                    .unwrap();
+            assert_eq!(
+                &buf[..len],
+                nat_pmp_map_request(8080, 8080, NAT_PMP_LIFETIME_SEC)
+            );
+
+            let mut response = vec![0, 129, 0, 0, 0, 0, 0, 0, 0x1f, 0x90, 0x1f, 0x90];
+            response.extend_from_slice(&1u32.to_be_bytes());
+            router.send_to(&response, client).await.unwrap();
+        }
+        renewal.abort();
+
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use game_server_sample::{rules::GameRules, PlayerId};
+
+// Weight of each expected ping in the loss estimate, roughly averages over the last 50 pings
+const LOSS_SMOOTHING: f32 = 0.02;
+
+// RFC 3550 jitter smoothing
+const JITTER_SMOOTHING: f32 = 1.0 / 16.0;
+
+// Longer gaps are counted as this many lost pings, so a stall doesn't poison the estimate
+const MAX_COUNTED_GAP: u32 = 100;
+
+// No pings for this long, or two ping intervals if longer, means the connection is stalling,
+// whatever the averages say
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Upper bounds of packet loss and jitter for 4, 3, 2 and 1 bars
+const BAR_LIMITS: [(f32, Duration); 4] = [
+    (0.01, Duration::from_millis(5)),
+    (0.03, Duration::from_millis(15)),
+    (0.08, Duration::from_millis(40)),
+    (0.20, Duration::from_millis(100)),
+];
+
+/// Connection health estimated from the server's pings, which go out every
+/// [`GameRules::ping_interval`] with increasing sequence numbers. Gaps in the sequence are lost
+/// packets, variation in the arrival spacing is jitter.
+pub struct ConnectionQuality {
+    ping_interval: Duration,
+    last: Option<(u32, Instant)>,
+
+    /// Fraction of pings lost, smoothed
+    loss: f32,
+
+    /// Smoothed variation of the arrival spacing
+    jitter: Duration,
+}
+
+/// Snapshot of the connection quality for display
+#[derive(Clone, Copy, Debug)]
+pub struct QualityReport {
+    /// 0 (unusable) to 4 (perfect)
+    pub bars: u8,
+    pub loss: f32,
+    pub jitter: Duration,
+    pub liveness: Liveness,
+}
+
+/// Whether the server still counts as there. Pings come at a steady pace, so every interval
+/// without one is a missed ping, and too many of them in a row mean the server is gone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Liveness {
+    /// Pings due since the last one arrived
+    pub missed: u32,
+
+    /// Misses in a row the server's rules allow, see [`GameRules::max_missed_pings`]
+    pub max_missed: u32,
+}
+
+impl Liveness {
+    /// Liveness after `silence` without a ping from a server playing by `rules`
+    pub fn after(silence: Duration, rules: &GameRules) -> Self {
+        let missed = silence.as_nanos() / rules.ping_interval.as_nanos().max(1);
+
+        Self {
+            missed: missed.min(u32::MAX as u128) as u32,
+            max_missed: rules.max_missed_pings,
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.missed < self.max_missed
+    }
+}
+
+impl ConnectionQuality {
+    pub fn new(ping_interval: Duration) -> Self {
+        Self {
+            ping_interval,
+            last: None,
+            loss: 0.0,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn on_ping(&mut self, seq: u32, arrived_at: Instant) {
+        let Some((last_seq, last_arrival)) = self.last else {
+            self.last = Some((seq, arrived_at));
+            return;
+        };
+
+        // Late duplicates or reordered pings say nothing about loss
+        if seq <= last_seq {
+            return;
+        }
+        self.last = Some((seq, arrived_at));
+
+        let gap = seq - last_seq - 1;
+        for _ in 0..gap.min(MAX_COUNTED_GAP) {
+            self.loss += LOSS_SMOOTHING * (1.0 - self.loss);
+        }
+        self.loss -= LOSS_SMOOTHING * self.loss;
+
+        let expected = self.ping_interval.as_secs_f32() * (seq - last_seq) as f32;
+        let spacing = (arrived_at - last_arrival).as_secs_f32();
+        let deviation = (spacing - expected).abs();
+        let jitter = self.jitter.as_secs_f32();
+        self.jitter = Duration::from_secs_f32(jitter + (deviation - jitter) * JITTER_SMOOTHING);
+    }
+
+    pub fn report(&self, liveness: Liveness) -> QualityReport {
+        let stall_timeout = STALL_TIMEOUT.max(self.ping_interval * 2);
+        let stalled = !liveness.is_alive()
+            || self
+                .last
+                .is_some_and(|(_, arrived_at)| arrived_at.elapsed() > stall_timeout);
+
+        let bars = if stalled {
+            0
+        } else {
+            BAR_LIMITS
+                .iter()
+                .position(|&(loss, jitter)| self.loss <= loss && self.jitter <= jitter)
+                .map_or(0, |i| (BAR_LIMITS.len() - i) as u8)
+        };
+
+        QualityReport {
+            bars,
+            loss: self.loss,
+            jitter: self.jitter,
+            liveness,
+        }
+    }
+}
+
+// Snapshot spacing deviations the interpolation delay covers on top of one tick interval
+const JITTER_MARGIN: u32 = 3;
+
+// Changes of the tuned delay smaller than this are not worth a visible jump in the playback
+const MIN_ADJUSTMENT: Duration = Duration::from_millis(5);
+
+/// Delay remote players are drawn behind the replication, tuned to the snapshot arrival jitter.
+/// Long enough that the next snapshot is usually there before it is needed, no longer than
+/// that. Snapshots of a player arrive once per server tick, variation in their spacing is
+/// jitter.
+pub struct InterpolationDelay {
+    bounds: RangeInclusive<Duration>,
+    tick_interval: Duration,
+    last_arrivals: HashMap<PlayerId, Instant>,
+
+    /// Smoothed variation of the snapshot spacing
+    jitter: Duration,
+    delay: Duration,
+    adjustments: u32,
+}
+
+/// Snapshot of the interpolation delay for display
+#[derive(Clone, Copy, Debug)]
+pub struct InterpolationReport {
+    pub delay: Duration,
+    pub jitter: Duration,
+
+    /// Times the delay was changed since joining
+    pub adjustments: u32,
+}
+
+impl InterpolationDelay {
+    pub fn new(bounds: RangeInclusive<Duration>, tick_rate: u32) -> Self {
+        let mut interpolation = Self {
+            delay: Duration::ZERO,
+            bounds,
+            tick_interval: Duration::from_secs(1) / tick_rate.max(1),
+            last_arrivals: HashMap::new(),
+            jitter: Duration::ZERO,
+            adjustments: 0,
+        };
+        interpolation.delay = interpolation.target();
+
+        interpolation
+    }
+
+    /// Server changed its simulation rate, snapshots are that much further apart now
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.tick_interval = Duration::from_secs(1) / tick_rate.max(1);
+        self.last_arrivals.clear();
+        self.retune();
+    }
+
+    pub fn on_snapshot(&mut self, player_id: PlayerId, arrived_at: Instant) {
+        let Some(last_arrival) = self.last_arrivals.insert(player_id, arrived_at) else {
+            return;
+        };
+
+        let spacing = arrived_at
+            .saturating_duration_since(last_arrival)
+            .as_secs_f32();
+        let deviation = (spacing - self.tick_interval.as_secs_f32()).abs();
+        let jitter = self.jitter.as_secs_f32();
+        self.jitter = Duration::from_secs_f32(jitter + (deviation - jitter) * JITTER_SMOOTHING);
+
+        self.retune();
+    }
+
+    /// Forget a player who left, their next snapshot would look like a long gap otherwise
+    pub fn forget(&mut self, player_id: PlayerId) {
+        self.last_arrivals.remove(&player_id);
+    }
+
+    pub fn report(&self) -> InterpolationReport {
+        InterpolationReport {
+            delay: self.delay,
+            jitter: self.jitter,
+            adjustments: self.adjustments,
+        }
+    }
+
+    fn target(&self) -> Duration {
+        (self.tick_interval + self.jitter * JITTER_MARGIN)
+            .clamp(*self.bounds.start(), *self.bounds.end())
+    }
+
+    fn retune(&mut self) {
+        let target = self.target();
+        if target.abs_diff(self.delay) >= MIN_ADJUSTMENT {
+            self.delay = target;
+            self.adjustments += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: RangeInclusive<Duration> = Duration::from_millis(20)..=Duration::from_millis(200);
+
+    #[test]
+    fn steady_snapshots_keep_the_delay_at_one_tick() {
+        let mut interpolation = InterpolationDelay::new(BOUNDS, 30);
+        let start = Instant::now();
+        for tick in 0..100 {
+            interpolation.on_snapshot(1, start + Duration::from_secs(tick) / 30);
+        }
+
+        let report = interpolation.report();
+        assert_eq!(report.delay, Duration::from_secs(1) / 30);
+        assert_eq!(report.adjustments, 0);
+    }
+
+    #[test]
+    fn jitter_raises_the_delay_within_bounds() {
+        let mut interpolation = InterpolationDelay::new(BOUNDS, 60);
+        let start = Instant::now();
+        let mut arrival = start;
+        for tick in 0..200 {
+            // Bursts of two snapshots every other tick
+            arrival += match tick % 2 {
+                0 => Duration::from_millis(33),
+                _ => Duration::ZERO,
+            };
+            interpolation.on_snapshot(1, arrival);
+        }
+
+        let report = interpolation.report();
+        assert!(report.delay > Duration::from_millis(50), "{report:?}");
+        assert!(report.delay <= *BOUNDS.end());
+        assert!(report.adjustments > 0);
+
+        // Wilder spacing than the upper bound allows for stays at the bound
+        for tick in 0..200 {
+            arrival += Duration::from_millis(if tick % 2 == 0 { 500 } else { 0 });
+            interpolation.on_snapshot(1, arrival);
+        }
+        assert_eq!(interpolation.report().delay, *BOUNDS.end());
+    }
+
+    #[test]
+    fn server_counts_as_gone_after_the_allowed_missed_pings() {
+        let rules = GameRules {
+            ping_interval: Duration::from_millis(100),
+            max_missed_pings: 5,
+            ..GameRules::default()
+        };
+
+        let liveness = Liveness::after(Duration::from_millis(250), &rules);
+        assert_eq!(liveness.missed, 2);
+        assert!(liveness.is_alive());
+        assert!(!Liveness::after(Duration::from_millis(500), &rules).is_alive());
+
+        // A lost server shows no bars, however good the last pings were
+        let mut quality = ConnectionQuality::new(rules.ping_interval);
+        let now = Instant::now();
+        for seq in 0..10 {
+            quality.on_ping(seq, now + rules.ping_interval * seq);
+        }
+        assert_eq!(
+            quality.report(Liveness::after(Duration::ZERO, &rules)).bars,
+            4
+        );
+        assert_eq!(
+            quality
+                .report(Liveness::after(Duration::from_secs(1), &rules))
+                .bars,
+            0
+        );
+    }
+}
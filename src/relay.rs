@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use game_server_sample::globals;
+
+/// Host announcing itself to the relay, repeated to keep its NAT mapping open
+const REGISTER: &str = "RELAYREG";
+
+/// Packet the relay should forward to the target
+const TO: &str = "RELAYTO";
+
+/// Packet forwarded by the relay, tagged with where it came from
+const FROM: &str = "RELAYFROM";
+
+// Hosts that stopped registering are forgotten after a few missed keep-alives
+const HOST_EXPIRY: Duration = Duration::from_secs(5 * globals::KEEP_ALIVE_INTERVAL.as_secs());
+
+const PEER_EXPIRY: Duration = globals::CONNECTION_TIMEOUT_SEC;
+
+/// Wrapper around game messages for players who can't reach each other directly. The payload
+/// is a regular game message and goes last, so it may contain anything.
+#[derive(Debug, PartialEq)]
+pub enum RelayPacket<'a> {
+    /// Host running its game server on `game_port`
+    Register { game_port: u16 },
+
+    To {
+        target: SocketAddr,
+        payload: &'a str,
+    },
+
+    From {
+        source: SocketAddr,
+        payload: &'a str,
+    },
+}
+
+impl<'a> RelayPacket<'a> {
+    /// `None` for anything that is not relay traffic
+    pub fn parse(packet: &'a str) -> Option<Self> {
+        let mut parts = packet.splitn(3, ' ');
+
+        match (parts.next()?, parts.next(), parts.next()) {
+            (REGISTER, Some(port), None) => Some(RelayPacket::Register {
+                game_port: port.parse().ok()?,
+            }),
+            (TO, Some(target), Some(payload)) => Some(RelayPacket::To {
+                target: target.parse().ok()?,
+                payload,
+            }),
+            (FROM, Some(source), Some(payload)) => Some(RelayPacket::From {
+                source: source.parse().ok()?,
+                payload,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        match self {
+            RelayPacket::Register { game_port } => format!("{REGISTER} {game_port}"),
+            RelayPacket::To { target, payload } => format!("{TO} {target} {payload}"),
+            RelayPacket::From { source, payload } => format!("{FROM} {source} {payload}"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////
+
+struct RegisteredHost {
+    /// Where the registrations come from, i.e. the host's NAT mapping of its game socket
+    source: SocketAddr,
+    last_seen: Instant,
+}
+
+struct RelayedPeer {
+    host: SocketAddr,
+    last_seen: Instant,
+}
+
+/// Relay side bookkeeping. Hosts are known by their public IP and game port, the address their
+/// friends try to join. Only registered hosts are forwarded to, and hosts may only answer peers
+/// that contacted them through the relay, so the relay can't be used to reach arbitrary hosts.
+#[derive(Default)]
+pub struct RelayService {
+    hosts: HashMap<SocketAddr, RegisteredHost>,
+    peers: HashMap<SocketAddr, RelayedPeer>,
+}
+
+impl RelayService {
+    /// Handle a packet received from `from`. Returns where to send what, if anywhere.
+    pub fn handle(
+        &mut self,
+        from: SocketAddr,
+        packet: RelayPacket,
+    ) -> Option<(SocketAddr, String)> {
+        let now = Instant::now();
+        self.hosts
+            .retain(|_, host| now - host.last_seen < HOST_EXPIRY);
+        self.peers
+            .retain(|_, peer| now - peer.last_seen < PEER_EXPIRY);
+
+        match packet {
+            RelayPacket::Register { game_port } => {
+                self.hosts.insert(
+                    SocketAddr::new(from.ip(), game_port),
+                    RegisteredHost {
+                        source: from,
+                        last_seen: now,
+                    },
+                );
+
+                None
+            }
+
+            RelayPacket::To { target, payload } => {
+                // Host answering one of its peers
+                if let Some((host, _)) = self.hosts.iter().find(|(_, host)| host.source == from) {
+                    let peer = self.peers.get(&target).filter(|peer| peer.host == *host)?;
+                    let packet = RelayPacket::From {
+                        source: peer.host,
+                        payload,
+                    };
+
+                    return Some((target, packet.serialize()));
+                }
+
+                // Peer reaching out to a host
+                let host = self.hosts.get(&target)?;
+                self.peers.insert(
+                    from,
+                    RelayedPeer {
+                        host: target,
+                        last_seen: now,
+                    },
+                );
+                let packet = RelayPacket::From {
+                    source: from,
+                    payload,
+                };
+
+                Some((host.source, packet.serialize()))
+            }
+
+            // Only the relay sends these
+            RelayPacket::From { .. } => None,
+        }
+    }
+}
@@ -1,16 +1,19 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, rc::Rc, sync::Arc, time::Duration};
 
-use cgmath::{Matrix, Matrix4, Vector2, Vector3};
-use game_server_sample::{globals, Player, PlayerId};
+use cgmath::{InnerSpace, Matrix, Matrix4, Rad, Vector2, Vector3};
+use game_server_sample::{
+    globals, rules::GameRules, terrain::TerrainMap, Palette, Player, PlayerId, Shape, WorldBounds,
+    WorldMode,
+};
 use glow::HasContext;
 use glutin::{
-    config::{ConfigTemplateBuilder, GlConfig},
+    config::{Config, ConfigTemplateBuilder, GlConfig},
     context::{ContextApi, ContextAttributesBuilder, PossiblyCurrentContext, Version},
     display::GetGlDisplay,
-    prelude::{GlDisplay, NotCurrentGlContext},
+    prelude::{GlDisplay, NotCurrentGlContext, PossiblyCurrentGlContext},
     surface::{GlSurface, Surface, WindowSurface},
 };
-use glutin_winit::{DisplayBuilder, GlWindow};
+use glutin_winit::{finalize_window, DisplayBuilder, GlWindow};
 use raw_window_handle::HasWindowHandle;
 use winit::{
     dpi::PhysicalSize,
@@ -18,50 +21,389 @@ use winit::{
     window::{Window, WindowAttributes},
 };
 
-use crate::{fsm, gui::Gui};
+use crate::{
+    accessibility::Accessibility,
+    crash, fsm,
+    gui::Gui,
+    particles::Particle,
+    shaders::{Shader, Stage},
+};
+
+#[cfg(debug_assertions)]
+use crate::shaders::ShaderWatcher;
+
+#[cfg(feature = "wgpu")]
+pub mod wgpu_backend;
 
 const GRID_COL_COUNT: usize = 40;
+const PLAYER_OUTLINE_WIDTH: f32 = 3.0;
+
+// Size of the facing marker relative to the player quad
+const FACING_MARKER_SIZE: f32 = 0.3;
+
+// Rim vertices of the circle's triangle fan, the more the rounder
+const CIRCLE_SEGMENTS: usize = 24;
+
+// The map overlay keeps this many pixels clear around the world, and players on it never get
+// smaller than this many pixels
+const MAP_MARGIN: f32 = 40.0;
+const MAP_MIN_PLAYER_SIZE: f32 = 6.0;
 const GRID_ROW_COUNT: usize = GRID_COL_COUNT;
 
-const GRID_VERTEX_SHADER_SRC: &str = r#"
-    #version 120
+// Velocity lines show how far a player gets in this many seconds
+const VELOCITY_LINE_SECONDS: f32 = 0.25;
+const VELOCITY_LINE_WIDTH: f32 = 2.0;
+
+// Remote players fade from green to red as their last server update ages towards this
+const STALE_UPDATE_AGE: Duration = Duration::from_millis(250);
+
+// Dash trails start out this wide and get thinner as they fade
+const STREAK_WIDTH: f32 = 12.0;
+
+// Crosshair arms start this far from the cursor, in pixels
+const CROSSHAIR_GAP: f32 = 4.0;
+const CROSSHAIR_ARM: f32 = 8.0;
+const CROSSHAIR_WIDTH: f32 = 2.0;
+
+// Labels are drawn with a built-in 5x7 pixel font, scaled up by this much
+const LABEL_SCALE: f32 = 2.0;
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// Atlas cells leave a blank pixel right of and below each glyph, so neighbours don't bleed in
+const ATLAS_CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const ATLAS_CELL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+const ATLAS_COLUMNS: usize = 16;
+const ATLAS_ROWS: usize = FONT.len().div_ceil(ATLAS_COLUMNS);
+const ATLAS_WIDTH: usize = ATLAS_COLUMNS * ATLAS_CELL_WIDTH;
+const ATLAS_HEIGHT: usize = ATLAS_ROWS * ATLAS_CELL_HEIGHT;
+
+// Position, texture coordinates and color of each glyph quad corner
+const TEXT_VERTEX_FLOATS: usize = 7;
 
-    attribute vec2 aPos;
-    uniform mat4 uMVP;
+// Grid line brightness in high contrast mode, relative to the normal lines
+const HIGH_CONTRAST_GRID_SHADE: f32 = 0.3;
 
-    void main() {
-        gl_Position = uMVP * vec4(aPos, 0.0, 1.0);
+// Position and color with opacity of each particle quad corner
+const PARTICLE_VERTEX_FLOATS: usize = 6;
+
+// Printable ASCII from ' ' on, one byte per column with the top row in the lowest bit
+const FONT_FIRST_CHAR: char = ' ';
+const FONT: [[u8; GLYPH_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // "'"
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x01, 0x01], // 'F'
+    [0x3E, 0x41, 0x41, 0x51, 0x32], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x04, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x7F, 0x20, 0x18, 0x20, 0x7F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x03, 0x04, 0x78, 0x04, 0x03], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x08, 0x14, 0x54, 0x54, 0x3C], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x00, 0x7F, 0x10, 0x28, 0x44], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x02, 0x01, 0x02, 0x04, 0x02], // '~'
+];
+
+/// Graphics API the world is drawn with. Every backend implements [`Render`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RendererBackend {
+    /// OpenGL 2.1, see [`Renderer`]
+    #[default]
+    Gl,
+
+    /// Vulkan, Metal or DirectX 12 through wgpu, for systems whose OpenGL drivers misbehave. Only
+    /// in builds with the `wgpu` feature, others fall back to OpenGL.
+    Wgpu,
+}
+
+/// Create the window with the renderer of `settings.backend` and the GUI painted by it. Falls back
+/// to OpenGL if that backend can't start.
+pub fn create_graphics(
+    event_loop: &ActiveEventLoop,
+    settings: RenderSettings,
+) -> (Arc<Window>, Box<dyn Render>, Gui) {
+    match settings.backend {
+        RendererBackend::Gl => {}
+
+        #[cfg(feature = "wgpu")]
+        RendererBackend::Wgpu => {
+            match wgpu_backend::WgpuRenderer::create_graphics(event_loop, settings) {
+                Ok((window, renderer, gui)) => return (window, Box::new(renderer), gui),
+                Err(e) => eprintln!("Failed to start the wgpu renderer, using OpenGL: {e}"),
+            }
+        }
+
+        #[cfg(not(feature = "wgpu"))]
+        RendererBackend::Wgpu => {
+            eprintln!("This build has no wgpu renderer, using OpenGL. Build with --features wgpu.")
+        }
     }
-"#;
 
-const GRID_FRAGMENT_SHADER_SRC: &str = r#"
-    #version 120
+    let (window, renderer, gui) = Renderer::create_graphics(event_loop, settings);
+    (Arc::new(window), Box::new(renderer), gui)
+}
+
+/// What happens to the mouse cursor while playing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CursorGrab {
+    /// The cursor can leave the window
+    #[default]
+    None,
+
+    /// The cursor is kept inside the window
+    Confined,
+}
+
+/// Client-side visual options which don't affect gameplay
+#[derive(Clone, Copy, Default)]
+pub struct RenderSettings {
+    pub backend: RendererBackend,
+
+    /// Replace the OS cursor over the game with a crosshair drawn in the world
+    pub crosshair: bool,
+
+    pub cursor_grab: CursorGrab,
+
+    /// Palette player colors are snapped to before drawing
+    pub palette: Palette,
+
+    /// Draw a high-contrast outline around player quads
+    pub player_outline: bool,
+
+    /// Particle effects for dashes, collisions and players coming and going
+    pub particles: bool,
+
+    /// Strength of the camera shake when knocked back, 0 for none
+    pub screen_shake: f32,
+
+    /// Slow the game down for a moment when knocking someone back
+    pub hit_stop: bool,
+
+    /// How fast the local player is pulled to where the server corrected it, in world units per
+    /// second. 0 jumps there right away.
+    pub max_correction_rate: f32,
+
+    /// Changed from the menu while running, see [`Render::set_accessibility`]
+    pub accessibility: Accessibility,
+}
+
+/// World state received from the server that affects how everything is drawn
+#[derive(Clone, Copy)]
+pub struct WorldView<'a> {
+    pub world_mode: WorldMode,
+
+    /// Rules of the joined server, for the world bounds and player size
+    pub rules: &'a GameRules,
+
+    pub terrain: &'a TerrainMap,
+
+    /// Server's time of day between 0 and 1, drives the ambient light
+    pub time_of_day: f32,
+}
+
+/// Motion data drawn by the debug render mode, to diagnose interpolation and extrapolation
+#[derive(Default)]
+pub struct MotionDebug {
+    /// World units per second, the local player included
+    pub velocities: HashMap<PlayerId, Vector2<f32>>,
+
+    /// Time since the last server update of each remote player
+    pub update_ages: HashMap<PlayerId, Duration>,
+}
+
+/// Everything a frame shows of the world
+pub struct Scene<'a> {
+    pub camera: Vector2<f32>,
+    pub local_player: &'a Player,
+    pub remote_players: &'a HashMap<PlayerId, Player>,
+    pub state: Option<&'a fsm::State>,
+    pub world: WorldView<'a>,
+    pub motion_debug: Option<&'a MotionDebug>,
+
+    /// Text shown over the world while in game, e.g. player names
+    pub labels: &'a [WorldLabel],
+
+    /// World position of the mouse cursor, drawn as a crosshair while in game
+    pub crosshair: Option<Vector2<f32>>,
+
+    /// Trails of recent dashes
+    pub streaks: &'a [Streak],
 
-    void main() {
-        gl_FragColor = vec4(0.5, 0.5, 0.5, 1.0);
+    pub particles: &'a [Particle],
+
+    /// Draw the whole world scaled into the window on top of everything (M)
+    pub map_overlay: bool,
+}
+
+/// Trail left behind by a dash, shrinking towards where the dash ended as it fades
+pub struct Streak {
+    pub from: Vector2<f32>,
+    pub to: Vector2<f32>,
+    pub color: Vector3<f32>,
+
+    /// 0 when the dash just happened, 1 when the trail is gone
+    pub fade: f32,
+}
+
+/// Text drawn in world space with the renderer's bitmap font instead of the GUI, so it stays
+/// attached to whatever it labels. Characters outside printable ASCII show up as '?'.
+pub struct WorldLabel {
+    /// Bottom center of the text
+    pub pos: Vector2<f32>,
+    pub text: String,
+    pub color: Vector3<f32>,
+}
+
+/// Rendering backend the app draws the world with. The GUI is painted on top between `draw` and
+/// `present`.
+pub trait Render {
+    fn draw(&mut self, scene: &Scene);
+
+    /// Show the finished frame
+    fn present(&mut self);
+
+    /// Apply accessibility settings changed in the menu
+    fn set_accessibility(&mut self, _accessibility: Accessibility) {}
+
+    /// Open another window drawn to with the same graphics context, e.g. to move GUI out of the
+    /// game view. `None` if the backend can't.
+    fn open_window(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _attributes: WindowAttributes,
+    ) -> Option<SecondaryWindow> {
+        None
     }
-"#;
+}
 
-const QUAD_VERTEX_SHADER_SRC: &str = r#"
-    #version 120
+/// Extra window sharing the renderer's OpenGL context. Make it current before drawing, the
+/// renderer takes the context back for its own frames.
+pub struct SecondaryWindow {
+    pub window: Window,
+    pub gl: Arc<glow::Context>,
+    surface: Surface<WindowSurface>,
+    gl_context: Rc<PossiblyCurrentContext>,
+}
 
-    attribute vec2 aPos;
-    uniform mat4 uMVP;
+impl SecondaryWindow {
+    pub fn make_current(&self) {
+        self.gl_context.make_current(&self.surface).unwrap();
+    }
 
-    void main() {
-        gl_Position = uMVP * vec4(aPos.x, aPos.y, 0.0, 1.0);
+    pub fn swap_buffers(&self) {
+        self.surface.swap_buffers(&self.gl_context).unwrap();
     }
-"#;
+}
 
-const QUAD_FRAGMENT_SHADER_SRC: &str = r#"
-    #version 120
+/// Draws nothing, for running the app without a graphics context, e.g. in tests
+#[derive(Default)]
+pub struct NullRenderer {
+    /// Frames presented so far
+    pub frames: u64,
+
+    /// Players in the last scene drawn, the local player included
+    pub players_drawn: usize,
+
+    /// Labels in the last scene drawn
+    pub labels_drawn: usize,
+}
 
-    uniform vec3 uColor;
+impl Render for NullRenderer {
+    fn draw(&mut self, scene: &Scene) {
+        (self.players_drawn, self.labels_drawn) = if shows_players(scene.state) {
+            (scene.remote_players.len() + 1, scene.labels.len())
+        } else {
+            (0, 0)
+        };
+    }
 
-    void main() {
-        gl_FragColor = vec4(uColor, 1.0);
+    fn present(&mut self) {
+        self.frames += 1;
     }
-"#;
+}
 
 /// Client-side graphics rendering layer for player sprite (quad) and playfield display. Uses
 /// OpenGL 2.1 for backwards compatibility.
@@ -73,18 +415,42 @@ pub struct Renderer {
     grid_shader_program: glow::Program,
     grid_vbo: glow::Buffer,
     grid_mvp_location: glow::UniformLocation,
+    grid_ambient_location: glow::UniformLocation,
     quad_mvp_location: glow::UniformLocation,
     quad_color_location: glow::UniformLocation,
+    quad_ambient_location: glow::UniformLocation,
     quad_shader_program: glow::Program,
     quad_vbo: glow::Buffer,
+    text_shader_program: glow::Program,
+    text_mvp_location: glow::UniformLocation,
+    text_atlas: glow::Texture,
+
+    // Refilled with every glyph quad of a frame, so all labels take one draw call
+    text_vbo: glow::Buffer,
+    particle_shader_program: glow::Program,
+    particle_mvp_location: glow::UniformLocation,
+
+    // Same for the particles
+    particle_vbo: glow::Buffer,
     gl_surface: Surface<WindowSurface>,
-    gl_context: PossiblyCurrentContext,
+
+    // Shared with secondary windows, which draw through it as well
+    gl_context: Rc<PossiblyCurrentContext>,
+    gl_config: Config,
     gl: Arc<glow::Context>,
+    settings: RenderSettings,
+
+    /// Rebuilds programs as their sources change on disk, in debug builds only
+    #[cfg(debug_assertions)]
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 impl Renderer {
     /// Create native window and initialize OpenGL context.
-    pub fn create_graphics(event_loop: &ActiveEventLoop) -> (Window, Renderer, Gui) {
+    pub fn create_graphics(
+        event_loop: &ActiveEventLoop,
+        settings: RenderSettings,
+    ) -> (Window, Renderer, Gui) {
         unsafe {
             // Create window
             let window_attributes = WindowAttributes::default()
@@ -133,76 +499,44 @@ impl Renderer {
 
             // Create context
             let gl = glow::Context::from_loader_function_cstr(|s| gl_display.get_proc_address(s));
+            crash::set_gl_info(format!(
+                "{} {}, {}",
+                gl.get_parameter_string(glow::VENDOR),
+                gl.get_parameter_string(glow::RENDERER),
+                gl.get_parameter_string(glow::VERSION)
+            ));
 
             // Set background color to white
             gl.clear_color(1.0, 1.0, 1.0, 1.0);
 
             // Load quad shaders
-            let quad_vertex_shader = gl.create_shader(glow::VERTEX_SHADER).unwrap();
-            gl.shader_source(quad_vertex_shader, QUAD_VERTEX_SHADER_SRC);
-            gl.compile_shader(quad_vertex_shader);
-
-            let quad_fragment_shader = gl.create_shader(glow::FRAGMENT_SHADER).unwrap();
-            gl.shader_source(quad_fragment_shader, QUAD_FRAGMENT_SHADER_SRC);
-            gl.compile_shader(quad_fragment_shader);
-
-            let quad_shader_program = gl.create_program().unwrap();
-            gl.attach_shader(quad_shader_program, quad_vertex_shader);
-            gl.attach_shader(quad_shader_program, quad_fragment_shader);
-            gl.link_program(quad_shader_program);
+            let quad_shader_program = load_program(&gl, Shader::Quad);
             gl.use_program(Some(quad_shader_program));
 
-            // (Shader programs are already created, individual shaders can be removed from memory)
-            gl.delete_shader(quad_vertex_shader);
-            gl.delete_shader(quad_fragment_shader);
-
             let quad_vbo = gl.create_buffer().unwrap();
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(quad_vbo));
 
-            // Create quad buffers
-            let quad_vertices: [f32; 12] =
-                [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0];
-
+            // Create quad buffers, with the other player shapes behind the quad
             gl.buffer_data_u8_slice(
                 glow::ARRAY_BUFFER,
-                bytemuck::cast_slice(&quad_vertices),
+                bytemuck::cast_slice(&shape_vertices()),
                 glow::STATIC_DRAW,
             );
 
-            let quad_mvp_location = gl
-                .get_uniform_location(quad_shader_program, "uMVP")
-                .unwrap();
-            let quad_color_location = gl
-                .get_uniform_location(quad_shader_program, "uColor")
-                .unwrap();
+            let quad_mvp_location = uniform(&gl, quad_shader_program, "uMVP");
+            let quad_color_location = uniform(&gl, quad_shader_program, "uColor");
+            let quad_ambient_location = uniform(&gl, quad_shader_program, "uAmbient");
 
             gl.use_program(None); // Unbind shader needed to associate uniforms with
 
             // Load grid shaders
-            let grid_vertex_shader = gl.create_shader(glow::VERTEX_SHADER).unwrap();
-            gl.shader_source(grid_vertex_shader, GRID_VERTEX_SHADER_SRC);
-            gl.compile_shader(grid_vertex_shader);
-
-            let grid_fragment_shader = gl.create_shader(glow::FRAGMENT_SHADER).unwrap();
-            gl.shader_source(grid_fragment_shader, GRID_FRAGMENT_SHADER_SRC);
-            gl.compile_shader(grid_fragment_shader);
-
-            let grid_shader_program = gl.create_program().unwrap();
-            gl.attach_shader(grid_shader_program, grid_vertex_shader);
-            gl.attach_shader(grid_shader_program, grid_fragment_shader);
-            gl.link_program(grid_shader_program);
+            let grid_shader_program = load_program(&gl, Shader::Grid);
             gl.use_program(Some(grid_shader_program));
 
-            gl.delete_shader(grid_vertex_shader);
-            gl.delete_shader(grid_fragment_shader);
-
             // Create grid buffers
-            let grid_vertices: Vec<f32> = create_grid_vertices(
-                GRID_COL_COUNT,
-                GRID_ROW_COUNT,
-                globals::WORLD_BOUNDS.max_x * 2.0,
-                globals::WORLD_BOUNDS.max_y * 2.0,
-            );
+            // On a unit square, scaled to the world bounds of the joined server when drawn
+            let grid_vertices: Vec<f32> =
+                create_grid_vertices(GRID_COL_COUNT, GRID_ROW_COUNT, 1.0, 1.0);
             let grid_vbo = gl.create_buffer().unwrap();
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(grid_vbo));
             gl.buffer_data_u8_slice(
@@ -211,29 +545,79 @@ impl Renderer {
                 glow::STATIC_DRAW,
             );
 
-            let grid_mvp_location = gl
-                .get_uniform_location(grid_shader_program, "uMVP")
-                .unwrap();
+            let grid_mvp_location = uniform(&gl, grid_shader_program, "uMVP");
+            let grid_ambient_location = uniform(&gl, grid_shader_program, "uAmbient");
 
             gl.use_program(None);
 
+            // Load text shaders
+            let text_shader_program = load_program(&gl, Shader::Text);
+            let text_mvp_location = uniform(&gl, text_shader_program, "uMVP");
+            bind_atlas_unit(&gl, text_shader_program);
+
+            // Font atlas is uploaded once, labels only send their glyph quads
+            let text_atlas = gl.create_texture().unwrap();
+            gl.bind_texture(glow::TEXTURE_2D, Some(text_atlas));
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::ALPHA as i32,
+                ATLAS_WIDTH as i32,
+                ATLAS_HEIGHT as i32,
+                0,
+                glow::ALPHA,
+                glow::UNSIGNED_BYTE,
+                Some(&font_atlas()),
+            );
+            // Nearest filtering keeps the scaled up pixels crisp
+            for (parameter, value) in [
+                (glow::TEXTURE_MIN_FILTER, glow::NEAREST),
+                (glow::TEXTURE_MAG_FILTER, glow::NEAREST),
+                (glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE),
+                (glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE),
+            ] {
+                gl.tex_parameter_i32(glow::TEXTURE_2D, parameter, value as i32);
+            }
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            let text_vbo = gl.create_buffer().unwrap();
+
+            // Load particle shaders
+            let particle_shader_program = load_program(&gl, Shader::Particle);
+            let particle_mvp_location = uniform(&gl, particle_shader_program, "uMVP");
+            let particle_vbo = gl.create_buffer().unwrap();
+
             let gl = Arc::new(gl);
 
             let renderer = Self {
                 gl: gl.clone(),
-                gl_context,
+                gl_context: Rc::new(gl_context),
+                gl_config,
                 gl_surface,
                 grid_shader_program,
                 grid_vbo,
                 grid_mvp_location,
+                grid_ambient_location,
                 quad_shader_program,
                 quad_vbo,
                 quad_mvp_location,
                 quad_color_location,
+                quad_ambient_location,
+                text_shader_program,
+                text_mvp_location,
+                text_atlas,
+                text_vbo,
+                particle_shader_program,
+                particle_mvp_location,
+                particle_vbo,
+                settings,
+                #[cfg(debug_assertions)]
+                shader_watcher: ShaderWatcher::new(),
             };
 
             // Create GUI
-            let gui = Gui::new(&event_loop, gl.clone());
+            let gui = Gui::new(event_loop, gl.clone());
 
             (window, renderer, gui)
         }
@@ -242,54 +626,218 @@ impl Renderer {
     // TODO: Ideally rendering should not know about game logic
     // TODO: Occlusion culling based on camera area
     // TODO: Batch draw calls
-    pub fn draw(
+    fn draw_scene(&self, scene: &Scene) {
+        let Scene {
+            camera,
+            local_player,
+            remote_players,
+            state,
+            world,
+            motion_debug,
+            labels,
+            crosshair,
+            streaks,
+            particles,
+            map_overlay,
+        } = *scene;
+        let WorldView {
+            world_mode,
+            rules,
+            terrain,
+            time_of_day,
+        } = world;
+
+        unsafe {
+            self.gl.clear(glow::COLOR_BUFFER_BIT);
+
+            // Same ambient light for everything in the world, uniforms stick to their program.
+            // High contrast darkens the grid lines on top.
+            let ambient = ambient_color(time_of_day);
+            let grid_ambient = if self.settings.accessibility.high_contrast {
+                ambient * HIGH_CONTRAST_GRID_SHADE
+            } else {
+                ambient
+            };
+            for (program, location, ambient) in [
+                (
+                    self.grid_shader_program,
+                    &self.grid_ambient_location,
+                    grid_ambient,
+                ),
+                (
+                    self.quad_shader_program,
+                    &self.quad_ambient_location,
+                    ambient,
+                ),
+            ] {
+                self.gl.use_program(Some(program));
+                self.gl
+                    .uniform_3_f32(Some(location), ambient.x, ambient.y, ambient.z);
+            }
+
+            let projection = window_projection();
+            let pv = projection * camera_view(camera);
+
+            // A wrapping world is tiled, so the neighbouring copies show across the seam
+            let world_size = rules.bounds.size();
+            let tiles = match world_mode {
+                WorldMode::Bounded => 0,
+                WorldMode::Wrap => 1,
+            };
+            for tile_x in -tiles..=tiles {
+                for tile_y in -tiles..=tiles {
+                    let offset =
+                        Vector2::new(tile_x as f32 * world_size.x, tile_y as f32 * world_size.y);
+                    self.draw_terrain(&pv, terrain, offset);
+                    self.draw_grid(&pv, &rules.bounds, offset);
+                }
+            }
+
+            if shows_players(state) {
+                // Underneath the players, so the dasher is drawn at the end of its trail
+                self.draw_streaks(&camera, streaks, &pv, &world);
+                self.draw_quads(
+                    &camera,
+                    local_player,
+                    remote_players,
+                    &pv,
+                    &world,
+                    motion_debug,
+                );
+                self.draw_particles(&camera, particles, &pv, &world);
+                self.draw_labels(&camera, labels, &pv, &world);
+
+                if let Some(pos) = crosshair {
+                    self.draw_crosshair(pos, &pv);
+                }
+
+                if map_overlay {
+                    self.draw_map(&projection, local_player, remote_players, &world);
+                }
+            }
+        }
+    }
+
+    /// The whole world with everyone on it, scaled to fit the window
+    fn draw_map(
         &self,
-        camera: &Vector2<f32>,
+        projection: &Matrix4<f32>,
         local_player: &Player,
         remote_players: &HashMap<PlayerId, Player>,
-        state: Option<&fsm::State>,
+        world: &WorldView,
     ) {
-        unsafe {
-            self.gl.clear(glow::COLOR_BUFFER_BIT);
+        let bounds = &world.rules.bounds;
+        let (view, scale) = map_view(bounds);
+        let map_pv = projection * view;
 
-            // Camera calculations
-            // Camera moves the world itself around!
-            let projection: Matrix4<f32> = cgmath::ortho(
-                0.0,
-                globals::WINDOW_SIZE.0 as f32,
-                globals::WINDOW_SIZE.1 as f32,
-                0.0,
-                -1.0,
-                1.0,
-            );
-            let camera_offset = Vector2::new(
-                globals::WINDOW_SIZE.0 as f32 / 2.0,
-                globals::WINDOW_SIZE.1 as f32 / 2.0,
-            );
-            let view = Matrix4::from_translation(Vector3::new(
-                -camera.x + camera_offset.x,
-                -camera.y + camera_offset.y,
-                0.0,
-            ));
-            let pv = projection * view;
+        self.use_quad_program();
+        unsafe {
+            // Readable whatever the time of day
+            self.gl
+                .uniform_3_f32(Some(&self.quad_ambient_location), 1.0, 1.0, 1.0);
+        }
 
-            self.draw_grid(&pv);
+        // Dark frame around a blank world, with the terrain zones on it
+        let frame = MAP_MARGIN / 2.0 / scale;
+        self.draw_rect(
+            Vector2::new(bounds.min_x - frame, bounds.min_y - frame),
+            Vector2::new(bounds.max_x + frame, bounds.max_y + frame),
+            &Vector3::new(0.15, 0.15, 0.15),
+            &map_pv,
+        );
+        self.draw_rect(
+            Vector2::new(bounds.min_x, bounds.min_y),
+            Vector2::new(bounds.max_x, bounds.max_y),
+            &Vector3::new(1.0, 1.0, 1.0),
+            &map_pv,
+        );
+        self.draw_terrain(&map_pv, world.terrain, Vector2::new(0.0, 0.0));
 
-            // Keep drawing players even when Quit dialog is active
-            if matches!(
-                state,
-                Some(fsm::State::Playing) | Some(fsm::State::QuitDialog)
-            ) {
-                self.draw_quads(local_player, remote_players, &pv);
-            }
+        let min_size = MAP_MIN_PLAYER_SIZE / scale;
+        for player in remote_players.values() {
+            let size = world.rules.size_of(player).max(min_size);
+            self.draw_shape(
+                &player.pos,
+                &self.settings.palette.remap(player.color),
+                size,
+                player.facing,
+                player.avatar.shape,
+                &map_pv,
+            );
         }
+
+        // Last and outlined, so the local player is easy to find
+        let size = world.rules.size_of(local_player).max(min_size);
+        self.draw_shape(
+            &local_player.pos,
+            &Vector3::new(0.0, 0.0, 0.0),
+            size + 2.0 * PLAYER_OUTLINE_WIDTH / scale,
+            local_player.facing,
+            local_player.avatar.shape,
+            &map_pv,
+        );
+        self.draw_player(local_player, size, None, &map_pv);
     }
 
     pub fn swap_buffers(&self) {
         self.gl_surface.swap_buffers(&self.gl_context).unwrap();
     }
+}
+
+impl Render for Renderer {
+    fn draw(&mut self, scene: &Scene) {
+        // A secondary window may have drawn since the last frame
+        self.gl_context.make_current(&self.gl_surface).unwrap();
+
+        #[cfg(debug_assertions)]
+        self.reload_changed_shaders();
+
+        unsafe {
+            self.gl.viewport(
+                0,
+                0,
+                globals::WINDOW_SIZE.0 as i32,
+                globals::WINDOW_SIZE.1 as i32,
+            );
+        }
+
+        self.draw_scene(scene);
+    }
 
-    fn draw_grid(&self, pv: &Matrix4<f32>) {
+    fn present(&mut self) {
+        self.swap_buffers();
+    }
+
+    fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.settings.accessibility = accessibility;
+    }
+
+    fn open_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        attributes: WindowAttributes,
+    ) -> Option<SecondaryWindow> {
+        // Same config as the main window, so the context can draw to both
+        let window = finalize_window(event_loop, attributes, &self.gl_config).ok()?;
+        let surface_attributes = window.build_surface_attributes(Default::default()).ok()?;
+        let surface = unsafe {
+            self.gl_config
+                .display()
+                .create_window_surface(&self.gl_config, &surface_attributes)
+                .ok()?
+        };
+
+        Some(SecondaryWindow {
+            window,
+            gl: self.gl.clone(),
+            surface,
+            gl_context: self.gl_context.clone(),
+        })
+    }
+}
+
+impl Renderer {
+    fn draw_grid(&self, pv: &Matrix4<f32>, bounds: &WorldBounds, offset: Vector2<f32>) {
         unsafe {
             self.gl.use_program(Some(self.grid_shader_program));
             self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.grid_vbo));
@@ -311,11 +859,12 @@ impl Renderer {
 
             // Grid start location is the upper-left corner of world
             let translation = Matrix4::from_translation(cgmath::vec3(
-                globals::WORLD_BOUNDS.min_x,
-                globals::WORLD_BOUNDS.min_y,
+                bounds.min_x + offset.x,
+                bounds.min_y + offset.y,
                 0.0,
             ));
-            let model = translation;
+            let size = bounds.size();
+            let model = translation * Matrix4::from_nonuniform_scale(size.x, size.y, 1.0);
             let mvp = pv * model;
 
             let mvp_slice = std::slice::from_raw_parts(mvp.as_ptr(), 16);
@@ -329,12 +878,121 @@ impl Renderer {
         }
     }
 
+    /// Terrain zones as tinted rectangles underneath the grid lines
+    fn draw_terrain(&self, pv: &Matrix4<f32>, terrain: &TerrainMap, offset: Vector2<f32>) {
+        self.use_quad_program();
+
+        for zone in &terrain.zones {
+            self.draw_rect(
+                zone.min + offset,
+                zone.max + offset,
+                &zone.terrain.color(),
+                pv,
+            );
+        }
+    }
+
     fn draw_quads(
         &self,
+        camera: &Vector2<f32>,
         local_player: &Player,
         remote_players: &HashMap<PlayerId, Player>,
         pv: &Matrix4<f32>,
+        world: &WorldView,
+        motion_debug: Option<&MotionDebug>,
     ) {
+        self.use_quad_program();
+
+        // Draw players at their copy closest to the camera, so they show up across the seam of a
+        // wrapping world
+        let players = std::iter::once(local_player)
+            .chain(remote_players.values())
+            .map(|p| Player {
+                pos: camera + world.rules.world_delta(*camera, p.pos, world.world_mode),
+                ..*p
+            });
+
+        for player in players.clone() {
+            let freshness = motion_debug
+                .and_then(|debug| debug.update_ages.get(&player.id))
+                .map(|age| freshness_color(*age));
+            self.draw_player(&player, world.rules.size_of(&player), freshness, pv);
+        }
+
+        // On top of every quad, so they don't hide behind other players
+        if let Some(debug) = motion_debug {
+            for player in players {
+                if let Some(velocity) = debug.velocities.get(&player.id) {
+                    self.draw_velocity(player.pos, *velocity, pv);
+                }
+            }
+        }
+    }
+
+    fn draw_streaks(
+        &self,
+        camera: &Vector2<f32>,
+        streaks: &[Streak],
+        pv: &Matrix4<f32>,
+        world: &WorldView,
+    ) {
+        self.use_quad_program();
+
+        for streak in streaks {
+            let to = camera
+                + world
+                    .rules
+                    .world_delta(*camera, streak.to, world.world_mode);
+            let from = to
+                + world
+                    .rules
+                    .world_delta(streak.to, streak.from, world.world_mode);
+            let width = STREAK_WIDTH * (1.0 - streak.fade);
+
+            self.draw_line(
+                from + (to - from) * streak.fade,
+                to,
+                &self.settings.palette.remap(streak.color),
+                width,
+                pv,
+            );
+        }
+    }
+
+    /// Four arms around `pos` with a gap in the middle, black on a white outline so it shows on
+    /// any terrain
+    fn draw_crosshair(&self, pos: Vector2<f32>, pv: &Matrix4<f32>) {
+        self.use_quad_program();
+
+        let half_width = CROSSHAIR_WIDTH / 2.0;
+        let arms = [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)].map(|(x, y)| {
+            let direction = Vector2::new(x, y);
+            let across = Vector2::new(y, x) * half_width;
+            let start = pos + direction * CROSSHAIR_GAP;
+            let end = pos + direction * (CROSSHAIR_GAP + CROSSHAIR_ARM);
+            let (a, b) = (start - across, end + across);
+            (
+                Vector2::new(a.x.min(b.x), a.y.min(b.y)),
+                Vector2::new(a.x.max(b.x), a.y.max(b.y)),
+            )
+        });
+
+        let outline = Vector2::new(1.0, 1.0);
+        for (min, max) in arms {
+            self.draw_rect(
+                min - outline,
+                max + outline,
+                &Vector3::new(1.0, 1.0, 1.0),
+                pv,
+            );
+        }
+        for (min, max) in arms {
+            self.draw_rect(min, max, &Vector3::new(0.0, 0.0, 0.0), pv);
+        }
+    }
+
+    /// Bind the quad program and VBO for `draw_unit_quad` and everything built on it
+    fn use_quad_program(&self) {
         unsafe {
             self.gl.use_program(Some(self.quad_shader_program));
             self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.quad_vbo));
@@ -353,27 +1011,299 @@ impl Renderer {
                 8,
                 0,
             );
+        }
+    }
+
+    /// All labels in one batch of glyph quads, at their copy closest to the camera like players
+    fn draw_labels(
+        &self,
+        camera: &Vector2<f32>,
+        labels: &[WorldLabel],
+        pv: &Matrix4<f32>,
+        world: &WorldView,
+    ) {
+        let mut vertices = Vec::new();
+        for label in labels {
+            let pos = camera
+                + world
+                    .rules
+                    .world_delta(*camera, label.pos, world.world_mode);
+            layout_label(label, pos, &mut vertices);
+        }
+        if vertices.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.gl.use_program(Some(self.text_shader_program));
+            self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.text_vbo));
+            self.gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                bytemuck::cast_slice(&vertices),
+                glow::STREAM_DRAW,
+            );
 
-            self.draw_quad(&local_player.pos, &local_player.color, &pv);
-            for (_, p) in remote_players.iter() {
-                self.draw_quad(&p.pos, &p.color, &pv);
+            let stride = (TEXT_VERTEX_FLOATS * size_of::<f32>()) as i32;
+            for (name, size, offset) in [("aPos", 2, 0), ("aUv", 2, 2), ("aColor", 3, 4)] {
+                let location = self
+                    .gl
+                    .get_attrib_location(self.text_shader_program, name)
+                    .unwrap();
+                self.gl.enable_vertex_attrib_array(location);
+                self.gl.vertex_attrib_pointer_f32(
+                    location,
+                    size,
+                    glow::FLOAT,
+                    false,
+                    stride,
+                    offset * size_of::<f32>() as i32,
+                );
+            }
+
+            let mvp_slice = std::slice::from_raw_parts(pv.as_ptr(), 16);
+            self.gl
+                .uniform_matrix_4_f32_slice(Some(&self.text_mvp_location), false, mvp_slice);
+
+            self.gl.active_texture(glow::TEXTURE0);
+            self.gl
+                .bind_texture(glow::TEXTURE_2D, Some(self.text_atlas));
+            self.gl.enable(glow::BLEND);
+            self.gl
+                .blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+
+            self.gl.draw_arrays(
+                glow::TRIANGLES,
+                0,
+                (vertices.len() / TEXT_VERTEX_FLOATS) as i32,
+            );
+
+            self.gl.disable(glow::BLEND);
+            self.gl.bind_texture(glow::TEXTURE_2D, None);
+
+            // The other programs only use aPos, leave the rest off so they don't read past the
+            // end of their buffers
+            for name in ["aUv", "aColor"] {
+                if let Some(location) = self.gl.get_attrib_location(self.text_shader_program, name)
+                {
+                    self.gl.disable_vertex_attrib_array(location);
+                }
             }
         }
     }
 
-    fn draw_quad(&self, pos: &Vector2<f32>, color: &Vector3<f32>, pv: &Matrix4<f32>) {
-        // Move to position
-        let mut model = Matrix4::from_translation(cgmath::vec3(pos.x, pos.y, 0.0));
-        // Move local coordinate space origin from bottom-right corner of quad to center
-        model = model
-            * Matrix4::from_translation(cgmath::vec3(
-                -0.5 * globals::PLAYER_QUAD_SIZE,
-                -0.5 * globals::PLAYER_QUAD_SIZE,
-                0.0,
-            ));
-        // Scale
-        model = model * Matrix4::from_scale(globals::PLAYER_QUAD_SIZE);
+    /// All particles in one batch of quads, at their copy closest to the camera like players
+    fn draw_particles(
+        &self,
+        camera: &Vector2<f32>,
+        particles: &[Particle],
+        pv: &Matrix4<f32>,
+        world: &WorldView,
+    ) {
+        if particles.is_empty() {
+            return;
+        }
+
+        let mut vertices = Vec::with_capacity(particles.len() * 6 * PARTICLE_VERTEX_FLOATS);
+        for particle in particles {
+            let pos = camera
+                + world
+                    .rules
+                    .world_delta(*camera, particle.pos, world.world_mode);
+            let half = particle.size / 2.0;
+            let (x0, y0, x1, y1) = (pos.x - half, pos.y - half, pos.x + half, pos.y + half);
+            let color = self.settings.palette.remap(particle.color);
+            let opacity = particle.opacity();
+
+            for (x, y) in [(x0, y0), (x1, y0), (x0, y1), (x1, y1), (x0, y1), (x1, y0)] {
+                vertices.extend_from_slice(&[x, y, color.x, color.y, color.z, opacity]);
+            }
+        }
+
+        unsafe {
+            self.gl.use_program(Some(self.particle_shader_program));
+            self.gl
+                .bind_buffer(glow::ARRAY_BUFFER, Some(self.particle_vbo));
+            self.gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                bytemuck::cast_slice(&vertices),
+                glow::STREAM_DRAW,
+            );
+
+            let stride = (PARTICLE_VERTEX_FLOATS * size_of::<f32>()) as i32;
+            for (name, size, offset) in [("aPos", 2, 0), ("aColor", 4, 2)] {
+                let location = self
+                    .gl
+                    .get_attrib_location(self.particle_shader_program, name)
+                    .unwrap();
+                self.gl.enable_vertex_attrib_array(location);
+                self.gl.vertex_attrib_pointer_f32(
+                    location,
+                    size,
+                    glow::FLOAT,
+                    false,
+                    stride,
+                    offset * size_of::<f32>() as i32,
+                );
+            }
+
+            let mvp_slice = std::slice::from_raw_parts(pv.as_ptr(), 16);
+            self.gl
+                .uniform_matrix_4_f32_slice(Some(&self.particle_mvp_location), false, mvp_slice);
+
+            self.gl.enable(glow::BLEND);
+            self.gl
+                .blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+            self.gl.draw_arrays(
+                glow::TRIANGLES,
+                0,
+                (vertices.len() / PARTICLE_VERTEX_FLOATS) as i32,
+            );
+            self.gl.disable(glow::BLEND);
+
+            // Like for the labels, only aPos stays on for the other programs
+            if let Some(location) = self
+                .gl
+                .get_attrib_location(self.particle_shader_program, "aColor")
+            {
+                self.gl.disable_vertex_attrib_array(location);
+            }
+        }
+    }
+
+    /// Velocity as a yellow line, with its x and y components in red and green
+    fn draw_velocity(&self, pos: Vector2<f32>, velocity: Vector2<f32>, pv: &Matrix4<f32>) {
+        let line = velocity * VELOCITY_LINE_SECONDS;
+
+        self.draw_line(
+            pos,
+            pos + Vector2::new(line.x, 0.0),
+            &Vector3::new(1.0, 0.0, 0.0),
+            VELOCITY_LINE_WIDTH,
+            pv,
+        );
+        self.draw_line(
+            pos,
+            pos + Vector2::new(0.0, line.y),
+            &Vector3::new(0.0, 1.0, 0.0),
+            VELOCITY_LINE_WIDTH,
+            pv,
+        );
+        self.draw_line(
+            pos,
+            pos + line,
+            &Vector3::new(1.0, 1.0, 0.0),
+            VELOCITY_LINE_WIDTH,
+            pv,
+        );
+    }
+
+    /// `size` is the side length of the quad. `color_override` replaces the player's own color,
+    /// e.g. for debug rendering.
+    fn draw_player(
+        &self,
+        player: &Player,
+        size: f32,
+        color_override: Option<Vector3<f32>>,
+        pv: &Matrix4<f32>,
+    ) {
+        let shape = player.avatar.shape;
+
+        // Outline pass is simply a bigger black shape behind the player
+        if self.settings.player_outline || self.settings.accessibility.high_contrast {
+            self.draw_shape(
+                &player.pos,
+                &Vector3::new(0.0, 0.0, 0.0),
+                size + 2.0 * PLAYER_OUTLINE_WIDTH,
+                player.facing,
+                shape,
+                pv,
+            );
+        }
+
+        let color = color_override.unwrap_or_else(|| self.settings.palette.remap(player.color));
+        self.draw_shape(&player.pos, &color, size, player.facing, shape, pv);
+
+        // The tip of a triangle points the way already
+        if shape == Shape::Triangle {
+            return;
+        }
+
+        // A square looks the same every quarter turn, a darker marker on the front edge shows
+        // where the player faces
+        let marker_size = size * FACING_MARKER_SIZE;
+        let front =
+            Vector2::new(player.facing.cos(), player.facing.sin()) * (size - marker_size) / 2.0;
+        self.draw_quad(
+            &(player.pos + front),
+            &(color * 0.4),
+            marker_size,
+            player.facing,
+            pv,
+        );
+    }
+
+    fn draw_rect(
+        &self,
+        min: Vector2<f32>,
+        max: Vector2<f32>,
+        color: &Vector3<f32>,
+        pv: &Matrix4<f32>,
+    ) {
+        self.draw_unit_quad(&rect_model(min, max), color, pv);
+    }
+
+    fn draw_line(
+        &self,
+        from: Vector2<f32>,
+        to: Vector2<f32>,
+        color: &Vector3<f32>,
+        width: f32,
+        pv: &Matrix4<f32>,
+    ) {
+        if let Some(model) = line_model(from, to, width) {
+            self.draw_unit_quad(&model, color, pv);
+        }
+    }
+
+    fn draw_quad(
+        &self,
+        pos: &Vector2<f32>,
+        color: &Vector3<f32>,
+        size: f32,
+        angle: f32,
+        pv: &Matrix4<f32>,
+    ) {
+        self.draw_shape(pos, color, size, angle, Shape::Square, pv);
+    }
+
+    /// `shape` filling a `size` wide square around `pos`, turned by `angle`
+    fn draw_shape(
+        &self,
+        pos: &Vector2<f32>,
+        color: &Vector3<f32>,
+        size: f32,
+        angle: f32,
+        shape: Shape,
+        pv: &Matrix4<f32>,
+    ) {
+        self.draw_unit_shape(&shape_model(pos, size, angle), color, shape, pv);
+    }
+
+    /// Draw the unit quad of the quad VBO transformed by `model`
+    fn draw_unit_quad(&self, model: &Matrix4<f32>, color: &Vector3<f32>, pv: &Matrix4<f32>) {
+        self.draw_unit_shape(model, color, Shape::Square, pv);
+    }
+
+    /// Draw one of the unit shapes of the quad VBO transformed by `model`
+    fn draw_unit_shape(
+        &self,
+        model: &Matrix4<f32>,
+        color: &Vector3<f32>,
+        shape: Shape,
+        pv: &Matrix4<f32>,
+    ) {
         let mvp = pv * model;
+        let (mode, first, count) = shape_range(shape);
 
         unsafe {
             let mvp_slice = std::slice::from_raw_parts(mvp.as_ptr(), 16);
@@ -387,7 +1317,176 @@ impl Renderer {
                 color[2],
             );
 
-            self.gl.draw_arrays(glow::TRIANGLES, 0, 6);
+            self.gl.draw_arrays(mode, first, count);
+        }
+    }
+}
+
+/// Window pixels to clip space, y pointing down
+fn window_projection() -> Matrix4<f32> {
+    cgmath::ortho(
+        0.0,
+        globals::WINDOW_SIZE.0 as f32,
+        globals::WINDOW_SIZE.1 as f32,
+        0.0,
+        -1.0,
+        1.0,
+    )
+}
+
+/// World to window pixels, with `camera` in the middle of the window. The camera moves the world
+/// itself around!
+fn camera_view(camera: Vector2<f32>) -> Matrix4<f32> {
+    let camera_offset = Vector2::new(
+        globals::WINDOW_SIZE.0 as f32 / 2.0,
+        globals::WINDOW_SIZE.1 as f32 / 2.0,
+    );
+
+    Matrix4::from_translation(Vector3::new(
+        -camera.x + camera_offset.x,
+        -camera.y + camera_offset.y,
+        0.0,
+    ))
+}
+
+/// Keep drawing players even when a dialog is open over the game
+fn shows_players(state: Option<&fsm::State>) -> bool {
+    matches!(
+        state,
+        Some(fsm::State::Playing | fsm::State::QuitDialog | fsm::State::Motd(_))
+    )
+}
+
+/// Moves the unit square onto the rectangle from `min` to `max`
+fn rect_model(min: Vector2<f32>, max: Vector2<f32>) -> Matrix4<f32> {
+    let size = max - min;
+
+    Matrix4::from_translation(cgmath::vec3(min.x, min.y, 0.0))
+        * Matrix4::from_nonuniform_scale(size.x, size.y, 1.0)
+}
+
+/// Moves the unit square onto a `width` wide line from `from` to `to`. `None` for lines too short
+/// to see.
+fn line_model(from: Vector2<f32>, to: Vector2<f32>, width: f32) -> Option<Matrix4<f32>> {
+    let delta = to - from;
+    let length = delta.magnitude();
+    if length < 1.0 {
+        return None;
+    }
+
+    // Unit quad stretched along x, then turned towards the end point
+    Some(
+        Matrix4::from_translation(cgmath::vec3(from.x, from.y, 0.0))
+            * Matrix4::from_angle_z(Rad(delta.y.atan2(delta.x)))
+            * Matrix4::from_translation(cgmath::vec3(0.0, -0.5 * width, 0.0))
+            * Matrix4::from_nonuniform_scale(length, width, 1.0),
+    )
+}
+
+/// Moves the unit square onto a `size` wide square around `pos`, turned by `angle`
+fn shape_model(pos: &Vector2<f32>, size: f32, angle: f32) -> Matrix4<f32> {
+    // Move to position
+    let mut model = Matrix4::from_translation(cgmath::vec3(pos.x, pos.y, 0.0));
+    // Turn around the center
+    model = model * Matrix4::from_angle_z(Rad(angle));
+    // Move local coordinate space origin from bottom-right corner of quad to center
+    model = model * Matrix4::from_translation(cgmath::vec3(-0.5 * size, -0.5 * size, 0.0));
+    // Scale
+    model * Matrix4::from_scale(size)
+}
+
+/// View of the map overlay, showing `bounds` centered in the window and as large as fits with
+/// [`MAP_MARGIN`] around it. Also returns the pixels per world unit.
+fn map_view(bounds: &WorldBounds) -> (Matrix4<f32>, f32) {
+    let window = Vector2::new(globals::WINDOW_SIZE.0 as f32, globals::WINDOW_SIZE.1 as f32);
+    let size = bounds.size();
+    let scale =
+        ((window.x - 2.0 * MAP_MARGIN) / size.x).min((window.y - 2.0 * MAP_MARGIN) / size.y);
+    let center = Vector2::new(
+        (bounds.min_x + bounds.max_x) / 2.0,
+        (bounds.min_y + bounds.max_y) / 2.0,
+    );
+
+    let view = Matrix4::from_translation(Vector3::new(window.x / 2.0, window.y / 2.0, 0.0))
+        * Matrix4::from_scale(scale)
+        * Matrix4::from_translation(Vector3::new(-center.x, -center.y, 0.0));
+
+    (view, scale)
+}
+
+/// Unit square, circle and triangle one after another, each filling the unit square. The circle is
+/// a triangle fan around its center, the triangle points along +x.
+fn shape_vertices() -> Vec<f32> {
+    let mut vertices = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0];
+
+    vertices.extend_from_slice(&[0.5, 0.5]);
+    for i in 0..=CIRCLE_SEGMENTS {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+        vertices.extend_from_slice(&[0.5 + 0.5 * angle.cos(), 0.5 + 0.5 * angle.sin()]);
+    }
+
+    vertices.extend_from_slice(&[1.0, 0.5, 0.0, 1.0, 0.0, 0.0]);
+
+    vertices
+}
+
+/// Primitive, first vertex and vertex count of `shape` in [`shape_vertices`]
+fn shape_range(shape: Shape) -> (u32, i32, i32) {
+    let circle_vertices = CIRCLE_SEGMENTS as i32 + 2;
+
+    match shape {
+        Shape::Square => (glow::TRIANGLES, 0, 6),
+        Shape::Circle => (glow::TRIANGLE_FAN, 6, circle_vertices),
+        Shape::Triangle => (glow::TRIANGLES, 6 + circle_vertices, 3),
+    }
+}
+
+impl Renderer {
+    /// Rebuild the programs whose sources changed on disk. A program that fails to build keeps
+    /// running on its previous version.
+    #[cfg(debug_assertions)]
+    fn reload_changed_shaders(&mut self) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+
+        for shader in watcher.changed() {
+            let program = match unsafe { compile_program(&self.gl, shader, Shader::source) } {
+                Ok(program) => program,
+                Err(e) => {
+                    eprintln!("Failed to reload the {} shader: {e}", shader.name());
+                    continue;
+                }
+            };
+
+            unsafe {
+                let gl = &self.gl;
+                let old = match shader {
+                    Shader::Grid => {
+                        self.grid_mvp_location = uniform(gl, program, "uMVP");
+                        self.grid_ambient_location = uniform(gl, program, "uAmbient");
+                        std::mem::replace(&mut self.grid_shader_program, program)
+                    }
+                    Shader::Quad => {
+                        self.quad_mvp_location = uniform(gl, program, "uMVP");
+                        self.quad_color_location = uniform(gl, program, "uColor");
+                        self.quad_ambient_location = uniform(gl, program, "uAmbient");
+                        std::mem::replace(&mut self.quad_shader_program, program)
+                    }
+                    Shader::Text => {
+                        self.text_mvp_location = uniform(gl, program, "uMVP");
+                        bind_atlas_unit(gl, program);
+                        std::mem::replace(&mut self.text_shader_program, program)
+                    }
+                    Shader::Particle => {
+                        self.particle_mvp_location = uniform(gl, program, "uMVP");
+                        std::mem::replace(&mut self.particle_shader_program, program)
+                    }
+                };
+                gl.delete_program(old);
+            }
+
+            println!("Reloaded the {} shader", shader.name());
         }
     }
 }
@@ -399,7 +1498,174 @@ impl Drop for Renderer {
             self.gl.delete_buffer(self.quad_vbo);
             self.gl.delete_program(self.grid_shader_program);
             self.gl.delete_buffer(self.grid_vbo);
+            self.gl.delete_program(self.text_shader_program);
+            self.gl.delete_buffer(self.text_vbo);
+            self.gl.delete_program(self.particle_shader_program);
+            self.gl.delete_buffer(self.particle_vbo);
+            self.gl.delete_texture(self.text_atlas);
+        }
+    }
+}
+
+/// Program of `shader` from the sources on disk, or from the built-in ones if those don't build
+unsafe fn load_program(gl: &glow::Context, shader: Shader) -> glow::Program {
+    compile_program(gl, shader, Shader::source).unwrap_or_else(|e| {
+        eprintln!(
+            "Failed to build the {} shader, using the built-in one: {e}",
+            shader.name()
+        );
+
+        compile_program(gl, shader, Shader::builtin_source).expect("Built-in shader doesn't build")
+    })
+}
+
+/// Compile and link both stages of `shader` from the sources `source` returns. The error is the
+/// driver's log.
+unsafe fn compile_program(
+    gl: &glow::Context,
+    shader: Shader,
+    source: impl Fn(Shader, Stage) -> Cow<'static, str>,
+) -> Result<glow::Program, String> {
+    let program = gl.create_program()?;
+    let mut stages = Vec::new();
+
+    for (stage, kind) in [
+        (Stage::Vertex, glow::VERTEX_SHADER),
+        (Stage::Fragment, glow::FRAGMENT_SHADER),
+    ] {
+        let stage_shader = gl.create_shader(kind)?;
+        gl.shader_source(stage_shader, &source(shader, stage));
+        gl.compile_shader(stage_shader);
+        stages.push(stage_shader);
+
+        if !gl.get_shader_compile_status(stage_shader) {
+            let log = gl.get_shader_info_log(stage_shader);
+            for stage_shader in stages {
+                gl.delete_shader(stage_shader);
+            }
+            gl.delete_program(program);
+            return Err(log);
+        }
+        gl.attach_shader(program, stage_shader);
+    }
+    gl.link_program(program);
+
+    // (Shader programs are already created, individual shaders can be removed from memory)
+    for stage_shader in stages {
+        gl.detach_shader(program, stage_shader);
+        gl.delete_shader(stage_shader);
+    }
+
+    if gl.get_program_link_status(program) {
+        Ok(program)
+    } else {
+        let log = gl.get_program_info_log(program);
+        gl.delete_program(program);
+        Err(log)
+    }
+}
+
+/// A uniform the renderer sets, every program has to declare the ones it is looked up for
+unsafe fn uniform(gl: &glow::Context, program: glow::Program, name: &str) -> glow::UniformLocation {
+    gl.get_uniform_location(program, name)
+        .unwrap_or_else(|| panic!("Shader lacks the uniform {name}"))
+}
+
+/// Point the text program at texture unit 0, where the font atlas is bound
+unsafe fn bind_atlas_unit(gl: &glow::Context, program: glow::Program) {
+    gl.use_program(Some(program));
+    gl.uniform_1_i32(Some(&uniform(gl, program, "uAtlas")), 0);
+    gl.use_program(None);
+}
+
+/// Green for a fresh server update, turning red as it gets stale
+fn freshness_color(age: Duration) -> Vector3<f32> {
+    let staleness = (age.as_secs_f32() / STALE_UPDATE_AGE.as_secs_f32()).min(1.0);
+
+    Vector3::new(staleness, 1.0 - staleness, 0.0)
+}
+
+/// Light multiplied onto the world colors over a day. Full brightness at noon, dim blue at
+/// midnight and a warm tint around sunrise and sunset.
+fn ambient_color(time_of_day: f32) -> Vector3<f32> {
+    let day = Vector3::new(1.0, 1.0, 1.0);
+    let night = Vector3::new(0.3, 0.35, 0.6);
+    let twilight = Vector3::new(0.25, 0.05, -0.15);
+
+    // 0 at midnight, 1 at noon
+    let daylight = 0.5 - 0.5 * (time_of_day * std::f32::consts::TAU).cos();
+    let warmth = 1.0 - (2.0 * daylight - 1.0).abs();
+
+    night + (day - night) * daylight + twilight * warmth
+}
+
+/// Alpha of every font glyph, laid out in rows of `ATLAS_COLUMNS` cells
+fn font_atlas() -> Vec<u8> {
+    let mut pixels = vec![0; ATLAS_WIDTH * ATLAS_HEIGHT];
+
+    for (index, columns) in FONT.iter().enumerate() {
+        let cell_x = index % ATLAS_COLUMNS * ATLAS_CELL_WIDTH;
+        let cell_y = index / ATLAS_COLUMNS * ATLAS_CELL_HEIGHT;
+
+        for (x, column) in columns.iter().enumerate() {
+            for y in 0..GLYPH_HEIGHT {
+                if column & (1 << y) != 0 {
+                    pixels[(cell_y + y) * ATLAS_WIDTH + cell_x + x] = u8::MAX;
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Index into `FONT`, anything the font doesn't have falls back to '?'
+fn glyph_index(c: char) -> usize {
+    let index = (c as u32).wrapping_sub(FONT_FIRST_CHAR as u32) as usize;
+
+    if index < FONT.len() {
+        index
+    } else {
+        '?' as usize - FONT_FIRST_CHAR as usize
+    }
+}
+
+/// Append two triangles per visible character of `label`, centered above `pos`
+fn layout_label(label: &WorldLabel, pos: Vector2<f32>, vertices: &mut Vec<f32>) {
+    let advance = ATLAS_CELL_WIDTH as f32 * LABEL_SCALE;
+    let glyph_size = Vector2::new(GLYPH_WIDTH as f32, GLYPH_HEIGHT as f32) * LABEL_SCALE;
+
+    // The blank column after the last glyph doesn't count towards the width
+    let width = label.text.chars().count() as f32 * advance - LABEL_SCALE;
+    let top = pos.y - glyph_size.y;
+    let mut left = pos.x - width / 2.0;
+
+    for c in label.text.chars() {
+        if c != ' ' {
+            let index = glyph_index(c);
+            let u = (index % ATLAS_COLUMNS * ATLAS_CELL_WIDTH) as f32 / ATLAS_WIDTH as f32;
+            let v = (index / ATLAS_COLUMNS * ATLAS_CELL_HEIGHT) as f32 / ATLAS_HEIGHT as f32;
+            let uv_size = Vector2::new(
+                GLYPH_WIDTH as f32 / ATLAS_WIDTH as f32,
+                GLYPH_HEIGHT as f32 / ATLAS_HEIGHT as f32,
+            );
+
+            let (x0, y0, x1, y1) = (left, top, left + glyph_size.x, top + glyph_size.y);
+            let (u0, v0, u1, v1) = (u, v, u + uv_size.x, v + uv_size.y);
+            let color = label.color;
+            for (x, y, u, v) in [
+                (x0, y0, u0, v0),
+                (x1, y0, u1, v0),
+                (x0, y1, u0, v1),
+                (x1, y1, u1, v1),
+                (x0, y1, u0, v1),
+                (x1, y0, u1, v0),
+            ] {
+                vertices.extend_from_slice(&[x, y, u, v, color.x, color.y, color.z]);
+            }
         }
+
+        left += advance;
     }
 }
 
@@ -427,3 +1693,173 @@ fn create_grid_vertices(
 
     vertices
 }
+
+#[cfg(test)]
+mod tests {
+    use game_server_sample::terrain::TerrainMap;
+
+    use super::*;
+
+    fn draw_frame(renderer: &mut dyn Render, state: &fsm::State, remote_players: usize) {
+        let local_player = Player::default();
+        let remote_players: HashMap<PlayerId, Player> = (1..=remote_players as PlayerId)
+            .map(|id| (id, Player::new(id, Vector3::new(1.0, 0.0, 0.0))))
+            .collect();
+        let labels: Vec<WorldLabel> = remote_players
+            .values()
+            .map(|p| label(p.pos, &p.id.to_string()))
+            .collect();
+        let terrain = TerrainMap::builtin();
+
+        renderer.draw(&Scene {
+            camera: Vector2::new(0.0, 0.0),
+            local_player: &local_player,
+            remote_players: &remote_players,
+            state: Some(state),
+            world: WorldView {
+                world_mode: WorldMode::Bounded,
+                rules: &GameRules::default(),
+                terrain: &terrain,
+                time_of_day: 0.5,
+            },
+            motion_debug: None,
+            labels: &labels,
+            crosshair: None,
+            streaks: &[],
+            particles: &[],
+            map_overlay: false,
+        });
+        renderer.present();
+    }
+
+    fn label(pos: Vector2<f32>, text: &str) -> WorldLabel {
+        WorldLabel {
+            pos,
+            text: String::from(text),
+            color: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn null_renderer_draws_players_only_in_game() {
+        let mut renderer = NullRenderer::default();
+
+        draw_frame(&mut renderer, &fsm::State::Menu, 3);
+        assert_eq!(renderer.players_drawn, 0);
+        assert_eq!(renderer.labels_drawn, 0);
+
+        draw_frame(&mut renderer, &fsm::State::Playing, 3);
+        assert_eq!(renderer.players_drawn, 4);
+        assert_eq!(renderer.labels_drawn, 3);
+
+        draw_frame(&mut renderer, &fsm::State::QuitDialog, 2);
+        assert_eq!(renderer.players_drawn, 3);
+
+        assert_eq!(renderer.frames, 3);
+    }
+
+    #[test]
+    fn font_atlas_has_every_glyph_in_its_cell() {
+        let atlas = font_atlas();
+        let pixel = |x: usize, y: usize| atlas[y * ATLAS_WIDTH + x] != 0;
+        let cell = |c: char| {
+            let index = glyph_index(c);
+            (
+                index % ATLAS_COLUMNS * ATLAS_CELL_WIDTH,
+                index / ATLAS_COLUMNS * ATLAS_CELL_HEIGHT,
+            )
+        };
+
+        // Space is blank, '|' is a single full column
+        let (x, y) = cell(' ');
+        assert!(
+            (0..ATLAS_CELL_HEIGHT).all(|dy| (0..ATLAS_CELL_WIDTH).all(|dx| !pixel(x + dx, y + dy)))
+        );
+        let (x, y) = cell('|');
+        assert!((0..GLYPH_HEIGHT).all(|dy| pixel(x + 2, y + dy) && !pixel(x + 1, y + dy)));
+
+        // Spacing column and row stay blank for every glyph
+        for c in (' '..='~').chain(['\u{e9}']) {
+            let (x, y) = cell(c);
+            assert!(
+                (0..ATLAS_CELL_HEIGHT).all(|dy| !pixel(x + GLYPH_WIDTH, y + dy)),
+                "{c:?}"
+            );
+            assert!(
+                (0..ATLAS_CELL_WIDTH).all(|dx| !pixel(x + dx, y + GLYPH_HEIGHT)),
+                "{c:?}"
+            );
+        }
+
+        assert_eq!(glyph_index('\u{e9}'), glyph_index('?'));
+        assert_eq!(glyph_index('~'), FONT.len() - 1);
+    }
+
+    #[test]
+    fn map_fits_the_world_into_the_window() {
+        let bounds = WorldBounds {
+            min_x: -2000.0,
+            min_y: -500.0,
+            max_x: 2000.0,
+            max_y: 500.0,
+        };
+        let (view, scale) = map_view(&bounds);
+        let window = Vector2::new(globals::WINDOW_SIZE.0 as f32, globals::WINDOW_SIZE.1 as f32);
+        let on_screen = |x: f32, y: f32| {
+            let p = view * cgmath::vec4(x, y, 0.0, 1.0);
+            Vector2::new(p.x, p.y)
+        };
+
+        assert_eq!(on_screen(0.0, 0.0), window / 2.0);
+
+        // The wider side touches the margins, the other one has room to spare
+        assert_eq!(on_screen(bounds.min_x, 0.0).x, MAP_MARGIN);
+        assert_eq!(on_screen(bounds.max_x, 0.0).x, window.x - MAP_MARGIN);
+        assert!(on_screen(0.0, bounds.min_y).y > MAP_MARGIN);
+        assert_eq!(scale, (window.x - 2.0 * MAP_MARGIN) / 4000.0);
+    }
+
+    #[test]
+    fn player_shapes_fill_the_unit_square_end_to_end() {
+        let vertices = shape_vertices();
+        assert!(vertices.iter().all(|v| (0.0..=1.0).contains(v)));
+
+        let mut next = 0;
+        for shape in [Shape::Square, Shape::Circle, Shape::Triangle] {
+            let (_, first, count) = shape_range(shape);
+            assert_eq!(first, next, "{shape:?}");
+            next += count;
+        }
+        assert_eq!(next as usize * 2, vertices.len());
+    }
+
+    #[test]
+    fn labels_are_centered_above_their_position() {
+        let mut vertices = Vec::new();
+        layout_label(
+            &label(Vector2::new(100.0, 50.0), "a b"),
+            Vector2::new(100.0, 50.0),
+            &mut vertices,
+        );
+
+        // Two glyphs, the space only moves the next one along
+        assert_eq!(vertices.len(), 2 * 6 * TEXT_VERTEX_FLOATS);
+
+        let xs = vertices.iter().step_by(TEXT_VERTEX_FLOATS);
+        let ys = vertices.iter().skip(1).step_by(TEXT_VERTEX_FLOATS);
+        let (min_x, max_x) = xs.fold((f32::MAX, f32::MIN), |(min, max), x| {
+            (min.min(*x), max.max(*x))
+        });
+        let (min_y, max_y) = ys.fold((f32::MAX, f32::MIN), |(min, max), y| {
+            (min.min(*y), max.max(*y))
+        });
+
+        assert_eq!(min_x + max_x, 200.0);
+        assert_eq!(
+            max_x - min_x,
+            (3 * ATLAS_CELL_WIDTH - 1) as f32 * LABEL_SCALE
+        );
+        assert_eq!(max_y, 50.0);
+        assert_eq!(max_y - min_y, GLYPH_HEIGHT as f32 * LABEL_SCALE);
+    }
+}
@@ -0,0 +1,1045 @@
+//! Experimental renderer on wgpu, for systems whose OpenGL 2.1 contexts are unreliable. Draws the
+//! same scene as the OpenGL [`Renderer`](super::Renderer): every shape is an instanced quad and
+//! all labels are one batch of glyph quads. The GUI is painted over it with egui-wgpu in the same
+//! frame.
+
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+
+use cgmath::{ElementWise, Matrix4, Vector2, Vector3, Vector4};
+use egui_glow::egui_winit;
+use game_server_sample::{
+    globals, terrain::TerrainMap, Player, PlayerId, Shape, WorldBounds, WorldMode,
+};
+use wgpu::util::DeviceExt;
+use winit::{
+    dpi::PhysicalSize,
+    event::WindowEvent,
+    event_loop::ActiveEventLoop,
+    window::{Window, WindowAttributes},
+};
+
+use super::{
+    ambient_color, camera_view, font_atlas, freshness_color, layout_label, line_model, map_view,
+    rect_model, shape_model, shows_players, window_projection, MotionDebug, Render, RenderSettings,
+    Scene, Streak, WorldLabel, WorldView, ATLAS_HEIGHT, ATLAS_WIDTH, CROSSHAIR_ARM, CROSSHAIR_GAP,
+    CROSSHAIR_WIDTH, FACING_MARKER_SIZE, GRID_COL_COUNT, GRID_ROW_COUNT, HIGH_CONTRAST_GRID_SHADE,
+    MAP_MARGIN, MAP_MIN_PLAYER_SIZE, PLAYER_OUTLINE_WIDTH, STREAK_WIDTH, TEXT_VERTEX_FLOATS,
+    VELOCITY_LINE_SECONDS, VELOCITY_LINE_WIDTH,
+};
+use crate::{accessibility::Accessibility, crash, gui::Gui, particles::Particle};
+
+// Origin, both axes, color with opacity and shape of each quad instance
+const QUAD_INSTANCE_FLOATS: usize = 11;
+
+const QUAD_ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+    0 => Float32x2,
+    1 => Float32x2,
+    2 => Float32x2,
+    3 => Float32x4,
+    4 => Float32,
+];
+const TEXT_ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+    wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x3];
+
+// Same as the OpenGL grid, which is drawn with one pixel wide lines
+const GRID_LINE_WIDTH: f32 = 1.0;
+
+// Vertex buffers start out this big and double whenever a frame doesn't fit
+const INITIAL_BUFFER_SIZE: u64 = 64 * 1024;
+
+/// Draws the world through wgpu. Unlike [`Renderer`](super::Renderer) it can't open secondary
+/// windows, so the debug overlay stays in the game window.
+pub struct WgpuRenderer {
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    quad_pipeline: wgpu::RenderPipeline,
+    text_pipeline: wgpu::RenderPipeline,
+
+    // Projection, font atlas and its sampler, shared by both pipelines
+    bind_group: wgpu::BindGroup,
+
+    // Refilled with every instance and glyph of a frame
+    quad_buffer: StreamBuffer,
+    text_buffer: StreamBuffer,
+
+    egui_renderer: egui_wgpu::Renderer,
+
+    /// Painted by the GUI since the last frame, drawn over the world by `present`
+    gui_frame: Rc<RefCell<GuiFrame>>,
+
+    /// Surface texture of the frame between `draw` and `present`, with the world drawn to it
+    frame: Option<(wgpu::SurfaceTexture, wgpu::CommandEncoder)>,
+    settings: RenderSettings,
+}
+
+impl WgpuRenderer {
+    /// Create the native window and a wgpu device drawing to it. The error says why no device
+    /// could be set up, the caller falls back to OpenGL.
+    pub fn create_graphics(
+        event_loop: &ActiveEventLoop,
+        settings: RenderSettings,
+    ) -> Result<(Arc<Window>, WgpuRenderer, Gui), String> {
+        let window_attributes = WindowAttributes::default()
+            .with_title(globals::WINDOW_TITLE)
+            .with_inner_size(PhysicalSize::new(
+                globals::WINDOW_SIZE.0,
+                globals::WINDOW_SIZE.1,
+            ))
+            .with_resizable(false);
+        let window = Arc::new(
+            event_loop
+                .create_window(window_attributes)
+                .map_err(|e| e.to_string())?,
+        );
+
+        // Only the native APIs, OpenGL is what the other renderer is for
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::PRIMARY,
+            ..Default::default()
+        });
+        let surface = instance
+            .create_surface(window.clone())
+            .map_err(|e| e.to_string())?;
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            compatible_surface: Some(&surface),
+            ..Default::default()
+        }))
+        .ok_or("No graphics adapter can draw to the window")?;
+
+        let info = adapter.get_info();
+        crash::set_gl_info(format!(
+            "wgpu {:?}, {} {}",
+            info.backend, info.name, info.driver_info
+        ));
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("renderer"),
+                required_limits:
+                    wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                ..Default::default()
+            },
+            None,
+        ))
+        .map_err(|e| e.to_string())?;
+
+        // Colors are written as they are, like the OpenGL renderer and egui expect
+        let capabilities = surface.get_capabilities(&adapter);
+        let format = capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|format| !format.is_srgb())
+            .or(capabilities.formats.first().copied())
+            .ok_or("The window can't be drawn to")?;
+        let size = window.inner_size();
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::AutoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: Vec::new(),
+        };
+        surface.configure(&device, &surface_config);
+
+        // Same projection for every frame, what moves is baked into the instances
+        let projection = window_projection();
+        let projection: &[f32; 16] = projection.as_ref();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("projection"),
+            contents: bytemuck::cast_slice(projection),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // Font atlas is uploaded once, labels only send their glyph quads
+        let atlas = device.create_texture_with_data(
+            &queue,
+            &wgpu::TextureDescriptor {
+                label: Some("font atlas"),
+                size: wgpu::Extent3d {
+                    width: ATLAS_WIDTH as u32,
+                    height: ATLAS_HEIGHT as u32,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &font_atlas(),
+        );
+        // The default nearest filtering keeps the scaled up pixels crisp
+        let atlas_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("renderer"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("renderer"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &atlas.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&atlas_sampler),
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("renderer"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let quad_pipeline = create_pipeline(
+            &device,
+            &layout,
+            format,
+            "quad",
+            include_str!("../../assets/shaders/quad.wgsl"),
+            wgpu::VertexBufferLayout {
+                array_stride: (QUAD_INSTANCE_FLOATS * size_of::<f32>()) as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &QUAD_ATTRIBUTES,
+            },
+        );
+        let text_pipeline = create_pipeline(
+            &device,
+            &layout,
+            format,
+            "text",
+            include_str!("../../assets/shaders/text.wgsl"),
+            wgpu::VertexBufferLayout {
+                array_stride: (TEXT_VERTEX_FLOATS * size_of::<f32>()) as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &TEXT_ATTRIBUTES,
+            },
+        );
+
+        let egui_renderer = egui_wgpu::Renderer::new(&device, format, None, 1, true);
+        let gui_frame = Rc::new(RefCell::new(GuiFrame::default()));
+        let gui = Gui::with_wgpu(EguiWgpu::new(
+            event_loop,
+            device.limits().max_texture_dimension_2d as usize,
+            gui_frame.clone(),
+        ));
+
+        let renderer = WgpuRenderer {
+            quad_buffer: StreamBuffer::new(&device, "quad instances"),
+            text_buffer: StreamBuffer::new(&device, "glyph vertices"),
+            window: window.clone(),
+            surface,
+            surface_config,
+            device,
+            queue,
+            quad_pipeline,
+            text_pipeline,
+            bind_group,
+            egui_renderer,
+            gui_frame,
+            frame: None,
+            settings,
+        };
+
+        Ok((window, renderer, gui))
+    }
+
+    /// Texture to draw the next frame to, `None` to skip the frame
+    fn next_texture(&mut self) -> Option<wgpu::SurfaceTexture> {
+        match self.surface.get_current_texture() {
+            Ok(texture) => Some(texture),
+
+            // The window changed under the surface, e.g. it was moved to another screen
+            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                let size = self.window.inner_size();
+                self.surface_config.width = size.width.max(1);
+                self.surface_config.height = size.height.max(1);
+                self.surface.configure(&self.device, &self.surface_config);
+
+                self.surface.get_current_texture().ok()
+            }
+            Err(e) => {
+                eprintln!("Skipping a frame: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// Quads and glyphs of `scene`, in the order the OpenGL renderer draws them
+fn frame_geometry(settings: RenderSettings, scene: &Scene) -> FrameGeometry {
+    let Scene {
+        camera,
+        local_player,
+        remote_players,
+        state,
+        world,
+        motion_debug,
+        labels,
+        crosshair,
+        streaks,
+        particles,
+        map_overlay,
+    } = *scene;
+
+    // Same ambient light for everything in the world, high contrast darkens the grid lines
+    let ambient = ambient_color(world.time_of_day);
+    let grid_ambient = if settings.accessibility.high_contrast {
+        ambient * HIGH_CONTRAST_GRID_SHADE
+    } else {
+        ambient
+    };
+    let view = camera_view(camera);
+    let mut quads = Quads {
+        floats: Vec::new(),
+        view,
+        ambient,
+        settings,
+    };
+
+    // A wrapping world is tiled, so the neighbouring copies show across the seam
+    let world_size = world.rules.bounds.size();
+    let tiles = match world.world_mode {
+        WorldMode::Bounded => 0,
+        WorldMode::Wrap => 1,
+    };
+    for tile_x in -tiles..=tiles {
+        for tile_y in -tiles..=tiles {
+            let offset = Vector2::new(tile_x as f32 * world_size.x, tile_y as f32 * world_size.y);
+            quads.terrain(world.terrain, offset);
+            quads.grid(&world.rules.bounds, offset, grid_ambient);
+        }
+    }
+
+    if !shows_players(state) {
+        return FrameGeometry::under_labels(quads.floats);
+    }
+
+    // Underneath the players, so the dasher is drawn at the end of its trail
+    quads.streaks(camera, streaks, &world);
+    quads.players(camera, local_player, remote_players, &world, motion_debug);
+    quads.particles(camera, particles, &world);
+
+    let mut frame = FrameGeometry::under_labels(std::mem::take(&mut quads.floats));
+    frame.text = label_vertices(camera, labels, &view, &world);
+
+    if let Some(pos) = crosshair {
+        quads.crosshair(pos);
+    }
+    if map_overlay {
+        quads.map(local_player, remote_players, &world);
+    }
+    frame.quads.append(&mut quads.floats);
+
+    frame
+}
+
+impl Render for WgpuRenderer {
+    fn draw(&mut self, scene: &Scene) {
+        let geometry = frame_geometry(self.settings, scene);
+        let Some(texture) = self.next_texture() else {
+            return;
+        };
+
+        self.quad_buffer
+            .write(&self.device, &self.queue, &geometry.quads);
+        self.text_buffer
+            .write(&self.device, &self.queue, &geometry.text);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("frame"),
+            });
+        {
+            let view = texture.texture.create_view(&Default::default());
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("world"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_bind_group(0, &self.bind_group, &[]);
+
+            let instances = (geometry.quads.len() / QUAD_INSTANCE_FLOATS) as u32;
+            let glyph_vertices = (geometry.text.len() / TEXT_VERTEX_FLOATS) as u32;
+
+            pass.set_pipeline(&self.quad_pipeline);
+            pass.set_vertex_buffer(0, self.quad_buffer.buffer.slice(..));
+            pass.draw(0..6, 0..geometry.overlay_start);
+
+            // Labels go over the world but under the crosshair and map
+            if glyph_vertices > 0 {
+                pass.set_pipeline(&self.text_pipeline);
+                pass.set_vertex_buffer(0, self.text_buffer.buffer.slice(..));
+                pass.draw(0..glyph_vertices, 0..1);
+
+                pass.set_pipeline(&self.quad_pipeline);
+                pass.set_vertex_buffer(0, self.quad_buffer.buffer.slice(..));
+            }
+            pass.draw(0..6, geometry.overlay_start..instances);
+        }
+
+        self.frame = Some((texture, encoder));
+    }
+
+    fn present(&mut self) {
+        let gui = std::mem::take(&mut *self.gui_frame.borrow_mut());
+
+        // Textures are updated even without a frame to draw, egui only sends them once
+        for (id, image_delta) in &gui.textures_delta.set {
+            self.egui_renderer
+                .update_texture(&self.device, &self.queue, *id, image_delta);
+        }
+
+        if let Some((texture, mut encoder)) = self.frame.take() {
+            let screen = egui_wgpu::ScreenDescriptor {
+                size_in_pixels: [self.surface_config.width, self.surface_config.height],
+                pixels_per_point: gui.pixels_per_point,
+            };
+            let gui_commands = self.egui_renderer.update_buffers(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &gui.primitives,
+                &screen,
+            );
+            {
+                let view = texture.texture.create_view(&Default::default());
+                let mut pass = encoder
+                    .begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("gui"),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: &view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Load,
+                                store: wgpu::StoreOp::Store,
+                            },
+                        })],
+                        ..Default::default()
+                    })
+                    .forget_lifetime();
+                self.egui_renderer
+                    .render(&mut pass, &gui.primitives, &screen);
+            }
+
+            self.queue
+                .submit(gui_commands.into_iter().chain([encoder.finish()]));
+            texture.present();
+        }
+
+        for id in &gui.textures_delta.free {
+            self.egui_renderer.free_texture(id);
+        }
+    }
+
+    fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.settings.accessibility = accessibility;
+    }
+}
+
+/// Render pipeline drawing triangles of `buffer` with the shader `source`, blended over what is
+/// there already
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    label: &str,
+    source: &'static str,
+    buffer: wgpu::VertexBufferLayout,
+) -> wgpu::RenderPipeline {
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            compilation_options: Default::default(),
+            buffers: &[buffer],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Vertex buffer refilled every frame, replaced by a bigger one when a frame outgrows it
+struct StreamBuffer {
+    buffer: wgpu::Buffer,
+    label: &'static str,
+}
+
+impl StreamBuffer {
+    fn new(device: &wgpu::Device, label: &'static str) -> Self {
+        Self {
+            buffer: Self::create(device, label, INITIAL_BUFFER_SIZE),
+            label,
+        }
+    }
+
+    fn create(device: &wgpu::Device, label: &'static str, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[f32]) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        if bytes.is_empty() {
+            return;
+        }
+
+        let size = bytes.len() as u64;
+        if size > self.buffer.size() {
+            self.buffer = Self::create(device, self.label, size.next_power_of_two());
+        }
+        queue.write_buffer(&self.buffer, 0, bytes);
+    }
+}
+
+/// Quad instances and glyph vertices of a frame, in window pixels
+struct FrameGeometry {
+    /// Instances under the labels, then from `overlay_start` on the ones over them
+    quads: Vec<f32>,
+    overlay_start: u32,
+    text: Vec<f32>,
+}
+
+impl FrameGeometry {
+    fn under_labels(quads: Vec<f32>) -> Self {
+        Self {
+            overlay_start: (quads.len() / QUAD_INSTANCE_FLOATS) as u32,
+            quads,
+            text: Vec::new(),
+        }
+    }
+}
+
+/// Glyph quads of all labels, at their copy closest to the camera like players
+fn label_vertices(
+    camera: Vector2<f32>,
+    labels: &[WorldLabel],
+    view: &Matrix4<f32>,
+    world: &WorldView,
+) -> Vec<f32> {
+    let mut vertices = Vec::new();
+    for label in labels {
+        let pos = camera + world.rules.world_delta(camera, label.pos, world.world_mode);
+        layout_label(label, pos, &mut vertices);
+    }
+
+    for vertex in vertices.chunks_exact_mut(TEXT_VERTEX_FLOATS) {
+        let pos = view * Vector4::new(vertex[0], vertex[1], 0.0, 1.0);
+        vertex[0] = pos.x;
+        vertex[1] = pos.y;
+    }
+
+    vertices
+}
+
+/// Quad instances of a frame. Each is the unit square moved by `view` times its model matrix,
+/// world colors are lit by `ambient`.
+struct Quads {
+    floats: Vec<f32>,
+    view: Matrix4<f32>,
+    ambient: Vector3<f32>,
+    settings: RenderSettings,
+}
+
+impl Quads {
+    fn push(&mut self, model: &Matrix4<f32>, color: Vector4<f32>, shape: Shape) {
+        let transform = self.view * model;
+        let (origin, axis_x, axis_y) = (transform.w, transform.x, transform.y);
+        let shape = match shape {
+            Shape::Square => 0.0,
+            Shape::Circle => 1.0,
+            Shape::Triangle => 2.0,
+        };
+
+        self.floats.extend_from_slice(&[
+            origin.x, origin.y, axis_x.x, axis_x.y, axis_y.x, axis_y.y, color.x, color.y, color.z,
+            color.w, shape,
+        ]);
+    }
+
+    fn lit(&self, color: &Vector3<f32>) -> Vector4<f32> {
+        color.mul_element_wise(self.ambient).extend(1.0)
+    }
+
+    fn rect(&mut self, min: Vector2<f32>, max: Vector2<f32>, color: &Vector3<f32>) {
+        self.push(&rect_model(min, max), self.lit(color), Shape::Square);
+    }
+
+    fn line(&mut self, from: Vector2<f32>, to: Vector2<f32>, color: &Vector3<f32>, width: f32) {
+        if let Some(model) = line_model(from, to, width) {
+            self.push(&model, self.lit(color), Shape::Square);
+        }
+    }
+
+    fn shape(
+        &mut self,
+        pos: &Vector2<f32>,
+        color: &Vector3<f32>,
+        size: f32,
+        angle: f32,
+        shape: Shape,
+    ) {
+        self.push(&shape_model(pos, size, angle), self.lit(color), shape);
+    }
+
+    /// Terrain zones as tinted rectangles underneath the grid lines
+    fn terrain(&mut self, terrain: &TerrainMap, offset: Vector2<f32>) {
+        for zone in &terrain.zones {
+            self.rect(zone.min + offset, zone.max + offset, &zone.terrain.color());
+        }
+    }
+
+    fn grid(&mut self, bounds: &WorldBounds, offset: Vector2<f32>, ambient: Vector3<f32>) {
+        let color = Vector3::new(0.5, 0.5, 0.5)
+            .mul_element_wise(ambient)
+            .extend(1.0);
+        let min = Vector2::new(bounds.min_x, bounds.min_y) + offset;
+        let size = bounds.size();
+        let half_width = GRID_LINE_WIDTH / 2.0;
+
+        for column in 0..=GRID_COL_COUNT {
+            let x = min.x + column as f32 * size.x / GRID_COL_COUNT as f32;
+            let model = rect_model(
+                Vector2::new(x - half_width, min.y),
+                Vector2::new(x + half_width, min.y + size.y),
+            );
+            self.push(&model, color, Shape::Square);
+        }
+        for row in 0..=GRID_ROW_COUNT {
+            let y = min.y + row as f32 * size.y / GRID_ROW_COUNT as f32;
+            let model = rect_model(
+                Vector2::new(min.x, y - half_width),
+                Vector2::new(min.x + size.x, y + half_width),
+            );
+            self.push(&model, color, Shape::Square);
+        }
+    }
+
+    fn streaks(&mut self, camera: Vector2<f32>, streaks: &[Streak], world: &WorldView) {
+        for streak in streaks {
+            let to = camera + world.rules.world_delta(camera, streak.to, world.world_mode);
+            let from = to
+                + world
+                    .rules
+                    .world_delta(streak.to, streak.from, world.world_mode);
+            let width = STREAK_WIDTH * (1.0 - streak.fade);
+
+            self.line(
+                from + (to - from) * streak.fade,
+                to,
+                &self.settings.palette.remap(streak.color),
+                width,
+            );
+        }
+    }
+
+    /// Players at their copy closest to the camera, with the motion debug lines on top
+    fn players(
+        &mut self,
+        camera: Vector2<f32>,
+        local_player: &Player,
+        remote_players: &HashMap<PlayerId, Player>,
+        world: &WorldView,
+        motion_debug: Option<&MotionDebug>,
+    ) {
+        let players = std::iter::once(local_player)
+            .chain(remote_players.values())
+            .map(|p| Player {
+                pos: camera + world.rules.world_delta(camera, p.pos, world.world_mode),
+                ..*p
+            });
+
+        for player in players.clone() {
+            let freshness = motion_debug
+                .and_then(|debug| debug.update_ages.get(&player.id))
+                .map(|age| freshness_color(*age));
+            self.player(&player, world.rules.size_of(&player), freshness);
+        }
+
+        if let Some(debug) = motion_debug {
+            for player in players {
+                if let Some(velocity) = debug.velocities.get(&player.id) {
+                    self.velocity(player.pos, *velocity);
+                }
+            }
+        }
+    }
+
+    /// Like [`Renderer::draw_player`](super::Renderer), outline and facing marker included
+    fn player(&mut self, player: &Player, size: f32, color_override: Option<Vector3<f32>>) {
+        let shape = player.avatar.shape;
+
+        if self.settings.player_outline || self.settings.accessibility.high_contrast {
+            self.shape(
+                &player.pos,
+                &Vector3::new(0.0, 0.0, 0.0),
+                size + 2.0 * PLAYER_OUTLINE_WIDTH,
+                player.facing,
+                shape,
+            );
+        }
+
+        let color = color_override.unwrap_or_else(|| self.settings.palette.remap(player.color));
+        self.shape(&player.pos, &color, size, player.facing, shape);
+
+        if shape == Shape::Triangle {
+            return;
+        }
+
+        let marker_size = size * FACING_MARKER_SIZE;
+        let front =
+            Vector2::new(player.facing.cos(), player.facing.sin()) * (size - marker_size) / 2.0;
+        self.shape(
+            &(player.pos + front),
+            &(color * 0.4),
+            marker_size,
+            player.facing,
+            Shape::Square,
+        );
+    }
+
+    fn velocity(&mut self, pos: Vector2<f32>, velocity: Vector2<f32>) {
+        let line = velocity * VELOCITY_LINE_SECONDS;
+
+        for (end, color) in [
+            (pos + Vector2::new(line.x, 0.0), Vector3::new(1.0, 0.0, 0.0)),
+            (pos + Vector2::new(0.0, line.y), Vector3::new(0.0, 1.0, 0.0)),
+            (pos + line, Vector3::new(1.0, 1.0, 0.0)),
+        ] {
+            self.line(pos, end, &color, VELOCITY_LINE_WIDTH);
+        }
+    }
+
+    /// Unlit, with their own opacity
+    fn particles(&mut self, camera: Vector2<f32>, particles: &[Particle], world: &WorldView) {
+        for particle in particles {
+            let pos = camera
+                + world
+                    .rules
+                    .world_delta(camera, particle.pos, world.world_mode);
+            let half = Vector2::new(particle.size, particle.size) / 2.0;
+            let color = self
+                .settings
+                .palette
+                .remap(particle.color)
+                .extend(particle.opacity());
+
+            self.push(&rect_model(pos - half, pos + half), color, Shape::Square);
+        }
+    }
+
+    fn crosshair(&mut self, pos: Vector2<f32>) {
+        let half_width = CROSSHAIR_WIDTH / 2.0;
+        let arms = [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)].map(|(x, y)| {
+            let direction = Vector2::new(x, y);
+            let across = Vector2::new(y, x) * half_width;
+            let start = pos + direction * CROSSHAIR_GAP;
+            let end = pos + direction * (CROSSHAIR_GAP + CROSSHAIR_ARM);
+            let (a, b) = (start - across, end + across);
+            (
+                Vector2::new(a.x.min(b.x), a.y.min(b.y)),
+                Vector2::new(a.x.max(b.x), a.y.max(b.y)),
+            )
+        });
+
+        let outline = Vector2::new(1.0, 1.0);
+        for (min, max) in arms {
+            self.rect(min - outline, max + outline, &Vector3::new(1.0, 1.0, 1.0));
+        }
+        for (min, max) in arms {
+            self.rect(min, max, &Vector3::new(0.0, 0.0, 0.0));
+        }
+    }
+
+    /// The whole world with everyone on it, scaled to fit the window
+    fn map(
+        &mut self,
+        local_player: &Player,
+        remote_players: &HashMap<PlayerId, Player>,
+        world: &WorldView,
+    ) {
+        let bounds = &world.rules.bounds;
+        let (view, scale) = map_view(bounds);
+        self.view = view;
+
+        // Readable whatever the time of day
+        self.ambient = Vector3::new(1.0, 1.0, 1.0);
+
+        let frame = MAP_MARGIN / 2.0 / scale;
+        self.rect(
+            Vector2::new(bounds.min_x - frame, bounds.min_y - frame),
+            Vector2::new(bounds.max_x + frame, bounds.max_y + frame),
+            &Vector3::new(0.15, 0.15, 0.15),
+        );
+        self.rect(
+            Vector2::new(bounds.min_x, bounds.min_y),
+            Vector2::new(bounds.max_x, bounds.max_y),
+            &Vector3::new(1.0, 1.0, 1.0),
+        );
+        self.terrain(world.terrain, Vector2::new(0.0, 0.0));
+
+        let min_size = MAP_MIN_PLAYER_SIZE / scale;
+        for player in remote_players.values() {
+            let size = world.rules.size_of(player).max(min_size);
+            let color = self.settings.palette.remap(player.color);
+            self.shape(
+                &player.pos,
+                &color,
+                size,
+                player.facing,
+                player.avatar.shape,
+            );
+        }
+
+        // Last and outlined, so the local player is easy to find
+        let size = world.rules.size_of(local_player).max(min_size);
+        self.shape(
+            &local_player.pos,
+            &Vector3::new(0.0, 0.0, 0.0),
+            size + 2.0 * PLAYER_OUTLINE_WIDTH / scale,
+            local_player.facing,
+            local_player.avatar.shape,
+        );
+        self.player(local_player, size, None);
+    }
+}
+
+/// What the GUI painted for the next frame
+struct GuiFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+impl Default for GuiFrame {
+    fn default() -> Self {
+        Self {
+            primitives: Vec::new(),
+            textures_delta: Default::default(),
+            pixels_per_point: 1.0,
+        }
+    }
+}
+
+/// egui on the window of a [`WgpuRenderer`]. Works like `EguiGlow`, except that painting hands
+/// the frame to the renderer, which draws it over the world in `present`.
+pub struct EguiWgpu {
+    pub egui_ctx: egui::Context,
+    pub egui_winit: egui_winit::State,
+    viewport_info: egui::ViewportInfo,
+
+    // Output of the last run
+    shapes: Vec<egui::epaint::ClippedShape>,
+    pixels_per_point: f32,
+    textures_delta: egui::TexturesDelta,
+
+    target: Rc<RefCell<GuiFrame>>,
+}
+
+impl EguiWgpu {
+    fn new(
+        event_loop: &ActiveEventLoop,
+        max_texture_side: usize,
+        target: Rc<RefCell<GuiFrame>>,
+    ) -> Self {
+        let egui_ctx = egui::Context::default();
+        let egui_winit = egui_winit::State::new(
+            egui_ctx.clone(),
+            egui::ViewportId::ROOT,
+            event_loop,
+            None,
+            event_loop.system_theme(),
+            Some(max_texture_side),
+        );
+
+        Self {
+            egui_ctx,
+            egui_winit,
+            viewport_info: Default::default(),
+            shapes: Vec::new(),
+            pixels_per_point: 1.0,
+            textures_delta: Default::default(),
+            target,
+        }
+    }
+
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) {
+        let _ = self.egui_winit.on_window_event(window, event);
+    }
+
+    /// Lay out a frame, [`Self::paint`] hands it to the renderer
+    pub fn run(&mut self, window: &Window, run_ui: impl FnMut(&egui::Context)) {
+        let raw_input = self.egui_winit.take_egui_input(window);
+        let egui::FullOutput {
+            platform_output,
+            textures_delta,
+            shapes,
+            pixels_per_point,
+            viewport_output,
+        } = self.egui_ctx.run(raw_input, run_ui);
+
+        // The GUI never opens viewports of its own, only the root one gets commands
+        for (_, egui::ViewportOutput { commands, .. }) in viewport_output {
+            let mut actions_requested = Default::default();
+            egui_winit::process_viewport_commands(
+                &self.egui_ctx,
+                &mut self.viewport_info,
+                commands,
+                window,
+                &mut actions_requested,
+            );
+        }
+
+        self.egui_winit
+            .handle_platform_output(window, platform_output);
+
+        self.shapes = shapes;
+        self.pixels_per_point = pixels_per_point;
+        self.textures_delta.append(textures_delta);
+    }
+
+    pub fn paint(&mut self, _window: &Window) {
+        let shapes = std::mem::take(&mut self.shapes);
+        let mut target = self.target.borrow_mut();
+
+        target.primitives = self.egui_ctx.tessellate(shapes, self.pixels_per_point);
+        target.pixels_per_point = self.pixels_per_point;
+        target
+            .textures_delta
+            .append(std::mem::take(&mut self.textures_delta));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game_server_sample::rules::GameRules;
+
+    use super::*;
+    use crate::fsm;
+
+    fn instances(quads: &[f32]) -> u32 {
+        (quads.len() / QUAD_INSTANCE_FLOATS) as u32
+    }
+
+    #[test]
+    fn labels_are_drawn_between_the_world_and_the_crosshair() {
+        let local_player = Player::default();
+        let remote_players = HashMap::from([(1, Player::new(1, Vector3::new(1.0, 0.0, 0.0)))]);
+        let labels = [WorldLabel {
+            pos: Vector2::new(10.0, 10.0),
+            text: String::from("ab"),
+            color: Vector3::new(0.0, 0.0, 0.0),
+        }];
+        let terrain = TerrainMap::builtin();
+        let rules = GameRules::default();
+        let scene = |state| Scene {
+            camera: Vector2::new(0.0, 0.0),
+            local_player: &local_player,
+            remote_players: &remote_players,
+            state: Some(state),
+            world: WorldView {
+                world_mode: WorldMode::Bounded,
+                rules: &rules,
+                terrain: &terrain,
+                time_of_day: 0.5,
+            },
+            motion_debug: None,
+            labels: &labels,
+            crosshair: Some(Vector2::new(5.0, 5.0)),
+            streaks: &[],
+            particles: &[],
+            map_overlay: false,
+        };
+
+        // Terrain zones and grid lines only
+        let menu = frame_geometry(RenderSettings::default(), &scene(&fsm::State::Menu));
+        let world = terrain.zones.len() + GRID_COL_COUNT + 1 + GRID_ROW_COUNT + 1;
+        assert_eq!(instances(&menu.quads), world as u32);
+        assert_eq!(menu.overlay_start, world as u32);
+        assert!(menu.text.is_empty());
+
+        // Two squares with their facing markers, then the crosshair arms with their outlines
+        let playing = frame_geometry(RenderSettings::default(), &scene(&fsm::State::Playing));
+        assert_eq!(playing.overlay_start, world as u32 + 4);
+        assert_eq!(instances(&playing.quads), playing.overlay_start + 8);
+        assert_eq!(playing.text.len(), 2 * 6 * TEXT_VERTEX_FLOATS);
+    }
+}
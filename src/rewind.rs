@@ -0,0 +1,109 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use cgmath::Vector2;
+use game_server_sample::{rules::GameRules, PlayerId, WorldMode};
+
+/// How far back hits can be checked. Shooters lagging further behind are checked against the
+/// oldest state kept.
+pub const REWIND_WINDOW: Duration = Duration::from_millis(500);
+
+/// Player positions at the end of one simulation tick
+struct WorldSnapshot {
+    tick: u64,
+    at: Instant,
+    positions: HashMap<PlayerId, Vector2<f32>>,
+}
+
+/// Recent world states at tick resolution, so hits can be checked against the world as the
+/// shooter saw it instead of where everyone is by the time the shot arrives (lag compensation)
+pub struct WorldHistory {
+    snapshots: VecDeque<WorldSnapshot>,
+    world_mode: WorldMode,
+    rules: GameRules,
+}
+
+impl WorldHistory {
+    pub fn new(world_mode: WorldMode, rules: GameRules) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            world_mode,
+            rules,
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, at: Instant, positions: HashMap<PlayerId, Vector2<f32>>) {
+        self.snapshots.push_back(WorldSnapshot {
+            tick,
+            at,
+            positions,
+        });
+
+        // Keep one snapshot older than the window to interpolate from
+        while self
+            .snapshots
+            .get(1)
+            .is_some_and(|s| at.saturating_duration_since(s.at) > REWIND_WINDOW)
+        {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Player positions at `at`, interpolated between the two ticks around it. Clamped to the
+    /// oldest and newest state kept.
+    pub fn positions_at(&self, at: Instant) -> HashMap<PlayerId, Vector2<f32>> {
+        let after = self.snapshots.partition_point(|s| s.at <= at);
+        let (from, to) = match (after.checked_sub(1), self.snapshots.get(after)) {
+            (Some(before), Some(to)) => (&self.snapshots[before], to),
+            (Some(before), None) => return self.snapshots[before].positions.clone(),
+            (None, Some(to)) => return to.positions.clone(),
+            (None, None) => return HashMap::new(),
+        };
+
+        let span = to.at.duration_since(from.at).as_secs_f32();
+        let alpha = match span {
+            0.0 => 1.0,
+            span => at.duration_since(from.at).as_secs_f32() / span,
+        };
+
+        // Players who joined or left in between are taken from whichever side has them
+        to.positions
+            .iter()
+            .map(|(id, &to_pos)| match from.positions.get(id) {
+                Some(&from_pos) => {
+                    let delta = self.rules.world_delta(from_pos, to_pos, self.world_mode);
+                    (*id, from_pos + delta * alpha)
+                }
+                None => (*id, to_pos),
+            })
+            .collect()
+    }
+
+    /// Position of a single player at `at`, see [`WorldHistory::positions_at`]
+    pub fn position_at(&self, player_id: PlayerId, at: Instant) -> Option<Vector2<f32>> {
+        self.positions_at(at).remove(&player_id)
+    }
+
+    /// Ticks covered by the history, oldest first
+    pub fn ticks(&self) -> Option<(u64, u64)> {
+        Some((self.snapshots.front()?.tick, self.snapshots.back()?.tick))
+    }
+}
+
+/// Point in time a client was looking at when it acted at `now`: its inputs took half the round
+/// trip to get here, and it draws remote players `interp_delay` behind. Clients that don't report
+/// their delay are taken to draw them about one server tick behind.
+pub fn shooter_view_time(
+    now: Instant,
+    rtt: Option<Duration>,
+    interp_delay: Option<Duration>,
+    tick_rate: u32,
+) -> Instant {
+    let interp_delay =
+        interp_delay.unwrap_or_else(|| Duration::from_secs_f32(1.0 / tick_rate as f32));
+    let lag = rtt.unwrap_or_default() / 2 + interp_delay;
+
+    now.checked_sub(lag.min(REWIND_WINDOW)).unwrap_or(now)
+}
@@ -0,0 +1,207 @@
+//! Numbers both ends of a connection have to agree on. The server picks them and sends them to
+//! every client joining it, clients predict, clamp and draw with what they got instead of their
+//! own compiled-in defaults.
+
+use std::time::Duration;
+
+use cgmath::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::{globals, simulation::MovementConfig, Avatar, Player, WorldBounds, WorldMode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameRules {
+    pub bounds: WorldBounds,
+
+    /// Side length of a player's quad, for drawing, collisions and the distance kept from the
+    /// world edge
+    pub player_size: f32,
+
+    pub movement: MovementConfig,
+
+    /// Simulation rate of the server in Hz while it keeps up, one of
+    /// [`globals::SERVER_TICK_RATES`]
+    pub tick_rate: u32,
+
+    /// How often the server pings every client
+    pub ping_interval: Duration,
+
+    /// Pings in a row a client may miss before it counts the server as gone
+    pub max_missed_pings: u32,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            bounds: globals::WORLD_BOUNDS,
+            player_size: globals::PLAYER_QUAD_SIZE,
+            movement: MovementConfig::default(),
+            tick_rate: globals::SERVER_TICK_RATES[0],
+            ping_interval: globals::PING_INTERVAL_MS,
+            max_missed_pings: missed_pings_within(
+                globals::CONNECTION_TIMEOUT_SEC,
+                globals::PING_INTERVAL_MS,
+            ),
+        }
+    }
+}
+
+impl GameRules {
+    /// Whether a game can be played by these rules: a world with room for a player, movement
+    /// that moves, a known tick rate
+    pub fn is_valid(&self) -> bool {
+        let size = self.bounds.size();
+
+        self.player_size.is_finite()
+            && self.player_size > 0.0
+            && size.x.is_finite()
+            && size.y.is_finite()
+            && size.x > self.player_size
+            && size.y > self.player_size
+            && self.movement.is_valid()
+            && globals::SERVER_TICK_RATES.contains(&self.tick_rate)
+            && !self.ping_interval.is_zero()
+            && self.max_missed_pings > 0
+    }
+
+    /// Silence after which the server counts as gone
+    pub fn connection_timeout(&self) -> Duration {
+        self.ping_interval * self.max_missed_pings
+    }
+
+    /// What differs from `other`, one line each, e.g. for warning players that a server plays
+    /// differently than usual
+    pub fn differences(&self, other: &GameRules) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, ours: String, theirs: String| {
+            if ours != theirs {
+                differences.push(format!("{name} {ours} instead of {theirs}"));
+            }
+        };
+
+        compare(
+            "world",
+            format_bounds(&self.bounds),
+            format_bounds(&other.bounds),
+        );
+        compare(
+            "player size",
+            self.player_size.to_string(),
+            other.player_size.to_string(),
+        );
+        compare(
+            "speed",
+            self.movement.speed.to_string(),
+            other.movement.speed.to_string(),
+        );
+        compare(
+            "acceleration",
+            self.movement.acceleration.to_string(),
+            other.movement.acceleration.to_string(),
+        );
+        compare(
+            "friction",
+            self.movement.friction.to_string(),
+            other.movement.friction.to_string(),
+        );
+        compare(
+            "tick rate",
+            format!("{} Hz", self.tick_rate),
+            format!("{} Hz", other.tick_rate),
+        );
+        compare(
+            "ping interval",
+            format!("{:?}", self.ping_interval),
+            format!("{:?}", other.ping_interval),
+        );
+        compare(
+            "missed pings allowed",
+            self.max_missed_pings.to_string(),
+            other.max_missed_pings.to_string(),
+        );
+
+        differences
+    }
+
+    /// Width `player` is drawn and collides with, its avatar's size or the player size of the rules
+    pub fn size_of(&self, player: &Player) -> f32 {
+        player.avatar.size.unwrap_or(self.player_size)
+    }
+
+    /// Whether a player with `avatar` fits into the world
+    pub fn fits(&self, avatar: &Avatar) -> bool {
+        let size = self.bounds.size();
+
+        avatar
+            .size
+            .is_none_or(|s| s.is_finite() && s > 0.0 && s < size.x && s < size.y)
+    }
+
+    /// Keep the player inside the world according to the world mode
+    pub fn apply_world_bounds(&self, player: &mut Player, mode: WorldMode) {
+        match mode {
+            WorldMode::Bounded => self.clamp_player_to_bounds(player),
+            WorldMode::Wrap => self.wrap_player_around_bounds(player),
+        }
+    }
+
+    fn clamp_player_to_bounds(&self, player: &mut Player) {
+        let half_size = self.size_of(player) / 2.0;
+
+        player.pos.x = player
+            .pos
+            .x
+            .clamp(self.bounds.min_x + half_size, self.bounds.max_x - half_size);
+
+        player.pos.y = player
+            .pos
+            .y
+            .clamp(self.bounds.min_y + half_size, self.bounds.max_y - half_size);
+    }
+
+    fn wrap_player_around_bounds(&self, player: &mut Player) {
+        let size = self.bounds.size();
+
+        player.pos.x = self.bounds.min_x + (player.pos.x - self.bounds.min_x).rem_euclid(size.x);
+        player.pos.y = self.bounds.min_y + (player.pos.y - self.bounds.min_y).rem_euclid(size.y);
+    }
+
+    /// Offset from `from` to `to`. In a wrapping world this takes the shortest way, which may
+    /// cross the seam.
+    pub fn world_delta(
+        &self,
+        from: Vector2<f32>,
+        to: Vector2<f32>,
+        mode: WorldMode,
+    ) -> Vector2<f32> {
+        let delta = to - from;
+
+        match mode {
+            WorldMode::Bounded => delta,
+            WorldMode::Wrap => {
+                let size = self.bounds.size();
+
+                Vector2::new(
+                    delta.x - (delta.x / size.x).round() * size.x,
+                    delta.y - (delta.y / size.y).round() * size.y,
+                )
+            }
+        }
+    }
+}
+
+fn format_bounds(bounds: &WorldBounds) -> String {
+    format!(
+        "{},{} to {},{}",
+        bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y
+    )
+}
+
+/// Pings missed over `timeout` of silence, at least one. Turns a timeout into a miss count for
+/// older peers that only know the connection timeout.
+pub fn missed_pings_within(timeout: Duration, ping_interval: Duration) -> u32 {
+    timeout
+        .as_nanos()
+        .div_ceil(ping_interval.as_nanos().max(1))
+        .clamp(1, u32::MAX as u128) as u32
+}
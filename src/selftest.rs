@@ -0,0 +1,190 @@
+use std::{
+    error::Error,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use cgmath::{vec2, InnerSpace};
+use game_server_sample::{
+    globals, identity::Identity, simulation, terrain::TerrainMap, ClientId, Player, PlayerId,
+};
+
+use crate::{
+    client::{self, ClientConfig, ClientEvent, ClientSession},
+    server::{ServerBuilder, ServerHandle},
+};
+
+// How long the second client may take to see the first one leave
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Distance the moving player has to cover in the watching client's view
+const MIN_REPLICATED_DISTANCE: f32 = 10.0;
+
+/// Host a server on a free port, join it with two clients over UDP, move one of them and check
+/// the other one sees it. For packagers and players checking their setup, any failure makes the
+/// process exit nonzero.
+pub fn run(duration: Duration) -> Result<(), Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()?;
+
+    println!("Running selftest for {} s", duration.as_secs_f32());
+
+    match rt.block_on(check(duration)) {
+        Ok(()) => {
+            println!("Selftest passed");
+            Ok(())
+        }
+        Err(e) => Err(format!("Selftest failed: {e}").into()),
+    }
+}
+
+async fn check(duration: Duration) -> Result<(), String> {
+    let server = ServerBuilder::new()
+        .start()
+        .await
+        .map_err(|e| format!("server did not start: {e}"))?;
+
+    // Only the selftest's own lines, the server log is printed when something fails
+    server.set_log_echo(false);
+
+    let result = check_with_server(&server, duration).await;
+    if result.is_err() {
+        for line in server.status().await.log_lines {
+            println!("  server: {line}");
+        }
+    }
+    server.shutdown().await;
+
+    result
+}
+
+async fn check_with_server(server: &ServerHandle, duration: Duration) -> Result<(), String> {
+    let port = server
+        .local_addr()
+        .map_err(|e| format!("server socket has no address: {e}"))?
+        .port();
+    passed(format!("server listening on port {port}"));
+
+    // Over the loopback socket rather than in memory, so a firewall in the way shows up
+    let server_address = SocketAddr::from((Ipv4Addr::LOCALHOST, port)).to_string();
+    let mut mover = join(&server_address, "client 1").await?;
+    let mut watcher = join(&server_address, "client 2").await?;
+
+    let map = TerrainMap::builtin();
+    let world_mode = mover.world_mode();
+    let rules = mover.rules();
+    let mut player = mover.get_session_player_data();
+    let start = player.pos;
+
+    // Towards the farther side of the world, so the bounds don't stop the player early
+    let direction = vec2(if start.x > 0.0 { -1.0 } else { 1.0 }, 0.0);
+    let mut seen: Option<Player> = None;
+    let mut step: u32 = 0;
+
+    let mut tick =
+        tokio::time::interval(Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC));
+    let started = Instant::now();
+
+    while started.elapsed() < duration {
+        tick.tick().await;
+        step = step.wrapping_add(1);
+
+        simulation::step_player(&mut player, direction, &map, world_mode, &rules);
+        mover.send_pos(&player, step);
+
+        drain(&mut mover);
+        for event in drain(&mut watcher) {
+            if let ClientEvent::PlayerUpdated(replicated) = event {
+                if replicated.id == player.id {
+                    seen = Some(replicated);
+                }
+            }
+        }
+
+        if !mover.is_server_alive() || !watcher.is_server_alive() {
+            return Err(String::from("connection to the server was lost"));
+        }
+    }
+
+    let seen = seen.ok_or("client 2 never received client 1's player")?;
+    let moved = (seen.pos - start).magnitude();
+    if moved < MIN_REPLICATED_DISTANCE {
+        return Err(format!(
+            "client 1 moved {:.0} units but client 2 saw only {moved:.0}",
+            (player.pos - start).magnitude()
+        ));
+    }
+    passed(format!(
+        "movement replicated, client 2 saw {moved:.0} units"
+    ));
+
+    mover.leave_server(player.id);
+    wait_for_leave(&mut watcher, player.id).await?;
+    passed(String::from("client 2 saw client 1 leave"));
+
+    Ok(())
+}
+
+async fn join(server_address: &str, name: &str) -> Result<ClientSession, String> {
+    // Throwaway identities, the selftest must not show up as the player's own
+    let config = ClientConfig {
+        client_id: ClientId::random(),
+        identity: Identity::generate(),
+        relay: None,
+        check_determinism: false,
+        invite_code: None,
+        in_process_host: false,
+        interp_delay: client::DEFAULT_INTERP_DELAY,
+    };
+
+    let started = Instant::now();
+    let session = ClientSession::new(server_address.to_string(), &config)
+        .await
+        .map_err(|e| format!("{name} could not join: {e}"))?;
+
+    passed(format!(
+        "{name} joined as player {} in {} ms",
+        session.get_session_player_data().id,
+        started.elapsed().as_millis()
+    ));
+
+    Ok(session)
+}
+
+async fn wait_for_leave(watcher: &mut ClientSession, player_id: PlayerId) -> Result<(), String> {
+    let started = Instant::now();
+
+    while started.elapsed() < LEAVE_TIMEOUT {
+        let left = drain(watcher)
+            .iter()
+            .any(|event| matches!(event, ClientEvent::PlayerLeft(id) if *id == player_id));
+        if left {
+            return Ok(());
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    Err(String::from("client 2 never saw client 1 leave"))
+}
+
+// Everything the server sent since the last call
+fn drain(session: &mut ClientSession) -> Vec<ClientEvent> {
+    std::iter::from_fn(|| session.poll_event()).collect()
+}
+
+fn passed(step: String) {
+    println!("  ok  {step}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn selftest_passes() {
+        check(Duration::from_secs(1)).await.unwrap();
+    }
+}
@@ -1,302 +1,948 @@
 use std::{
     error::Error,
-    net::SocketAddr,
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use rand::seq::SliceRandom;
+
+use game_server_sample::{
+    globals, identity::PublicKey, rules::GameRules, terrain::TerrainMap, Palette, WorldMode,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, watch},
+};
+
+use crate::{
+    anticheat::CheatConfig,
+    daemon::RotatingLogFile,
+    transport::{LocalTransport, LOCAL_QUEUE_LEN},
+};
+
+use self::{
+    broadcast::broadcast_sender,
+    context::{BroadcastMessage, ServerContext},
+    listener::{
+        bind_sockets, listen_handler, relay_registration, spawn_receive_workers, worker_index,
+        DatagramSender,
     },
+    metrics::metrics_sampler,
 };
 
-use cgmath::Vector2;
-use tokio::{net::UdpSocket, sync::Mutex};
+mod admin;
+mod broadcast;
+mod context;
+mod listener;
+mod metrics;
+mod simulation;
 
-use egui::ahash::{HashMap, HashMapExt};
-use game_server_sample::{generate_color, globals, Player, PlayerId};
-use tokio::sync::mpsc;
+pub use admin::{PlayerStatus, SavedPlayer, ServerStatus, WorldSnapshot};
+pub use metrics::{metrics_csv, MetricsSample};
 
-use crate::message::{self, Message};
+// Ports tried with auto port, the requested one included
+const AUTO_PORT_ATTEMPTS: u16 = 20;
 
-/////////////////////////////////////////////
+// Addresses of clients joined in memory, RFC 6666 discard-only prefix 100::/64
+const LOCAL_CLIENT_PREFIX: u128 = 0x0100 << 112;
 
-// Store user connected in a hashmap
-type PlayerMap = HashMap<SocketAddr, Player>;
+// Invite codes leave out letters and digits that are easy to mix up when read out loud
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LEN: usize = 6;
 
-// Define message and channel
-struct BroadcastMessage {
-    msg: Vec<u8>,
-    excluded_client: Option<SocketAddr>,
-}
-type ChannelSender = mpsc::UnboundedSender<BroadcastMessage>;
-type ChannelReceiver = mpsc::UnboundedReceiver<BroadcastMessage>;
-
-// Define Server
-struct ServerContext {
-    server_socket: UdpSocket,
-    broadcast_tx: ChannelSender,
-    players: Mutex<PlayerMap>,
-    player_id_counter: AtomicU64,
-}
+/// Default outgoing bandwidth cap per client in bytes per second
+pub const DEFAULT_BANDWIDTH_LIMIT: u32 = 20 * 1024;
 
-impl ServerContext {
-    fn new(server_socket: UdpSocket, broadcast_tx: ChannelSender) -> Self {
-        Self {
-            server_socket,
-            broadcast_tx,
-            players: Mutex::new(PlayerMap::new()),
-            player_id_counter: AtomicU64::new(1),
-        }
-    }
-}
+pub const DEFAULT_MAX_PLAYERS: usize = 16;
+
+/// Server settings chosen by whoever hosts the server
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub palette: Palette,
+
+    /// Outgoing bytes per second each client may receive, `None` for no limit
+    pub bandwidth_limit: Option<u32>,
+
+    pub world_mode: WorldMode,
+
+    /// Terrain sent to every client when joining
+    pub map: TerrainMap,
+
+    /// Sent to every client when joining. The movement settings are also used for the speed
+    /// check, the tick rate is the highest simulation rate, rounded down to one of
+    /// [`globals::SERVER_TICK_RATES`]. The server steps down from it when ticks take too long.
+    pub rules: GameRules,
+
+    pub cheat: CheatConfig,
 
-//////////////////////////////////////////////////////
+    pub duplicate_identity: DuplicateIdentity,
 
-// Network method
+    /// Relay to register with, so clients that can't reach the server directly can join
+    /// through it
+    pub relay: Option<String>,
 
-// Receive message from client
-async fn listen_handler(context: Arc<ServerContext>) {
-    loop {
-        let mut buf = [0u8; 32];
-        // NOTE: consider using non-blocking I/O UDP - match case
-        let (len, client) = context.server_socket.recv_from(&mut buf).await.unwrap();
+    /// Also forward traffic for other hosts and their players, see [`RelayService`]
+    pub relay_service: bool,
 
-        if len > 1 {
-            let request_msg = String::from_utf8_lossy(&buf[..len]).to_string();
+    /// JSON Lines file every finished player session is appended to, for later analysis
+    pub session_summaries: Option<PathBuf>,
 
-            tokio::spawn(process_client_message(context.clone(), client, request_msg));
+    /// Kick players who haven't moved for this long, `None` to let them idle forever
+    pub idle_kick: Option<Duration>,
+
+    /// Message of the day, e.g. the server rules, shown to players as they join. The admin can
+    /// change it while the server runs.
+    pub motd: Option<String>,
+
+    /// Private session: only handshakes with this code get in, whatever its case
+    pub invite_code: Option<String>,
+
+    /// UDP sockets sharing the port through `SO_REUSEPORT`, each with its own receive task.
+    /// The kernel spreads the clients over them. More than one needs a Unix system.
+    pub sockets: usize,
+
+    /// Public key of the player hosting the server, told to status queries so friends can tell
+    /// it is them hosting
+    pub host_key: Option<PublicKey>,
+
+    /// Players beyond this are turned away. Players already in keep their place when moving to
+    /// a new address.
+    pub max_players: usize,
+}
+
+/// What to do when a client connects with the identity of a player that is already connected
+/// from another address
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicateIdentity {
+    /// Move the existing player over to the new address, e.g. after a NAT rebind
+    #[default]
+    Migrate,
+
+    /// Turn the new connection away
+    Refuse,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            palette: Palette::default(),
+            bandwidth_limit: Some(DEFAULT_BANDWIDTH_LIMIT),
+            world_mode: WorldMode::default(),
+            map: TerrainMap::builtin(),
+            rules: GameRules::default(),
+            cheat: CheatConfig::default(),
+            duplicate_identity: DuplicateIdentity::default(),
+            relay: None,
+            relay_service: false,
+            session_summaries: None,
+            idle_kick: None,
+            motd: None,
+            invite_code: None,
+            sockets: 1,
+            host_key: None,
+            max_players: DEFAULT_MAX_PLAYERS,
         }
     }
 }
 
-// Sender loop to response to all players except the player who owning the broadcast message
-async fn broadcast_sender(context: Arc<ServerContext>, mut broadcast_rx: ChannelReceiver) {
-    while let Some(broadcast) = broadcast_rx.recv().await {
-        message::trace(format!(
-            "Broadcasting: {}",
-            String::from_utf8_lossy(&broadcast.msg)
-        ));
+/// Handle to a running server for the process hosting it. Dropping the handle does not stop the
+/// server.
+#[derive(Clone)]
+pub struct ServerHandle {
+    context: Arc<ServerContext>,
+    workers: Vec<DatagramSender>,
+}
 
-        let players = context.players.lock().await;
+impl ServerHandle {
+    /// In-memory link for a client of the hosting process, which skips the loopback socket. The
+    /// client shows up under an address no UDP peer can have, one of the discard-only prefix
+    /// `100::/64` numbered so that none is ever handed out twice.
+    pub fn connect_local(&self) -> LocalTransport {
+        let (to_server, mut from_client) = mpsc::channel::<Vec<u8>>(LOCAL_QUEUE_LEN);
+        let (to_client, from_server) = mpsc::channel(LOCAL_QUEUE_LEN);
+
+        let number = self
+            .context
+            .local_client_counter
+            .fetch_add(1, Ordering::Relaxed);
+        let client =
+            SocketAddr::from((Ipv6Addr::from(LOCAL_CLIENT_PREFIX | u128::from(number)), 0));
+        self.context
+            .local_clients
+            .lock()
+            .unwrap()
+            .insert(client, to_client);
+
+        let context = self.context.clone();
+        let worker = self.workers[worker_index(&client)].clone();
+        tokio::spawn(async move {
+            while let Some(datagram) = from_client.recv().await {
+                if datagram.len() <= 1 {
+                    continue;
+                }
 
-        for (client_addr, _) in players.iter() {
-            if Some(*client_addr) != broadcast.excluded_client {
-                if let Err(e) = context
-                    .server_socket
-                    .send_to(&broadcast.msg, client_addr)
+                // Waits for the worker rather than dropping, the host's own input matters most
+                context.receive_queue_depth.fetch_add(1, Ordering::Relaxed);
+                if worker
+                    .send((client, datagram, Instant::now()))
                     .await
+                    .is_err()
                 {
-                    eprintln!("Failed to broadcast: {:?}", e);
+                    context.receive_queue_depth.fetch_sub(1, Ordering::Relaxed);
+                    break;
                 }
             }
-        }
+
+            context.local_clients.lock().unwrap().remove(&client);
+        });
+
+        LocalTransport::new(to_server, from_server)
+    }
+
+    /// Number of players, changing as they join and leave
+    pub fn player_count(&self) -> watch::Receiver<usize> {
+        self.context.player_count.subscribe()
+    }
+
+    pub fn max_players(&self) -> usize {
+        self.context.config.max_players
+    }
+
+    /// Address the first socket listens on, with the actual port when started on port 0
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.context.server_socket().local_addr()
     }
 }
 
-// Healthcheck for server
-async fn ping_sender(context: Arc<ServerContext>) {
-    let mut interval = tokio::time::interval(globals::PING_INTERVAL_MS);
+/// Fresh code for a private session, short enough to read out to friends
+pub fn generate_invite_code() -> String {
+    let mut rng = rand::thread_rng();
 
-    loop {
-        interval.tick().await;
-        let _ = context.broadcast_tx.send(BroadcastMessage {
-            msg: Message::Ping.serialize().into_bytes(),
-            excluded_client: None,
-        });
+    (0..INVITE_CODE_LEN)
+        .map(|_| *INVITE_CODE_ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect()
+}
+
+/// Invite codes travel as a message field, plain letters and digits keep them clear of the
+/// separator
+pub fn is_valid_invite_code(code: &str) -> bool {
+    !code.is_empty() && code.len() <= 16 && code.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Starting failed because the port could not be bound, as opposed to a bad log file, world or
+/// relay
+#[derive(Debug)]
+pub struct BindFailure(String);
+
+impl std::fmt::Display for BindFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
-/// Authoritative game update logic simulation - Game loop
-///
-/// Required fixed processing, because timing has to be synchronized accross all the connected
-/// clients. A server simulation loop does not need to play "catch-up" like a local game loop does
-/// because there no point in sending stale state
-async fn simulation_handler(context: Arc<ServerContext>) {
-    let desired_frame_duration =
-        std::time::Duration::from_secs_f32(globals::FIXED_UPDATE_TIMESTEP_SEC);
+impl Error for BindFailure {}
 
-    let mut interval = tokio::time::interval(desired_frame_duration);
+pub type ServerSessionResult = Result<ServerHandle, Box<dyn Error + Send + Sync>>;
 
-    interval.tick().await;
+/// Sets up a server and starts it, for the dedicated server, for servers hosted from the GUI and
+/// for anything else embedding one
+#[derive(Clone, Default)]
+pub struct ServerBuilder {
+    port: u16,
+    config: ServerConfig,
 
-    loop {
-        let current_time = std::time::Instant::now();
+    /// Try the following ports when the requested one is taken
+    auto_port: bool,
 
-        // Add new scope here so when finish the lock will be release
-        {
-            let mut players = context.players.lock().await;
-            for (client_addr, player) in players.iter_mut() {
-                // Bound checking
-                globals::clamp_player_to_bounds(player);
+    /// Saved world picked up before the first client gets in
+    world: Option<WorldSnapshot>,
+    log_file: Option<PathBuf>,
+}
 
-                // Gameplay state replication
-                let msg = Message::Replicate(*player).serialize();
+impl ServerBuilder {
+    /// Default settings on a free port
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-                let _ = context.broadcast_tx.send(BroadcastMessage {
-                    msg: msg.into_bytes(),
-                    excluded_client: Some(*client_addr),
-                });
-            }
+    /// Replaces all settings made so far except the port and persistence
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// UDP port to listen on, 0 picks a free one. See [`ServerHandle::local_addr`].
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// When the port is taken, take the first free one of the following ports instead. See
+    /// [`ServerHandle::local_addr`] for the one bound.
+    pub fn auto_port(mut self, auto_port: bool) -> Self {
+        self.auto_port = auto_port;
+        self
+    }
+
+    /// Highest simulation rate, see [`ServerConfig::rules`]
+    pub fn tick_rate(mut self, tick_rate: u32) -> Self {
+        self.config.rules.tick_rate = tick_rate;
+        self
+    }
+
+    pub fn max_players(mut self, max_players: usize) -> Self {
+        self.config.max_players = max_players;
+        self
+    }
+
+    /// UDP sockets sharing the port, see [`ServerConfig::sockets`]
+    pub fn sockets(mut self, sockets: usize) -> Self {
+        self.config.sockets = sockets;
+        self
+    }
+
+    /// Relay to register with, `None` for clients reaching the server directly only
+    pub fn relay(mut self, relay: Option<String>) -> Self {
+        self.config.relay = relay;
+        self
+    }
+
+    /// Private session, see [`ServerConfig::invite_code`]
+    pub fn invite_code(mut self, invite_code: Option<String>) -> Self {
+        self.config.invite_code = invite_code;
+        self
+    }
+
+    pub fn host_key(mut self, host_key: PublicKey) -> Self {
+        self.config.host_key = Some(host_key);
+        self
+    }
+
+    /// Pick up a saved world, see [`ServerHandle::load_world`]
+    pub fn world(mut self, world: WorldSnapshot) -> Self {
+        self.world = Some(world);
+        self
+    }
+
+    /// Append the server log to this file from the first line on, rotating it as it grows
+    pub fn log_file(mut self, path: PathBuf) -> Self {
+        self.log_file = Some(path);
+        self
+    }
+
+    /// Bind the port and start serving. Players join through the returned handle or over UDP.
+    pub async fn start(self) -> ServerSessionResult {
+        let log_file = self
+            .log_file
+            .as_deref()
+            .map(RotatingLogFile::open)
+            .transpose()?;
+
+        let sockets = self.bind()?;
+        let (broadcast_tx, broadcast_rx) = mpsc::unbounded_channel::<BroadcastMessage>();
+
+        let relay_addr = match &self.config.relay {
+            Some(relay) => Some(lookup_relay(relay).await?),
+            None => None,
+        };
+
+        let context = Arc::new(ServerContext::new(
+            sockets,
+            broadcast_tx.clone(),
+            self.config,
+            relay_addr,
+        ));
+
+        if let Some(relay_addr) = relay_addr {
+            tokio::spawn(relay_registration(context.clone(), relay_addr));
         }
 
-        // Calcualte the time has passed, if the update happendes too fast then the
-        // tick will wait until the next tick to continue the loop
-        let elapsed_time = current_time.elapsed();
-        if elapsed_time < desired_frame_duration {
-            interval.tick().await;
+        // One receive task per socket, feeding the same workers
+        let workers = spawn_receive_workers(&context);
+        for socket_index in 0..context.sockets.len() {
+            tokio::spawn(listen_handler(
+                context.clone(),
+                socket_index,
+                workers.clone(),
+            ));
         }
-    }
-}
 
-//////////////////////////////////////////////
+        // Broadcase message to other client
+        tokio::spawn(broadcast_sender(context.clone(), broadcast_rx));
+        tokio::spawn(metrics_sampler(context.clone()));
 
-// Proccessing client request
-async fn process_client_message(context: Arc<ServerContext>, client: SocketAddr, msg: String) {
-    // If trace enable then log the trace
-    message::trace(format!("Received: {msg}"));
+        let server = ServerHandle { context, workers };
 
-    match Message::deserialize(&msg) {
-        Ok(Message::Handshake) => {
-            if let Err(e) = accept_client(context.clone(), client).await {
-                eprintln!("Error accepting client {}: {}", client, e);
-            }
+        if let Some(log_file) = log_file {
+            server.set_log_file(log_file).await;
         }
 
-        Ok(Message::Position(player_id, pos)) => {
-            if let Err(e) = update_position(context, client, player_id, pos).await {
-                eprintln!("Error updating player position {}: {}", player_id, e);
-            }
+        // Nobody can be connected yet, so loading can't be refused
+        if let Some(world) = self.world {
+            let waiting = server.load_world(world).await?;
+            server
+                .log(format!("Loaded world, {waiting} players can rejoin"))
+                .await;
         }
 
-        Ok(Message::Leave(player_id)) => {
-            if let Err(e) = drop_player(context.clone(), client, player_id).await {
-                eprintln!("Error dropping player {}: {}", player_id, e);
+        Ok(server)
+    }
+
+    // Bind the requested port, or with auto port the first free one from there on
+    fn bind(&self) -> Result<Vec<UdpSocket>, BindFailure> {
+        let last = if self.auto_port && self.port != 0 {
+            self.port.saturating_add(AUTO_PORT_ATTEMPTS - 1)
+        } else {
+            self.port
+        };
+
+        let mut in_use = None;
+        for port in self.port..=last {
+            match bind_sockets(port, self.config.sockets) {
+                Ok(sockets) => return Ok(sockets),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => in_use = Some(port),
+                Err(e) => return Err(BindFailure(format!("Failed to bind UDP port {port}: {e}"))),
             }
         }
 
-        _ => (),
+        Err(BindFailure(match in_use {
+            Some(last) if last != self.port => {
+                format!("UDP ports {} to {last} are all in use", self.port)
+            }
+            _ => format!("UDP port {} is already in use", self.port),
+        }))
     }
 }
 
-// Accept client connect
-async fn accept_client(
-    context: Arc<ServerContext>,
-    client: SocketAddr,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut players = context.players.lock().await;
-
-    let ack_msg: String;
-    if let Some(existing_player) = players.get(&client) {
-        // Getting multiple handshakes from and sending out multiple ACK for the same
-        // client is not a problem, that just means that previous ACK was dropped, so the
-        // client retried the HANDSHAKE. Server just resends ACK with same player info that
-        // was already registered as response to new HANDSHAKE. It is made sure here not to
-        // accidentally add the same player multiple times, because that would lead to
-        // "Player 3 joined, Player
-        // 4 joined, Player 5 joined" bug for each accepted HANDSHAKE from the same client.
-        ack_msg = Message::Ack(existing_player.id, existing_player.color).serialize();
-    } else {
-        let new_player = Player::new(
-            context.player_id_counter.fetch_add(1, Ordering::SeqCst),
-            generate_color(),
-        );
-
-        players.insert(client, new_player);
-
-        // First time game startup: Start sending PING message to everyone and start
-        // the game simulation when the first player
-        // connected
-
-        if players.len() == 1 {
-
-            // Ping the server only the first time to check if the server is working
-            // or not
-            tokio::spawn(ping_sender(context.clone()));
-
-            // This onl created one when the server start with 1 user first
-            // connected, after that it just run and update the state to anyone
-            // inside the server
-            // This is the place where keep update the game state to everyone inside
-            // the game with 60fps
-            tokio::spawn(simulation_handler(context.clone()));
+// Relay address as configured, a lookup that hangs doesn't hold the server up forever
+async fn lookup_relay(relay: &str) -> Result<SocketAddr, String> {
+    let lookup = tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
+        tokio::net::lookup_host(relay)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))
+    });
+
+    match lookup.await {
+        Ok(Ok(addr)) => Ok(addr),
+        Ok(Err(e)) => Err(format!("Relay address {relay} did not resolve: {e}")),
+        Err(_) => Err(format!(
+            "Relay address {relay} did not resolve within {} seconds",
+            globals::CONNECTION_TIMEOUT_SEC.as_secs()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use cgmath::Vector2;
+    use game_server_sample::{
+        codec::Codec, identity::Identity, simulation::MovementConfig, Avatar, ClientId, Shape,
+        WorldBounds,
+    };
+
+    use super::*;
+    use crate::{
+        client::{self, ClientConfig, ClientEvent, ClientSession},
+        message::Message,
+    };
+
+    fn client_config() -> ClientConfig {
+        ClientConfig {
+            client_id: ClientId::random(),
+            identity: Identity::generate(),
+            relay: None,
+            check_determinism: false,
+            invite_code: None,
+            in_process_host: true,
+            interp_delay: client::DEFAULT_INTERP_DELAY,
         }
+    }
 
-        ack_msg = Message::Ack(new_player.id, new_player.color).serialize();
+    #[tokio::test]
+    async fn host_joins_own_server_in_memory() {
+        let server = ServerBuilder::new().start().await.unwrap();
+
+        let session = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+
+        assert!(session.get_session_player_data().id > 0);
+        assert_eq!(server.status().await.players.len(), 1);
     }
 
-    // Send ACK message
-    context
-        .server_socket
-        .send_to(ack_msg.as_bytes(), client)
-        .await?;
+    #[tokio::test]
+    async fn local_clients_keep_apart_past_u16_many() {
+        let server = ServerBuilder::new().start().await.unwrap();
+
+        let _first = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+        server
+            .context
+            .local_client_counter
+            .fetch_add(u64::from(u16::MAX), Ordering::Relaxed);
+        let _second = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+
+        assert_eq!(server.status().await.players.len(), 2);
+    }
 
-    message::trace(format!("Sent: {ack_msg}"));
+    #[tokio::test]
+    async fn full_server_turns_new_players_away() {
+        let server = ServerBuilder::new().max_players(1).start().await.unwrap();
+        let player_count = server.player_count();
 
-    Ok(())
-}
+        let _first = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+        let second = ClientSession::local(server.connect_local(), &client_config()).await;
 
-// Update user position if they moved
-async fn update_position(
-    context: Arc<ServerContext>,
-    client: SocketAddr,
-    player_id: PlayerId,
-    new_pos: Vector2<f32>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    if let Some(player) = context.players.lock().await.get_mut(&client) {
-        if player_id != player.id {
-            return Ok(());
+        assert!(second.is_err_and(|e| e.to_string().contains("full")));
+        assert_eq!(*player_count.borrow(), 1);
+    }
+
+    #[tokio::test]
+    async fn taken_port_is_reported_or_skipped_with_auto_port() {
+        let taken = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let refused = ServerBuilder::new().port(port).start().await;
+        assert!(refused.is_err_and(|e| e.to_string().contains("already in use")));
+
+        let server = ServerBuilder::new()
+            .port(port)
+            .auto_port(true)
+            .start()
+            .await
+            .unwrap();
+        assert!(server.local_addr().unwrap().port() > port);
+    }
+
+    #[tokio::test]
+    async fn highest_port_is_bound_and_auto_port_stops_there() {
+        let server = ServerBuilder::new().port(u16::MAX).start().await.unwrap();
+        assert_eq!(server.local_addr().unwrap().port(), u16::MAX);
+
+        let refused = ServerBuilder::new()
+            .port(u16::MAX)
+            .auto_port(true)
+            .start()
+            .await;
+        assert!(refused.is_err_and(|e| e.to_string().contains("already in use")));
+    }
+
+    #[tokio::test]
+    async fn joining_clients_get_the_server_rules() {
+        let rules = GameRules {
+            bounds: WorldBounds {
+                min_x: -300.0,
+                min_y: -200.0,
+                max_x: 300.0,
+                max_y: 200.0,
+            },
+            movement: MovementConfig {
+                speed: 14.0,
+                ..MovementConfig::default()
+            },
+            ..GameRules::default()
+        };
+        let server = ServerBuilder::new()
+            .config(ServerConfig {
+                rules,
+                ..ServerConfig::default()
+            })
+            .start()
+            .await
+            .unwrap();
+        let session = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+
+        assert_eq!(session.rules(), rules);
+    }
+
+    #[tokio::test]
+    async fn avatars_are_replicated_to_everyone_and_their_own_player() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let mut sessions = [
+            ClientSession::local(server.connect_local(), &client_config())
+                .await
+                .unwrap(),
+            ClientSession::local(server.connect_local(), &client_config())
+                .await
+                .unwrap(),
+        ];
+        let id = sessions[0].get_session_player_data().id;
+
+        let avatar = Avatar {
+            shape: Shape::Circle,
+            size: Some(40.0),
+        };
+        server.set_avatar(id, avatar).await.unwrap();
+
+        for session in &mut sessions {
+            tokio::time::timeout(Duration::from_secs(2), async {
+                loop {
+                    match session.poll_event() {
+                        Some(ClientEvent::PlayerUpdated(player))
+                            if player.id == id && player.avatar == avatar =>
+                        {
+                            break
+                        }
+                        Some(_) => {}
+                        None => tokio::time::sleep(Duration::from_millis(5)).await,
+                    }
+                }
+            })
+            .await
+            .unwrap();
         }
-        player.pos.x = new_pos.x;
-        player.pos.y = new_pos.y;
+
+        let too_big = Avatar {
+            size: Some(1e6),
+            ..avatar
+        };
+        assert!(server.set_avatar(id, too_big).await.is_err());
+        assert!(server.set_avatar(id + 100, avatar).await.is_err());
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn lower_tick_rate_is_in_the_rules_for_joining_clients() {
+        let server = ServerBuilder::new().tick_rate(45).start().await.unwrap();
+        let session = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
 
-// Remove client when disconnect
-async fn drop_player(
-    context: Arc<ServerContext>,
-    client: SocketAddr,
-    player_id: PlayerId,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut players = context.players.lock().await;
-    players.remove(&client);
+        assert_eq!(session.rules().tick_rate, 30);
+        assert_eq!(session.server_tick_rate(), 30);
+    }
 
-    println!("Player {player_id} left the server");
+    #[tokio::test]
+    async fn unknown_session_is_asked_to_join_again() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        // Like a client still playing on from before a restart
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stale = Message::Position(7, Vector2::new(10.0, 20.0), Some(300));
+        socket
+            .send_to(stale.serialize().as_bytes(), server_addr)
+            .await
+            .unwrap();
+
+        let mut buf = [0; 64];
+        let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            Message::deserialize(std::str::from_utf8(&buf[..len]).unwrap()),
+            Ok(Message::Resync)
+        ));
+    }
 
-    context.broadcast_tx.send(BroadcastMessage {
-        msg: Message::Leave(player_id).serialize().into_bytes(),
-        excluded_client: Some(client),
-    })?;
+    #[tokio::test]
+    async fn retried_handshake_gets_the_same_ack() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let handshake = Message::Handshake(Some(ClientId::random()), None, None, None);
+        let mut acks = Vec::new();
+
+        for _ in 0..2 {
+            socket
+                .send_to(handshake.serialize().as_bytes(), server_addr)
+                .await
+                .unwrap();
+
+            // Pings and replication of the joined player come in between
+            let mut buf = [0; 2048];
+            loop {
+                let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+                    .await
+                    .expect("Handshake was not answered with an ACK")
+                    .unwrap();
+                let response = std::str::from_utf8(&buf[..len]).unwrap();
+                if let Ok(Message::Ack(player_id, _, _, token, _)) = Message::deserialize(response)
+                {
+                    acks.push((player_id, token));
+                    break;
+                }
+            }
+        }
 
-    Ok(())
-}
+        assert_eq!(acks[0], acks[1]);
+        assert_eq!(server.status().await.players.len(), 1);
+    }
 
-///////////////////////////////////////////////////
+    #[tokio::test]
+    async fn binary_and_text_clients_play_together() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let mut session = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+        let binary_id = session.get_session_player_data().id;
+
+        // The host's own client offers binary and the server went along
+        assert_eq!(server.context.binary_clients.lock().unwrap().len(), 1);
+
+        // A client from before there was a binary codec
+        let server_addr =
+            SocketAddr::from((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port()));
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let handshake = Message::Handshake(Some(ClientId::random()), None, None, None);
+        socket
+            .send_to(handshake.serialize().as_bytes(), server_addr)
+            .await
+            .unwrap();
+
+        async fn recv_text(socket: &UdpSocket) -> Message {
+            let mut buf = [0; 2048];
+            let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+                .await
+                .expect("Text client got nothing")
+                .unwrap();
+            let text = std::str::from_utf8(&buf[..len]).expect("Binary sent to a text client");
+            Message::deserialize(text).unwrap()
+        }
 
-pub type ServerSessionResult = Result<(), Box<dyn Error + Send + Sync>>;
-pub async fn start_server(port: u16) -> ServerSessionResult {
-    match tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
-        let addr = format!("0.0.0.0:{port}");
+        let text_id = loop {
+            if let Message::Ack(player_id, ..) = recv_text(&socket).await {
+                break player_id;
+            }
+        };
 
-        let server_socket = UdpSocket::bind(&addr).await?;
-        let (broadcast_tx, broadcast_rx) = mpsc::unbounded_channel::<BroadcastMessage>();
+        session.send_chat(binary_id, "sent as binary");
+        loop {
+            if let Message::Chat(player_id, text) = recv_text(&socket).await {
+                assert_eq!((player_id, text.as_str()), (binary_id, "sent as binary"));
+                break;
+            }
+        }
+
+        let chat = Message::Chat(text_id, String::from("sent as text"));
+        socket
+            .send_to(chat.serialize().as_bytes(), server_addr)
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                match session.poll_event() {
+                    Some(ClientEvent::Chat(player_id, text)) if player_id == text_id => {
+                        assert_eq!(text, "sent as text");
+                        break;
+                    }
+                    Some(_) => {}
+                    None => tokio::time::sleep(Duration::from_millis(5)).await,
+                }
+            }
+        })
+        .await
+        .expect("Binary client did not get the text client's chat");
+    }
+
+    #[tokio::test]
+    async fn relayed_client_is_no_longer_relayed_after_leaving() {
+        let relay = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server = ServerBuilder::new()
+            .relay(Some(relay.local_addr().unwrap().to_string()))
+            .start()
+            .await
+            .unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port()));
+        let source = SocketAddr::from(([203, 0, 113, 7], 40000));
+
+        let from_source = |msg: Message| {
+            let payload = msg.serialize();
+            crate::relay::RelayPacket::From {
+                source,
+                payload: &payload,
+            }
+            .serialize()
+        };
+
+        // Offered, but the relay only passes text on
+        let handshake = from_source(Message::Handshake(
+            Some(ClientId::random()),
+            None,
+            None,
+            Some(Codec::Binary),
+        ));
+        relay
+            .send_to(handshake.as_bytes(), server_addr)
+            .await
+            .unwrap();
+
+        // The registration comes first, the ACK is wrapped for the relay to pass on
+        let mut buf = [0; 2048];
+        let player_id = loop {
+            let len = tokio::time::timeout(Duration::from_secs(2), relay.recv(&mut buf))
+                .await
+                .expect("Handshake was not answered through the relay")
+                .unwrap();
+            let packet = std::str::from_utf8(&buf[..len]).unwrap();
+            if let Some(crate::relay::RelayPacket::To { payload, .. }) =
+                crate::relay::RelayPacket::parse(packet)
+            {
+                if let Ok(Message::Ack(player_id, ..)) = Message::deserialize(payload) {
+                    break player_id;
+                }
+            }
+        };
+        assert!(server.context.is_relayed(&source));
 
-        let context = Arc::new(ServerContext::new(server_socket, broadcast_tx.clone()));
+        let leave = from_source(Message::Leave(player_id));
+        relay.send_to(leave.as_bytes(), server_addr).await.unwrap();
 
-        // Spawn task for listen message
-        tokio::spawn(listen_handler(context.clone()));
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while server.context.is_relayed(&source) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Client that left is still sent to through the relay");
+    }
 
-        // Broadcase message to other client
-        tokio::spawn(broadcast_sender(context.clone(), broadcast_rx));
+    #[tokio::test]
+    async fn rewind_goes_back_the_interpolation_delay_the_client_reports() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let handshake = Message::Handshake(Some(ClientId::random()), None, None, None);
+        socket
+            .send_to(handshake.serialize().as_bytes(), server_addr)
+            .await
+            .unwrap();
+
+        let mut buf = [0; 2048];
+        let player_id = loop {
+            let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+                .await
+                .expect("Handshake was not answered with an ACK")
+                .unwrap();
+            let response = std::str::from_utf8(&buf[..len]).unwrap();
+            if let Ok(Message::Ack(player_id, ..)) = Message::deserialize(response) {
+                break player_id;
+            }
+        };
+
+        // Far more than the one tick assumed for clients that don't say
+        let interp_delay = Duration::from_millis(300);
+        let pong = Message::Pong(0, Some(interp_delay));
+        socket
+            .send_to(pong.serialize().as_bytes(), server_addr)
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while server.rewind(player_id).await.unwrap().0 < interp_delay {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Rewind ignored the reported interpolation delay");
+    }
 
-        Ok(()) as ServerSessionResult
-    })
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!(
-            "Server creation time out after {} seconds: {e}",
-            globals::CONNECTION_TIMEOUT_SEC.as_secs()
-        )
-        .into()),
+    #[tokio::test]
+    async fn latency_probe_is_answered_with_server_timings() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let server_addr =
+            SocketAddr::from((Ipv4Addr::LOCALHOST, server.local_addr().unwrap().port()));
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        for msg in [
+            Message::Handshake(Some(ClientId::random()), None, None, None),
+            Message::Probe(9),
+        ] {
+            socket
+                .send_to(msg.serialize().as_bytes(), server_addr)
+                .await
+                .unwrap();
+        }
+
+        let mut buf = [0; 2048];
+        loop {
+            let len = tokio::time::timeout(Duration::from_secs(2), socket.recv(&mut buf))
+                .await
+                .expect("Probe was not answered")
+                .unwrap();
+            let response = std::str::from_utf8(&buf[..len]).unwrap();
+            if let Ok(Message::ProbeReply(seq, queued, tick_wait)) = Message::deserialize(response)
+            {
+                // Answered by the next tick at the latest
+                assert_eq!(seq, 9);
+                assert!(queued + tick_wait < Duration::from_secs(1));
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn loaded_world_gives_players_their_place_back() {
+        let config = client_config();
+        let server = ServerBuilder::new().start().await.unwrap();
+        let session = ClientSession::local(server.connect_local(), &config)
+            .await
+            .unwrap();
+        let player_id = session.get_session_player_data().id;
+
+        // Through JSON like the admin commands
+        let world = serde_json::to_string(&server.save_world().await).unwrap();
+        server.shutdown().await;
+
+        let restarted = ServerBuilder::new()
+            .world(serde_json::from_str(&world).unwrap())
+            .start()
+            .await
+            .unwrap();
+
+        let rejoined = ClientSession::local(restarted.connect_local(), &config)
+            .await
+            .unwrap();
+        let newcomer = ClientSession::local(restarted.connect_local(), &client_config())
+            .await
+            .unwrap();
+        assert_eq!(rejoined.get_session_player_data().id, player_id);
+        assert!(newcomer.get_session_player_data().id > player_id);
+
+        // Nobody may lose their place to a saved player
+        let world = restarted.save_world().await;
+        assert_eq!(world.players.len(), 2);
+        assert!(restarted.load_world(world).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn metrics_sample_the_load_since_the_previous_sample() {
+        let server = ServerBuilder::new().start().await.unwrap();
+        let mut sampler = metrics::MetricsSampler::new(&server.context);
+
+        let _session = ClientSession::local(server.connect_local(), &client_config())
+            .await
+            .unwrap();
+        sampler.record(&server.context).await;
+
+        let samples = server.metrics_history();
+        let sample = samples.last().unwrap();
+        assert_eq!(sample.players, 1);
+        assert!(sample.sent_rate > 0.0 && sample.received_rate > 0.0);
+
+        let csv = metrics_csv(&samples);
+        assert!(csv.starts_with("uptime_sec,players,"));
+        assert_eq!(csv.lines().count(), samples.len() + 1);
+        assert!(csv.lines().all(|line| line.split(',').count() == 7));
     }
 }
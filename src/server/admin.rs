@@ -0,0 +1,590 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use cgmath::Vector2;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use game_server_sample::{clock::SimClock, Avatar, ClientId, Player, PlayerId, SessionToken};
+
+use crate::{
+    daemon::RotatingLogFile,
+    message::{Direction, Message, MessageStats, NoticeLevel},
+    rewind,
+};
+
+use super::{
+    broadcast::send_kick,
+    context::{Outgoing, ServerContext},
+    ServerHandle,
+};
+
+// Remove a client from the game, letting it and everyone else know. `kick_reason` is the code
+// sent to the client, `reason` what goes into the log.
+pub(super) async fn kick_player(
+    context: &ServerContext,
+    client: SocketAddr,
+    kick_reason: Option<&str>,
+    reason: &str,
+) {
+    let Some(connection) = context.players.lock().await.remove(&client) else {
+        return;
+    };
+    let player_id = connection.player.id;
+
+    send_kick(context, client, kick_reason).await;
+    context.forget_relayed(&client);
+    context
+        .log(format!("Player {player_id} ({client}) kicked: {reason}"))
+        .await;
+    context
+        .log_session_summary(&connection, &format!("kicked: {reason}"))
+        .await;
+
+    let _ = context.broadcast(Message::Leave(player_id), Some(client));
+
+    let who = connection
+        .name
+        .clone()
+        .unwrap_or_else(|| player_id.to_string());
+    let _ = context.notice(
+        NoticeLevel::Warning,
+        format!("Player {who} was kicked ({reason})"),
+    );
+}
+
+/// What a new process needs to pick up a session where this one left it, see
+/// [`ServerHandle::save_world`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub next_player_id: PlayerId,
+    pub clock: SimClock,
+    pub motd: Option<String>,
+    pub players: Vec<SavedPlayer>,
+}
+
+impl WorldSnapshot {
+    /// Read a world written by the `save` admin command
+    pub fn load(path: &Path) -> Result<Self, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to load the world from {}: {e}", path.display()))
+    }
+}
+
+/// Player of a saved world, handed back to the client of the same identity when it joins again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub client_id: ClientId,
+
+    /// Sent again in the ACK, so the client's keep-alives stay recognized
+    pub token: SessionToken,
+    pub name: Option<String>,
+    pub player: Player,
+    pub distance_traveled: f32,
+}
+
+/// Point in time view of the server for the server console
+pub struct ServerStatus {
+    pub players: Vec<PlayerStatus>,
+
+    /// Duration of recent simulation ticks, oldest first
+    pub tick_times: Vec<Duration>,
+    pub tick_budget: Duration,
+
+    /// Recent server log lines, oldest first
+    pub log_lines: Vec<String>,
+}
+
+pub struct PlayerStatus {
+    pub addr: SocketAddr,
+    pub player: Player,
+    pub rtt: Option<Duration>,
+
+    /// Share of pings sent since joining that were not answered, between 0 and 1
+    pub packet_loss: f32,
+    pub last_seen: Duration,
+
+    /// Messages skipped because the client was over its bandwidth budget
+    pub dropped_messages: u64,
+
+    /// Stamped positions waiting in the input buffer for their simulation tick
+    pub input_buffer_depth: usize,
+
+    /// Decaying score of speed and rate violations, see [`CheatTracker`]
+    pub suspicion: f32,
+    pub flagged: bool,
+}
+
+impl ServerHandle {
+    pub async fn status(&self) -> ServerStatus {
+        let ping_seq = self.context.ping_seq.load(Ordering::SeqCst);
+
+        let mut players: Vec<PlayerStatus> = self
+            .context
+            .players
+            .lock()
+            .await
+            .iter_mut()
+            .map(|(addr, connection)| {
+                // Skip the newest ping, its reply is most likely still in flight
+                let pings_sent = ping_seq.saturating_sub(connection.first_ping_seq + 1);
+                let packet_loss = if pings_sent == 0 {
+                    0.0
+                } else {
+                    1.0 - (connection.pongs_received as f32 / pings_sent as f32).min(1.0)
+                };
+
+                PlayerStatus {
+                    addr: *addr,
+                    player: connection.player,
+                    rtt: connection.rtt,
+                    packet_loss,
+                    last_seen: connection.last_seen.elapsed(),
+                    dropped_messages: connection.bandwidth.dropped_messages,
+                    input_buffer_depth: connection.inputs.depth(),
+                    suspicion: connection.cheat.score(),
+                    flagged: connection.cheat.reported,
+                }
+            })
+            .collect();
+        players.sort_by_key(|p| p.player.id);
+
+        ServerStatus {
+            players,
+            tick_times: self
+                .context
+                .tick_history
+                .lock()
+                .await
+                .iter()
+                .copied()
+                .collect(),
+            tick_budget: Duration::from_secs_f32(
+                1.0 / self.context.tick_rate.load(Ordering::Relaxed) as f32,
+            ),
+            log_lines: self
+                .context
+                .log_history
+                .lock()
+                .await
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Players, simulation clock and message of the day, to be restored with
+    /// [`Self::load_world`] after a restart. Players without a client identity can't be
+    /// recognized when they come back and are left out.
+    pub async fn save_world(&self) -> WorldSnapshot {
+        let context = &self.context;
+
+        let mut players: Vec<SavedPlayer> = context
+            .players
+            .lock()
+            .await
+            .values()
+            .filter_map(|connection| {
+                Some(SavedPlayer {
+                    client_id: connection.client_id?,
+                    token: connection.token,
+                    name: connection.name.clone(),
+                    player: connection.player,
+                    distance_traveled: connection.distance_traveled,
+                })
+            })
+            .collect();
+
+        // Loaded ones who didn't make it back yet are still part of the world
+        players.extend(context.saved_players.lock().unwrap().values().cloned());
+
+        WorldSnapshot {
+            next_player_id: context.player_id_counter.load(Ordering::SeqCst),
+            clock: *context.clock.lock().unwrap(),
+            motd: context.motd.lock().unwrap().clone(),
+            players,
+        }
+    }
+
+    /// Pick up a saved world. Its players get their id, color, position and name back as soon
+    /// as their client joins again. Refused while anyone is connected, a player already in the
+    /// game could hold the id of a saved one. Returns how many players are waiting to come back.
+    pub async fn load_world(&self, world: WorldSnapshot) -> Result<usize, String> {
+        let context = &self.context;
+
+        // Held until the end, so no handshake gets in between
+        let players = context.players.lock().await;
+        if !players.is_empty() {
+            return Err(format!(
+                "Can't load a world while {} players are connected",
+                players.len()
+            ));
+        }
+
+        let next_player_id = world
+            .players
+            .iter()
+            .map(|saved| saved.player.id + 1)
+            .fold(world.next_player_id, PlayerId::max);
+        context
+            .player_id_counter
+            .fetch_max(next_player_id, Ordering::SeqCst);
+
+        *context.clock.lock().unwrap() = world.clock;
+        *context.motd.lock().unwrap() = world.motd;
+
+        let waiting = world.players.len();
+        *context.saved_players.lock().unwrap() = world
+            .players
+            .into_iter()
+            .map(|saved| (saved.client_id, saved))
+            .collect();
+        drop(players);
+
+        Ok(waiting)
+    }
+
+    /// Full server state as JSON for debugging stuck or desynced sessions
+    /// What a spectator sees: the world and where everyone is in it, without addresses or
+    /// anything else about the connections
+    pub async fn observer_snapshot(&self) -> serde_json::Value {
+        let context = &self.context;
+        let rules = &context.config.rules;
+
+        let players: Vec<serde_json::Value> = context
+            .players
+            .lock()
+            .await
+            .values()
+            .map(|connection| {
+                let player = &connection.player;
+
+                json!({
+                    "id": player.id,
+                    "name": connection.name,
+                    "pos": [player.pos.x, player.pos.y],
+                    "facing": player.facing,
+                    "color": [player.color.x, player.color.y, player.color.z],
+                    "shape": player.avatar.shape.as_str(),
+                    "size": rules.size_of(player),
+                })
+            })
+            .collect();
+
+        let terrain: Vec<serde_json::Value> = context
+            .config
+            .map
+            .zones
+            .iter()
+            .map(|zone| {
+                let color = zone.terrain.color();
+                json!({
+                    "min": [zone.min.x, zone.min.y],
+                    "max": [zone.max.x, zone.max.y],
+                    "color": [color.x, color.y, color.z],
+                })
+            })
+            .collect();
+
+        json!({
+            "tick": context.tick.load(Ordering::Relaxed),
+            "time_of_day": context.time_of_day(),
+            "world_mode": context.config.world_mode.as_str(),
+            "bounds": rules.bounds,
+            "terrain": terrain,
+            "players": players,
+        })
+    }
+
+    pub async fn dump(&self) -> serde_json::Value {
+        let context = &self.context;
+
+        let players: Vec<serde_json::Value> = context
+            .players
+            .lock()
+            .await
+            .iter_mut()
+            .map(|(addr, connection)| {
+                let player = &connection.player;
+                let outbox = connection.outbox.queue.lock().unwrap();
+
+                json!({
+                    "id": player.id,
+                    "addr": addr.to_string(),
+                    "pos": [player.pos.x, player.pos.y],
+                    "velocity": [player.velocity.x, player.velocity.y],
+                    "color": [player.color.x, player.color.y, player.color.z],
+                    "rtt_ms": connection.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                    "first_ping_seq": connection.first_ping_seq,
+                    "pongs_received": connection.pongs_received,
+                    "client_id": connection.client_id.map(|id| id.to_string()),
+                    "name": connection.name,
+                    "via_relay": context.is_relayed(addr),
+                    "last_seen_sec": connection.last_seen.elapsed().as_secs_f64(),
+                    "bandwidth": {
+                        "tokens": connection.bandwidth.tokens,
+                        "dropped_messages": connection.bandwidth.dropped_messages,
+                        "dropped_bytes": connection.bandwidth.dropped_bytes,
+                    },
+                    "outbox": {
+                        "depth": outbox.len(),
+                        "dropped": outbox.dropped(),
+                        "sent": connection.outbox.sent.load(Ordering::Relaxed),
+                    },
+                    "input_buffer": {
+                        "depth": connection.inputs.depth(),
+                        "max_depth": connection.inputs.max_depth,
+                        "late": connection.inputs.late,
+                        "resyncs": connection.inputs.resyncs,
+                    },
+                    "cheat": {
+                        "suspicion": connection.cheat.score(),
+                        "flagged": connection.cheat.reported,
+                        "speed_violations": connection.cheat.speed_violations,
+                        "rate_violations": connection.cheat.rate_violations,
+                    },
+                })
+            })
+            .collect();
+
+        json!({
+            "uptime_sec": context.started_at.elapsed().as_secs_f64(),
+            "tick": context.tick.load(Ordering::Relaxed),
+            "tick_rate": context.tick_rate.load(Ordering::Relaxed),
+            "time_scale": context.clock.lock().unwrap().wire_scale(),
+            "time_of_day": context.time_of_day(),
+            "ping_seq": context.ping_seq.load(Ordering::SeqCst),
+            "next_player_id": context.player_id_counter.load(Ordering::SeqCst),
+            "palette": format!("{:?}", context.config.palette),
+            "bandwidth_limit": context.config.bandwidth_limit,
+            "world_mode": context.config.world_mode.as_str(),
+            "map": context.config.map.serialize(),
+            "duplicate_identity": format!("{:?}", context.config.duplicate_identity),
+            "relay": context.relay_addr.map(|addr| addr.to_string()),
+            "relay_service": context.relay_service.is_some(),
+            "idle_kick_sec": context.config.idle_kick.map(|idle_kick| idle_kick.as_secs()),
+            "cheat": {
+                "speed_tolerance": context.config.cheat.speed_tolerance,
+                "max_messages_per_sec": context.config.cheat.max_messages_per_sec,
+                "flag_score": context.config.cheat.flag_score,
+                "kick_score": context.config.cheat.kick_score,
+            },
+            "players": players,
+            "history": context.history.lock().unwrap().ticks().map(|(oldest, newest)| json!({
+                "oldest_tick": oldest,
+                "newest_tick": newest,
+            })),
+            "queues": {
+                "broadcast": context.broadcast_queue_depth.load(Ordering::Relaxed),
+                "receive": context.receive_queue_depth.load(Ordering::Relaxed),
+            },
+            "sockets": context.sockets.iter().map(|listen_socket| json!({
+                "local_addr": listen_socket.socket.local_addr().ok().map(|addr| addr.to_string()),
+                "datagrams": listen_socket.datagrams.load(Ordering::Relaxed),
+                "bytes": listen_socket.bytes.load(Ordering::Relaxed),
+                "dropped": listen_socket.dropped.load(Ordering::Relaxed),
+            })).collect::<Vec<_>>(),
+            "messages": self.message_stats().to_json(),
+        })
+    }
+
+    /// Where every player was in the world as `player_id` saw it, the state their hits are
+    /// checked against. Returns how far back that is, or `None` if no such player is connected.
+    pub async fn rewind(
+        &self,
+        player_id: PlayerId,
+    ) -> Option<(Duration, Vec<(PlayerId, Vector2<f32>)>)> {
+        let (rtt, interp_delay) = self
+            .context
+            .players
+            .lock()
+            .await
+            .values()
+            .find(|connection| connection.player.id == player_id)
+            .map(|connection| (connection.rtt, connection.interp_delay))?;
+
+        let now = Instant::now();
+        let seen_at = rewind::shooter_view_time(
+            now,
+            rtt,
+            interp_delay,
+            self.context.tick_rate.load(Ordering::Relaxed),
+        );
+
+        let mut positions: Vec<(PlayerId, Vector2<f32>)> = self
+            .context
+            .history
+            .lock()
+            .unwrap()
+            .positions_at(seen_at)
+            .into_iter()
+            .collect();
+        positions.sort_by_key(|(id, _)| *id);
+
+        Some((now - seen_at, positions))
+    }
+
+    /// Players whose suspicion score crossed the flag score at some point, most suspicious first
+    pub async fn flagged_players(&self) -> Vec<PlayerStatus> {
+        let mut flagged: Vec<PlayerStatus> = self
+            .status()
+            .await
+            .players
+            .into_iter()
+            .filter(|p| p.flagged)
+            .collect();
+        flagged.sort_by(|a, b| b.suspicion.total_cmp(&a.suspicion));
+
+        flagged
+    }
+
+    /// Run simulation time at `scale` times real time, clamped to what the clock allows.
+    /// Returns the scale now in effect.
+    pub fn set_time_scale(&self, scale: f32) -> f32 {
+        let mut clock = self.context.clock.lock().unwrap();
+        clock.set_scale(scale);
+
+        let _ = self
+            .context
+            .broadcast(Message::TimeScale(clock.wire_scale()), None);
+        clock.scale()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.context.clock.lock().unwrap().is_paused()
+    }
+
+    /// Stop or restart simulation time for everyone
+    pub async fn set_paused(&self, paused: bool) {
+        let wire_scale = {
+            let mut clock = self.context.clock.lock().unwrap();
+            clock.set_paused(paused);
+            clock.wire_scale()
+        };
+
+        // The pause doesn't count towards the idle kick
+        if !paused {
+            for connection in self.context.players.lock().await.values_mut() {
+                connection.last_input = Instant::now();
+            }
+        }
+
+        let _ = self.context.broadcast(Message::TimeScale(wire_scale), None);
+    }
+
+    /// Kick a player from the server. Returns false if no such player is connected.
+    pub async fn kick(&self, player_id: PlayerId) -> bool {
+        let client = self
+            .context
+            .players
+            .lock()
+            .await
+            .iter()
+            .find(|(_, connection)| connection.player.id == player_id)
+            .map(|(addr, _)| *addr);
+
+        match client {
+            Some(client) => {
+                kick_player(&self.context, client, None, "by the admin").await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Give a player another shape or size. Everyone is sent the player's replication right
+    /// away, the player included.
+    pub async fn set_avatar(&self, player_id: PlayerId, avatar: Avatar) -> Result<(), String> {
+        let rules = &self.context.config.rules;
+        if !rules.fits(&avatar) {
+            return Err(String::from("Size doesn't fit into the world"));
+        }
+
+        let player = {
+            let mut players = self.context.players.lock().await;
+            let connection = players
+                .values_mut()
+                .find(|connection| connection.player.id == player_id)
+                .ok_or_else(|| format!("No player with id {player_id}"))?;
+
+            connection.player.avatar = avatar;
+            rules.apply_world_bounds(&mut connection.player, self.context.config.world_mode);
+            connection.player
+        };
+
+        self.context
+            .log(format!(
+                "Player {player_id} is now a {} of size {}",
+                avatar.shape.as_str(),
+                rules.size_of(&player)
+            ))
+            .await;
+        let _ = self.context.broadcast(Message::Replicate(player), None);
+
+        Ok(())
+    }
+
+    /// Message of the day shown to players joining from now on, `None` for none
+    pub fn set_motd(&self, motd: Option<String>) {
+        *self.context.motd.lock().unwrap() = motd;
+    }
+
+    pub fn motd(&self) -> Option<String> {
+        self.context.motd.lock().unwrap().clone()
+    }
+
+    /// Announce something in every client's log
+    pub async fn notice(&self, level: NoticeLevel, text: String) {
+        self.context.log(format!("Notice: {text}")).await;
+        let _ = self.context.notice(level, text);
+    }
+
+    /// Copy of the traffic counters per message type
+    pub fn message_stats(&self) -> MessageStats {
+        self.context.message_stats.lock().unwrap().clone()
+    }
+
+    /// Whether server log lines are also printed to stdout
+    pub fn set_log_echo(&self, enabled: bool) {
+        self.context.log_echo.store(enabled, Ordering::Relaxed);
+    }
+
+    pub async fn set_log_file(&self, log_file: RotatingLogFile) {
+        *self.context.log_file.lock().await = Some(log_file);
+    }
+
+    pub async fn log(&self, line: String) {
+        self.context.log(line).await;
+    }
+
+    /// Tell every connected client that the server is going away, so they can leave right away
+    /// instead of waiting for the ping timeout. Sent directly rather than through the broadcast
+    /// channel, so the message is out before the process exits.
+    pub async fn shutdown(&self) {
+        let msg = Outgoing::new(Message::ServerShutdown);
+        let players = self.context.players.lock().await;
+
+        for client_addr in players.keys() {
+            let bytes = msg.bytes(self.context.codec_for(client_addr));
+            match self.context.send_to(bytes, *client_addr).await {
+                Ok(len) => self
+                    .context
+                    .record_msg(Direction::Sent, client_addr, &msg.msg, len),
+                Err(e) => eprintln!("Failed to notify {client_addr} about shutdown: {e}"),
+            }
+        }
+
+        // The match ends for everyone still playing
+        for connection in players.values() {
+            self.context
+                .log_session_summary(connection, "server shutdown")
+                .await;
+        }
+    }
+}
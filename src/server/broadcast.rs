@@ -0,0 +1,144 @@
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::message::{Direction, Message};
+
+use super::context::{
+    push_bounded, ChannelReceiver, ClientOutbox, Outgoing, ServerContext, PING_HISTORY_LEN,
+};
+
+// Sender loop handing each broadcast to every player except the one owning the message. The
+// per-client sender tasks do the actual sending.
+pub(super) async fn broadcast_sender(
+    context: Arc<ServerContext>,
+    mut broadcast_rx: ChannelReceiver,
+) {
+    while let Some(broadcast) = broadcast_rx.recv().await {
+        context
+            .broadcast_queue_depth
+            .fetch_sub(1, Ordering::Relaxed);
+
+        let droppable = broadcast.msg.is_droppable();
+        let outgoing = Arc::new(Outgoing::new(broadcast.msg));
+
+        let mut players = context.players.lock().await;
+        for (client_addr, connection) in players.iter_mut() {
+            if Some(*client_addr) == broadcast.excluded_client {
+                continue;
+            }
+
+            // Clients over their budget skip stale replication, the next tick brings them up
+            // to date again
+            if !connection.bandwidth.try_spend(
+                context.config.bandwidth_limit,
+                outgoing.bytes(context.codec_for(client_addr)).len(),
+                droppable,
+            ) {
+                continue;
+            }
+
+            connection.outbox.push(outgoing.clone());
+        }
+    }
+}
+
+// Sends what is queued for one client, until the connection is gone and the queue is empty
+pub(super) async fn client_sender(context: Arc<ServerContext>, outbox: Arc<ClientOutbox>) {
+    loop {
+        let next = outbox.queue.lock().unwrap().pop();
+        let Some(outgoing) = next else {
+            if outbox.closed.load(Ordering::Relaxed) {
+                return;
+            }
+
+            outbox.ready.notified().await;
+            continue;
+        };
+
+        let client = *outbox.client.lock().unwrap();
+        let bytes = outgoing.bytes(context.codec_for(&client));
+        match context.send_to(bytes, client).await {
+            Ok(len) => {
+                outbox.sent.fetch_add(1, Ordering::Relaxed);
+                context.record_msg(Direction::Sent, &client, &outgoing.msg, len);
+            }
+            Err(e) => {
+                context
+                    .log(format!("Failed to send to {client}: {:?}", e))
+                    .await
+            }
+        }
+    }
+}
+
+// Healthcheck for server
+pub(super) async fn ping_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(context.config.rules.ping_interval);
+
+    loop {
+        interval.tick().await;
+
+        // Remember when each ping went out so PONG replies can be turned into round trip times
+        let seq = context.ping_seq.fetch_add(1, Ordering::SeqCst);
+        push_bounded(
+            &mut *context.ping_history.lock().await,
+            (seq, Instant::now()),
+            PING_HISTORY_LEN,
+        );
+
+        let _ = context.broadcast(Message::Ping(seq), None);
+    }
+}
+
+// Send a message to a single client right away rather than through the broadcast channel. Best
+// effort, the address may be dead already.
+pub(super) async fn send_message(context: &ServerContext, client: SocketAddr, msg: &Message) {
+    if let Ok(len) = context.send_msg(msg, client).await {
+        context.record_msg(Direction::Sent, &client, msg, len);
+    }
+}
+
+// Tell a client it is no longer part of the game
+pub(super) async fn send_kick(
+    context: &ServerContext,
+    client: SocketAddr,
+    kick_reason: Option<&str>,
+) {
+    send_message(
+        context,
+        client,
+        &Message::Kick(kick_reason.map(String::from)),
+    )
+    .await;
+}
+
+// Turn a ping reply into a round trip time sample
+pub(super) async fn record_pong(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    seq: u32,
+    interp_delay: Option<Duration>,
+) {
+    let sent_at = context
+        .ping_history
+        .lock()
+        .await
+        .iter()
+        .find(|(sent_seq, _)| *sent_seq == seq)
+        .map(|(_, sent_at)| *sent_at);
+
+    if let Some(connection) = context.players.lock().await.get_mut(&client) {
+        connection.pongs_received += 1;
+        if interp_delay.is_some() {
+            connection.interp_delay = interp_delay;
+        }
+        if let Some(sent_at) = sent_at {
+            let rtt = sent_at.elapsed();
+            connection.rtt = Some(rtt);
+            connection.peak_rtt = connection.peak_rtt.max(Some(rtt));
+        }
+    }
+}
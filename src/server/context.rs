@@ -0,0 +1,607 @@
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+use tokio::{
+    net::UdpSocket,
+    sync::{Mutex, Notify},
+};
+
+use egui::ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use game_server_sample::{
+    clock::SimClock, codec::Codec, globals, identity::PublicKey, outbox::Outbox, ClientId, Player,
+    PlayerId, SessionToken,
+};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    anticheat::CheatTracker,
+    crash,
+    daemon::RotatingLogFile,
+    jitter::InputBuffer,
+    message::{self, Direction, Message, NoticeLevel, SharedMessageStats},
+    relay::{RelayPacket, RelayService},
+    rewind::WorldHistory,
+};
+
+use super::{
+    metrics::{MetricsSample, METRICS_HISTORY_LEN},
+    simulation::TickRateGovernor,
+    SavedPlayer, ServerConfig,
+};
+
+// Number of samples kept for the server console
+pub(super) const TICK_HISTORY_LEN: usize = 120;
+const LOG_HISTORY_LEN: usize = 100;
+
+// Latency probes of one client waiting for the next tick, more are ignored
+pub(super) const PENDING_PROBES_LEN: usize = 4;
+pub(super) const PING_HISTORY_LEN: usize = 256;
+
+// Time of day when the server starts, 0.3 is a bit after 7 am
+const WORLD_CLOCK_START: f32 = 0.3;
+
+// Messages queued for a client before its state updates start being dropped
+const OUTBOX_CAPACITY: usize = 64;
+
+/// Token bucket limiting the outgoing traffic to a single client. Holds at most one second worth
+/// of bytes, so short bursts are fine but a sustained overrun is not.
+pub(super) struct BandwidthBudget {
+    pub(super) tokens: f64,
+    pub(super) last_refill: Instant,
+    pub(super) dropped_messages: u64,
+    pub(super) dropped_bytes: u64,
+}
+
+impl BandwidthBudget {
+    fn new(limit: Option<u32>) -> Self {
+        Self {
+            tokens: limit.unwrap_or_default() as f64,
+            last_refill: Instant::now(),
+            dropped_messages: 0,
+            dropped_bytes: 0,
+        }
+    }
+
+    /// Charge `bytes` against the budget. Returns false if the message should be dropped instead.
+    /// Critical messages are always let through, possibly putting the budget into debt which
+    /// the following droppable messages pay off.
+    pub(super) fn try_spend(&mut self, limit: Option<u32>, bytes: usize, droppable: bool) -> bool {
+        let Some(limit) = limit else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit as f64).min(limit as f64);
+        self.last_refill = now;
+
+        if droppable && self.tokens < bytes as f64 {
+            self.dropped_messages += 1;
+            self.dropped_bytes += bytes as u64;
+            return false;
+        }
+
+        self.tokens -= bytes as f64;
+        true
+    }
+}
+
+/// Connected client: replicated player state plus connection bookkeeping
+pub(super) struct Connection {
+    pub(super) player: Player,
+
+    /// Identity sent in the handshake, `None` for clients that don't send one
+    pub(super) client_id: Option<ClientId>,
+
+    /// Picked by the player, used to address whispers
+    pub(super) name: Option<String>,
+
+    /// Proven in the handshake, `None` for clients without an identity key
+    pub(super) public_key: Option<PublicKey>,
+
+    /// Handed to the client in the ACK, moves the connection when it arrives from a new address
+    pub(super) token: SessionToken,
+
+    /// Broadcasts waiting for this client's sender task
+    pub(super) outbox: Arc<ClientOutbox>,
+
+    /// Round trip time of the latest answered ping
+    pub(super) rtt: Option<Duration>,
+
+    /// How far behind the client draws other players, as of its latest pong. `None` for clients
+    /// that don't report it.
+    pub(super) interp_delay: Option<Duration>,
+
+    /// Ping sequence number at the time the client joined, for packet loss estimation
+    pub(super) first_ping_seq: u32,
+    pub(super) pongs_received: u32,
+    pub(super) last_seen: Instant,
+
+    /// Last time the player moved, keep-alives and pongs don't count
+    pub(super) last_input: Instant,
+    pub(super) bandwidth: BandwidthBudget,
+    pub(super) cheat: CheatTracker,
+    pub(super) inputs: InputBuffer,
+
+    /// Last accepted dash, for the cooldown
+    pub(super) last_dash: Option<Instant>,
+
+    /// Last time a collision knocked the player back
+    pub(super) last_knockback: Option<Instant>,
+
+    // Session totals for the scoreboard and the summary written when the player leaves
+    pub(super) joined_at: Instant,
+    pub(super) distance_traveled: f32,
+    pub(super) messages_received: u64,
+    pub(super) peak_rtt: Option<Duration>,
+
+    /// Latency probes handled since the last tick: sequence number, when the datagram came off
+    /// the socket and when a receive worker got to it
+    pub(super) probes: Vec<(u32, Instant, Instant)>,
+}
+
+impl Connection {
+    pub(super) fn new(
+        player: Player,
+        client: SocketAddr,
+        client_id: Option<ClientId>,
+        first_ping_seq: u32,
+        config: &ServerConfig,
+    ) -> Self {
+        Self {
+            player,
+            client_id,
+            name: None,
+            public_key: None,
+            token: rand::random(),
+            outbox: Arc::new(ClientOutbox::new(client)),
+            rtt: None,
+            interp_delay: None,
+            first_ping_seq,
+            pongs_received: 0,
+            last_seen: Instant::now(),
+            last_input: Instant::now(),
+            bandwidth: BandwidthBudget::new(config.bandwidth_limit),
+            cheat: CheatTracker::new(&config.cheat, &config.rules.movement),
+            inputs: InputBuffer::new(),
+            last_dash: None,
+            last_knockback: None,
+            joined_at: Instant::now(),
+            distance_traveled: 0.0,
+            messages_received: 0,
+            peak_rtt: None,
+            probes: Vec::new(),
+        }
+    }
+
+    /// The client now talks from `client`. Round trip and loss statistics of the old address
+    /// don't apply anymore.
+    pub(super) fn moved(&mut self, client: SocketAddr, ping_seq: u32) {
+        *self.outbox.client.lock().unwrap() = client;
+        self.rtt = None;
+        self.first_ping_seq = ping_seq;
+        self.pongs_received = 0;
+        self.last_seen = Instant::now();
+    }
+
+    /// Structured record of the whole session, `reason` being why it ended
+    fn summary(&self, reason: &str) -> serde_json::Value {
+        json!({
+            "player_id": self.player.id,
+            "client_id": self.client_id.map(|id| id.to_string()),
+            "reason": reason,
+            "duration_sec": self.joined_at.elapsed().as_secs_f64(),
+            "distance_traveled": self.distance_traveled,
+            "messages_received": self.messages_received,
+            "messages_sent": self.outbox.sent.load(Ordering::Relaxed),
+            "peak_rtt_ms": self.peak_rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+        })
+    }
+}
+
+// Nothing else is sent to the client once the connection is gone, its sender task finishes
+// what is already queued and stops
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.outbox.closed.store(true, Ordering::Relaxed);
+        self.outbox.ready.notify_one();
+    }
+}
+
+/// Messages on their way to one client, sent by a task of its own so a slow client only falls
+/// behind itself
+pub(super) struct ClientOutbox {
+    pub(super) queue: std::sync::Mutex<Outbox<(&'static str, PlayerId), Arc<Outgoing>>>,
+    pub(super) ready: Notify,
+    pub(super) closed: AtomicBool,
+
+    /// Follows the connection when the client's address changes
+    pub(super) client: std::sync::Mutex<SocketAddr>,
+    pub(super) sent: AtomicU64,
+}
+
+impl ClientOutbox {
+    fn new(client: SocketAddr) -> Self {
+        Self {
+            queue: std::sync::Mutex::new(Outbox::new(OUTBOX_CAPACITY)),
+            ready: Notify::new(),
+            closed: AtomicBool::new(false),
+            client: std::sync::Mutex::new(client),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn push(&self, outgoing: Arc<Outgoing>) {
+        let key = outgoing.msg.superseded_by();
+        self.queue.lock().unwrap().push(key, outgoing);
+        self.ready.notify_one();
+    }
+}
+
+/// Broadcast message, serialized once per codec for all the clients it goes to
+pub(super) struct Outgoing {
+    pub(super) msg: Message,
+    text: Vec<u8>,
+    binary: OnceLock<Vec<u8>>,
+}
+
+impl Outgoing {
+    pub(super) fn new(msg: Message) -> Self {
+        Self {
+            text: msg.serialize().into_bytes(),
+            binary: OnceLock::new(),
+            msg,
+        }
+    }
+
+    /// The message the way a client speaking `codec` gets it. The server never speaks JSON.
+    pub(super) fn bytes(&self, codec: Codec) -> &[u8] {
+        match codec {
+            Codec::Binary => self.binary.get_or_init(|| codec.encode(&self.msg)),
+            Codec::Text | Codec::Json => &self.text,
+        }
+    }
+}
+
+// Store user connected in a hashmap
+pub(super) type PlayerMap = HashMap<SocketAddr, Connection>;
+
+// Define message and channel
+pub(super) struct BroadcastMessage {
+    pub(super) msg: Message,
+    pub(super) excluded_client: Option<SocketAddr>,
+}
+pub(super) type ChannelSender = mpsc::UnboundedSender<BroadcastMessage>;
+pub(super) type ChannelSendResult = Result<(), mpsc::error::SendError<BroadcastMessage>>;
+pub(super) type ChannelReceiver = mpsc::UnboundedReceiver<BroadcastMessage>;
+
+// Define Server
+pub(super) struct ServerContext {
+    /// The first one also sends everything the server sends
+    pub(super) sockets: Vec<ListenSocket>,
+    pub(super) broadcast_tx: ChannelSender,
+    pub(super) players: Mutex<PlayerMap>,
+    pub(super) player_id_counter: AtomicU64,
+
+    /// Number of players, updated as they join and leave. See [`ServerHandle::player_count`].
+    pub(super) player_count: watch::Sender<usize>,
+    pub(super) config: ServerConfig,
+
+    /// Recent world states for lag compensated hit checks
+    pub(super) history: std::sync::Mutex<WorldHistory>,
+
+    /// Simulation time, paused or slowed down from a hosting player's console
+    pub(super) clock: std::sync::Mutex<SimClock>,
+
+    /// Starts out as the configured one, see [`ServerHandle::set_motd`]
+    pub(super) motd: std::sync::Mutex<Option<String>>,
+
+    /// Players of a loaded world whose clients haven't joined again yet
+    pub(super) saved_players: std::sync::Mutex<HashMap<ClientId, SavedPlayer>>,
+
+    /// Clients recently asked to join again, see [`request_resync`]
+    pub(super) resyncs_sent: std::sync::Mutex<HashMap<SocketAddr, Instant>>,
+
+    // Relay support
+    pub(super) relay_addr: Option<SocketAddr>,
+    pub(super) relayed_clients: std::sync::Mutex<HashSet<SocketAddr>>,
+    pub(super) relay_service: Option<std::sync::Mutex<RelayService>>,
+
+    /// Clients that asked for binary in their handshake, everyone else gets text
+    pub(super) binary_clients: std::sync::Mutex<HashSet<SocketAddr>>,
+
+    /// Clients of this process joined in memory, see [`ServerHandle::connect_local`]
+    pub(super) local_clients: std::sync::Mutex<HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>,
+    pub(super) local_client_counter: AtomicU64,
+
+    // Diagnostics
+    pub(super) started_at: Instant,
+    pub(super) tick: AtomicU64,
+    pub(super) tick_rate: AtomicU32,
+    pub(super) broadcast_queue_depth: AtomicUsize,
+    pub(super) receive_queue_depth: AtomicUsize,
+    pub(super) message_stats: SharedMessageStats,
+    pub(super) ping_seq: AtomicU32,
+    pub(super) ping_history: Mutex<VecDeque<(u32, Instant)>>,
+    pub(super) tick_history: Mutex<VecDeque<Duration>>,
+    pub(super) metrics_history: std::sync::Mutex<VecDeque<MetricsSample>>,
+    pub(super) log_history: Mutex<VecDeque<String>>,
+    pub(super) log_echo: AtomicBool,
+    pub(super) log_file: Mutex<Option<RotatingLogFile>>,
+}
+
+impl ServerContext {
+    pub(super) fn new(
+        sockets: Vec<UdpSocket>,
+        broadcast_tx: ChannelSender,
+        mut config: ServerConfig,
+        relay_addr: Option<SocketAddr>,
+    ) -> Self {
+        // Clients are told the rate the server actually starts at
+        let tick_rate = TickRateGovernor::new(config.rules.tick_rate).tick_rate();
+        config.rules.tick_rate = tick_rate;
+
+        Self {
+            sockets: sockets.into_iter().map(ListenSocket::new).collect(),
+            broadcast_tx,
+            players: Mutex::new(PlayerMap::new()),
+            player_id_counter: AtomicU64::new(1),
+            player_count: watch::Sender::new(0),
+            relay_addr,
+            relayed_clients: std::sync::Mutex::new(HashSet::new()),
+            relay_service: config
+                .relay_service
+                .then(|| std::sync::Mutex::new(RelayService::default())),
+            binary_clients: std::sync::Mutex::new(HashSet::new()),
+            local_clients: std::sync::Mutex::new(HashMap::new()),
+            local_client_counter: AtomicU64::new(1),
+            history: std::sync::Mutex::new(WorldHistory::new(config.world_mode, config.rules)),
+            clock: std::sync::Mutex::new(SimClock::new()),
+            motd: std::sync::Mutex::new(config.motd.clone()),
+            saved_players: std::sync::Mutex::new(HashMap::new()),
+            resyncs_sent: std::sync::Mutex::new(HashMap::new()),
+            config,
+            started_at: Instant::now(),
+            tick: AtomicU64::new(0),
+            tick_rate: AtomicU32::new(tick_rate),
+            broadcast_queue_depth: AtomicUsize::new(0),
+            receive_queue_depth: AtomicUsize::new(0),
+            message_stats: SharedMessageStats::default(),
+            ping_seq: AtomicU32::new(0),
+            ping_history: Mutex::new(VecDeque::with_capacity(PING_HISTORY_LEN)),
+            tick_history: Mutex::new(VecDeque::with_capacity(TICK_HISTORY_LEN)),
+            metrics_history: std::sync::Mutex::new(VecDeque::with_capacity(METRICS_HISTORY_LEN)),
+            log_history: Mutex::new(VecDeque::with_capacity(LOG_HISTORY_LEN)),
+            log_echo: AtomicBool::new(true),
+            log_file: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn server_socket(&self) -> &UdpSocket {
+        &self.sockets[0].socket
+    }
+
+    /// Queue a message for every connected client except `excluded_client`
+    pub(super) fn broadcast(
+        &self,
+        msg: Message,
+        excluded_client: Option<SocketAddr>,
+    ) -> ChannelSendResult {
+        // Counted before sending, so the sender task can never decrement first
+        self.broadcast_queue_depth.fetch_add(1, Ordering::Relaxed);
+
+        self.broadcast_tx
+            .send(BroadcastMessage {
+                msg,
+                excluded_client,
+            })
+            .inspect_err(|_| {
+                self.broadcast_queue_depth.fetch_sub(1, Ordering::Relaxed);
+            })
+    }
+
+    /// Put a line in every client's log
+    pub(super) fn notice(&self, level: NoticeLevel, text: String) -> ChannelSendResult {
+        self.broadcast(Message::ServerNotice(level, text), None)
+    }
+
+    /// Current world clock between 0 and 1, 0 being midnight. Starts in the morning so a new
+    /// server does not greet its first players in the dark.
+    pub(super) fn time_of_day(&self) -> f32 {
+        let elapsed = self.clock.lock().unwrap().elapsed();
+
+        (WORLD_CLOCK_START + elapsed.as_secs_f32() / globals::DAY_CYCLE_SEC).fract()
+    }
+
+    pub(super) fn is_relayed(&self, client: &SocketAddr) -> bool {
+        self.relayed_clients.lock().unwrap().contains(client)
+    }
+
+    /// Send to `client` directly and as text again, once it left or moved to another address
+    pub(super) fn forget_relayed(&self, client: &SocketAddr) {
+        self.relayed_clients.lock().unwrap().remove(client);
+        self.binary_clients.lock().unwrap().remove(client);
+    }
+
+    pub(super) fn codec_for(&self, client: &SocketAddr) -> Codec {
+        if self.binary_clients.lock().unwrap().contains(client) {
+            Codec::Binary
+        } else {
+            Codec::Text
+        }
+    }
+
+    /// Speak the codec a client offered in its handshake, if the server speaks it too. Only
+    /// binary is worth switching to, and the relay only forwards text.
+    pub(super) fn set_codec(&self, client: SocketAddr, offered: Option<Codec>) {
+        let mut binary_clients = self.binary_clients.lock().unwrap();
+
+        if offered == Some(Codec::Binary) && !self.is_relayed(&client) {
+            binary_clients.insert(client);
+        } else {
+            binary_clients.remove(&client);
+        }
+    }
+
+    /// Send a message to a client in the codec it speaks
+    pub(super) async fn send_msg(
+        &self,
+        msg: &Message,
+        client: SocketAddr,
+    ) -> std::io::Result<usize> {
+        self.send_to(&self.codec_for(&client).encode(msg), client)
+            .await
+    }
+
+    fn is_local(&self, client: &SocketAddr) -> bool {
+        self.local_clients.lock().unwrap().contains_key(client)
+    }
+
+    /// Hand a datagram to a client joined in memory, `None` for any other client. A full queue
+    /// drops the datagram like a full socket buffer would.
+    fn send_local(&self, bytes: &[u8], client: &SocketAddr) -> Option<std::io::Result<usize>> {
+        let mut local_clients = self.local_clients.lock().unwrap();
+        let to_client = local_clients.get(client)?;
+
+        match to_client.try_send(bytes.to_vec()) {
+            Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => Some(Ok(bytes.len())),
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                local_clients.remove(client);
+                Some(Err(std::io::ErrorKind::ConnectionReset.into()))
+            }
+        }
+    }
+
+    /// Send to a client directly, through the relay for clients that joined that way, or in
+    /// memory for clients of this process
+    pub(super) async fn send_to(&self, bytes: &[u8], client: SocketAddr) -> std::io::Result<usize> {
+        if let Some(result) = self.send_local(bytes, &client) {
+            return result;
+        }
+
+        match self.relay_addr {
+            Some(relay_addr) if self.is_relayed(&client) => {
+                let packet = RelayPacket::To {
+                    target: client,
+                    payload: &String::from_utf8_lossy(bytes),
+                };
+                self.server_socket()
+                    .send_to(packet.serialize().as_bytes(), relay_addr)
+                    .await
+            }
+            _ => self.server_socket().send_to(bytes, client).await,
+        }
+    }
+
+    pub(super) fn record_msg(
+        &self,
+        direction: Direction,
+        peer: &SocketAddr,
+        msg: &Message,
+        bytes: usize,
+    ) {
+        if self.is_relayed(peer) {
+            let peer = format!("{peer} via relay");
+            message::record_msg(&self.message_stats, direction, peer, msg, bytes);
+        } else if self.is_local(peer) {
+            let peer = String::from("in process");
+            message::record_msg(&self.message_stats, direction, peer, msg, bytes);
+        } else {
+            message::record_msg(&self.message_stats, direction, peer, msg, bytes);
+        }
+    }
+
+    /// Server log line, kept for the server console and echoed to stdout unless the console
+    /// owns the terminal. Also appended to the log file when one is configured.
+    pub(super) async fn log(&self, line: String) {
+        crash::record_log(&line);
+
+        if self.log_echo.load(Ordering::Relaxed) {
+            println!("{line}");
+        }
+
+        if let Some(log_file) = self.log_file.lock().await.as_mut() {
+            if let Err(e) = log_file.write_line(&line) {
+                eprintln!("Failed to write server log file: {e}");
+            }
+        }
+
+        push_bounded(&mut *self.log_history.lock().await, line, LOG_HISTORY_LEN);
+    }
+
+    /// Log the summary of a finished session and append it to the summaries file if configured
+    pub(super) async fn log_session_summary(&self, connection: &Connection, reason: &str) {
+        self.log(format!(
+            "Player {} session ended ({reason}): {:.0} s, {:.0} units traveled, {} messages in, \
+             {} out, peak RTT {}",
+            connection.player.id,
+            connection.joined_at.elapsed().as_secs_f32(),
+            connection.distance_traveled,
+            connection.messages_received,
+            connection.outbox.sent.load(Ordering::Relaxed),
+            connection
+                .peak_rtt
+                .map_or(String::from("unknown"), |rtt| format!(
+                    "{:.0} ms",
+                    rtt.as_secs_f32() * 1000.0
+                )),
+        ))
+        .await;
+
+        let Some(path) = &self.config.session_summaries else {
+            return;
+        };
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", connection.summary(reason)));
+
+        if let Err(e) = result {
+            self.log(format!(
+                "Failed to write session summary to {}: {e}",
+                path.display()
+            ))
+            .await;
+        }
+    }
+}
+
+pub(super) fn push_bounded<T>(queue: &mut VecDeque<T>, item: T, max_len: usize) {
+    if queue.len() == max_len {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+/// UDP socket the server listens on, with the traffic it got
+pub(super) struct ListenSocket {
+    pub(super) socket: UdpSocket,
+    pub(super) datagrams: AtomicU64,
+    pub(super) bytes: AtomicU64,
+
+    /// Datagrams thrown away because the receive worker was behind
+    pub(super) dropped: AtomicU64,
+}
+
+impl ListenSocket {
+    pub(super) fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            datagrams: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+}
@@ -0,0 +1,868 @@
+use std::{
+    error::Error,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use game_server_sample::{
+    codec::Codec,
+    globals,
+    identity::KeyProof,
+    udp_batch::{self, RecvBatch},
+    version::Version,
+    Avatar, ClientId, Player, PlayerId, SessionToken,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    message::{self, Direction, Message, WhisperError},
+    relay::RelayPacket,
+};
+
+use super::{
+    broadcast::{client_sender, ping_sender, record_pong, send_kick, send_message},
+    context::{Connection, PlayerMap, ServerContext, PENDING_PROBES_LEN},
+    simulation::{report_violation, simulation_handler, update_position, use_ability},
+    DuplicateIdentity, SavedPlayer,
+};
+
+// Incoming datagrams are handled by this many workers, each owning the clients hashed to it so
+// a client's messages stay in order
+const RECV_WORKERS: usize = 4;
+
+// Datagrams waiting per worker, beyond that the server is overloaded and new ones are dropped
+const RECV_QUEUE_LEN: usize = 1024;
+
+// Datagrams taken off the socket at once
+const RECV_BATCH_LEN: usize = 32;
+
+// Overload is logged at most this often, not once per dropped datagram
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// A client of an unknown session is asked to join again at most this often, it keeps sending at
+// its frame rate until the new handshake went through
+const RESYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// Client address, the message and when it came off the socket
+pub(super) type Datagram = (SocketAddr, Vec<u8>, Instant);
+pub(super) type DatagramSender = mpsc::Sender<Datagram>;
+
+// Bind the port, `count` times with SO_REUSEPORT so every socket gets a share of the clients
+pub(super) fn bind_sockets(port: u16, count: usize) -> std::io::Result<Vec<UdpSocket>> {
+    let mut addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let mut sockets = Vec::with_capacity(count);
+
+    for _ in 0..count.max(1) {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if count > 1 {
+            set_reuse_port(&socket)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+
+        let socket = UdpSocket::from_std(socket.into())?;
+
+        // Port 0 picks a free port, the other sockets have to join that one
+        addr = socket.local_addr()?;
+        sockets.push(socket);
+    }
+
+    Ok(sockets)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> std::io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_: &Socket) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Several server sockets need SO_REUSEPORT, which this system lacks",
+    ))
+}
+
+// Shared by the receive tasks of all sockets, a client always ends up with the same worker
+pub(super) fn spawn_receive_workers(context: &Arc<ServerContext>) -> Vec<DatagramSender> {
+    (0..RECV_WORKERS)
+        .map(|_| {
+            let (datagram_tx, datagram_rx) = mpsc::channel(RECV_QUEUE_LEN);
+            tokio::spawn(receive_worker(context.clone(), datagram_rx));
+            datagram_tx
+        })
+        .collect()
+}
+
+// Receive messages from clients on one socket and hand them to the receive workers. Never waits
+// for a worker: when one falls behind, datagrams for its clients are dropped and counted instead
+// of backing up the socket for everyone.
+pub(super) async fn listen_handler(
+    context: Arc<ServerContext>,
+    socket_index: usize,
+    workers: Vec<DatagramSender>,
+) {
+    let listen_socket = &context.sockets[socket_index];
+    let mut batch = RecvBatch::new(RECV_BATCH_LEN);
+    let mut dropped_since_report = 0;
+    let mut last_drop_report = Instant::now();
+
+    loop {
+        if let Err(e) = udp_batch::recv_batch(&listen_socket.socket, &mut batch).await {
+            // E.g. an ICMP port unreachable from a client that went away
+            message::trace(format!("Receive failed: {e}"));
+            continue;
+        }
+
+        let received_at = Instant::now();
+        for (client, datagram) in batch.iter() {
+            if datagram.len() <= 1 {
+                continue;
+            }
+
+            listen_socket.datagrams.fetch_add(1, Ordering::Relaxed);
+            listen_socket
+                .bytes
+                .fetch_add(datagram.len() as u64, Ordering::Relaxed);
+
+            let worker = &workers[worker_index(&client)];
+
+            context.receive_queue_depth.fetch_add(1, Ordering::Relaxed);
+            if worker
+                .try_send((client, datagram.to_vec(), received_at))
+                .is_err()
+            {
+                context.receive_queue_depth.fetch_sub(1, Ordering::Relaxed);
+                listen_socket.dropped.fetch_add(1, Ordering::Relaxed);
+                dropped_since_report += 1;
+            }
+        }
+
+        if dropped_since_report > 0 && last_drop_report.elapsed() >= DROP_REPORT_INTERVAL {
+            context
+                .log(format!(
+                    "Receive queues full, dropped {dropped_since_report} datagrams on socket {socket_index}"
+                ))
+                .await;
+
+            dropped_since_report = 0;
+            last_drop_report = Instant::now();
+        }
+    }
+}
+
+// Same client, same worker
+pub(super) fn worker_index(client: &SocketAddr) -> usize {
+    let mut hasher = DefaultHasher::new();
+    client.hash(&mut hasher);
+
+    hasher.finish() as usize % RECV_WORKERS
+}
+
+async fn receive_worker(context: Arc<ServerContext>, mut datagram_rx: mpsc::Receiver<Datagram>) {
+    while let Some((client, request_msg, received_at)) = datagram_rx.recv().await {
+        context.receive_queue_depth.fetch_sub(1, Ordering::Relaxed);
+
+        process_client_message(context.clone(), client, request_msg, received_at).await;
+    }
+}
+
+// Proccessing client request
+async fn process_client_message(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    msg: Vec<u8>,
+    received_at: Instant,
+) {
+    let relay_packet = std::str::from_utf8(&msg).ok().and_then(RelayPacket::parse);
+    let (client, msg) = match relay_packet {
+        Some(packet) => match process_relay_packet(&context, client, packet).await {
+            Some(unwrapped) => unwrapped,
+            None => return,
+        },
+        None => (client, msg),
+    };
+
+    let (known, violation) = match context.players.lock().await.get_mut(&client) {
+        Some(connection) => {
+            connection.last_seen = Instant::now();
+            connection.messages_received += 1;
+            (true, connection.cheat.on_message(&context.config.cheat))
+        }
+        None => (false, None),
+    };
+
+    if let Some(violation) = violation {
+        report_violation(context.clone(), client, violation).await;
+    }
+
+    // Whatever the client negotiated, every message says which codec it is in
+    let deserialized = Codec::of(&msg).decode(&msg);
+    match &deserialized {
+        Ok(m) => context.record_msg(Direction::Received, &client, m, msg.len()),
+        Err(e) => message::trace(format!(
+            "<- {client} invalid message ({e}): {}",
+            String::from_utf8_lossy(&msg)
+        )),
+    }
+
+    // Most likely a client of the server process before a restart, still playing on
+    if !known && deserialized.as_ref().is_ok_and(needs_session) {
+        request_resync(&context, client).await;
+        return;
+    }
+
+    match deserialized {
+        Ok(Message::Handshake(client_id, invite_code, key_proof, codec)) => {
+            if let Err(e) = accept_client(
+                context.clone(),
+                client,
+                client_id,
+                invite_code,
+                key_proof,
+                codec,
+            )
+            .await
+            {
+                context
+                    .log(format!("Error accepting client {}: {}", client, e))
+                    .await;
+            }
+        }
+
+        Ok(Message::StatusQuery) => answer_status_query(&context, client).await,
+
+        Ok(Message::Pong(seq, interp_delay)) => {
+            record_pong(context, client, seq, interp_delay).await
+        }
+
+        Ok(Message::KeepAlive(token)) => follow_address_change(context, client, token).await,
+
+        Ok(Message::Probe(seq)) => queue_probe(&context, client, seq, received_at).await,
+
+        Ok(Message::Position(player_id, pos, step)) => {
+            if let Err(e) = update_position(context.clone(), client, player_id, pos, step).await {
+                context
+                    .log(format!(
+                        "Error updating player position {}: {}",
+                        player_id, e
+                    ))
+                    .await;
+            }
+        }
+
+        Ok(Message::Ability(ability)) => use_ability(&context, client, ability).await,
+
+        Ok(Message::Name(player_id, name)) => {
+            set_player_name(&context, client, player_id, name).await
+        }
+
+        Ok(Message::Chat(player_id, text)) => relay_chat(&context, client, player_id, &text).await,
+
+        Ok(Message::Whisper(target, text)) => route_whisper(&context, client, &target, &text).await,
+
+        Ok(Message::Leave(player_id)) => {
+            if let Err(e) = drop_player(context.clone(), client, player_id).await {
+                context
+                    .log(format!("Error dropping player {}: {}", player_id, e))
+                    .await;
+            }
+        }
+
+        _ => (),
+    }
+}
+
+// Accept client connect
+async fn accept_client(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    client_id: Option<ClientId>,
+    invite_code: Option<String>,
+    key_proof: Option<Box<KeyProof>>,
+    codec: Option<Codec>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(expected) = &context.config.invite_code {
+        if !invite_code.is_some_and(|code| code.eq_ignore_ascii_case(expected)) {
+            send_kick(&context, client, Some(message::KICK_INVITE)).await;
+            context
+                .log(format!("Refused {client}: wrong or missing invite code"))
+                .await;
+
+            return Ok(());
+        }
+    }
+
+    let mut players = context.players.lock().await;
+
+    // Same identity already playing from another address: a second client on the same machine,
+    // or the same client after its NAT mapping changed
+    let previous_client = client_id.and_then(|client_id| {
+        players
+            .iter()
+            .find(|(addr, connection)| **addr != client && connection.client_id == Some(client_id))
+            .map(|(addr, _)| *addr)
+    });
+
+    let ack_msg: Message;
+    let mut restored = None;
+    if let Some(Connection {
+        player: existing_player,
+        token,
+        ..
+    }) = players.get(&client)
+    {
+        // Getting multiple handshakes from and sending out multiple ACK for the same
+        // client is not a problem, that just means that previous ACK was dropped, so the
+        // client retried the HANDSHAKE. Server just resends ACK with same player info that
+        // was already registered as response to new HANDSHAKE. It is made sure here not to
+        // accidentally add the same player multiple times, because that would lead to
+        // "Player 3 joined, Player
+        // 4 joined, Player 5 joined" bug for each accepted HANDSHAKE from the same client.
+        ack_msg = Message::Ack(
+            existing_player.id,
+            existing_player.color,
+            context.config.world_mode,
+            Some(*token),
+            Some(Version::current()),
+        );
+    } else if let Some(previous_client) = previous_client {
+        if context.config.duplicate_identity == DuplicateIdentity::Refuse {
+            drop(players);
+
+            send_kick(&context, client, None).await;
+            context
+                .log(format!(
+                    "Refused {client}: identity already connected from {previous_client}"
+                ))
+                .await;
+
+            return Ok(());
+        }
+
+        let mut connection = players
+            .remove(&previous_client)
+            .ok_or("Previous connection vanished")?;
+
+        connection.moved(client, context.ping_seq.load(Ordering::SeqCst));
+
+        let player = connection.player;
+        let token = connection.token;
+        players.insert(client, connection);
+
+        // Whatever is still listening on the old address is no longer this player
+        send_kick(&context, previous_client, None).await;
+        context.forget_relayed(&previous_client);
+        context
+            .log(format!(
+                "Player {} moved from {previous_client} to {client}",
+                player.id
+            ))
+            .await;
+
+        ack_msg = Message::Ack(
+            player.id,
+            player.color,
+            context.config.world_mode,
+            Some(token),
+            Some(Version::current()),
+        );
+    } else if players.len() >= context.config.max_players {
+        drop(players);
+
+        send_kick(&context, client, Some(message::KICK_FULL)).await;
+        context
+            .log(format!(
+                "Refused {client}: server is full with {} players",
+                context.config.max_players
+            ))
+            .await;
+
+        return Ok(());
+    } else {
+        let saved = client_id
+            .and_then(|client_id| context.saved_players.lock().unwrap().remove(&client_id));
+        let new_player = match &saved {
+            Some(saved) => saved.player,
+            None => {
+                let player_id = context.player_id_counter.fetch_add(1, Ordering::SeqCst);
+                Player::new(player_id, context.config.palette.player_color(player_id))
+            }
+        };
+
+        let first_ping_seq = context.ping_seq.load(Ordering::SeqCst);
+        let mut connection = Connection::new(
+            new_player,
+            client,
+            client_id,
+            first_ping_seq,
+            &context.config,
+        );
+        if let Some(saved) = saved {
+            connection.token = saved.token;
+            connection.name = saved.name.clone();
+            connection.distance_traveled = saved.distance_traveled;
+            restored = Some(saved);
+        }
+        let token = connection.token;
+        tokio::spawn(client_sender(context.clone(), connection.outbox.clone()));
+        players.insert(client, connection);
+        context.player_count.send_replace(players.len());
+
+        // First time game startup: Start sending PING message to everyone and start
+        // the game simulation when the first player
+        // connected
+
+        if players.len() == 1 {
+            // Ping the server only the first time to check if the server is working
+            // or not
+            tokio::spawn(ping_sender(context.clone()));
+
+            // This onl created one when the server start with 1 user first
+            // connected, after that it just run and update the state to anyone
+            // inside the server
+            // This is the place where keep update the game state to everyone inside
+            // the game with 60fps
+            tokio::spawn(simulation_handler(context.clone()));
+        }
+
+        ack_msg = Message::Ack(
+            new_player.id,
+            new_player.color,
+            context.config.world_mode,
+            Some(token),
+            Some(Version::current()),
+        );
+    }
+
+    // A key only counts when signed together with the client id, so it can't be replayed by
+    // someone else
+    let public_key = client_id
+        .zip(key_proof)
+        .filter(|(client_id, proof)| proof.verify(*client_id))
+        .map(|(_, proof)| proof.key);
+
+    let mut new_key = None;
+    if let Some(connection) = players.get_mut(&client) {
+        if connection.public_key != public_key {
+            connection.public_key = public_key;
+            new_key = public_key.map(|key| Message::PlayerKey(connection.player.id, key));
+        }
+    }
+
+    // Names and keys are only broadcast once, so the new client catches up on everyone's
+    let names: Vec<Message> = players
+        .values()
+        .flat_map(|connection| {
+            let name = connection
+                .name
+                .clone()
+                .map(|name| Message::Name(connection.player.id, name));
+            let key = connection
+                .public_key
+                .map(|key| Message::PlayerKey(connection.player.id, key));
+
+            name.into_iter().chain(key)
+        })
+        .collect();
+
+    // Nobody is replicated to themselves, a player with an avatar of their own learns it this way
+    let own_avatar = players
+        .get(&client)
+        .map(|connection| connection.player)
+        .filter(|player| player.avatar != Avatar::default())
+        .map(Message::Replicate);
+    drop(players);
+
+    if let Some(msg) = new_key {
+        let _ = context.broadcast(msg, Some(client));
+    }
+
+    // Players who joined before learn the name the same way as when it's picked
+    if let Some(SavedPlayer {
+        player,
+        name: Some(name),
+        ..
+    }) = &restored
+    {
+        let _ = context.broadcast(Message::Name(player.id, name.clone()), Some(client));
+    }
+
+    // Everything from the ACK on is in the codec the client asked for, the ACK itself is how
+    // the client learns whether the server went along
+    context.set_codec(client, codec);
+
+    // Send ACK message
+    let len = context.send_msg(&ack_msg, client).await?;
+
+    context.record_msg(Direction::Sent, &client, &ack_msg, len);
+
+    let motd = context.motd.lock().unwrap().clone();
+    if let Some(motd) = motd {
+        let msg = Message::Motd(motd);
+        let len = context.send_msg(&msg, client).await?;
+
+        context.record_msg(Direction::Sent, &client, &msg, len);
+    }
+
+    // Prediction has to move the player the way the speed check expects, and clamp and draw
+    // the world the way the server sees it
+    let msg = Message::Rules(context.config.rules);
+    let len = context.send_msg(&msg, client).await?;
+
+    context.record_msg(Direction::Sent, &client, &msg, len);
+
+    // Terrain follows every ACK, the client keeps retrying the handshake until it got both
+    let map_msg = Message::Map(context.config.map.clone());
+    let len = context.send_msg(&map_msg, client).await?;
+
+    context.record_msg(Direction::Sent, &client, &map_msg, len);
+
+    if let Some(msg) = own_avatar {
+        let len = context.send_msg(&msg, client).await?;
+
+        context.record_msg(Direction::Sent, &client, &msg, len);
+    }
+
+    // Clients assume the tick rate of the rules until told otherwise
+    let tick_rate = context.tick_rate.load(Ordering::Relaxed);
+    if tick_rate != context.config.rules.tick_rate {
+        let msg = Message::TickRateChange(tick_rate);
+        let len = context.send_msg(&msg, client).await?;
+
+        context.record_msg(Direction::Sent, &client, &msg, len);
+    }
+
+    // Same for the speed of time
+    let time_scale = context.clock.lock().unwrap().wire_scale();
+    if time_scale != 1.0 {
+        let msg = Message::TimeScale(time_scale);
+        let len = context.send_msg(&msg, client).await?;
+
+        context.record_msg(Direction::Sent, &client, &msg, len);
+    }
+
+    for msg in names {
+        let len = context.send_msg(&msg, client).await?;
+
+        context.record_msg(Direction::Sent, &client, &msg, len);
+    }
+
+    // A player of a loaded world spawns where they were when it was saved
+    if let Some(saved) = restored {
+        let msg = Message::Correction(saved.player.pos);
+        let len = context.send_msg(&msg, client).await?;
+
+        context.record_msg(Direction::Sent, &client, &msg, len);
+        context
+            .log(format!(
+                "Player {} of the loaded world is back from {client}",
+                saved.player.id
+            ))
+            .await;
+    }
+
+    Ok(())
+}
+
+// Anyone may ask, that's how the server list and friends list learn who is playing where
+async fn answer_status_query(context: &ServerContext, client: SocketAddr) {
+    let players = context.players.lock().await.len() as u32;
+    let msg = Message::Status(players, context.config.host_key, Some(Version::current()));
+
+    match context.send_msg(&msg, client).await {
+        Ok(len) => context.record_msg(Direction::Sent, &client, &msg, len),
+        Err(e) => {
+            context
+                .log(format!("Error answering status query of {client}: {e}"))
+                .await
+        }
+    }
+}
+
+// Forward relay traffic when running the relay service, or unwrap messages of relayed clients
+// arriving from our own relay. Returns the unwrapped message and the client it is from.
+async fn process_relay_packet(
+    context: &ServerContext,
+    from: SocketAddr,
+    packet: RelayPacket<'_>,
+) -> Option<(SocketAddr, Vec<u8>)> {
+    if let RelayPacket::From { source, payload } = packet {
+        if Some(from) != context.relay_addr {
+            return None;
+        }
+
+        context.relayed_clients.lock().unwrap().insert(source);
+        return Some((source, payload.as_bytes().to_vec()));
+    }
+
+    let (target, forwarded) = context
+        .relay_service
+        .as_ref()?
+        .lock()
+        .unwrap()
+        .handle(from, packet)?;
+
+    match context
+        .server_socket()
+        .send_to(forwarded.as_bytes(), target)
+        .await
+    {
+        Ok(len) => message::trace(format!("[RELAY] {from} -> {target} {len:>4} B")),
+        Err(e) => message::trace(format!("[RELAY] {from} -> {target} failed: {e}")),
+    }
+
+    None
+}
+
+// Keep the host registered with the relay and its NAT mapping towards the relay open
+pub(super) async fn relay_registration(context: Arc<ServerContext>, relay_addr: SocketAddr) {
+    let Ok(local_addr) = context.server_socket().local_addr() else {
+        return;
+    };
+    let packet = RelayPacket::Register {
+        game_port: local_addr.port(),
+    }
+    .serialize();
+
+    let mut interval = tokio::time::interval(globals::KEEP_ALIVE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = context
+            .server_socket()
+            .send_to(packet.as_bytes(), relay_addr)
+            .await
+        {
+            message::trace(format!("[RELAY] Failed to register with {relay_addr}: {e}"));
+        }
+    }
+}
+
+// Move the session holding `token` to the address the keep-alive came from. Home NATs may pick a
+// new source port mid-session, without this everything would keep going to the dead mapping.
+// Clients of a session the server doesn't have are asked to join again.
+async fn follow_address_change(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    token: SessionToken,
+) {
+    let mut players = context.players.lock().await;
+    if players.contains_key(&client) {
+        return;
+    }
+
+    let Some(previous_client) = players
+        .iter()
+        .find(|(_, connection)| connection.token == token)
+        .map(|(addr, _)| *addr)
+    else {
+        drop(players);
+        request_resync(&context, client).await;
+        return;
+    };
+
+    let Some(mut connection) = players.remove(&previous_client) else {
+        return;
+    };
+
+    connection.moved(client, context.ping_seq.load(Ordering::SeqCst));
+
+    let player_id = connection.player.id;
+    players.insert(client, connection);
+    drop(players);
+    context.set_codec(client, Some(context.codec_for(&previous_client)));
+    context.forget_relayed(&previous_client);
+
+    context
+        .log(format!(
+            "Player {player_id} address changed from {previous_client} to {client}"
+        ))
+        .await;
+}
+
+// Messages only a joined client sends
+fn needs_session(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::Pong(..)
+            | Message::Position(..)
+            | Message::Ability(_)
+            | Message::Name(..)
+            | Message::Chat(..)
+            | Message::Whisper(..)
+    )
+}
+
+// Hold a latency probe for the next tick, which answers it with where the probe spent its time
+async fn queue_probe(context: &ServerContext, client: SocketAddr, seq: u32, received_at: Instant) {
+    if let Some(connection) = context.players.lock().await.get_mut(&client) {
+        if connection.probes.len() < PENDING_PROBES_LEN {
+            connection.probes.push((seq, received_at, Instant::now()));
+        }
+    }
+}
+
+// Ask a client the server has no session for to join again, instead of leaving it to time out
+async fn request_resync(context: &ServerContext, client: SocketAddr) {
+    {
+        let now = Instant::now();
+        let mut resyncs_sent = context.resyncs_sent.lock().unwrap();
+        resyncs_sent.retain(|_, sent_at| now.duration_since(*sent_at) < RESYNC_INTERVAL);
+        if resyncs_sent.contains_key(&client) {
+            return;
+        }
+        resyncs_sent.insert(client, now);
+    }
+
+    let msg = Message::Resync;
+    match context.send_msg(&msg, client).await {
+        Ok(len) => context.record_msg(Direction::Sent, &client, &msg, len),
+        Err(e) => {
+            context
+                .log(format!("Error asking {client} to join again: {e}"))
+                .await
+        }
+    }
+}
+
+// Remember the name a player picked and tell everyone
+async fn set_player_name(
+    context: &ServerContext,
+    client: SocketAddr,
+    player_id: PlayerId,
+    name: String,
+) {
+    if !globals::is_valid_player_name(&name) {
+        return;
+    }
+
+    match context.players.lock().await.get_mut(&client) {
+        Some(connection) if connection.player.id == player_id => {
+            connection.name = Some(name.clone())
+        }
+        _ => return,
+    }
+
+    context
+        .log(format!(
+            "Player {player_id} ({client}) is now called {name}"
+        ))
+        .await;
+
+    let _ = context.broadcast(Message::Name(player_id, name), None);
+}
+
+// Pass a chat line on to everyone else, the sender shows its own line right away
+async fn relay_chat(context: &ServerContext, client: SocketAddr, player_id: PlayerId, text: &str) {
+    let Some(text) = chat_text(text) else {
+        return;
+    };
+
+    let is_sender = context
+        .players
+        .lock()
+        .await
+        .get(&client)
+        .is_some_and(|connection| connection.player.id == player_id);
+    if !is_sender {
+        return;
+    }
+
+    context.log(format!("Player {player_id}: {text}")).await;
+
+    let _ = context.broadcast(Message::Chat(player_id, text), Some(client));
+}
+
+// Deliver a whisper to the one player going by `target` only, or tell the sender why not
+async fn route_whisper(context: &ServerContext, client: SocketAddr, target: &str, text: &str) {
+    let Some(text) = chat_text(text) else {
+        return;
+    };
+
+    let players = context.players.lock().await;
+    let Some(sender_id) = players.get(&client).map(|connection| connection.player.id) else {
+        return;
+    };
+    let recipient = find_player_by_name(&players, target);
+    drop(players);
+
+    match recipient {
+        Ok((recipient, recipient_id)) => {
+            // Only who talked to whom, whispers are private
+            context
+                .log(format!(
+                    "Player {sender_id} whispered to player {recipient_id}"
+                ))
+                .await;
+
+            send_message(context, recipient, &Message::WhisperFrom(sender_id, text)).await;
+        }
+        Err(error) => {
+            let msg = Message::WhisperFailed(error, target.to_string());
+            send_message(context, client, &msg).await;
+        }
+    }
+}
+
+// Trimmed and cut to the maximum length, `None` when nothing is left
+fn chat_text(text: &str) -> Option<String> {
+    let text = text.trim();
+
+    (!text.is_empty()).then(|| text.chars().take(globals::MAX_CHAT_LEN).collect())
+}
+
+// Player going by `name`, ignoring case. A full name wins over players whose name merely starts
+// with it.
+fn find_player_by_name(
+    players: &PlayerMap,
+    name: &str,
+) -> Result<(SocketAddr, PlayerId), WhisperError> {
+    let name = name.to_lowercase();
+    let named = || {
+        players.iter().filter_map(|(addr, connection)| {
+            let player_name = connection.name.as_ref()?.to_lowercase();
+            Some((*addr, connection.player.id, player_name))
+        })
+    };
+
+    let mut matches: Vec<_> = named().filter(|(_, _, n)| *n == name).collect();
+    if matches.is_empty() {
+        matches = named().filter(|(_, _, n)| n.starts_with(&name)).collect();
+    }
+
+    match matches.as_slice() {
+        [(addr, player_id, _)] => Ok((*addr, *player_id)),
+        [] => Err(WhisperError::Unknown),
+        _ => Err(WhisperError::Ambiguous),
+    }
+}
+
+// Remove client when disconnect
+async fn drop_player(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    player_id: PlayerId,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut players = context.players.lock().await;
+    let connection = players.remove(&client);
+    context.player_count.send_replace(players.len());
+
+    drop(players);
+    context.forget_relayed(&client);
+    context
+        .log(format!("Player {player_id} left the server"))
+        .await;
+    if let Some(connection) = connection {
+        context.log_session_summary(&connection, "left").await;
+    }
+
+    context.broadcast(Message::Leave(player_id), Some(client))?;
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::{
+    fmt::Write as _,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::message::Direction;
+
+use super::{
+    context::{push_bounded, ServerContext},
+    ServerHandle,
+};
+
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+// An hour of samples, enough for a load test with the bots
+pub(super) const METRICS_HISTORY_LEN: usize = 3600;
+
+/// Load of the server over one sampling interval
+#[derive(Clone, Copy, Debug)]
+pub struct MetricsSample {
+    /// Time since the server started
+    pub uptime: Duration,
+    pub players: usize,
+    pub tick_rate: u32,
+
+    /// Mean and longest simulation tick of the interval, zero while nobody plays
+    pub tick_time_avg: Duration,
+    pub tick_time_max: Duration,
+
+    /// Bytes per second sent to and received from all clients
+    pub sent_rate: f64,
+    pub received_rate: f64,
+}
+
+/// Turns the server counters into samples, each covering the time since the previous one
+pub(super) struct MetricsSampler {
+    sampled_at: Instant,
+    tick: u64,
+    sent: u64,
+    received: u64,
+}
+
+impl MetricsSampler {
+    pub(super) fn new(context: &ServerContext) -> Self {
+        let (sent, received) = traffic_totals(context);
+        Self {
+            sampled_at: Instant::now(),
+            tick: context.tick.load(Ordering::Relaxed),
+            sent,
+            received,
+        }
+    }
+
+    /// Take a sample and add it to the server's metrics history
+    pub(super) async fn record(&mut self, context: &ServerContext) {
+        let sample = self.sample(context).await;
+        push_bounded(
+            &mut context.metrics_history.lock().unwrap(),
+            sample,
+            METRICS_HISTORY_LEN,
+        );
+    }
+
+    async fn sample(&mut self, context: &ServerContext) -> MetricsSample {
+        let now = Instant::now();
+        let elapsed = (now - self.sampled_at).as_secs_f64().max(f64::EPSILON);
+        let tick = context.tick.load(Ordering::Relaxed);
+        let (sent, received) = traffic_totals(context);
+
+        // Only the ticks run since the last sample, the history holds a couple of seconds
+        let ticks = (tick - self.tick) as usize;
+        let (tick_time_avg, tick_time_max) = {
+            let history = context.tick_history.lock().await;
+            let recent: Vec<Duration> = history.iter().rev().take(ticks).copied().collect();
+            match recent.len() {
+                0 => (Duration::ZERO, Duration::ZERO),
+                len => (
+                    recent.iter().sum::<Duration>() / len as u32,
+                    recent.iter().copied().max().unwrap_or_default(),
+                ),
+            }
+        };
+
+        let sample = MetricsSample {
+            uptime: now - context.started_at,
+            players: *context.player_count.borrow(),
+            tick_rate: context.tick_rate.load(Ordering::Relaxed),
+            tick_time_avg,
+            tick_time_max,
+            sent_rate: (sent - self.sent) as f64 / elapsed,
+            received_rate: (received - self.received) as f64 / elapsed,
+        };
+
+        *self = Self {
+            sampled_at: now,
+            tick,
+            sent,
+            received,
+        };
+        sample
+    }
+}
+
+// Bytes sent and received since the server started
+fn traffic_totals(context: &ServerContext) -> (u64, u64) {
+    context.message_stats.lock().unwrap().iter().fold(
+        (0, 0),
+        |(sent, received), (direction, _, stats)| match direction {
+            Direction::Sent => (sent + stats.bytes, received),
+            Direction::Received => (sent, received + stats.bytes),
+        },
+    )
+}
+
+/// Sample the server load every second for as long as the server runs
+pub(super) async fn metrics_sampler(context: Arc<ServerContext>) {
+    let mut sampler = MetricsSampler::new(&context);
+    let mut interval = tokio::time::interval(METRICS_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        sampler.record(&context).await;
+    }
+}
+
+/// Samples as CSV with a header row, times in milliseconds and traffic in bytes per second
+pub fn metrics_csv(samples: &[MetricsSample]) -> String {
+    let mut csv = String::from(
+        "uptime_sec,players,tick_rate,tick_ms_avg,tick_ms_max,sent_bytes_per_sec,\
+         received_bytes_per_sec\n",
+    );
+
+    for sample in samples {
+        let _ = writeln!(
+            csv,
+            "{:.1},{},{},{:.3},{:.3},{:.0},{:.0}",
+            sample.uptime.as_secs_f64(),
+            sample.players,
+            sample.tick_rate,
+            sample.tick_time_avg.as_secs_f64() * 1000.0,
+            sample.tick_time_max.as_secs_f64() * 1000.0,
+            sample.sent_rate,
+            sample.received_rate,
+        );
+    }
+
+    csv
+}
+
+impl ServerHandle {
+    /// Load samples of the last hour, oldest first
+    pub fn metrics_history(&self) -> Vec<MetricsSample> {
+        self.context
+            .metrics_history
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Sizes of what the server keeps per client or has queued. They go up and down with the
+    /// players but must not keep growing with every player that ever joined, see `bench --soak`.
+    pub async fn resource_counts(&self) -> Vec<(&'static str, usize)> {
+        let context = &self.context;
+        let (players, outboxes, inputs) = {
+            let players = context.players.lock().await;
+            (
+                players.len(),
+                players
+                    .values()
+                    .map(|connection| connection.outbox.queue.lock().unwrap().len())
+                    .sum(),
+                players
+                    .values()
+                    .map(|connection| connection.inputs.depth())
+                    .sum(),
+            )
+        };
+
+        vec![
+            ("players", players),
+            ("outbox messages", outboxes),
+            ("buffered inputs", inputs),
+            (
+                "broadcast queue",
+                context.broadcast_queue_depth.load(Ordering::Relaxed),
+            ),
+            (
+                "receive queue",
+                context.receive_queue_depth.load(Ordering::Relaxed),
+            ),
+            ("resyncs sent", context.resyncs_sent.lock().unwrap().len()),
+            ("saved players", context.saved_players.lock().unwrap().len()),
+            ("local clients", context.local_clients.lock().unwrap().len()),
+        ]
+    }
+}
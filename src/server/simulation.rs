@@ -0,0 +1,489 @@
+use std::{
+    error::Error,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use cgmath::{InnerSpace, Vector2};
+
+use egui::ahash::{HashMap, HashMapExt};
+use game_server_sample::{globals, simulate_player, simulation, Player, PlayerId};
+
+use crate::{
+    anticheat::Violation,
+    message::{self, Ability, Message},
+};
+
+use super::{
+    admin::kick_player,
+    broadcast::send_message,
+    context::{push_bounded, Connection, Outgoing, PlayerMap, ServerContext, TICK_HISTORY_LEN},
+    ServerConfig,
+};
+
+// Dashes timed by the client's clock can arrive this much early through network jitter
+const DASH_COOLDOWN_TOLERANCE: Duration = Duration::from_millis(200);
+
+/// Lowers the simulation rate when ticks keep exceeding their budget and restores it once the
+/// server has been comfortably fast for a while
+pub(super) struct TickRateGovernor {
+    level: usize,
+
+    // Level of the configured rate, never stepped up past
+    top_level: usize,
+
+    // Ticks over budget minus ticks within budget, never below zero
+    overload: u32,
+
+    // Consecutive ticks that would have fit into half the budget of the next higher rate
+    calm: u32,
+}
+
+impl TickRateGovernor {
+    // Net half a second of blown ticks before stepping down
+    const STEP_DOWN_SEC: f32 = 0.5;
+    // Five seconds of headroom before stepping back up
+    const STEP_UP_SEC: u32 = 5;
+
+    pub(super) fn new(max_tick_rate: u32) -> Self {
+        let top_level = globals::SERVER_TICK_RATES
+            .iter()
+            .position(|rate| *rate <= max_tick_rate)
+            .unwrap_or(globals::SERVER_TICK_RATES.len() - 1);
+
+        Self {
+            level: top_level,
+            top_level,
+            overload: 0,
+            calm: 0,
+        }
+    }
+
+    pub(super) fn tick_rate(&self) -> u32 {
+        globals::SERVER_TICK_RATES[self.level]
+    }
+
+    fn tick_budget(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.tick_rate() as f32)
+    }
+
+    /// Feed the duration of the last tick. Returns the new tick rate if it changed.
+    fn record(&mut self, elapsed: Duration) -> Option<u32> {
+        let rate = self.tick_rate();
+
+        if elapsed > self.tick_budget() {
+            self.overload += 1;
+        } else {
+            self.overload = self.overload.saturating_sub(1);
+        }
+
+        if self.overload as f32 >= rate as f32 * Self::STEP_DOWN_SEC
+            && self.level + 1 < globals::SERVER_TICK_RATES.len()
+        {
+            self.level += 1;
+            self.overload = 0;
+            self.calm = 0;
+            return Some(self.tick_rate());
+        }
+
+        if self.level > self.top_level {
+            let higher_budget =
+                Duration::from_secs_f32(1.0 / globals::SERVER_TICK_RATES[self.level - 1] as f32);
+
+            if elapsed < higher_budget / 2 {
+                self.calm += 1;
+            } else {
+                self.calm = 0;
+            }
+
+            if self.calm >= rate * Self::STEP_UP_SEC {
+                self.level -= 1;
+                self.overload = 0;
+                self.calm = 0;
+                return Some(self.tick_rate());
+            }
+        }
+
+        None
+    }
+}
+
+/// Authoritative game update logic simulation - Game loop
+///
+/// Required fixed processing, because timing has to be synchronized accross all the connected
+/// clients. A server simulation loop does not need to play "catch-up" like a local game loop does
+/// because there no point in sending stale state
+pub(super) async fn simulation_handler(context: Arc<ServerContext>) {
+    let mut governor = TickRateGovernor::new(context.config.rules.tick_rate);
+    let mut desired_frame_duration = governor.tick_budget();
+
+    let mut interval = tokio::time::interval(desired_frame_duration);
+
+    // Simulation time owed to the world, ticks in slow motion only step it every so often
+    let mut sim_lag = Duration::ZERO;
+
+    interval.tick().await;
+
+    loop {
+        sim_lag += context
+            .clock
+            .lock()
+            .unwrap()
+            .advance(desired_frame_duration);
+        if sim_lag < desired_frame_duration {
+            interval.tick().await;
+            continue;
+        }
+        sim_lag -= desired_frame_duration;
+
+        let current_time = std::time::Instant::now();
+
+        // Add new scope here so when finish the lock will be release
+        let mut outcomes = Vec::new();
+        let tick = context.tick.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut players = context.players.lock().await;
+            for (client_addr, connection) in players.iter_mut() {
+                // Buffered positions due by now, at the pace the client produced them
+                for pos in connection.inputs.pop_due(current_time) {
+                    outcomes.push((
+                        *client_addr,
+                        apply_position(connection, pos, &context.config),
+                    ));
+                }
+
+                // Answered through the client's outbox, so the reply queues like everything else
+                for (seq, received_at, handled_at) in connection.probes.drain(..) {
+                    let reply = Message::ProbeReply(
+                        seq,
+                        handled_at - received_at,
+                        current_time.saturating_duration_since(handled_at),
+                    );
+                    connection.outbox.push(Arc::new(Outgoing::new(reply)));
+                }
+
+                let replication = simulate_player(
+                    &mut connection.player,
+                    context.config.world_mode,
+                    &context.config.rules,
+                );
+                let _ = context.broadcast(replication, Some(*client_addr));
+            }
+
+            for knockback in knock_back_colliding_players(&mut players, &context.config) {
+                let _ = context.broadcast(knockback, None);
+            }
+
+            let positions = players
+                .values()
+                .map(|connection| (connection.player.id, connection.player.pos))
+                .collect();
+            context
+                .history
+                .lock()
+                .unwrap()
+                .record(tick, current_time, positions);
+        }
+
+        for (client, outcome) in outcomes {
+            finish_move(context.clone(), client, outcome).await;
+        }
+
+        // World clock and scoreboard once per second, clients run the clock forward on their
+        // own in between
+        if tick.is_multiple_of(governor.tick_rate() as u64) {
+            let _ = context.broadcast(Message::WorldClock(context.time_of_day()), None);
+
+            for connection in context.players.lock().await.values() {
+                let _ = context.broadcast(
+                    Message::Stats(
+                        connection.player.id,
+                        connection.distance_traveled,
+                        connection.joined_at.elapsed().as_secs() as u32,
+                    ),
+                    None,
+                );
+            }
+
+            if let Some(idle_kick) = context.config.idle_kick {
+                kick_idle_players(&context, idle_kick).await;
+            }
+        }
+
+        // Calcualte the time has passed, if the update happendes too fast then the
+        // tick will wait until the next tick to continue the loop
+        let elapsed_time = current_time.elapsed();
+        push_bounded(
+            &mut *context.tick_history.lock().await,
+            elapsed_time,
+            TICK_HISTORY_LEN,
+        );
+
+        // Trade update frequency for stability when the server cannot keep up
+        if let Some(tick_rate) = governor.record(elapsed_time) {
+            let previous = context.tick_rate.swap(tick_rate, Ordering::Relaxed);
+            context
+                .log(format!(
+                    "Tick rate changed from {previous} Hz to {tick_rate} Hz"
+                ))
+                .await;
+
+            let _ = context.broadcast(Message::TickRateChange(tick_rate), None);
+
+            desired_frame_duration = governor.tick_budget();
+            interval = tokio::time::interval(desired_frame_duration);
+            interval.tick().await;
+        }
+
+        if elapsed_time < desired_frame_duration {
+            interval.tick().await;
+        }
+    }
+}
+
+// Update user position if they moved. Positions stamped with the client's simulation step wait
+// in the input buffer until the simulation gets to them.
+pub(super) async fn update_position(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    player_id: PlayerId,
+    new_pos: Vector2<f32>,
+    step: Option<u32>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let outcome = match context.players.lock().await.get_mut(&client) {
+        Some(connection) if connection.player.id == player_id => match step {
+            Some(step) => {
+                connection.inputs.push(step, new_pos, Instant::now());
+                MoveOutcome::default()
+            }
+            None => apply_position(connection, new_pos, &context.config),
+        },
+        _ => MoveOutcome::default(),
+    };
+
+    finish_move(context, client, outcome).await;
+
+    Ok(())
+}
+
+/// What applying a reported position led to, dealt with once the player map is unlocked
+#[derive(Default)]
+struct MoveOutcome {
+    violation: Option<Violation>,
+
+    /// Where the player ended up instead of the reported position
+    correction: Option<Vector2<f32>>,
+}
+
+fn apply_position(
+    connection: &mut Connection,
+    new_pos: Vector2<f32>,
+    config: &ServerConfig,
+) -> MoveOutcome {
+    let player = &mut connection.player;
+
+    let delta = config
+        .rules
+        .world_delta(player.pos, new_pos, config.world_mode);
+    let distance = delta.magnitude();
+    if distance > 0.0 {
+        connection.last_input = Instant::now();
+    }
+    player.turn_towards(delta);
+
+    let violation = connection
+        .cheat
+        .on_position(distance, &config.cheat, &config.rules.movement);
+
+    // Only the allowed part of a too fast move is taken, the client is told where it ended up
+    // instead
+    match violation {
+        Some(Violation::Speed { allowed, .. }) => {
+            player.pos = message::snap_position(player.pos + delta * (allowed / distance));
+            config.rules.apply_world_bounds(player, config.world_mode);
+            connection.distance_traveled += allowed;
+
+            MoveOutcome {
+                violation,
+                correction: Some(player.pos),
+            }
+        }
+        _ => {
+            player.pos = new_pos;
+            connection.distance_traveled += distance;
+
+            MoveOutcome {
+                violation,
+                correction: None,
+            }
+        }
+    }
+}
+
+/// Push overlapping players apart. Each knocked back player's client moves it with the impulse,
+/// so the speed check lets the extra distance through.
+fn knock_back_colliding_players(players: &mut PlayerMap, config: &ServerConfig) -> Vec<Message> {
+    let now = Instant::now();
+    let ready = |connection: &Connection| {
+        connection
+            .last_knockback
+            .is_none_or(|at| now.duration_since(at) >= globals::KNOCKBACK_COOLDOWN)
+    };
+
+    let candidates: Vec<Player> = players
+        .values()
+        .filter(|connection| ready(connection))
+        .map(|connection| connection.player)
+        .collect();
+
+    let mut impulses = HashMap::new();
+    for (i, player) in candidates.iter().enumerate() {
+        for other in &candidates[i + 1..] {
+            if let (Some(pushed), Some(pushed_other)) = (
+                simulation::knockback(player, other, config.world_mode, &config.rules),
+                simulation::knockback(other, player, config.world_mode, &config.rules),
+            ) {
+                *impulses.entry(player.id).or_insert(Vector2::new(0.0, 0.0)) += pushed;
+                *impulses.entry(other.id).or_insert(Vector2::new(0.0, 0.0)) += pushed_other;
+            }
+        }
+    }
+
+    players
+        .values_mut()
+        .filter_map(|connection| {
+            let impulse = *impulses.get(&connection.player.id)?;
+            connection.last_knockback = Some(now);
+            connection
+                .cheat
+                .grant_movement(simulation::knockback_distance(
+                    impulse,
+                    &config.rules.movement,
+                ));
+
+            Some(Message::Knockback(connection.player.id, impulse))
+        })
+        .collect()
+}
+
+async fn finish_move(context: Arc<ServerContext>, client: SocketAddr, outcome: MoveOutcome) {
+    if let Some(pos) = outcome.correction {
+        send_message(&context, client, &Message::Correction(pos)).await;
+    }
+
+    if let Some(violation) = outcome.violation {
+        report_violation(context, client, violation).await;
+    }
+}
+
+// Raise the client's suspicion score, tell the admin once it crosses the flag score and kick
+// it once it crosses the kick score
+pub(super) async fn report_violation(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    violation: Violation,
+) {
+    let config = &context.config.cheat;
+
+    let mut players = context.players.lock().await;
+    let Some(connection) = players.get_mut(&client) else {
+        return;
+    };
+
+    let player_id = connection.player.id;
+    let score = connection.cheat.score();
+    message::trace(format!(
+        "Player {player_id} ({client}) {violation:?}, suspicion {score:.1}"
+    ));
+
+    let newly_flagged = score >= config.flag_score && !connection.cheat.reported;
+    if newly_flagged {
+        connection.cheat.reported = true;
+    }
+
+    let speed_violations = connection.cheat.speed_violations;
+    let rate_violations = connection.cheat.rate_violations;
+    drop(players);
+
+    if newly_flagged {
+        context
+            .log(format!(
+                "Player {player_id} ({client}) flagged as suspicious: score {score:.1}, \
+                 {speed_violations} speed and {rate_violations} rate violations"
+            ))
+            .await;
+    }
+
+    if config
+        .kick_score
+        .is_some_and(|kick_score| score >= kick_score)
+    {
+        kick_player(
+            &context,
+            client,
+            None,
+            &format!("suspicion score {score:.1}"),
+        )
+        .await;
+    }
+}
+
+// Accept a dash once its cooldown is over: the jump the client made passes the speed check, and
+// everyone else is told so they can show it. A dash during the cooldown gets corrected like any
+// other too fast move.
+pub(super) async fn use_ability(context: &ServerContext, client: SocketAddr, ability: Ability) {
+    let mut players = context.players.lock().await;
+    let Some(connection) = players.get_mut(&client) else {
+        return;
+    };
+
+    match ability {
+        Ability::Dash => {
+            let now = Instant::now();
+            let ready = connection.last_dash.is_none_or(|at| {
+                now.duration_since(at) + DASH_COOLDOWN_TOLERANCE >= globals::DASH_COOLDOWN
+            });
+            if !ready {
+                message::trace(format!(
+                    "Player {} ({client}) dashed during the cooldown",
+                    connection.player.id
+                ));
+                return;
+            }
+
+            connection.last_dash = Some(now);
+            connection.cheat.grant_movement(globals::DASH_DISTANCE);
+
+            let player = connection.player;
+            drop(players);
+
+            let _ = context.broadcast(
+                Message::Dash(player.id, player.pos, player.facing),
+                Some(client),
+            );
+        }
+    }
+}
+
+// Remove everyone who hasn't moved within `idle_kick`
+async fn kick_idle_players(context: &ServerContext, idle_kick: Duration) {
+    let idle: Vec<SocketAddr> = context
+        .players
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, connection)| connection.last_input.elapsed() >= idle_kick)
+        .map(|(client, _)| *client)
+        .collect();
+
+    for client in idle {
+        kick_player(
+            context,
+            client,
+            Some(message::KICK_IDLE),
+            &format!("idle for {} seconds", idle_kick.as_secs()),
+        )
+        .await;
+    }
+}
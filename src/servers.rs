@@ -0,0 +1,215 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+// Older ones drop off the list, favorites stay until unstarred
+const MAX_RECENT_SERVERS: usize = 8;
+
+/// Servers listed in the menu: favorites starred by the player, kept in the user config, and
+/// the ones joined lately, kept in the data directory
+pub struct ServerList {
+    config_path: PathBuf,
+    recent_path: PathBuf,
+    favorites: Vec<String>,
+
+    /// Most recent first
+    recent: Vec<String>,
+}
+
+impl ServerList {
+    pub fn load(config_path: PathBuf, recent_path: PathBuf) -> Self {
+        let favorites = read_json(&config_path)
+            .map(|config| addresses(&config["favorite_servers"]))
+            .unwrap_or_default();
+        let recent = read_json(&recent_path)
+            .map(|recent| addresses(&recent))
+            .unwrap_or_default();
+
+        Self {
+            config_path,
+            recent_path,
+            favorites,
+            recent,
+        }
+    }
+
+    /// Every listed server once, favorites first
+    pub fn addresses(&self) -> Vec<String> {
+        let mut addresses = self.favorites.clone();
+        for address in &self.recent {
+            if !addresses.contains(address) {
+                addresses.push(address.clone());
+            }
+        }
+
+        addresses
+    }
+
+    pub fn is_favorite(&self, address: &str) -> bool {
+        self.favorites.iter().any(|favorite| favorite == address)
+    }
+
+    /// Star or unstar a server
+    pub fn toggle_favorite(&mut self, address: &str) -> io::Result<()> {
+        if self.is_favorite(address) {
+            self.favorites.retain(|favorite| favorite != address);
+        } else {
+            self.favorites.push(address.to_string());
+        }
+
+        self.save_favorites()
+    }
+
+    /// Star the favorites of another config, e.g. one dropped onto the window. Returns how many
+    /// were new.
+    pub fn import_favorites(&mut self, config: &Value) -> io::Result<usize> {
+        let mut added = 0;
+        for address in addresses(&config["favorite_servers"]) {
+            if !self.is_favorite(&address) {
+                self.favorites.push(address);
+                added += 1;
+            }
+        }
+
+        if added > 0 {
+            self.save_favorites()?;
+        }
+
+        Ok(added)
+    }
+
+    fn save_favorites(&self) -> io::Result<()> {
+        // Other settings may live in the same file, only the favorites are replaced
+        let mut config = read_json(&self.config_path)
+            .filter(Value::is_object)
+            .unwrap_or_else(|| json!({}));
+        config["favorite_servers"] = json!(self.favorites);
+
+        write_json(&self.config_path, &config)
+    }
+
+    /// Move a server just joined to the top of the recent ones
+    pub fn joined(&mut self, address: &str) -> io::Result<()> {
+        self.recent.retain(|recent| recent != address);
+        self.recent.insert(0, address.to_string());
+        self.recent.truncate(MAX_RECENT_SERVERS);
+
+        write_json(&self.recent_path, &json!(self.recent))
+    }
+}
+
+/// Settings of a config file from elsewhere, e.g. dropped onto the window. TOML, or JSON like
+/// the user config.
+pub fn read_config_file(path: &Path) -> io::Result<Value> {
+    let text = std::fs::read_to_string(path)?;
+
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    } else {
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+pub fn read_json(path: &PathBuf) -> Option<Value> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+pub fn write_json(path: &PathBuf, value: &Value) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    std::fs::write(path, serde_json::to_string_pretty(value)?)
+}
+
+fn addresses(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| entry.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("game-server-sample-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    #[test]
+    fn favorites_are_kept_next_to_other_settings() {
+        let dir = scratch_dir("favorites");
+        let config = dir.join("config.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&config, r#"{"language": "vi"}"#).unwrap();
+
+        let mut servers = ServerList::load(config.clone(), dir.join("recent.json"));
+        servers.joined("10.0.0.2:8080").unwrap();
+        servers.joined("10.0.0.1:8080").unwrap();
+        servers.toggle_favorite("10.0.0.2:8080").unwrap();
+
+        let reloaded = ServerList::load(config.clone(), dir.join("recent.json"));
+        assert_eq!(reloaded.addresses(), ["10.0.0.2:8080", "10.0.0.1:8080"]);
+        assert!(reloaded.is_favorite("10.0.0.2:8080"));
+        assert_eq!(read_json(&config).unwrap()["language"], "vi");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn imported_favorites_are_added_once() {
+        let dir = scratch_dir("import");
+        let config = dir.join("config.json");
+
+        let mut servers = ServerList::load(config.clone(), dir.join("recent.json"));
+        servers.toggle_favorite("10.0.0.1:8080").unwrap();
+
+        let dropped = json!({"favorite_servers": ["10.0.0.1:8080", "10.0.0.2:8080"]});
+        assert_eq!(servers.import_favorites(&dropped).unwrap(), 1);
+        assert_eq!(servers.import_favorites(&dropped).unwrap(), 0);
+
+        let reloaded = ServerList::load(config, dir.join("recent.json"));
+        assert_eq!(reloaded.addresses(), ["10.0.0.1:8080", "10.0.0.2:8080"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_config_files_are_read_as_toml_or_json() {
+        let dir = scratch_dir("dropped");
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml_file = dir.join("friends.toml");
+        let json_file = dir.join("friends.json");
+        std::fs::write(
+            &toml_file,
+            "language = \"vi\"\nfavorite_servers = [\"10.0.0.1:8080\"]\n",
+        )
+        .unwrap();
+        std::fs::write(&json_file, r#"{"favorite_servers": ["10.0.0.1:8080"]}"#).unwrap();
+
+        let from_toml = read_config_file(&toml_file).unwrap();
+        assert_eq!(from_toml["language"], "vi");
+        assert_eq!(
+            addresses(&from_toml["favorite_servers"]),
+            addresses(&read_config_file(&json_file).unwrap()["favorite_servers"])
+        );
+
+        std::fs::write(&toml_file, "favorite_servers = [").unwrap();
+        assert!(read_config_file(&toml_file).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
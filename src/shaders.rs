@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+
+use crate::assets;
+#[cfg(debug_assertions)]
+use {crate::paths, std::path::PathBuf};
+
+/// Shader programs of the renderer, each built from a `.vert` and a `.frag` file named after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Shader {
+    Grid,
+    Quad,
+    Text,
+    Particle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Vertex,
+    Fragment,
+}
+
+impl Shader {
+    pub fn name(self) -> &'static str {
+        match self {
+            Shader::Grid => "grid",
+            Shader::Quad => "quad",
+            Shader::Text => "text",
+            Shader::Particle => "particle",
+        }
+    }
+
+    /// Source from the asset files if there are any, the built-in copy otherwise
+    pub fn source(self, stage: Stage) -> Cow<'static, str> {
+        match assets::read(&self.asset_name(stage)) {
+            Ok(Cow::Owned(bytes)) => String::from_utf8_lossy(&bytes).into_owned().into(),
+            Ok(Cow::Borrowed(bytes)) => String::from_utf8_lossy(bytes),
+            Err(e) => {
+                eprintln!("{e}");
+                self.builtin_source(stage)
+            }
+        }
+    }
+
+    /// Source the executable was built with
+    pub fn builtin_source(self, stage: Stage) -> Cow<'static, str> {
+        let bytes = assets::embedded(&self.asset_name(stage)).expect("Shader is not built in");
+
+        String::from_utf8_lossy(bytes)
+    }
+
+    fn asset_name(self, stage: Stage) -> String {
+        let extension = match stage {
+            Stage::Vertex => "vert",
+            Stage::Fragment => "frag",
+        };
+
+        format!("shaders/{}.{extension}", self.name())
+    }
+
+    /// Program a changed shader file belongs to
+    #[cfg(debug_assertions)]
+    fn from_path(path: &std::path::Path) -> Option<Shader> {
+        let stem = path.file_stem()?.to_str()?;
+        let extension = path.extension()?.to_str()?;
+
+        [Shader::Grid, Shader::Quad, Shader::Text, Shader::Particle]
+            .into_iter()
+            .find(|shader| shader.name() == stem && matches!(extension, "vert" | "frag"))
+    }
+}
+
+/// Watches the shader directories in debug builds, so the renderer can rebuild programs whose
+/// sources changed while the game runs
+#[cfg(debug_assertions)]
+pub struct ShaderWatcher {
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+#[cfg(debug_assertions)]
+impl ShaderWatcher {
+    /// `None` without a shader directory to watch, e.g. when not started from the source tree
+    pub fn new() -> Option<Self> {
+        use notify::Watcher;
+
+        let dirs: Vec<_> = [paths::asset_dir(), PathBuf::from(assets::LOCAL_ASSET_DIR)]
+            .into_iter()
+            .map(|dir| dir.join("shaders"))
+            .filter(|dir| dir.is_dir())
+            .collect();
+        if dirs.is_empty() {
+            return None;
+        }
+
+        let (tx, events) = std::sync::mpsc::channel();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                eprintln!("Failed to watch the shaders: {e}");
+                return None;
+            }
+        };
+
+        for dir in dirs {
+            if let Err(e) = watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+                eprintln!("Failed to watch {}: {e}", dir.display());
+                return None;
+            }
+            println!("Watching {} for shader changes", dir.display());
+        }
+
+        Some(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Programs with a source file written to since the last call, each once
+    pub fn changed(&self) -> Vec<Shader> {
+        let mut changed = Vec::new();
+
+        for event in self.events.try_iter().flatten() {
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                continue;
+            }
+
+            for shader in event
+                .paths
+                .iter()
+                .filter_map(|path| Shader::from_path(path))
+            {
+                if !changed.contains(&shader) {
+                    changed.push(shader);
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn changed_files_map_to_their_program() {
+        assert_eq!(
+            Shader::from_path(Path::new("assets/shaders/quad.frag")),
+            Some(Shader::Quad)
+        );
+        assert_eq!(
+            Shader::from_path(Path::new("/tmp/x/text.vert")),
+            Some(Shader::Text)
+        );
+
+        // Editor swap and backup files don't count
+        assert_eq!(Shader::from_path(Path::new("grid.frag.swp")), None);
+        assert_eq!(Shader::from_path(Path::new("grid.frag~")), None);
+    }
+}
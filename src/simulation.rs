@@ -0,0 +1,206 @@
+use cgmath::{InnerSpace, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    globals,
+    message::{self, Message},
+    rules::GameRules,
+    simulate_player,
+    terrain::{TerrainMap, Traction},
+    Player, WorldMode,
+};
+
+/// How players move, part of the [`GameRules`] a server sends to every client joining it, so
+/// prediction and the server's checks use the same numbers. All per fixed update step on normal
+/// ground.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MovementConfig {
+    /// Top speed
+    pub speed: f32,
+
+    /// Velocity gained towards where the player wants to go
+    pub acceleration: f32,
+
+    /// Share of its velocity a player loses once it lets go of the keys
+    pub friction: f32,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            speed: globals::PLAYER_SPEED,
+            acceleration: globals::PLAYER_ACCELERATION,
+            friction: globals::PLAYER_FRICTION,
+        }
+    }
+}
+
+impl MovementConfig {
+    /// Whether the numbers make for movement at all: positive, and friction doesn't reverse
+    /// the velocity
+    pub fn is_valid(&self) -> bool {
+        [self.speed, self.acceleration, self.friction]
+            .iter()
+            .all(|value| value.is_finite() && *value > 0.0)
+            && self.friction <= 1.0
+    }
+}
+
+/// Move a player for one fixed update step. Client prediction and anything replaying a client's
+/// inputs go through here, so they can't drift apart.
+pub fn step_player(
+    player: &mut Player,
+    direction: Vector2<f32>,
+    map: &TerrainMap,
+    world_mode: WorldMode,
+    rules: &GameRules,
+) {
+    let movement = &rules.movement;
+    let traction = map.terrain_at(player.pos).traction();
+    accelerate(player, direction, traction, movement);
+    player.pos += player.velocity + player.impulse;
+    player.impulse = apply_friction(player.impulse, traction, movement);
+
+    // Stay on the wire grid, so the server gets the exact position this step ended at
+    player.pos = message::snap_position(player.pos);
+    rules.apply_world_bounds(player, world_mode);
+
+    // Same turn the server makes from the reported move
+    player.turn_towards(player.velocity);
+}
+
+/// Speed up towards `direction` (normalized or zero), or slow down without one. The terrain under
+/// the player scales top speed, acceleration and friction.
+fn accelerate(
+    player: &mut Player,
+    direction: Vector2<f32>,
+    traction: Traction,
+    movement: &MovementConfig,
+) {
+    let max_speed = movement.speed * traction.max_speed;
+
+    if direction == Vector2::new(0.0, 0.0) {
+        player.velocity = apply_friction(player.velocity, traction, movement);
+    } else {
+        // Steer towards the wanted velocity by at most one step of acceleration
+        let change = direction * max_speed - player.velocity;
+        let acceleration = movement.acceleration * traction.acceleration;
+        player.velocity += match change.magnitude() {
+            m if m > acceleration => change.normalize_to(acceleration),
+            _ => change,
+        };
+    }
+
+    // Never faster than on normal ground, e.g. right after sliding off ice
+    player.velocity = match player.velocity.magnitude() {
+        m if m > movement.speed => player.velocity.normalize_to(movement.speed),
+        m if m < globals::PLAYER_MIN_SPEED => Vector2::new(0.0, 0.0),
+        _ => player.velocity,
+    };
+}
+
+/// One step of friction, stopping altogether once it gets slow
+fn apply_friction(
+    velocity: Vector2<f32>,
+    traction: Traction,
+    movement: &MovementConfig,
+) -> Vector2<f32> {
+    match velocity * (1.0 - movement.friction * traction.friction) {
+        v if v.magnitude() < globals::PLAYER_MIN_SPEED => Vector2::new(0.0, 0.0),
+        v => v,
+    }
+}
+
+/// Impulse knocking `player` away from `other` when they overlap, as circles as wide as their
+/// avatars. Players on the same spot get pushed apart along x, the lower id to the left. The
+/// impulse is on the wire grid, so every client moves the same way with it.
+pub fn knockback(
+    player: &Player,
+    other: &Player,
+    world_mode: WorldMode,
+    rules: &GameRules,
+) -> Option<Vector2<f32>> {
+    let away = rules.world_delta(other.pos, player.pos, world_mode);
+    let distance = away.magnitude();
+    if distance >= (rules.size_of(player) + rules.size_of(other)) / 2.0 {
+        return None;
+    }
+
+    let direction = match distance {
+        0.0 if player.id < other.id => Vector2::new(-1.0, 0.0),
+        0.0 => Vector2::new(1.0, 0.0),
+        _ => away / distance,
+    };
+
+    Some(message::snap_position(
+        direction * globals::KNOCKBACK_IMPULSE,
+    ))
+}
+
+/// How much further than its own movement an impulse takes a player on normal ground, the server
+/// lets that much through the speed check
+pub fn knockback_distance(impulse: Vector2<f32>, movement: &MovementConfig) -> f32 {
+    impulse.magnitude() / movement.friction
+}
+
+/// Jump [`globals::DASH_DISTANCE`] the way the player faces. The client does this right away
+/// when the player dashes, the server only accepts the jump after checking the cooldown.
+pub fn dash(player: &mut Player, world_mode: WorldMode, rules: &GameRules) {
+    let direction = Vector2::new(player.facing.cos(), player.facing.sin());
+
+    player.pos = message::snap_position(player.pos + direction * globals::DASH_DISTANCE);
+    rules.apply_world_bounds(player, world_mode);
+}
+
+/// Take a position reported by the client the way the server does: through the wire format,
+/// then the server's simulation tick
+pub fn server_apply(
+    player: &mut Player,
+    reported: &Player,
+    step: u32,
+    world_mode: WorldMode,
+    rules: &GameRules,
+) {
+    let wire = Message::Position(reported.id, reported.pos, Some(step)).serialize();
+    if let Ok(Message::Position(_, pos, _)) = Message::deserialize(&wire) {
+        player.pos = pos;
+    }
+
+    simulate_player(player, world_mode, rules);
+}
+
+/// Debug check of one predicted step: replay it from `before` and panic if the result differs
+/// from `after` in any bit, e.g. because the step picked up state outside the player, or if the
+/// server would end up somewhere else
+pub fn check_step(
+    before: &Player,
+    direction: Vector2<f32>,
+    map: &TerrainMap,
+    world_mode: WorldMode,
+    rules: &GameRules,
+    after: &Player,
+) {
+    let mut replayed = *before;
+    step_player(&mut replayed, direction, map, world_mode, rules);
+
+    assert!(
+        same_bits(after.pos, replayed.pos) && same_bits(after.velocity, replayed.velocity),
+        "predicted step is not deterministic: {:?} replayed as {:?}",
+        after.pos,
+        replayed.pos
+    );
+
+    let mut server = *before;
+    server_apply(&mut server, after, 0, world_mode, rules);
+    assert!(
+        same_bits(after.pos, server.pos),
+        "server ends up at {:?} instead of the predicted {:?}",
+        server.pos,
+        after.pos
+    );
+}
+
+/// Exact equality, unlike `==` it tells apart 0.0 and -0.0
+pub fn same_bits(a: Vector2<f32>, b: Vector2<f32>) -> bool {
+    a.x.to_bits() == b.x.to_bits() && a.y.to_bits() == b.y.to_bits()
+}
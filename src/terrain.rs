@@ -0,0 +1,210 @@
+use std::{fmt::Write as _, io::Error};
+
+use cgmath::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{codec::Vector2Def, globals, message::Message};
+
+/// Ground type changing how players move over it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Terrain {
+    Normal,
+
+    /// Halves the movement speed
+    Mud,
+
+    /// Almost no friction, players keep sliding and turn slowly
+    Ice,
+}
+
+const MUD_SPEED_FACTOR: f32 = 0.5;
+
+// Ice only lets players speed up, slow down and steer with a fraction of the usual grip
+const ICE_ACCELERATION_FACTOR: f32 = 0.1;
+const ICE_FRICTION_FACTOR: f32 = 0.08;
+
+/// How a terrain changes movement, as factors on the values for normal ground in
+/// [`crate::globals`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Traction {
+    pub max_speed: f32,
+    pub acceleration: f32,
+    pub friction: f32,
+}
+
+impl Terrain {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Terrain::Normal => "normal",
+            Terrain::Mud => "mud",
+            Terrain::Ice => "ice",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "normal" => Some(Terrain::Normal),
+            "mud" => Some(Terrain::Mud),
+            "ice" => Some(Terrain::Ice),
+            _ => None,
+        }
+    }
+
+    pub fn traction(self) -> Traction {
+        match self {
+            Terrain::Normal => Traction {
+                max_speed: 1.0,
+                acceleration: 1.0,
+                friction: 1.0,
+            },
+            Terrain::Mud => Traction {
+                max_speed: MUD_SPEED_FACTOR,
+                acceleration: 1.0,
+                friction: 1.0,
+            },
+            Terrain::Ice => Traction {
+                max_speed: 1.0,
+                acceleration: ICE_ACCELERATION_FACTOR,
+                friction: ICE_FRICTION_FACTOR,
+            },
+        }
+    }
+
+    /// Tint the terrain is drawn with
+    pub fn color(self) -> Vector3<f32> {
+        match self {
+            Terrain::Normal => Vector3::new(0.0, 0.0, 0.0),
+            Terrain::Mud => Vector3::new(0.36, 0.25, 0.13),
+            Terrain::Ice => Vector3::new(0.55, 0.8, 0.95),
+        }
+    }
+}
+
+/// Axis aligned rectangle of terrain
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainZone {
+    pub terrain: Terrain,
+    #[serde(with = "Vector2Def")]
+    pub min: Vector2<f32>,
+    #[serde(with = "Vector2Def")]
+    pub max: Vector2<f32>,
+}
+
+impl TerrainZone {
+    pub fn contains(&self, pos: Vector2<f32>) -> bool {
+        pos.x >= self.min.x && pos.x < self.max.x && pos.y >= self.min.y && pos.y < self.max.y
+    }
+}
+
+/// Terrain layout of the world. Anything not covered by a zone is normal ground, overlapping
+/// zones are resolved in favour of the one listed last.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TerrainMap {
+    pub zones: Vec<TerrainZone>,
+}
+
+impl TerrainMap {
+    /// Map used when the server is not given one
+    pub fn builtin() -> Self {
+        let zone = |terrain, min: (f32, f32), max: (f32, f32)| TerrainZone {
+            terrain,
+            min: Vector2::new(min.0, min.1),
+            max: Vector2::new(max.0, max.1),
+        };
+
+        Self {
+            zones: vec![
+                zone(Terrain::Mud, (-780.0, -780.0), (-300.0, -420.0)),
+                zone(Terrain::Mud, (360.0, 240.0), (660.0, 780.0)),
+                zone(Terrain::Ice, (-300.0, 300.0), (180.0, 600.0)),
+                zone(Terrain::Ice, (420.0, -900.0), (900.0, -480.0)),
+            ],
+        }
+    }
+
+    pub fn terrain_at(&self, pos: Vector2<f32>) -> Terrain {
+        self.zones
+            .iter()
+            .rev()
+            .find(|zone| zone.contains(pos))
+            .map_or(Terrain::Normal, |zone| zone.terrain)
+    }
+
+    /// Parse the map file format: one zone per line as `<terrain> <min_x> <min_y> <max_x>
+    /// <max_y>`. Empty lines and lines starting with `#` are ignored. Maps whose MAP message
+    /// doesn't fit in one client datagram are refused.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let zones = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_number, line)| {
+                parse_zone(line.split_whitespace())
+                    .ok_or_else(|| format!("Invalid terrain zone on line {line_number}: {line}"))
+            })
+            .collect::<Result<_, _>>()?;
+        let map = Self { zones };
+
+        let len = Message::Map(map.clone()).serialize().len();
+        if len > globals::MAX_CLIENT_DATAGRAM_LEN {
+            return Err(format!(
+                "Map takes {len} bytes to send, clients receive at most {}",
+                globals::MAX_CLIENT_DATAGRAM_LEN
+            ));
+        }
+
+        Ok(map)
+    }
+
+    /// Compact form for the MAP message: zones separated by `;`, fields by `,`. Coordinates keep
+    /// full precision, clients predict movement on the same zone edges as the server.
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            if i > 0 {
+                out.push(';');
+            }
+            let _ = write!(
+                out,
+                "{},{},{},{},{}",
+                zone.terrain.as_str(),
+                zone.min.x,
+                zone.min.y,
+                zone.max.x,
+                zone.max.y
+            );
+        }
+
+        out
+    }
+
+    pub fn deserialize(data: &str) -> Result<Self, Error> {
+        let zones = data
+            .split(';')
+            .filter(|zone| !zone.is_empty())
+            .map(|zone| {
+                parse_zone(zone.split(',')).ok_or_else(|| {
+                    Error::new(std::io::ErrorKind::InvalidData, "Invalid terrain zone")
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { zones })
+    }
+}
+
+fn parse_zone<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<TerrainZone> {
+    let terrain = Terrain::parse(fields.next()?)?;
+    let mut number = || fields.next()?.parse::<f32>().ok();
+
+    let min = Vector2::new(number()?, number()?);
+    let max = Vector2::new(number()?, number()?);
+
+    if fields.next().is_some() || min.x >= max.x || min.y >= max.y {
+        return None;
+    }
+
+    Some(TerrainZone { terrain, min, max })
+}
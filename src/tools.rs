@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use game_server_sample::{codec::Codec, message::Message};
+use serde_json::{json, Value};
+
+use crate::relay::RelayPacket;
+
+/// Decode captured datagrams and print each as pretty JSON. `input` is either the hex dump of a
+/// single datagram, or a capture file with one datagram per line, as hex or as the text the
+/// wire carries. Lines starting with `#` are comments.
+pub fn decode(input: &str, codec: Codec) -> Result<(), String> {
+    let datagrams = if Path::new(input).is_file() {
+        let text = std::fs::read_to_string(input)
+            .map_err(|e| format!("Failed to read capture {input}: {e}"))?;
+
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(parse_datagram)
+            .collect()
+    } else {
+        vec![decode_hex(input).ok_or("Expected a capture file or a hex dump")?]
+    };
+
+    for datagram in datagrams {
+        let decoded = decode_datagram(&datagram, codec);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&decoded).expect("JSON values always serialize")
+        );
+    }
+
+    Ok(())
+}
+
+/// JSON description of one datagram, with the error if it doesn't decode
+pub fn decode_datagram(datagram: &[u8], codec: Codec) -> Value {
+    // Traffic between a host and its relay wraps the game message
+    if let Some(packet) = std::str::from_utf8(datagram)
+        .ok()
+        .and_then(RelayPacket::parse)
+    {
+        let (relay, payload) = match packet {
+            RelayPacket::Register { game_port } => {
+                return json!({
+                    "bytes": datagram.len(),
+                    "relay": { "register": game_port },
+                })
+            }
+            RelayPacket::To { target, payload } => (json!({ "to": target.to_string() }), payload),
+            RelayPacket::From { source, payload } => {
+                (json!({ "from": source.to_string() }), payload)
+            }
+        };
+
+        let mut decoded = decode_datagram(payload.as_bytes(), codec);
+        decoded["bytes"] = json!(datagram.len());
+        decoded["relay"] = relay;
+        return decoded;
+    }
+
+    match codec.decode(datagram) {
+        Ok(msg) => json!({
+            "bytes": datagram.len(),
+            "type": msg.name(),
+            "message": message_json(&msg),
+        }),
+        Err(e) => json!({
+            "bytes": datagram.len(),
+            "error": e.to_string(),
+            "hex": encode_hex(datagram),
+        }),
+    }
+}
+
+fn message_json(msg: &Message) -> Value {
+    serde_json::from_slice(&Codec::Json.encode(msg)).expect("the JSON codec writes valid JSON")
+}
+
+// Hex lines are taken as hex, anything else as the datagram's text
+fn parse_datagram(line: &str) -> Vec<u8> {
+    decode_hex(line).unwrap_or_else(|| line.as_bytes().to_vec())
+}
+
+/// Bytes of a hex dump. Whitespace and `:` between bytes are allowed, so dumps copied from
+/// Wireshark or `xxd -p` work as they are.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':')
+        .collect();
+
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_decodes_to_message() {
+        let datagram = decode_hex("50 49 4e 47 3a 37").unwrap();
+        let decoded = decode_datagram(&datagram, Codec::Text);
+
+        assert_eq!(decoded["type"], "PING");
+        assert_eq!(decoded["message"], json!({ "Ping": 7 }));
+    }
+
+    #[test]
+    fn relayed_message_is_unwrapped() {
+        let decoded = decode_datagram(b"RELAYTO 10.0.0.2:4000 LEAVE:3", Codec::Text);
+
+        assert_eq!(decoded["relay"]["to"], "10.0.0.2:4000");
+        assert_eq!(decoded["message"], json!({ "Leave": 3 }));
+    }
+
+    #[test]
+    fn text_lines_are_not_mistaken_for_hex() {
+        assert_eq!(parse_datagram("LEAVE:3"), b"LEAVE:3");
+        assert_eq!(parse_datagram("4c 45"), b"LE");
+    }
+}
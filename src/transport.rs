@@ -0,0 +1,166 @@
+use std::{future::Future, io};
+
+use tokio::sync::{mpsc, Mutex};
+
+/// Datagram link between a client and the server it joined. Keeps the client session logic
+/// independent of the platform socket. A browser build would plug in a WebSocket here, though
+/// the session still spawns its tasks and keeps time with tokio, which wasm32 lacks.
+///
+/// Every `send` carries exactly one serialized message and every `recv` yields exactly one, the
+/// same framing UDP datagrams give for free.
+pub trait Transport: Send + Sync + 'static {
+    fn send(&self, data: &[u8]) -> impl Future<Output = io::Result<usize>> + Send;
+
+    fn recv(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send;
+
+    /// Whether any bytes get through, rather than text only. Decides if the client offers the
+    /// server the binary codec.
+    fn carries_binary(&self) -> bool {
+        true
+    }
+}
+
+////////////////////////////////////////////////////
+
+/// Datagrams queued on either side of a [`LocalTransport`] before new ones are dropped, like a
+/// full socket buffer would
+pub const LOCAL_QUEUE_LEN: usize = 1024;
+
+/// In-memory link to a server running in the same process, for the hosting player's own client.
+/// Skips the loopback socket and with it the syscalls and scheduling jitter.
+pub struct LocalTransport {
+    to_server: mpsc::Sender<Vec<u8>>,
+    from_server: Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl LocalTransport {
+    /// Client end of a link, the server end being the other two halves of the channels
+    pub fn new(to_server: mpsc::Sender<Vec<u8>>, from_server: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            to_server,
+            from_server: Mutex::new(from_server),
+        }
+    }
+}
+
+impl Transport for LocalTransport {
+    async fn send(&self, data: &[u8]) -> io::Result<usize> {
+        self.to_server
+            .send(data.to_vec())
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionReset))?;
+
+        Ok(data.len())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self
+            .from_server
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionReset))?;
+
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+}
+
+////////////////////////////////////////////////////
+
+pub use udp::UdpTransport;
+
+mod udp {
+    use std::{io, net::SocketAddr};
+
+    use tokio::net::{lookup_host, UdpSocket};
+
+    use super::Transport;
+    use crate::relay::RelayPacket;
+
+    // Room for the largest message plus the relay header
+    const RELAY_BUFFER_LEN: usize = 2048;
+
+    /// Native transport: a UDP socket on an ephemeral port connected to the server, or to a
+    /// relay forwarding to the server
+    pub struct UdpTransport {
+        socket: UdpSocket,
+
+        /// Server behind the relay, `None` when talking to the server directly
+        relay_target: Option<SocketAddr>,
+    }
+
+    impl UdpTransport {
+        pub async fn connect(server_address: &str) -> io::Result<Self> {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(server_address).await?;
+
+            Ok(Self {
+                socket,
+                relay_target: None,
+            })
+        }
+
+        /// Reach the server through a relay, for when strict NATs block the direct path
+        pub async fn via_relay(relay_address: &str, server_address: &str) -> io::Result<Self> {
+            let relay_target = lookup_host(server_address).await?.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "Server address did not resolve")
+            })?;
+
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(relay_address).await?;
+
+            Ok(Self {
+                socket,
+                relay_target: Some(relay_target),
+            })
+        }
+    }
+
+    impl Transport for UdpTransport {
+        async fn send(&self, data: &[u8]) -> io::Result<usize> {
+            let Some(target) = self.relay_target else {
+                return self.socket.send(data).await;
+            };
+
+            let packet = RelayPacket::To {
+                target,
+                payload: &String::from_utf8_lossy(data),
+            };
+            self.socket.send(packet.serialize().as_bytes()).await
+        }
+
+        async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(target) = self.relay_target else {
+                return self.socket.recv(buf).await;
+            };
+
+            let mut packet = [0u8; RELAY_BUFFER_LEN];
+            loop {
+                let len = self.socket.recv(&mut packet).await?;
+
+                // Anything but traffic from the server is dropped
+                if let Some(RelayPacket::From { source, payload }) =
+                    std::str::from_utf8(&packet[..len])
+                        .ok()
+                        .and_then(RelayPacket::parse)
+                {
+                    if source == target {
+                        let len = payload.len().min(buf.len());
+                        buf[..len].copy_from_slice(&payload.as_bytes()[..len]);
+
+                        return Ok(len);
+                    }
+                }
+            }
+        }
+
+        // Relay packets are text
+        fn carries_binary(&self) -> bool {
+            self.relay_target.is_none()
+        }
+    }
+}
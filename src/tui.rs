@@ -0,0 +1,240 @@
+use std::{error::Error, time::Duration};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, List, Paragraph, Row, Sparkline, Table},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    admin, paths,
+    server::{ServerHandle, ServerStatus},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Interactive console for the headless server showing connected players, tick timings and recent
+/// log lines. Blocks until the operator quits with q, Esc or Ctrl + C. Pressing d dumps the server
+/// state to a JSON file in the dumps folder of the data directory, : opens a command line for
+/// the admin commands, their output goes to the log panel.
+pub fn run(
+    rt: &tokio::runtime::Runtime,
+    server: &ServerHandle,
+    port: u16,
+) -> Result<(), Box<dyn Error>> {
+    // Log lines printed to stdout would tear the screen apart, they are shown in the log panel
+    // instead
+    server.set_log_echo(false);
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, rt, server, port);
+    ratatui::restore();
+
+    server.set_log_echo(true);
+
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    rt: &tokio::runtime::Runtime,
+    server: &ServerHandle,
+    port: u16,
+) -> Result<(), Box<dyn Error>> {
+    let commands = admin::admin_commands();
+
+    // Command line being typed after pressing :
+    let mut command: Option<String> = None;
+
+    loop {
+        let status = rt.block_on(server.status());
+        terminal.draw(|frame| draw(frame, &status, port, command.as_deref()))?;
+
+        if !event::poll(REFRESH_INTERVAL)? {
+            continue;
+        }
+
+        // Raw mode swallows the SIGINT, so Ctrl + C arrives as a regular key press
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if let Some(line) = command.as_mut() {
+                match key.code {
+                    KeyCode::Enter => {
+                        let line = command.take().unwrap_or_default();
+                        if let Some(output) = rt.block_on(admin::execute(&commands, server, &line))
+                        {
+                            for output_line in output.lines() {
+                                rt.block_on(server.log(output_line.to_string()));
+                            }
+                        }
+                    }
+                    KeyCode::Esc => command = None,
+                    KeyCode::Backspace => {
+                        line.pop();
+                    }
+                    KeyCode::Char(c) if !ctrl_c => line.push(c),
+                    _ => (),
+                }
+
+                if !ctrl_c {
+                    continue;
+                }
+            }
+
+            if key.code == KeyCode::Char(':') {
+                command = Some(String::new());
+                continue;
+            }
+
+            if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                return Ok(());
+            }
+
+            if key.code == KeyCode::Char('d') {
+                let file_name = format!("server-dump-{}.json", std::process::id());
+                match paths::ensure_dir(paths::dump_dir()) {
+                    Ok(dir) => rt.block_on(admin::dump_to_file(server, &dir.join(file_name))),
+                    Err(e) => rt.block_on(server.log(format!("Failed to create dump folder: {e}"))),
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////
+
+fn draw(frame: &mut Frame, status: &ServerStatus, port: u16, command: Option<&str>) {
+    let [header_area, body_area, log_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(8),
+        Constraint::Length(12),
+    ])
+    .areas(frame.area());
+
+    let [players_area, tick_area] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+            .areas(body_area);
+
+    let header = match command {
+        Some(command) => Paragraph::new(format!(":{command}_")),
+        None => Paragraph::new(format!(
+            " Server on port {port} | {} player(s) connected | d: dump state | \
+             : command | q: quit",
+            status.players.len()
+        ))
+        .bold(),
+    };
+    frame.render_widget(header, header_area);
+
+    draw_players(frame, status, players_area);
+    draw_tick_graph(frame, status, tick_area);
+    draw_log(frame, status, log_area);
+}
+
+fn draw_players(frame: &mut Frame, status: &ServerStatus, area: Rect) {
+    let rows = status.players.iter().map(|p| {
+        let rtt = p
+            .rtt
+            .map(|rtt| format!("{:.1} ms", rtt.as_secs_f32() * 1000.0))
+            .unwrap_or_else(|| String::from("-"));
+
+        let loss_style = if p.flagged {
+            Style::default().fg(Color::Magenta)
+        } else if p.packet_loss > 0.1 {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+
+        Row::new(vec![
+            p.player.id.to_string(),
+            p.addr.to_string(),
+            format!("{:.0}, {:.0}", p.player.pos.x, p.player.pos.y),
+            rtt,
+            format!("{:.0}%", p.packet_loss * 100.0),
+            format!("{:.1} s", p.last_seen.as_secs_f32()),
+            p.dropped_messages.to_string(),
+            p.input_buffer_depth.to_string(),
+            format!("{:.1}", p.suspicion),
+        ])
+        .style(loss_style)
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(4),
+            Constraint::Length(22),
+            Constraint::Length(12),
+            Constraint::Length(9),
+            Constraint::Length(5),
+            Constraint::Length(9),
+            Constraint::Length(8),
+            Constraint::Length(4),
+            Constraint::Length(9),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "Id",
+            "Address",
+            "Position",
+            "RTT",
+            "Loss",
+            "Last seen",
+            "Dropped",
+            "Buf",
+            "Suspicion",
+        ])
+        .bold(),
+    )
+    .block(Block::bordered().title(" Players "));
+
+    frame.render_widget(table, area);
+}
+
+fn draw_tick_graph(frame: &mut Frame, status: &ServerStatus, area: Rect) {
+    let last_tick = status.tick_times.last().copied().unwrap_or_default();
+    let title = format!(
+        " Tick {:.2} ms / {:.2} ms ",
+        last_tick.as_secs_f32() * 1000.0,
+        status.tick_budget.as_secs_f32() * 1000.0
+    );
+
+    // Show the newest samples that fit, the graph is scaled so a full bar means a blown budget
+    let width = area.width.saturating_sub(2) as usize;
+    let samples: Vec<u64> = status
+        .tick_times
+        .iter()
+        .skip(status.tick_times.len().saturating_sub(width))
+        .map(|t| t.as_micros() as u64)
+        .collect();
+
+    let graph = Sparkline::default()
+        .block(Block::bordered().title(title))
+        .data(&samples)
+        .max(status.tick_budget.as_micros() as u64)
+        .style(Style::default().fg(Color::Green));
+
+    frame.render_widget(graph, area);
+}
+
+fn draw_log(frame: &mut Frame, status: &ServerStatus, area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+    let lines = status
+        .log_lines
+        .iter()
+        .skip(status.log_lines.len().saturating_sub(height))
+        .map(String::as_str);
+
+    let log = List::new(lines).block(Block::bordered().title(" Log "));
+    frame.render_widget(log, area);
+}
@@ -0,0 +1,158 @@
+//! Receiving several UDP datagrams at once. With the `mmsg` feature on Linux every batch is a
+//! single `recvmmsg` syscall, elsewhere the socket is drained without going back to the runtime
+//! between datagrams. Sending goes through each client's outbox one datagram at a time.
+
+use std::{io, net::SocketAddr};
+
+use tokio::net::UdpSocket;
+
+/// Largest datagram received, relayed messages come with a header on top
+pub const MAX_DATAGRAM_LEN: usize = 2048;
+
+/// Reusable buffers for [`recv_batch`]
+pub struct RecvBatch {
+    bufs: Vec<[u8; MAX_DATAGRAM_LEN]>,
+
+    /// Sender and length of each datagram in the last batch, in `bufs` order
+    received: Vec<(SocketAddr, usize)>,
+}
+
+impl RecvBatch {
+    /// Room for up to `capacity` datagrams per batch
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            bufs: vec![[0; MAX_DATAGRAM_LEN]; capacity.max(1)],
+            received: Vec::with_capacity(capacity.max(1)),
+        }
+    }
+
+    /// Datagrams of the last batch with their senders
+    pub fn iter(&self) -> impl Iterator<Item = (SocketAddr, &[u8])> {
+        self.received
+            .iter()
+            .zip(&self.bufs)
+            .map(|((addr, len), buf)| (*addr, &buf[..*len]))
+    }
+}
+
+/// Wait for at least one datagram, then take whatever else is already queued up to the batch
+/// capacity. Returns the number of datagrams received.
+pub async fn recv_batch(socket: &UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+    batch.received.clear();
+
+    #[cfg(all(target_os = "linux", feature = "mmsg"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        let fd = socket.as_raw_fd();
+        let RecvBatch { bufs, received } = batch;
+        socket
+            .async_io(tokio::io::Interest::READABLE, || {
+                mmsg::recv(fd, bufs, received)
+            })
+            .await?;
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "mmsg")))]
+    {
+        let (len, addr) = socket.recv_from(&mut batch.bufs[0]).await?;
+        batch.received.push((addr, len));
+
+        // Errors end the batch early, the next call reports them if they persist
+        for buf in batch.bufs.iter_mut().skip(1) {
+            match socket.try_recv_from(buf) {
+                Ok((len, addr)) => batch.received.push((addr, len)),
+                Err(_) => break,
+            }
+        }
+    }
+
+    Ok(batch.received.len())
+}
+
+#[cfg(all(target_os = "linux", feature = "mmsg"))]
+mod mmsg {
+    use std::{
+        io, mem,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+        os::fd::RawFd,
+        ptr,
+    };
+
+    use super::MAX_DATAGRAM_LEN;
+
+    pub fn recv(
+        fd: RawFd,
+        bufs: &mut [[u8; MAX_DATAGRAM_LEN]],
+        received: &mut Vec<(SocketAddr, usize)>,
+    ) -> io::Result<()> {
+        // SAFETY: all-zero is a valid sockaddr_storage, iovec and mmsghdr
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; bufs.len()];
+        let mut iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(iovecs.iter_mut())
+            .map(|(addr, iovec)| {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as u32;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        // SAFETY: every header points at a live address and buffer of the stated size
+        let count = unsafe {
+            libc::recvmmsg(
+                fd,
+                headers.as_mut_ptr(),
+                headers.len() as u32,
+                libc::MSG_DONTWAIT,
+                ptr::null_mut(),
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        for (header, addr) in headers.iter().zip(&addrs).take(count as usize) {
+            received.push((to_socket_addr(addr)?, header.msg_len as usize));
+        }
+
+        Ok(())
+    }
+
+    fn to_socket_addr(addr: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+        match addr.ss_family as libc::c_int {
+            libc::AF_INET => {
+                // SAFETY: the family says it's a sockaddr_in, which fits in sockaddr_storage
+                let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+                Ok(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                // SAFETY: as above, for sockaddr_in6
+                let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            family => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected address family {family}"),
+            )),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Which build this is. The commit and build date come from `build.rs`.
+
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the wire format changes in a way older peers can't follow
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Package version and commit, e.g. `0.1.0+1a2b3c4`
+pub const BUILD: &str = concat!(env!("CARGO_PKG_VERSION"), "+", env!("GIT_HASH"));
+
+/// For `--version`
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (commit ",
+    env!("GIT_HASH"),
+    ", built ",
+    env!("BUILD_DATE"),
+    ")"
+);
+
+/// Version a peer announces, in ACKs and status replies
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    pub protocol: u32,
+
+    /// See [`BUILD`]
+    pub build: String,
+}
+
+impl Version {
+    /// This build's
+    pub fn current() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            build: String::from(BUILD),
+        }
+    }
+
+    /// Whether a peer of this version and this build understand each other
+    pub fn is_compatible(&self) -> bool {
+        self.protocol == PROTOCOL_VERSION
+    }
+
+    /// `protocol/build`, e.g. `1/0.1.0+1a2b3c4`
+    pub fn serialize(&self) -> String {
+        format!("{}/{}", self.protocol, self.build)
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let (protocol, build) = s.split_once('/')?;
+
+        Some(Self {
+            protocol: protocol.parse().ok()?,
+            build: build.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (protocol {})", self.build, self.protocol)
+    }
+}
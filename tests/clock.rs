@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use game_server_sample::clock::{SimClock, MIN_TIME_SCALE};
+
+#[test]
+fn slow_motion_and_pause_hold_simulation_time_back() {
+    let mut clock = SimClock::new();
+    assert_eq!(
+        clock.advance(Duration::from_secs(1)),
+        Duration::from_secs(1)
+    );
+
+    clock.set_scale(0.5);
+    assert_eq!(
+        clock.advance(Duration::from_secs(1)),
+        Duration::from_millis(500)
+    );
+
+    clock.set_paused(true);
+    assert_eq!(clock.advance(Duration::from_secs(1)), Duration::ZERO);
+    assert_eq!(clock.elapsed(), Duration::from_millis(1500));
+}
+
+#[test]
+fn wire_scale_carries_the_pause() {
+    let mut server = SimClock::new();
+    server.set_scale(0.25);
+    server.set_paused(true);
+
+    let mut client = SimClock::new();
+    client.set_wire_scale(server.wire_scale());
+    assert!(client.is_paused());
+
+    server.set_paused(false);
+    client.set_wire_scale(server.wire_scale());
+    assert!(!client.is_paused());
+    assert_eq!(client.scale(), 0.25);
+
+    // Out of range speeds are clamped, not trusted
+    client.set_wire_scale(0.001);
+    assert_eq!(client.scale(), MIN_TIME_SCALE);
+}
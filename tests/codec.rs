@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use cgmath::{vec2, vec3};
+use game_server_sample::{
+    codec::Codec,
+    globals,
+    identity::Identity,
+    message::{Message, NoticeLevel},
+    rules::GameRules,
+    simulation::MovementConfig,
+    terrain::TerrainMap,
+    version::Version,
+    Avatar, ClientId, Player, Shape, WorldMode,
+};
+
+fn sample_messages() -> Vec<Message> {
+    let mut player = Player::new(42, vec3(0.25, 0.5, 0.75));
+    player.pos = vec2(-512.25, 1024.5);
+    player.facing = 1.5;
+    let mut triangle = player;
+    triangle.avatar = Avatar {
+        shape: Shape::Triangle,
+        size: Some(37.5),
+    };
+    let mut circle = player;
+    circle.avatar.shape = Shape::Circle;
+    let client_id = ClientId::random();
+
+    vec![
+        Message::Handshake(
+            Some(client_id),
+            Some(String::from("K7QX2M")),
+            Some(Box::new(Identity::generate().prove(client_id))),
+            Some(Codec::Binary),
+        ),
+        Message::Handshake(Some(client_id), None, None, Some(Codec::Binary)),
+        Message::Ack(
+            42,
+            vec3(0.25, 0.5, 0.75),
+            WorldMode::Wrap,
+            Some(7),
+            Some(Version::current()),
+        ),
+        Message::Replicate(player),
+        Message::Replicate(triangle),
+        Message::Replicate(circle),
+        Message::Map(TerrainMap::builtin()),
+        Message::Chat(42, String::from("meet at the ice lake: north side")),
+        Message::ServerNotice(NoticeLevel::Warning, String::from("restart soon")),
+        Message::Position(42, vec2(-512.25, 1024.5), Some(7200)),
+        Message::Status(3, Some(Identity::generate().public_key()), None),
+        Message::Status(3, None, Some(Version::current())),
+        Message::Resync,
+        Message::Pong(7, None),
+        Message::Pong(8, Some(Duration::from_micros(41_250))),
+        Message::Probe(7),
+        Message::Rules(GameRules {
+            player_size: 30.0,
+            movement: MovementConfig {
+                speed: 12.5,
+                acceleration: 2.0,
+                friction: 0.2,
+            },
+            tick_rate: 30,
+            ping_interval: Duration::from_millis(50),
+            max_missed_pings: 40,
+            ..GameRules::default()
+        }),
+        Message::ProbeReply(7, Duration::from_micros(350), Duration::from_micros(12_500)),
+    ]
+}
+
+#[test]
+fn every_codec_round_trips_the_same_message() {
+    for codec in [Codec::Text, Codec::Json, Codec::Binary] {
+        for msg in sample_messages() {
+            let decoded = codec.decode(&codec.encode(&msg)).unwrap();
+
+            // The text form is what the wire carried so far, every codec has to agree with it
+            assert_eq!(decoded.serialize(), msg.serialize(), "{codec:?}");
+        }
+    }
+}
+
+#[test]
+fn garbage_is_rejected() {
+    for codec in [Codec::Text, Codec::Json, Codec::Binary] {
+        assert!(codec.decode(&[0xff, 0xfe, 0x00]).is_err(), "{codec:?}");
+    }
+}
+
+#[test]
+fn binary_and_text_tell_themselves_apart() {
+    for msg in sample_messages() {
+        for codec in [Codec::Text, Codec::Binary] {
+            let bytes = codec.encode(&msg);
+
+            assert_eq!(Codec::of(&bytes), codec, "{}", msg.serialize());
+        }
+    }
+}
+
+#[test]
+fn binary_is_smaller_than_text_for_replication() {
+    let msg = Message::Position(42, vec2(-512.25, 1024.5), Some(7200));
+
+    assert!(Codec::Binary.encode(&msg).len() < Codec::Text.encode(&msg).len());
+}
+
+#[test]
+fn handshake_codec_comes_last_and_unknown_ones_fall_back_to_text() {
+    let client_id = ClientId::random();
+
+    // Older servers read the first four fields only
+    let wire = Message::Handshake(Some(client_id), None, None, Some(Codec::Binary)).serialize();
+    assert_eq!(wire.split(':').nth(4), Some("binary"));
+
+    let wire = format!("HANDSHAKE:{client_id}:::zstd");
+    assert!(matches!(
+        Message::deserialize(&wire),
+        Ok(Message::Handshake(Some(_), None, None, None))
+    ));
+}
+
+#[test]
+fn maps_too_big_for_a_client_datagram_are_refused() {
+    let zone = "mud -1000.125 -1000.125 1000.125 1000.125\n";
+
+    assert!(TerrainMap::parse(&zone.repeat(4)).is_ok());
+    assert!(TerrainMap::parse(&zone.repeat(100)).is_err());
+    assert!(
+        Message::Map(TerrainMap::builtin()).serialize().len() <= globals::MAX_CLIENT_DATAGRAM_LEN
+    );
+}
@@ -0,0 +1,180 @@
+use cgmath::{vec2, vec3, InnerSpace, Vector2};
+use game_server_sample::{
+    message::Message,
+    rules::GameRules,
+    simulation::{same_bits, server_apply, step_player, MovementConfig},
+    terrain::TerrainMap,
+    Player, WorldBounds, WorldMode,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const TICKS: usize = 20_000;
+
+// Zone edges off the integer grid, so a lossy MAP message shows up
+const FRACTIONAL_MAP: &str = "
+mud -780.5 -780.25 -300.75 -420.125
+ice -300.3 300.7 180.1 600.9
+ice 20.5 -400.5 400.5 -20.5
+mud 360.6 240.2 660.4 780.8
+";
+
+/// Inputs like a player holding keys for a while, normalized like the client does
+fn input_sequence(seed: u64) -> Vec<Vector2<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut direction = vec2(0.0, 0.0);
+
+    (0..TICKS)
+        .map(|_| {
+            if rng.gen_range(0..20) == 0 {
+                let raw = vec2(rng.gen_range(-1..=1) as f32, rng.gen_range(-1..=1) as f32);
+                direction = match raw {
+                    raw if raw == vec2(0.0, 0.0) => raw,
+                    raw => raw.normalize(),
+                };
+            }
+            direction
+        })
+        .collect()
+}
+
+/// Run the same inputs with the server's map and world mode and with the copies a client gets
+/// over the wire, positions must match in every bit after every tick
+fn assert_client_matches_server(server_map: &TerrainMap, world_mode: WorldMode, seed: u64) {
+    let client_map = TerrainMap::deserialize(&server_map.serialize()).unwrap();
+    let client_world_mode = WorldMode::parse(world_mode.as_str()).unwrap();
+
+    // The rules also come over the wire, anything lost on the way shows up as drift
+    let server_rules = GameRules {
+        bounds: WorldBounds {
+            min_x: -900.3,
+            min_y: -700.7,
+            max_x: 800.1,
+            max_y: 950.9,
+        },
+        player_size: 21.5,
+        movement: MovementConfig {
+            speed: 11.3,
+            acceleration: 1.7,
+            friction: 0.3,
+        },
+        ..GameRules::default()
+    };
+    let Ok(Message::Rules(client_rules)) =
+        Message::deserialize(&Message::Rules(server_rules).serialize())
+    else {
+        panic!("game rules don't survive the wire");
+    };
+
+    let mut server = Player::new(1, vec3(1.0, 1.0, 1.0));
+    let mut client = server;
+
+    for (tick, direction) in input_sequence(seed).into_iter().enumerate() {
+        step_player(
+            &mut server,
+            direction,
+            server_map,
+            world_mode,
+            &server_rules,
+        );
+        step_player(
+            &mut client,
+            direction,
+            &client_map,
+            client_world_mode,
+            &client_rules,
+        );
+
+        assert!(
+            same_bits(client.pos, server.pos) && same_bits(client.velocity, server.velocity),
+            "{world_mode:?}: client at {:?}, server at {:?} after {tick} ticks",
+            client.pos,
+            server.pos
+        );
+    }
+}
+
+#[test]
+fn client_prediction_matches_server_bounded() {
+    for seed in 0..4 {
+        assert_client_matches_server(&TerrainMap::builtin(), WorldMode::Bounded, seed);
+    }
+}
+
+#[test]
+fn client_prediction_matches_server_wrapping() {
+    for seed in 0..4 {
+        assert_client_matches_server(&TerrainMap::builtin(), WorldMode::Wrap, seed);
+    }
+}
+
+#[test]
+fn client_prediction_matches_server_fractional_map() {
+    let map = TerrainMap::parse(FRACTIONAL_MAP).unwrap();
+
+    for seed in 0..4 {
+        assert_client_matches_server(&map, WorldMode::Bounded, seed);
+    }
+}
+
+#[test]
+fn server_ends_up_where_client_predicted() {
+    let map = TerrainMap::builtin();
+
+    for world_mode in [WorldMode::Bounded, WorldMode::Wrap] {
+        let mut client = Player::new(1, vec3(1.0, 1.0, 1.0));
+        let mut server = client;
+
+        for (step, direction) in input_sequence(7).into_iter().enumerate() {
+            step_player(
+                &mut client,
+                direction,
+                &map,
+                world_mode,
+                &GameRules::default(),
+            );
+            server_apply(
+                &mut server,
+                &client,
+                step as u32,
+                world_mode,
+                &GameRules::default(),
+            );
+
+            assert!(
+                same_bits(client.pos, server.pos),
+                "{world_mode:?}: client at {:?}, server at {:?} after {step} ticks",
+                client.pos,
+                server.pos
+            );
+        }
+    }
+}
+
+#[test]
+fn replaying_inputs_is_bit_identical() {
+    let map = TerrainMap::builtin();
+    let inputs = input_sequence(42);
+
+    let run = || {
+        let mut player = Player::new(1, vec3(1.0, 1.0, 1.0));
+        let mut terrains = std::collections::HashSet::new();
+        for direction in &inputs {
+            step_player(
+                &mut player,
+                *direction,
+                &map,
+                WorldMode::Bounded,
+                &GameRules::default(),
+            );
+            terrains.insert(map.terrain_at(player.pos).as_str());
+        }
+        (player, terrains.len())
+    };
+
+    let (first, terrains) = run();
+    let (second, _) = run();
+
+    assert!(same_bits(first.pos, second.pos));
+    assert!(same_bits(first.velocity, second.velocity));
+    assert!(terrains > 1, "inputs never left normal ground");
+}
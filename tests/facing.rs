@@ -0,0 +1,78 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use cgmath::{vec2, vec3};
+use game_server_sample::{
+    angle_difference, globals, lerp_angle, message::Message, normalize_angle, Player,
+};
+
+fn assert_angle_eq(a: f32, b: f32) {
+    assert!(angle_difference(a, b).abs() < 1e-4, "{a} != {b}");
+}
+
+#[test]
+fn angles_blend_the_short_way_round() {
+    let from = 170f32.to_radians();
+    let to = -170f32.to_radians();
+
+    assert_angle_eq(lerp_angle(from, to, 0.5), PI);
+    assert_angle_eq(lerp_angle(to, from, 0.5), PI);
+    assert_angle_eq(lerp_angle(0.1, -0.1, 0.5), 0.0);
+    assert_angle_eq(lerp_angle(from, to, 1.0), to);
+
+    for angle in [-3.0 * PI, -PI, 0.0, PI, 2.5 * TAU] {
+        let normalized = normalize_angle(angle);
+        assert!(
+            normalized > -PI && normalized <= PI,
+            "{angle} -> {normalized}"
+        );
+    }
+}
+
+#[test]
+fn players_turn_towards_their_movement_gradually() {
+    let mut player = Player::new(1, vec3(1.0, 1.0, 1.0));
+
+    // Half a turn is made in steps, never more than the turn speed at once
+    let mut steps = 0;
+    while angle_difference(player.facing, PI).abs() > 1e-4 {
+        let before = player.facing;
+        player.turn_towards(vec2(-1.0, 0.0));
+        assert!(angle_difference(before, player.facing).abs() <= globals::PLAYER_TURN_SPEED + 1e-6);
+
+        steps += 1;
+        assert!(steps < 100, "never arrived");
+    }
+    assert_eq!(steps, (PI / globals::PLAYER_TURN_SPEED).ceil() as usize);
+
+    // Standing still keeps the facing
+    player.turn_towards(vec2(0.0, 0.0));
+    assert_angle_eq(player.facing, PI);
+
+    // Facing down takes the short way, through -3/4 PI rather than through 0
+    player.turn_towards(vec2(0.0, -1.0));
+    assert!(player.facing < -FRAC_PI_2);
+}
+
+#[test]
+fn facing_is_replicated() {
+    for facing in [0.0, 1.0, -2.5, PI, -PI + 0.01] {
+        let player = Player {
+            facing,
+            ..Player::new(4, vec3(0.0, 1.0, 0.0))
+        };
+
+        match Message::deserialize(&Message::Replicate(player).serialize()) {
+            Ok(Message::Replicate(received)) => {
+                // Within half a wire step
+                assert!(angle_difference(received.facing, facing).abs() <= TAU / 512.0);
+            }
+            _ => panic!("replication message did not round trip"),
+        }
+    }
+
+    // From servers that don't send it
+    match Message::deserialize("REPL:4:64,-128,#00FF00") {
+        Ok(Message::Replicate(received)) => assert_eq!(received.facing, 0.0),
+        _ => panic!("replication without facing was refused"),
+    }
+}
@@ -0,0 +1,44 @@
+use game_server_sample::{
+    identity::{Identity, KeyProof, PublicKey},
+    message::Message,
+    ClientId,
+};
+
+#[test]
+fn handshake_proves_the_key_for_that_client_only() {
+    let identity = Identity::generate();
+    let client_id = ClientId::random();
+    let proof = identity.prove(client_id);
+
+    let wire = Message::Handshake(Some(client_id), None, Some(Box::new(proof)), None).serialize();
+    match Message::deserialize(&wire) {
+        Ok(Message::Handshake(Some(id), None, Some(received), None)) => {
+            assert_eq!(id, client_id);
+            assert_eq!(*received, proof);
+            assert!(received.verify(client_id));
+        }
+        _ => panic!("Handshake did not survive the wire: {wire}"),
+    }
+
+    // Replayed by someone joining under another client id
+    assert!(!proof.verify(ClientId::random()));
+
+    let mut forged = proof;
+    forged.key = Identity::generate().public_key();
+    assert!(!forged.verify(client_id));
+}
+
+#[test]
+fn identity_survives_being_stored() {
+    let identity = Identity::generate();
+    let restored = Identity::from_secret_hex(&identity.secret_hex()).unwrap();
+    assert_eq!(restored.public_key(), identity.public_key());
+
+    let key = identity.public_key();
+    assert_eq!(PublicKey::parse(&key.to_string()), Some(key));
+    assert_eq!(
+        Identity::from_secret_hex("not hex").map(|i| i.public_key()),
+        None
+    );
+    assert_eq!(KeyProof::deserialize("abc,def"), None);
+}
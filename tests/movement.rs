@@ -0,0 +1,150 @@
+use cgmath::{vec2, vec3, InnerSpace};
+use game_server_sample::{
+    globals,
+    message::Message,
+    rules::GameRules,
+    simulation::{knockback, knockback_distance, step_player, MovementConfig},
+    terrain::TerrainMap,
+    Avatar, Player, Shape, WorldBounds, WorldMode,
+};
+
+fn speed_after(player: &mut Player, direction: [f32; 2], steps: usize, map: &TerrainMap) -> f32 {
+    for _ in 0..steps {
+        step_player(
+            player,
+            direction.into(),
+            map,
+            WorldMode::Bounded,
+            &GameRules::default(),
+        );
+    }
+    player.velocity.magnitude()
+}
+
+#[test]
+fn players_speed_up_and_slow_down_over_several_steps() {
+    let map = TerrainMap::default();
+    let mut player = Player::new(1, vec3(1.0, 1.0, 1.0));
+
+    let first = speed_after(&mut player, [1.0, 0.0], 1, &map);
+    assert!(first > 0.0 && first < globals::PLAYER_SPEED);
+
+    assert_eq!(
+        speed_after(&mut player, [1.0, 0.0], 20, &map),
+        globals::PLAYER_SPEED
+    );
+
+    let coasting = speed_after(&mut player, [0.0, 0.0], 1, &map);
+    assert!(coasting > 0.0 && coasting < globals::PLAYER_SPEED);
+
+    assert_eq!(speed_after(&mut player, [0.0, 0.0], 60, &map), 0.0);
+}
+
+#[test]
+fn terrain_changes_top_speed_and_grip() {
+    let map = TerrainMap::parse("mud -400 -400 400 400\nice 500 -400 700 2000").unwrap();
+
+    let mut on_mud = Player::new(1, vec3(1.0, 1.0, 1.0));
+    let mud_speed = speed_after(&mut on_mud, [0.0, 1.0], 20, &map);
+    assert!(mud_speed < globals::PLAYER_SPEED * 0.75, "{mud_speed}");
+
+    // Still sliding on ice long after normal ground would have stopped
+    let mut on_ice = Player::new(2, vec3(1.0, 1.0, 1.0));
+    on_ice.pos = vec2(600.0, -300.0);
+    speed_after(&mut on_ice, [0.0, 1.0], 60, &map);
+    assert!(speed_after(&mut on_ice, [0.0, 0.0], 20, &map) > 1.0);
+}
+
+#[test]
+fn colliding_players_are_pushed_apart_until_friction_stops_them() {
+    let map = TerrainMap::default();
+    let mut player = Player::new(1, vec3(1.0, 1.0, 1.0));
+    let mut other = Player::new(2, vec3(1.0, 1.0, 1.0));
+    other.pos = vec2(globals::PLAYER_QUAD_SIZE / 2.0, 0.0);
+
+    let impulse = knockback(&player, &other, WorldMode::Bounded, &GameRules::default()).unwrap();
+    assert_eq!(impulse, vec2(-globals::KNOCKBACK_IMPULSE, 0.0));
+    assert_eq!(
+        knockback(&other, &player, WorldMode::Bounded, &GameRules::default()),
+        Some(-impulse)
+    );
+
+    // Arrives as sent, so the knocked back client moves exactly like the server expects
+    let wire = Message::Knockback(player.id, impulse).serialize();
+    assert!(matches!(
+        Message::deserialize(&wire),
+        Ok(Message::Knockback(1, received)) if received == impulse
+    ));
+
+    player.impulse = impulse;
+    speed_after(&mut player, [0.0, 0.0], 120, &map);
+    assert_eq!(player.impulse, vec2(0.0, 0.0));
+    assert!((player.pos.x + knockback_distance(impulse, &MovementConfig::default())).abs() < 1.0);
+
+    other.pos = vec2(globals::PLAYER_QUAD_SIZE, 0.0);
+    assert_eq!(
+        knockback(&player, &other, WorldMode::Bounded, &GameRules::default()),
+        None
+    );
+}
+
+#[test]
+fn players_stay_inside_the_world_of_the_rules() {
+    let rules = GameRules {
+        bounds: WorldBounds {
+            min_x: -100.0,
+            min_y: -50.0,
+            max_x: 100.0,
+            max_y: 50.0,
+        },
+        player_size: 10.0,
+        ..GameRules::default()
+    };
+    let map = TerrainMap::default();
+
+    let mut bounded = Player::new(1, vec3(1.0, 1.0, 1.0));
+    let mut wrapping = bounded;
+    for _ in 0..60 {
+        step_player(
+            &mut bounded,
+            vec2(1.0, 0.0),
+            &map,
+            WorldMode::Bounded,
+            &rules,
+        );
+        step_player(&mut wrapping, vec2(1.0, 0.0), &map, WorldMode::Wrap, &rules);
+    }
+
+    assert_eq!(bounded.pos.x, 95.0);
+    assert!(wrapping.pos.x >= -100.0 && wrapping.pos.x < 100.0);
+    assert_eq!(
+        rules.world_delta(vec2(95.0, 0.0), vec2(-95.0, 0.0), WorldMode::Wrap),
+        vec2(10.0, 0.0)
+    );
+}
+
+#[test]
+fn avatar_sizes_decide_collisions_and_the_world_edge() {
+    let rules = GameRules::default();
+    let mut big = Player::new(1, vec3(1.0, 1.0, 1.0));
+    big.avatar = Avatar {
+        shape: Shape::Circle,
+        size: Some(rules.player_size * 3.0),
+    };
+    let mut other = Player::new(2, vec3(1.0, 1.0, 1.0));
+    other.pos = vec2(rules.player_size * 1.5, 0.0);
+
+    // Out of reach of a normal sized player, touching the big one
+    assert!(knockback(
+        &Player::new(3, vec3(1.0, 1.0, 1.0)),
+        &other,
+        WorldMode::Bounded,
+        &rules
+    )
+    .is_none());
+    assert!(knockback(&big, &other, WorldMode::Bounded, &rules).is_some());
+
+    big.pos = vec2(rules.bounds.max_x, 0.0);
+    rules.apply_world_bounds(&mut big, WorldMode::Bounded);
+    assert_eq!(big.pos.x, rules.bounds.max_x - rules.player_size * 1.5);
+}
@@ -0,0 +1,52 @@
+use game_server_sample::outbox::Outbox;
+
+fn drain(outbox: &mut Outbox<u32, &'static str>) -> Vec<&'static str> {
+    std::iter::from_fn(|| outbox.pop()).collect()
+}
+
+#[test]
+fn newer_update_replaces_queued_one() {
+    let mut outbox = Outbox::new(8);
+    outbox.push(Some(1), "player 1 at 0");
+    outbox.push(None, "chat");
+    outbox.push(Some(2), "player 2 at 0");
+    outbox.push(Some(1), "player 1 at 5");
+
+    assert_eq!(
+        drain(&mut outbox),
+        vec!["player 1 at 5", "chat", "player 2 at 0"]
+    );
+    assert_eq!(outbox.dropped(), 1);
+}
+
+#[test]
+fn full_queue_drops_oldest_update_but_keeps_reliable_messages() {
+    let mut outbox = Outbox::new(3);
+    outbox.push(None, "join");
+    outbox.push(Some(1), "player 1");
+    outbox.push(Some(2), "player 2");
+    outbox.push(Some(3), "player 3");
+
+    assert_eq!(drain(&mut outbox), vec!["join", "player 2", "player 3"]);
+
+    // Nothing left to make room with, reliable messages still get in and the update doesn't
+    for reliable in ["a", "b", "c", "d"] {
+        outbox.push(None, reliable);
+    }
+    outbox.push(Some(1), "player 1");
+
+    assert_eq!(drain(&mut outbox), vec!["a", "b", "c", "d"]);
+    assert_eq!(outbox.dropped(), 2);
+}
+
+#[test]
+fn retain_keyed_only_drops_updates() {
+    let mut outbox = Outbox::new(8);
+    outbox.push(Some(1), "player 1");
+    outbox.push(None, "chat");
+    outbox.push(Some(2), "player 2");
+
+    outbox.retain_keyed(|key| *key != 1);
+
+    assert_eq!(drain(&mut outbox), vec!["chat", "player 2"]);
+}
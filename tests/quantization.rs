@@ -0,0 +1,75 @@
+use cgmath::{vec2, vec3, Vector2};
+use game_server_sample::{
+    message::{dequantize_position, quantize_position, snap_position, Message, POSITION_SCALE},
+    Player,
+};
+
+fn round_trip(pos: Vector2<f32>) -> Vector2<f32> {
+    match Message::deserialize(&Message::Position(1, pos, None).serialize()) {
+        Ok(Message::Position(_, pos, _)) => pos,
+        _ => panic!("position message did not round trip"),
+    }
+}
+
+#[test]
+fn quantization_error_is_at_most_half_a_step() {
+    let half_step = 0.5 / POSITION_SCALE;
+
+    for i in -10_000..10_000 {
+        let pos = vec2(i as f32 * 0.1237, i as f32 * -0.0719);
+        let received = round_trip(pos);
+
+        assert!(
+            (received.x - pos.x).abs() <= half_step,
+            "{pos:?} came back as {received:?}"
+        );
+        assert!(
+            (received.y - pos.y).abs() <= half_step,
+            "{pos:?} came back as {received:?}"
+        );
+    }
+}
+
+#[test]
+fn snapped_positions_round_trip_exactly() {
+    // Whole world and beyond, including the wrapping seam
+    for i in (-2_000_000..2_000_000).step_by(97) {
+        let pos = snap_position(vec2(i as f32 / 500.0, -i as f32 / 700.0));
+        let received = round_trip(pos);
+
+        assert_eq!(received.x.to_bits(), pos.x.to_bits());
+        assert_eq!(received.y.to_bits(), pos.y.to_bits());
+    }
+}
+
+#[test]
+fn snapping_is_stable() {
+    let pos = snap_position(vec2(123.456, -987.654));
+
+    assert_eq!(snap_position(pos), pos);
+    assert_eq!(dequantize_position(quantize_position(pos)), pos);
+    assert_eq!(
+        quantize_position(vec2(0.0, -1.0)),
+        vec2(0, -(POSITION_SCALE as i32))
+    );
+}
+
+#[test]
+fn snapshots_and_corrections_use_the_same_grid() {
+    let pos = vec2(-511.3071, 1023.9999);
+    let expected = snap_position(pos);
+
+    let player = Player {
+        pos,
+        ..Player::new(3, vec3(1.0, 0.0, 0.0))
+    };
+    match Message::deserialize(&Message::Replicate(player).serialize()) {
+        Ok(Message::Replicate(player)) => assert_eq!(player.pos, expected),
+        _ => panic!("replication message did not round trip"),
+    }
+
+    match Message::deserialize(&Message::Correction(pos).serialize()) {
+        Ok(Message::Correction(received)) => assert_eq!(received, expected),
+        _ => panic!("correction message did not round trip"),
+    }
+}
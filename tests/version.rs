@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use game_server_sample::{
+    message::Message,
+    rules::GameRules,
+    version::{Version, PROTOCOL_VERSION},
+};
+
+#[test]
+fn version_round_trips_and_checks_protocol() {
+    let version = Version::current();
+    assert_eq!(Version::parse(&version.serialize()), Some(version.clone()));
+    assert!(version.is_compatible());
+
+    let newer = Version {
+        protocol: PROTOCOL_VERSION + 1,
+        build: String::from("9.0.0+abcdef0"),
+    };
+    assert!(!newer.is_compatible());
+    assert_eq!(Version::parse("not a version"), None);
+}
+
+#[test]
+fn unversioned_replies_of_older_servers_still_parse() {
+    let ack = Message::deserialize("ACK:7:#FF0000:bounded:42").unwrap();
+    assert!(matches!(ack, Message::Ack(7, _, _, Some(42), None)));
+
+    let status = Message::deserialize("STATUS:3").unwrap();
+    assert!(matches!(status, Message::Status(3, None, None)));
+}
+
+#[test]
+fn rules_of_servers_without_a_ping_policy_turn_the_timeout_into_missed_pings() {
+    let current = Message::Rules(GameRules::default()).serialize();
+    let (without_policy, _) = current.rsplit_once(':').unwrap();
+    let (without_timeout, _) = without_policy.rsplit_once(':').unwrap();
+
+    let Ok(Message::Rules(rules)) = Message::deserialize(without_policy) else {
+        panic!("{without_policy}");
+    };
+    assert_eq!(rules, GameRules::default());
+
+    let Ok(Message::Rules(rules)) = Message::deserialize(&format!("{without_timeout}:7500")) else {
+        panic!("{without_timeout}");
+    };
+    assert_eq!(rules.connection_timeout(), Duration::from_millis(7500));
+}